@@ -1,13 +1,28 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Reverse, collections::HashMap, fmt, iter::Sum, ops::Add, str::FromStr,
+};
 
 use arbitrary::Arbitrary;
 use chrono::NaiveDateTime;
-use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
+use diesel::{
+    connection::LoadConnection,
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::{self, Output, ToSql},
+    sql_types::BigInt,
+    sqlite::{Sqlite, SqliteValue},
+};
+use fuzzcheck::DefaultMutator;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use rocket::form::{self, FromFormField, ValueField};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
-    schema::{adjudicator_ballot_entries, adjudicator_ballots, spar_teams},
+    schema::{
+        adjudicator_ballot_entries, adjudicator_ballots,
+        spar_series_ballot_settings, spar_series_score_rules, spar_teams,
+    },
     spar::SparRoomTeam,
 };
 
@@ -22,17 +37,197 @@ pub struct AdjudicatorBallotLink {
     pub member_id: i64,
     pub created_at: NaiveDateTime,
     pub expires_at: NaiveDateTime,
+    /// When the link was last emailed to the adjudicator.
+    pub sent_at: Option<NaiveDateTime>,
+    /// When the adjudicator first opened the link.
+    pub opened_at: Option<NaiveDateTime>,
+    /// When the adjudicator was last reminded to submit their ballot.
+    pub reminded_at: Option<NaiveDateTime>,
+    /// When the adjudicator was automatically reminded to submit their ballot
+    /// (reminders sent by administrators only update `reminded_at`).
+    pub auto_reminded_at: Option<NaiveDateTime>,
 }
 
 #[derive(
     Queryable, Serialize, Debug, Clone, Eq, PartialEq, Hash, Arbitrary,
 )]
+/// A single version of an adjudicator's ballot. Ballots are never modified
+/// once they have been submitted; resubmitting a ballot creates a new row.
 pub struct AdjudicatorBallot {
     pub id: i64,
     pub public_id: String,
     pub adjudicator_id: i64,
     pub room_id: i64,
     pub created_at: NaiveDateTime,
+    /// One of [`SOURCE_LINK`], [`SOURCE_ACCOUNT`] or [`SOURCE_ADMIN`].
+    pub source: String,
+    /// The user who entered this ballot (only set for ballots entered by
+    /// administrators, or by adjudicators who were logged in).
+    pub submitted_by: Option<i64>,
+    /// If set, this ballot is always used as the result of the room.
+    pub is_pinned: bool,
+}
+
+/// The ballot was submitted by the adjudicator using their private link.
+pub const SOURCE_LINK: &str = "link";
+/// The ballot was submitted by the adjudicator while logged in to the
+/// account linked to their spar series membership.
+pub const SOURCE_ACCOUNT: &str = "account";
+/// The ballot was entered by an administrator.
+pub const SOURCE_ADMIN: &str = "admin";
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Arbitrary,
+    DefaultMutator,
+    AsExpression,
+    FromSqlRow,
+)]
+#[diesel(sql_type = BigInt)]
+/// A speaker score. Scores may include half points (e.g. `75.5`), so they are
+/// stored as a whole number of half points (e.g. `151`). This is also how the
+/// bounds and step of [`ScoreRules`] are stored.
+pub struct Score(i64);
+
+impl Score {
+    pub const fn of_points(points: i64) -> Self {
+        Score(points * 2)
+    }
+
+    pub const fn of_half_points(half_points: i64) -> Self {
+        Score(half_points)
+    }
+
+    pub const fn half_points(self) -> i64 {
+        self.0
+    }
+
+    pub const fn is_whole(self) -> bool {
+        self.0 % 2 == 0
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let half_points = self.0.unsigned_abs();
+        if half_points % 2 == 0 {
+            write!(f, "{sign}{}", half_points / 2)
+        } else {
+            write!(f, "{sign}{}.5", half_points / 2)
+        }
+    }
+}
+
+impl FromStr for Score {
+    type Err = ();
+
+    /// Parses a score written as a decimal (e.g. `75`, `75.5` or `75.0`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((_, "")) => return Err(()),
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits, ""),
+        };
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let half = match fraction.trim_end_matches('0') {
+            "" => 0,
+            "5" => 1,
+            _ => return Err(()),
+        };
+        let half_points = whole
+            .parse::<i64>()
+            .ok()
+            .and_then(|whole| whole.checked_mul(2))
+            .and_then(|half_points| half_points.checked_add(half))
+            .ok_or(())?;
+        Ok(Score(if negative { -half_points } else { half_points }))
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, rhs: Score) -> Score {
+        Score(self.0 + rhs.0)
+    }
+}
+
+impl Sum for Score {
+    fn sum<I: Iterator<Item = Score>>(iter: I) -> Score {
+        Score(iter.map(|score| score.0).sum())
+    }
+}
+
+impl Serialize for Score {
+    /// Scores are serialized as numbers (whole scores as integers, so that
+    /// they look the same as they did before half points were supported).
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if self.is_whole() {
+            serializer.serialize_i64(self.0 / 2)
+        } else {
+            serializer.serialize_f64(self.0 as f64 / 2.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Score {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let points = f64::deserialize(deserializer)?;
+        let half_points = points * 2.0;
+        if half_points.fract() != 0.0 {
+            return Err(serde::de::Error::custom(
+                "scores must be a whole number of half points",
+            ));
+        }
+        Ok(Score(half_points as i64))
+    }
+}
+
+impl<'v> FromFormField<'v> for Score {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        field.value.parse().map_err(|_| {
+            form::Error::validation(
+                "scores must be a whole or half point (e.g. 75 or 75.5)",
+            )
+            .into()
+        })
+    }
+}
+
+impl FromSql<BigInt, Sqlite> for Score {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        <i64 as FromSql<BigInt, Sqlite>>::from_sql(value).map(Score)
+    }
+}
+
+impl ToSql<BigInt, Sqlite> for Score {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut Output<'b, '_, Sqlite>,
+    ) -> serialize::Result {
+        <i64 as ToSql<BigInt, Sqlite>>::to_sql(&self.0, out)
+    }
 }
 
 #[derive(
@@ -52,8 +247,20 @@ pub struct AdjudicatorBallotEntry {
     pub ballot_id: i64,
     pub speaker_id: i64,
     pub team_id: i64,
-    pub speak: i64,
+    pub speak: Score,
     pub position: i64,
+    /// Written feedback from the adjudicator to this speaker.
+    pub comments: Option<String>,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, Eq, PartialEq)]
+/// Written feedback from an adjudicator to one of the teams in their room.
+pub struct AdjudicatorBallotTeamComments {
+    pub id: i64,
+    pub public_id: String,
+    pub ballot_id: i64,
+    pub team_id: i64,
+    pub comments: String,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -109,7 +316,8 @@ impl BallotRepr {
                 })
                 .or_insert(vec![scoresheet]);
         }
-        assert_eq!(positions.len(), 4);
+        // half-rooms only have ballot entries for the opening teams
+        assert!(matches!(positions.len(), 2 | 4));
 
         let scoresheet = Scoresheet {
             teams: {
@@ -130,18 +338,20 @@ impl BallotRepr {
     /// Returns the ranking of these teams, with the best team first and the
     /// worst team last.
     ///
+    /// In half-rooms only the opening teams are ranked.
+    ///
     /// Panics if the debate is not currently stored in the database in BP
     /// format.
     pub fn bp_ranking(&self) -> Vec<BpTeam> {
         // todo: maybe return an option instead of asserting here?
-        assert_eq!(self.scoresheet.teams.len(), 4);
+        assert!(matches!(self.scoresheet.teams.len(), 2 | 4));
 
         self.scoresheet
             .teams
             .iter()
             .enumerate()
             .sorted_by_key(|(_, team)| {
-                (-1) * team.speakers.iter().map(|s| s.score).sum::<i64>()
+                Reverse(team.speakers.iter().map(|s| s.score).sum::<Score>())
             })
             .map(|(i, _)| {
                 let team = match i {
@@ -158,6 +368,168 @@ impl BallotRepr {
     }
 }
 
+/// Picks the canonical ballot out of the latest ballot submitted by each
+/// adjudicator on a panel.
+///
+/// If the chair (if there is one) has submitted a ballot then their ballot is
+/// canonical. Otherwise we take a majority vote on the ranking of the teams
+/// (ties are broken in favour of the ballot which was submitted first, so
+/// `ballots` should be ordered by submission time).
+pub fn resolve_canonical_ballot(
+    mut ballots: Vec<BallotRepr>,
+    chair_adjudicator_id: Option<i64>,
+) -> Option<BallotRepr> {
+    if let Some(chair) = chair_adjudicator_id {
+        if let Some(idx) = ballots
+            .iter()
+            .position(|ballot| ballot.inner.adjudicator_id == chair)
+        {
+            return Some(ballots.swap_remove(idx));
+        }
+    }
+
+    let rankings = ballots
+        .iter()
+        .map(|ballot| ballot.bp_ranking())
+        .collect::<Vec<_>>();
+    let idx = majority_ranking(&rankings)?;
+    Some(ballots.swap_remove(idx))
+}
+
+/// Returns the index of the first ranking which received the most votes.
+fn majority_ranking(rankings: &[Vec<BpTeam>]) -> Option<usize> {
+    let mut best: Option<(usize, usize)> = None;
+    for (i, ranking) in rankings.iter().enumerate() {
+        let votes = rankings.iter().filter(|other| *other == ranking).count();
+        if best
+            .map(|(_, best_votes)| votes > best_votes)
+            .unwrap_or(true)
+        {
+            best = Some((i, votes));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+/// Returns true if the adjudicators who submitted these ballots disagree on
+/// the ranking of the teams.
+pub fn ballots_conflict(ballots: &[BallotRepr]) -> bool {
+    !ballots.iter().map(|ballot| ballot.bp_ranking()).all_equal()
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, Eq, PartialEq)]
+/// Rules that the speaker scores on ballots submitted for the spars in a
+/// series should follow. The bounds and the step may include half points
+/// (e.g. a step of `0.5`).
+///
+/// There is no check for low-point wins, as the ranking of the teams on a
+/// ballot is derived from the team totals (rather than entered separately),
+/// so a low-point win cannot be submitted in the first place.
+pub struct ScoreRules {
+    pub id: i64,
+    pub spar_series_id: i64,
+    pub min_score: Option<Score>,
+    pub max_score: Option<Score>,
+    /// If set, speaker scores must go up from the minimum score (or from zero,
+    /// if there is no minimum) in multiples of this.
+    pub step: Option<Score>,
+    /// Either [`ENFORCE_ERROR`] or [`ENFORCE_WARN`].
+    pub enforcement: String,
+}
+
+/// Ballots which break the score rules are rejected.
+pub const ENFORCE_ERROR: &str = "error";
+/// Adjudicators who submit ballots which break the score rules are asked to
+/// confirm that their scores are correct.
+pub const ENFORCE_WARN: &str = "warn";
+
+impl ScoreRules {
+    /// Retrieves the score rules of the given spar series (if the
+    /// administrators have set any).
+    pub fn of_series(
+        spar_series_id: i64,
+        conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+    ) -> Result<Option<Self>, diesel::result::Error> {
+        spar_series_score_rules::table
+            .filter(spar_series_score_rules::spar_series_id.eq(spar_series_id))
+            .first::<ScoreRules>(conn)
+            .optional()
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.enforcement == ENFORCE_ERROR
+    }
+
+    /// Checks each of the provided `(position, score)` pairs against these
+    /// rules, returning a description of every score which breaks them.
+    pub fn violations(&self, scores: &[(&str, Score)]) -> Vec<String> {
+        let mut ret = Vec::new();
+        for (position, score) in scores {
+            if let Some(min) = self.min_score {
+                if *score < min {
+                    ret.push(format!(
+                        "The score given to {position} ({score}) is below \
+                         the minimum of {min}."
+                    ));
+                    continue;
+                }
+            }
+            if let Some(max) = self.max_score {
+                if *score > max {
+                    ret.push(format!(
+                        "The score given to {position} ({score}) is above \
+                         the maximum of {max}."
+                    ));
+                    continue;
+                }
+            }
+            if let Some(step) = self.step {
+                let base = self.min_score.unwrap_or_default().half_points();
+                let step_half_points = step.half_points();
+                if step_half_points > 0
+                    && (score.half_points() - base).rem_euclid(step_half_points)
+                        != 0
+                {
+                    ret.push(format!(
+                        "The score given to {position} ({score}) is not a \
+                         valid score (scores must go up in steps of {step})."
+                    ));
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, Eq, PartialEq)]
+/// Settings for how ballots are collected for the spars in a series.
+pub struct BallotSettings {
+    pub id: i64,
+    pub spar_series_id: i64,
+    /// If set, adjudicators who have not submitted their ballot this many
+    /// hours after the start of a spar are sent a reminder.
+    pub reminder_after_hours: Option<i64>,
+    /// If set, spars are marked as complete as soon as every room has a
+    /// ballot.
+    pub auto_complete: bool,
+}
+
+impl BallotSettings {
+    /// Retrieves the ballot settings of the given spar series (if the
+    /// administrators have changed them from the defaults).
+    pub fn of_series(
+        spar_series_id: i64,
+        conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+    ) -> Result<Option<Self>, diesel::result::Error> {
+        spar_series_ballot_settings::table
+            .filter(
+                spar_series_ballot_settings::spar_series_id.eq(spar_series_id),
+            )
+            .first::<BallotSettings>(conn)
+            .optional()
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Scoresheet {
     pub teams: Vec<TeamScoresheet>,
@@ -171,5 +543,110 @@ pub struct TeamScoresheet {
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct SpeakerScoresheet {
     pub speaker_id: i64,
-    pub score: i64,
+    pub score: Score,
+}
+
+#[cfg(test)]
+mod test_ballot_resolution {
+    use super::{majority_ranking, BpTeam::*, Score, ScoreRules, ENFORCE_WARN};
+
+    #[test]
+    fn majority_wins() {
+        let rankings = vec![
+            vec![Og, Oo, Cg, Co],
+            vec![Co, Cg, Oo, Og],
+            vec![Co, Cg, Oo, Og],
+        ];
+        assert_eq!(majority_ranking(&rankings), Some(1));
+    }
+
+    #[test]
+    fn ties_go_to_earliest_ballot() {
+        let rankings = vec![vec![Og, Oo, Cg, Co], vec![Co, Cg, Oo, Og]];
+        assert_eq!(majority_ranking(&rankings), Some(0));
+        assert_eq!(majority_ranking(&[]), None);
+    }
+
+    #[test]
+    fn score_rules() {
+        let rules = ScoreRules {
+            id: 1,
+            spar_series_id: 1,
+            min_score: Some(Score::of_points(50)),
+            max_score: Some(Score::of_points(100)),
+            step: Some(Score::of_points(5)),
+            enforcement: ENFORCE_WARN.to_string(),
+        };
+        let points = Score::of_points;
+        assert!(rules
+            .violations(&[("PM", points(75)), ("DPM", points(100))])
+            .is_empty());
+        assert_eq!(rules.violations(&[("PM", points(750))]).len(), 1);
+        assert_eq!(
+            rules
+                .violations(&[("PM", points(49)), ("DPM", points(77))])
+                .len(),
+            2
+        );
+        assert_eq!(
+            rules.violations(&[("PM", "75.5".parse().unwrap())]).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn half_point_score_rules() {
+        let rules = ScoreRules {
+            id: 1,
+            spar_series_id: 1,
+            min_score: Some(Score::of_points(70)),
+            max_score: None,
+            step: Some("0.5".parse().unwrap()),
+            enforcement: ENFORCE_WARN.to_string(),
+        };
+        assert!(rules
+            .violations(&[
+                ("PM", "75.5".parse().unwrap()),
+                ("DPM", Score::of_points(76))
+            ])
+            .is_empty());
+        assert_eq!(
+            rules.violations(&[("PM", "69.5".parse().unwrap())]).len(),
+            1
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_score {
+    use super::Score;
+
+    #[test]
+    fn parse_and_display() {
+        for (text, half_points, display) in [
+            ("75", 150, "75"),
+            ("75.5", 151, "75.5"),
+            ("75.0", 150, "75"),
+            ("75.50", 151, "75.5"),
+            (" 80 ", 160, "80"),
+            ("-0.5", -1, "-0.5"),
+        ] {
+            let score = text.parse::<Score>().unwrap();
+            assert_eq!(score, Score::of_half_points(half_points));
+            assert_eq!(score.to_string(), display);
+        }
+
+        for text in ["", "75.25", "75.", ".5", "abc", "7e1", "--1"] {
+            assert!(text.parse::<Score>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn serde() {
+        let scores = [Score::of_points(75), Score::of_half_points(151)];
+        let json = serde_json::to_string(&scores).unwrap();
+        assert_eq!(json, "[75,75.5]");
+        assert_eq!(serde_json::from_str::<Vec<Score>>(&json).unwrap(), scores);
+        assert!(serde_json::from_str::<Score>("75.25").is_err());
+    }
 }
@@ -3,7 +3,13 @@ use std::{
     fmt,
 };
 
-use chumsky::{prelude::just, text::whitespace, Parser};
+use chumsky::{
+    error::Rich,
+    extra,
+    prelude::{any, choice, end, just, none_of},
+    text::{self, inline_whitespace},
+    Parser,
+};
 use diesel::prelude::Queryable;
 use fuzzcheck::DefaultMutator;
 use fuzzcheck_util::chrono_mutators::{
@@ -12,6 +18,8 @@ use fuzzcheck_util::chrono_mutators::{
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use crate::spar::SparSignup;
+
 #[derive(
     Hash,
     Eq,
@@ -31,6 +39,19 @@ pub enum Team {
     Co,
 }
 
+impl Team {
+    pub const ALL: [Team; 4] = [Team::Og, Team::Oo, Team::Cg, Team::Co];
+
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Team::Og => "OG",
+            Team::Oo => "OO",
+            Team::Cg => "CG",
+            Team::Co => "CO",
+        }
+    }
+}
+
 #[derive(
     Debug,
     Queryable,
@@ -53,6 +74,42 @@ pub struct DraftDraw {
     pub created_at: chrono::NaiveDateTime,
 }
 
+/// The maximum number of versions of each draft draw which are retained.
+pub const MAX_DRAFT_DRAW_VERSIONS: i64 = 10;
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+/// A (possibly previous) version of a draft draw.
+pub struct DraftDrawVersion {
+    pub id: i64,
+    pub public_id: String,
+    pub draft_draw_id: i64,
+    pub version: i64,
+    pub data: String,
+    /// The edit command(s) which produced this version (`None` if this
+    /// version was produced by the draw generator).
+    pub command: Option<String>,
+    pub created_by: Option<i64>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+/// One of several candidate drafts produced by a single request to generate a
+/// draw, along with metrics which help to compare the candidates (these are
+/// `None` until the draw has been generated).
+pub struct DraftDrawCandidate {
+    pub id: i64,
+    pub draft_draw_id: i64,
+    pub generation_id: String,
+    /// The name of the weight preset used to generate this draw.
+    pub preset: String,
+    /// The largest difference (in any room) between the average ratings of
+    /// the strongest and weakest teams.
+    pub max_team_gap: Option<f64>,
+    pub mean_team_gap: Option<f64>,
+    pub partner_preferences_honoured: Option<i64>,
+    pub partner_preferences_requested: Option<i64>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct DraftDrawData {
     pub rooms: Vec<DraftDrawRoom>,
@@ -60,40 +117,210 @@ pub struct DraftDrawData {
     pub id_map: HashMap<i64, u64>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum EditAction {
+    /// `swap a b`
     Swap(u64, u64),
+    /// `remove a`
     Remove(u64),
+    /// `move a to room 3 og`
+    Move(u64, usize, DrawLoc),
+    /// `add <member name> to room 2 panel` (the member must not already be
+    /// in the draw). Names containing spaces should be quoted.
+    Add(String, usize, DrawLoc),
+    /// `newroom`
+    NewRoom,
+    /// `delroom 4` (the room must be empty)
+    DelRoom(usize),
+    /// `swapteams 1 og 2 co`
+    SwapTeams(usize, Team, usize, Team),
+    /// `chair a` (the person must be on a panel)
+    Chair(u64),
 }
 
-fn edit_action_parser<'src>() -> impl Parser<'src, &'src str, EditAction> {
-    (just("swap")
-        .then_ignore(whitespace())
-        .ignore_then(chumsky::prelude::text::int(10))
-        .then_ignore(whitespace())
-        .then(chumsky::prelude::text::int(10)))
-    .map(|(a, b): (&str, &str)| {
-        EditAction::Swap(a.parse().unwrap(), b.parse().unwrap())
-    })
-    .or(just("remove")
-        .then_ignore(whitespace())
-        .ignore_then(chumsky::prelude::text::int(10))
-        .map(|a: &str| EditAction::Remove(a.parse().unwrap())))
+type Extra<'src> = extra::Err<Rich<'src, char>>;
+
+fn edit_action_parser<'src>(
+) -> impl Parser<'src, &'src str, EditAction, Extra<'src>> {
+    let ws = || inline_whitespace().at_least(1);
+
+    let number = || {
+        text::int(10).try_map(|s: &str, span| {
+            s.parse::<u64>().map_err(|e| {
+                Rich::custom(span, format!("invalid number `{s}`: {e}"))
+            })
+        })
+    };
+    let person = || number().labelled("id of a speaker or judge");
+    let room_number = || number().map(|n| n as usize).labelled("room number");
+    let room = || {
+        just("room")
+            .ignore_then(ws())
+            .ignore_then(room_number())
+            .labelled("room (e.g. `room 3`)")
+    };
+
+    let team = || {
+        choice((
+            just("og").to(Team::Og),
+            just("oo").to(Team::Oo),
+            just("cg").to(Team::Cg),
+            just("co").to(Team::Co),
+        ))
+        .labelled("team (`og`, `oo`, `cg` or `co`)")
+    };
+    let loc = || {
+        team()
+            .map(DrawLoc::Team)
+            .or(just("panel").to(DrawLoc::Panel))
+            .labelled("position (`og`, `oo`, `cg`, `co` or `panel`)")
+    };
+
+    let member_name = none_of('"')
+        .repeated()
+        .at_least(1)
+        .to_slice()
+        .delimited_by(just('"'), just('"'))
+        .or(any()
+            .filter(|c: &char| !c.is_whitespace())
+            .repeated()
+            .at_least(1)
+            .to_slice())
+        .map(|s: &str| s.to_string())
+        .labelled("member name (use quotes if it contains spaces)");
+
+    let swap = just("swap")
+        .ignore_then(ws())
+        .ignore_then(person())
+        .then_ignore(ws())
+        .then(person())
+        .map(|(a, b)| EditAction::Swap(a, b));
+    let remove = just("remove")
+        .ignore_then(ws())
+        .ignore_then(person())
+        .map(EditAction::Remove);
+    let move_person = just("move")
+        .ignore_then(ws())
+        .ignore_then(person())
+        .then_ignore(ws())
+        .then_ignore(just("to"))
+        .then_ignore(ws())
+        .then(room())
+        .then_ignore(ws())
+        .then(loc())
+        .map(|((a, room), loc)| EditAction::Move(a, room, loc));
+    let add = just("add")
+        .ignore_then(ws())
+        .ignore_then(member_name)
+        .then_ignore(ws())
+        .then_ignore(just("to"))
+        .then_ignore(ws())
+        .then(room())
+        .then_ignore(ws())
+        .then(loc())
+        .map(|((name, room), loc)| EditAction::Add(name, room, loc));
+    let new_room = just("newroom").to(EditAction::NewRoom);
+    let del_room = just("delroom")
+        .ignore_then(ws())
+        .ignore_then(room_number())
+        .map(EditAction::DelRoom);
+    let swap_teams = just("swapteams")
+        .ignore_then(ws())
+        .ignore_then(room_number())
+        .then_ignore(ws())
+        .then(team())
+        .then_ignore(ws())
+        .then(room_number())
+        .then_ignore(ws())
+        .then(team())
+        .map(|(((r1, t1), r2), t2)| EditAction::SwapTeams(r1, t1, r2, t2));
+    let chair = just("chair")
+        .ignore_then(ws())
+        .ignore_then(person())
+        .map(EditAction::Chair);
+
+    choice((
+        // note: this must come before `swap`
+        swap_teams,
+        swap,
+        remove,
+        move_person,
+        add,
+        new_room,
+        del_room,
+        chair,
+    ))
+    .padded()
+    .then_ignore(end())
 }
 
 impl EditAction {
+    /// Parses a single command.
     pub fn parse(cmd: &str) -> Result<EditAction, EditError> {
         let parser_output = edit_action_parser().parse(cmd);
 
         if parser_output.has_errors() {
-            let err = parser_output.errors().map(|e| e.to_string()).join("\n");
+            let err = parser_output.errors().map(|e| e.to_string()).join("; ");
             Err(EditError::ParseErr(err))
         } else {
-            Ok(*parser_output.output().unwrap())
+            Ok(parser_output.output().unwrap().clone())
+        }
+    }
+
+    /// Parses a script consisting of several commands, which are separated
+    /// by newlines or semicolons. Blank lines (and lines starting with `#`)
+    /// are ignored.
+    ///
+    /// Each command is returned along with the (1-indexed) line it appears
+    /// on, so that errors can point to the offending line.
+    pub fn parse_script(script: &str) -> Result<Vec<ScriptCommand>, EditError> {
+        let mut actions = Vec::new();
+        for (line_no, line) in script.lines().enumerate() {
+            let line_no = line_no + 1;
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            for cmd in line.split(';') {
+                if cmd.trim().is_empty() {
+                    continue;
+                }
+                let text = cmd.trim().to_string();
+                let action = match EditAction::parse(cmd) {
+                    Ok(action) => action,
+                    Err(e) => {
+                        return Err(EditError::AtLine(
+                            line_no,
+                            text,
+                            Box::new(e),
+                        ))
+                    }
+                };
+                actions.push(ScriptCommand {
+                    line: line_no,
+                    text,
+                    action,
+                });
+            }
         }
+
+        if actions.is_empty() {
+            return Err(EditError::ParseErr(
+                "no commands were provided".to_string(),
+            ));
+        }
+
+        Ok(actions)
     }
 }
 
+/// A single command from a script of edit actions.
+#[derive(Clone, Debug)]
+pub struct ScriptCommand {
+    pub line: usize,
+    pub text: String,
+    pub action: EditAction,
+}
+
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub enum DrawLoc {
     Panel,
@@ -104,6 +331,16 @@ pub enum DrawLoc {
 pub enum EditError {
     NoPersonWithId,
     ParseErr(String),
+    NoSuchRoom(usize),
+    RoomNotEmpty(usize),
+    /// Only people on a panel can be made chair.
+    NotOnPanel,
+    NoMemberNamed(String),
+    AmbiguousMemberName(String),
+    AlreadyInDraw(String),
+    /// An error in a command which was part of a script (the line number and
+    /// the command are provided).
+    AtLine(usize, String, Box<EditError>),
 }
 
 impl fmt::Display for EditError {
@@ -111,6 +348,30 @@ impl fmt::Display for EditError {
         match self {
             EditError::NoPersonWithId => write!(f, "invalid ID provided"),
             EditError::ParseErr(err) => write!(f, "parse error: {err}"),
+            EditError::NoSuchRoom(room) => {
+                write!(f, "there is no room {room} in this draw")
+            }
+            EditError::RoomNotEmpty(room) => write!(
+                f,
+                "room {room} is not empty (move or remove everyone in it \
+                 before deleting it)"
+            ),
+            EditError::NotOnPanel => {
+                write!(f, "only a judge on a panel can be made chair")
+            }
+            EditError::NoMemberNamed(name) => {
+                write!(f, "no member of this spar series is called `{name}`")
+            }
+            EditError::AmbiguousMemberName(name) => write!(
+                f,
+                "more than one member of this spar series is called `{name}`"
+            ),
+            EditError::AlreadyInDraw(name) => {
+                write!(f, "`{name}` is already in the draw")
+            }
+            EditError::AtLine(line, cmd, err) => {
+                write!(f, "line {line} (`{cmd}`): {err}")
+            }
         }
     }
 }
@@ -143,12 +404,16 @@ impl DraftDrawData {
     /// Apply the given action to the current draw. This creates a new version
     /// of the current draw.
     ///
-    /// We only retain up to 10 steps of history. After draws are published, we
-    /// delete all but the latest version.
-    #[tracing::instrument(skip(self))]
+    /// `find_member` is used to look up members by name (for
+    /// [`EditAction::Add`]).
+    ///
+    /// We only retain up to [`MAX_DRAFT_DRAW_VERSIONS`] steps of history. After
+    /// draws are confirmed, we delete all but the latest version.
+    #[tracing::instrument(skip(self, find_member))]
     pub fn apply(
         &self,
         action: EditAction,
+        find_member: &mut dyn FnMut(&str) -> Result<i64, EditError>,
     ) -> Result<DraftDrawData, EditError> {
         let mut new_draw = self.clone();
         match action {
@@ -158,8 +423,8 @@ impl DraftDrawData {
                     let a = self.lookup_idx(a)?;
                     let b = self.lookup_idx(b)?;
 
-                    let (a_idx, a_loc) = new_draw.find_loc(a);
-                    let (b_idx, b_loc) = new_draw.find_loc(b);
+                    let (a_idx, a_loc) = new_draw.find_loc(a)?;
+                    let (b_idx, b_loc) = new_draw.find_loc(b)?;
 
                     if (a_loc != b_loc) || (a_idx != b_idx) {
                         let a_set = new_draw
@@ -175,6 +440,19 @@ impl DraftDrawData {
 
                         b_set.remove(&b);
                         b_set.insert(a);
+
+                        // whoever takes the place of a chair becomes chair
+                        for room in &mut new_draw.rooms {
+                            room.chair = room.chair.map(|c| {
+                                if c == a {
+                                    b
+                                } else if c == b {
+                                    a
+                                } else {
+                                    c
+                                }
+                            });
+                        }
                     }
                 }
             }
@@ -186,11 +464,129 @@ impl DraftDrawData {
                         team.remove(&member);
                     }
                 }
+                // so that later commands cannot refer to them
+                new_draw.id_map.remove(&member);
+            }
+            EditAction::Move(a, room, loc) => {
+                let a = self.lookup_idx(a)?;
+                if room >= new_draw.rooms.len() {
+                    return Err(EditError::NoSuchRoom(room));
+                }
+                let (a_idx, a_loc) = new_draw.find_loc(a)?;
+                new_draw
+                    .get_team_or_panel_set_mut(a_idx, a_loc)
+                    .unwrap()
+                    .remove(&a);
+                new_draw
+                    .get_team_or_panel_set_mut(room, loc)
+                    .unwrap()
+                    .insert(a);
+            }
+            EditAction::Add(name, room, loc) => {
+                let member = find_member(&name)?;
+                if new_draw.try_find_loc(member).is_some() {
+                    return Err(EditError::AlreadyInDraw(name));
+                }
+                let set = new_draw
+                    .get_team_or_panel_set_mut(room, loc)
+                    .ok_or(EditError::NoSuchRoom(room))?;
+                set.insert(member);
+
+                let next_id = new_draw
+                    .id_map
+                    .values()
+                    .max()
+                    .map(|id| id + 1)
+                    .unwrap_or(0);
+                new_draw.id_map.insert(member, next_id);
+            }
+            EditAction::NewRoom => {
+                new_draw.rooms.push(DraftDrawRoom {
+                    panel: HashSet::new(),
+                    teams: Team::ALL
+                        .into_iter()
+                        .map(|team| (team, HashSet::new()))
+                        .collect(),
+                    chair: None,
+                });
+            }
+            EditAction::DelRoom(room) => {
+                let to_delete = new_draw
+                    .rooms
+                    .get(room)
+                    .ok_or(EditError::NoSuchRoom(room))?;
+                if !to_delete.panel.is_empty()
+                    || to_delete.teams.values().any(|team| !team.is_empty())
+                {
+                    return Err(EditError::RoomNotEmpty(room));
+                }
+                new_draw.rooms.remove(room);
+            }
+            EditAction::SwapTeams(r1, t1, r2, t2) => {
+                for room in [r1, r2] {
+                    if room >= new_draw.rooms.len() {
+                        return Err(EditError::NoSuchRoom(room));
+                    }
+                }
+                if (r1, t1) == (r2, t2) {
+                    // swapping a team with itself does nothing (and the
+                    // `take` below would otherwise empty the team)
+                    return Ok(new_draw);
+                }
+                let first = std::mem::take(
+                    new_draw
+                        .get_team_or_panel_set_mut(r1, DrawLoc::Team(t1))
+                        .unwrap(),
+                );
+                let second = std::mem::replace(
+                    new_draw
+                        .get_team_or_panel_set_mut(r2, DrawLoc::Team(t2))
+                        .unwrap(),
+                    first,
+                );
+                *new_draw
+                    .get_team_or_panel_set_mut(r1, DrawLoc::Team(t1))
+                    .unwrap() = second;
+            }
+            EditAction::Chair(a) => {
+                let a = self.lookup_idx(a)?;
+                match new_draw.find_loc(a)? {
+                    (idx, DrawLoc::Panel) => {
+                        new_draw.rooms[idx].chair = Some(a)
+                    }
+                    (_, DrawLoc::Team(_)) => return Err(EditError::NotOnPanel),
+                }
             }
         };
+
+        // a chair who is no longer on the panel is no longer chair
+        for room in &mut new_draw.rooms {
+            if let Some(chair) = room.chair {
+                if !room.panel.contains(&chair) {
+                    room.chair = None;
+                }
+            }
+        }
+
         Ok(new_draw)
     }
 
+    /// Applies each action of a script (see [`EditAction::parse_script`]) in
+    /// turn. If any action fails then none of them are applied.
+    pub fn apply_script(
+        &self,
+        script: Vec<ScriptCommand>,
+        find_member: &mut dyn FnMut(&str) -> Result<i64, EditError>,
+    ) -> Result<DraftDrawData, EditError> {
+        let mut draw = self.clone();
+        for command in script {
+            draw = draw.apply(command.action, find_member).map_err(|e| {
+                EditError::AtLine(command.line, command.text, Box::new(e))
+            })?;
+        }
+        Ok(draw)
+    }
+
     fn get_team_or_panel_set_mut(
         &mut self,
         room: usize,
@@ -209,24 +605,29 @@ impl DraftDrawData {
         }
     }
 
-    /// Find the location (room + team/panel) of the given person.
-    ///
-    /// This function should only be called with valid member ids (it will
-    /// panic otherwise).
-    fn find_loc(&self, a: i64) -> (usize, DrawLoc) {
+    /// Find the location (room + team/panel) of the given person, returning
+    /// an error if they are not in the draw (e.g. because they have been
+    /// removed).
+    fn find_loc(&self, a: i64) -> Result<(usize, DrawLoc), EditError> {
+        self.try_find_loc(a).ok_or(EditError::NoPersonWithId)
+    }
+
+    /// Find the location (room + team/panel) of the given person, if they are
+    /// in the draw.
+    fn try_find_loc(&self, a: i64) -> Option<(usize, DrawLoc)> {
         for (idx, room) in self.rooms.iter().enumerate() {
             if room.panel.contains(&a) {
-                return (idx, DrawLoc::Panel);
+                return Some((idx, DrawLoc::Panel));
             }
 
             for (team, members) in room.teams.iter() {
                 if members.contains(&a) {
-                    return (idx, DrawLoc::Team(*team));
+                    return Some((idx, DrawLoc::Team(*team)));
                 }
             }
         }
 
-        unreachable!()
+        None
     }
 }
 
@@ -238,17 +639,380 @@ pub struct DraftDrawRoom {
         // maps each team (Og, Oo, Cg, Co) to the set of speakers
         HashSet<i64>,
     >,
+    /// The chair of the panel (if one has been chosen). This is always a
+    /// member of `panel`.
+    #[serde(default)]
+    pub chair: Option<i64>,
+}
+
+/// Someone whose position differs between two versions of a draw. A position
+/// of `None` means that they are not in that version of the draw.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MovedPerson {
+    pub member: i64,
+    pub before: Option<(usize, DrawLoc)>,
+    pub after: Option<(usize, DrawLoc)>,
+}
+
+/// The differences between two versions of a draw (see
+/// [`DraftDrawData::diff`]).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DrawDiff {
+    pub moved: Vec<MovedPerson>,
+    /// The rooms (by index) whose teams or panel differ.
+    pub changed_rooms: Vec<usize>,
+}
+
+impl DraftDrawRoom {
+    fn members(&self) -> impl Iterator<Item = i64> + '_ {
+        self.panel
+            .iter()
+            .chain(self.teams.values().flatten())
+            .copied()
+    }
+
+    fn same_composition(&self, other: &DraftDrawRoom) -> bool {
+        self.panel == other.panel && self.teams == other.teams
+    }
+}
+
+impl DraftDrawData {
+    /// Replaces `from` with `into` everywhere in the draw (used when two
+    /// members of a spar series are merged). If `into` is already in the draw
+    /// then `from` is removed instead, so that nobody appears twice.
+    ///
+    /// Returns true if the draw was changed.
+    pub fn replace_member(&mut self, from: i64, into: i64) -> bool {
+        if !self
+            .rooms
+            .iter()
+            .any(|room| room.members().any(|m| m == from))
+        {
+            return false;
+        }
+        let into_present = self
+            .rooms
+            .iter()
+            .any(|room| room.members().any(|m| m == into));
+
+        let replace = |set: &mut HashSet<i64>| {
+            if set.remove(&from) && !into_present {
+                set.insert(into);
+            }
+        };
+        for room in &mut self.rooms {
+            replace(&mut room.panel);
+            for team in room.teams.values_mut() {
+                replace(team);
+            }
+            if room.chair == Some(from) {
+                room.chair = if into_present { None } else { Some(into) };
+            }
+        }
+
+        if let Some(idx) = self.id_map.remove(&from) {
+            if !into_present {
+                self.id_map.insert(into, idx);
+            }
+        }
+        true
+    }
+
+    /// Whether both draws put everyone in the same place. The order of the
+    /// rooms (and the ids used to refer to people in edit commands) are
+    /// ignored.
+    pub fn is_same_draw_as(&self, other: &DraftDrawData) -> bool {
+        fn rooms(data: &DraftDrawData) -> Vec<(Vec<i64>, Vec<Vec<i64>>)> {
+            data.rooms
+                .iter()
+                .map(|room| {
+                    let teams = Team::ALL
+                        .iter()
+                        .map(|team| {
+                            room.teams
+                                .get(team)
+                                .map(|t| t.iter().copied().sorted().collect())
+                                .unwrap_or_default()
+                        })
+                        .collect();
+                    (room.panel.iter().copied().sorted().collect(), teams)
+                })
+                .sorted()
+                .collect()
+        }
+        rooms(self) == rooms(other)
+    }
+}
+
+impl DraftDrawData {
+    /// Computes what changed between this draw and `other`. Rooms are
+    /// compared by their position in the draw.
+    pub fn diff(&self, other: &DraftDrawData) -> DrawDiff {
+        let moved = self
+            .rooms
+            .iter()
+            .chain(other.rooms.iter())
+            .flat_map(|room| room.members())
+            .unique()
+            .sorted()
+            .filter_map(|member| {
+                let before = self.try_find_loc(member);
+                let after = other.try_find_loc(member);
+                (before != after).then_some(MovedPerson {
+                    member,
+                    before,
+                    after,
+                })
+            })
+            .collect();
+
+        let changed_rooms = (0..self.rooms.len().max(other.rooms.len()))
+            .filter(|idx| match (self.rooms.get(*idx), other.rooms.get(*idx)) {
+                (Some(a), Some(b)) => !a.same_composition(b),
+                _ => true,
+            })
+            .collect();
+
+        DrawDiff {
+            moved,
+            changed_rooms,
+        }
+    }
+}
+
+/// A problem found when validating a draft draw (see
+/// [`DraftDrawData::validate`]).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DrawProblem {
+    NoRooms,
+    NoJudges {
+        room: usize,
+    },
+    EmptyTeam {
+        room: usize,
+        team: Team,
+    },
+    OversizedTeam {
+        room: usize,
+        team: Team,
+        speakers: usize,
+    },
+    /// A team with only one speaker.
+    IronTeam {
+        room: usize,
+        team: Team,
+    },
+    InDrawMoreThanOnce {
+        member: i64,
+    },
+    /// Someone who signed up to the spar, but does not appear in the draw.
+    NotInDraw {
+        member: i64,
+    },
+    /// Someone in the draw who did not sign up to the spar.
+    NotSignedUp {
+        member: i64,
+        room: usize,
+    },
+    JudgeNotSignedUpToJudge {
+        member: i64,
+        room: usize,
+    },
+    SpeakerNotSignedUpToSpeak {
+        member: i64,
+        room: usize,
+    },
+}
+
+impl DrawProblem {
+    /// Errors prevent the draw from being confirmed, whereas warnings are
+    /// only shown to the user.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            DrawProblem::NoRooms
+                | DrawProblem::NoJudges { .. }
+                | DrawProblem::EmptyTeam { .. }
+                | DrawProblem::OversizedTeam { .. }
+                | DrawProblem::InDrawMoreThanOnce { .. }
+        )
+    }
+
+    /// The room which this problem concerns (if it concerns a single room).
+    pub fn room(&self) -> Option<usize> {
+        match self {
+            DrawProblem::NoRooms
+            | DrawProblem::InDrawMoreThanOnce { .. }
+            | DrawProblem::NotInDraw { .. } => None,
+            DrawProblem::NoJudges { room }
+            | DrawProblem::EmptyTeam { room, .. }
+            | DrawProblem::OversizedTeam { room, .. }
+            | DrawProblem::IronTeam { room, .. }
+            | DrawProblem::NotSignedUp { room, .. }
+            | DrawProblem::JudgeNotSignedUpToJudge { room, .. }
+            | DrawProblem::SpeakerNotSignedUpToSpeak { room, .. } => {
+                Some(*room)
+            }
+        }
+    }
+
+    /// Describes the problem, using `name_of` to look up the names of
+    /// members.
+    pub fn describe(&self, name_of: &dyn Fn(i64) -> String) -> String {
+        match self {
+            DrawProblem::NoRooms => "the draw has no rooms".to_string(),
+            DrawProblem::NoJudges { room } => {
+                format!("room {room} has no judges")
+            }
+            DrawProblem::EmptyTeam { room, team } => {
+                format!(
+                    "{} in room {room} has no speakers",
+                    team.abbreviation()
+                )
+            }
+            DrawProblem::OversizedTeam {
+                room,
+                team,
+                speakers,
+            } => format!(
+                "{} in room {room} has {speakers} speakers (the maximum is 2)",
+                team.abbreviation()
+            ),
+            DrawProblem::IronTeam { room, team } => format!(
+                "{} in room {room} only has one speaker",
+                team.abbreviation()
+            ),
+            DrawProblem::InDrawMoreThanOnce { member } => {
+                format!(
+                    "{} appears in the draw more than once",
+                    name_of(*member)
+                )
+            }
+            DrawProblem::NotInDraw { member } => format!(
+                "{} signed up for this spar, but is not in the draw",
+                name_of(*member)
+            ),
+            DrawProblem::NotSignedUp { member, room } => format!(
+                "{} (room {room}) did not sign up for this spar",
+                name_of(*member)
+            ),
+            DrawProblem::JudgeNotSignedUpToJudge { member, room } => format!(
+                "{} is judging in room {room}, but did not sign up to judge",
+                name_of(*member)
+            ),
+            DrawProblem::SpeakerNotSignedUpToSpeak { member, room } => format!(
+                "{} is speaking in room {room}, but did not sign up to speak",
+                name_of(*member)
+            ),
+        }
+    }
+}
+
+impl DraftDrawData {
+    /// Checks the draw for problems (e.g. rooms without judges, or people
+    /// who signed up but are not in the draw).
+    pub fn validate(&self, signups: &[SparSignup]) -> Vec<DrawProblem> {
+        let mut problems = Vec::new();
+        if self.rooms.is_empty() {
+            problems.push(DrawProblem::NoRooms);
+        }
+
+        let signups_by_member = signups
+            .iter()
+            .map(|signup| (signup.member_id, signup))
+            .collect::<HashMap<_, _>>();
+        let mut appearances: HashMap<i64, usize> = HashMap::new();
+
+        for (idx, room) in self.rooms.iter().enumerate() {
+            if room.panel.is_empty() {
+                problems.push(DrawProblem::NoJudges { room: idx });
+            }
+            for judge in room.panel.iter().sorted() {
+                *appearances.entry(*judge).or_default() += 1;
+                match signups_by_member.get(judge) {
+                    None => problems.push(DrawProblem::NotSignedUp {
+                        member: *judge,
+                        room: idx,
+                    }),
+                    Some(signup) if !signup.as_judge => {
+                        problems.push(DrawProblem::JudgeNotSignedUpToJudge {
+                            member: *judge,
+                            room: idx,
+                        })
+                    }
+                    Some(_) => (),
+                }
+            }
+
+            for team in Team::ALL {
+                let speakers =
+                    room.teams.get(&team).cloned().unwrap_or_default();
+                match speakers.len() {
+                    0 => problems
+                        .push(DrawProblem::EmptyTeam { room: idx, team }),
+                    1 => {
+                        problems.push(DrawProblem::IronTeam { room: idx, team })
+                    }
+                    2 => (),
+                    n => problems.push(DrawProblem::OversizedTeam {
+                        room: idx,
+                        team,
+                        speakers: n,
+                    }),
+                }
+                for speaker in speakers.iter().sorted() {
+                    *appearances.entry(*speaker).or_default() += 1;
+                    match signups_by_member.get(speaker) {
+                        None => problems.push(DrawProblem::NotSignedUp {
+                            member: *speaker,
+                            room: idx,
+                        }),
+                        Some(signup) if !signup.as_speaker => problems.push(
+                            DrawProblem::SpeakerNotSignedUpToSpeak {
+                                member: *speaker,
+                                room: idx,
+                            },
+                        ),
+                        Some(_) => (),
+                    }
+                }
+            }
+        }
+
+        for (member, count) in appearances.iter().sorted() {
+            if *count > 1 {
+                problems
+                    .push(DrawProblem::InDrawMoreThanOnce { member: *member });
+            }
+        }
+        for signup in signups.iter().sorted_by_key(|signup| signup.member_id) {
+            if !appearances.contains_key(&signup.member_id) {
+                problems.push(DrawProblem::NotInDraw {
+                    member: signup.member_id,
+                });
+            }
+        }
+
+        problems
+    }
 }
 
 #[cfg(test)]
 pub mod test_draft_draw {
     use std::collections::{HashMap, HashSet};
 
-    use super::{DraftDrawData, DraftDrawRoom, EditAction, Team};
+    use super::{
+        DraftDrawData, DraftDrawRoom, DrawLoc, DrawProblem, EditAction,
+        EditError, MovedPerson, Team,
+    };
+    use crate::spar::SparSignup;
 
-    #[test]
-    fn simple() {
-        let data = DraftDrawData {
+    fn no_members(name: &str) -> Result<i64, EditError> {
+        Err(EditError::NoMemberNamed(name.to_string()))
+    }
+
+    fn one_room() -> DraftDrawData {
+        DraftDrawData {
             rooms: vec![DraftDrawRoom {
                 panel: {
                     let mut set = HashSet::new();
@@ -283,6 +1047,7 @@ pub mod test_draft_draw {
                     });
                     map
                 },
+                chair: None,
             }],
             id_map: {
                 let mut map = HashMap::new();
@@ -291,10 +1056,16 @@ pub mod test_draft_draw {
                 }
                 map
             },
-        };
+        }
+    }
+
+    #[test]
+    fn simple() {
+        let data = one_room();
 
         // Original test case: swap 8 and 6
-        let new_draw = data.apply(EditAction::Swap(8, 6)).unwrap();
+        let new_draw =
+            data.apply(EditAction::Swap(8, 6), &mut no_members).unwrap();
 
         let cg = new_draw.rooms[0].teams.get(&Team::Cg).unwrap();
         let co = new_draw.rooms[0].teams.get(&Team::Co).unwrap();
@@ -305,7 +1076,252 @@ pub mod test_draft_draw {
         );
         assert!(co.len() == 1 && co.contains(&6), "cg = {cg:?}, co = {co:?}");
 
-        let same = data.apply(EditAction::Swap(6, 6)).unwrap();
+        let same = data.apply(EditAction::Swap(6, 6), &mut no_members).unwrap();
+        assert_eq!(same, data);
+    }
+
+    #[test]
+    fn removed_members_cannot_be_referred_to() {
+        let data = one_room();
+        let removed =
+            data.apply(EditAction::Remove(2), &mut no_members).unwrap();
+        assert!(removed.try_find_loc(2).is_none());
+
+        assert!(matches!(
+            removed.apply(
+                EditAction::Move(2, 0, DrawLoc::Team(Team::Co)),
+                &mut no_members
+            ),
+            Err(EditError::NoPersonWithId)
+        ));
+        assert!(matches!(
+            removed.apply(EditAction::Chair(2), &mut no_members),
+            Err(EditError::NoPersonWithId)
+        ));
+        assert!(matches!(
+            removed.apply(EditAction::Swap(2, 3), &mut no_members),
+            Err(EditError::NoPersonWithId)
+        ));
+
+        // the same applies within a script
+        let script = EditAction::parse_script("remove 2\nchair 2").unwrap();
+        let err = data
+            .apply_script(script, &mut no_members)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("line 2 (`chair 2`)"), "{err}");
+    }
+
+    #[test]
+    fn same_draw() {
+        let data = one_room();
+        let mut other = data.clone();
+        other.rooms.insert(
+            0,
+            DraftDrawRoom {
+                panel: HashSet::new(),
+                teams: HashMap::new(),
+                chair: None,
+            },
+        );
+        assert!(!data.is_same_draw_as(&other));
+
+        // rooms in a different order (with different ids) are the same draw
+        let mut reordered = other.clone();
+        reordered.rooms.reverse();
+        reordered.id_map.clear();
+        assert!(other.is_same_draw_as(&reordered));
+
+        let swapped =
+            data.apply(EditAction::Swap(8, 6), &mut no_members).unwrap();
+        assert!(!data.is_same_draw_as(&swapped));
+    }
+
+    #[test]
+    fn swap_team_with_itself() {
+        let data = one_room();
+        let same = data
+            .apply(
+                EditAction::SwapTeams(0, Team::Og, 0, Team::Og),
+                &mut no_members,
+            )
+            .unwrap();
         assert_eq!(same, data);
     }
+
+    #[test]
+    fn parse_commands() {
+        assert!(matches!(
+            EditAction::parse("move 3 to room 1 og").unwrap(),
+            EditAction::Move(3, 1, DrawLoc::Team(Team::Og))
+        ));
+        assert!(matches!(
+            EditAction::parse("swapteams 0 og 2 co").unwrap(),
+            EditAction::SwapTeams(0, Team::Og, 2, Team::Co)
+        ));
+        assert!(matches!(
+            EditAction::parse("  swap 1 2 ").unwrap(),
+            EditAction::Swap(1, 2)
+        ));
+        match EditAction::parse(r#"add "Jane Doe" to room 2 panel"#).unwrap() {
+            EditAction::Add(name, 2, DrawLoc::Panel) => {
+                assert_eq!(name, "Jane Doe")
+            }
+            other => panic!("{other:?}"),
+        }
+
+        assert!(EditAction::parse("move 3 to room 1 xx").is_err());
+        assert!(EditAction::parse("chair").is_err());
+
+        let err = EditAction::parse_script("newroom\nswap 1 2; delroom x")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("line 2 (`delroom x`)"), "{err}");
+    }
+
+    #[test]
+    fn script() {
+        let data = one_room();
+
+        let script = EditAction::parse_script(
+            "newroom\n\
+             # put the closing teams into the new room\n\
+             swapteams 0 cg 1 og; swapteams 0 co 1 oo\n\
+             move 0 to room 1 panel\n\
+             chair 0\n\
+             add newbie to room 0 panel",
+        )
+        .unwrap();
+        let new_draw = data
+            .apply_script(script, &mut |name| match name {
+                "newbie" => Ok(9),
+                _ => no_members(name),
+            })
+            .unwrap();
+
+        assert_eq!(new_draw.rooms.len(), 2);
+        assert!(new_draw.rooms[0].teams[&Team::Cg].is_empty());
+        assert!(new_draw.rooms[1].teams[&Team::Og].contains(&6));
+        assert!(new_draw.rooms[1].teams[&Team::Oo].contains(&8));
+        assert_eq!(new_draw.rooms[1].chair, Some(0));
+        assert!(new_draw.rooms[0].panel.contains(&9));
+        assert_eq!(new_draw.id_map[&9], 9);
+
+        // moving the chair off the panel means they are no longer chair
+        let moved = new_draw
+            .apply(
+                EditAction::Move(0, 1, DrawLoc::Team(Team::Cg)),
+                &mut no_members,
+            )
+            .unwrap();
+        assert_eq!(moved.rooms[1].chair, None);
+
+        // rooms which still have people in them can't be deleted
+        assert!(matches!(
+            new_draw.apply(EditAction::DelRoom(1), &mut no_members),
+            Err(EditError::RoomNotEmpty(1))
+        ));
+        // and a failing command means none of the script is applied
+        let script = EditAction::parse_script("newroom; delroom 0").unwrap();
+        assert!(data.apply_script(script, &mut no_members).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let signup =
+            |member_id: i64, as_judge: bool, as_speaker: bool| SparSignup {
+                id: member_id,
+                public_id: member_id.to_string(),
+                member_id,
+                spar_id: 1,
+                as_judge,
+                as_speaker,
+                partner_preference: None,
+            };
+        // 1 only signed up to speak, 9 is not in the draw and 8 did not sign
+        // up at all
+        let mut signups = vec![signup(0, true, false), signup(1, false, true)];
+        signups.extend((2..=7).map(|i| signup(i, false, true)));
+        signups.push(signup(9, true, true));
+
+        let problems = one_room().validate(&signups);
+        assert_eq!(
+            problems,
+            vec![
+                DrawProblem::JudgeNotSignedUpToJudge { member: 1, room: 0 },
+                DrawProblem::IronTeam {
+                    room: 0,
+                    team: Team::Co
+                },
+                DrawProblem::NotSignedUp { member: 8, room: 0 },
+                DrawProblem::NotInDraw { member: 9 },
+            ]
+        );
+        assert!(problems.iter().all(|problem| !problem.is_error()));
+
+        let without_judges = one_room()
+            .apply(EditAction::Remove(0), &mut no_members)
+            .unwrap()
+            .apply(EditAction::Remove(1), &mut no_members)
+            .unwrap();
+        assert!(without_judges
+            .validate(&signups)
+            .contains(&DrawProblem::NoJudges { room: 0 }));
+    }
+
+    #[test]
+    fn diff() {
+        let before = one_room();
+        let after = before
+            .apply(EditAction::NewRoom, &mut no_members)
+            .unwrap()
+            .apply(EditAction::Move(0, 1, DrawLoc::Panel), &mut no_members)
+            .unwrap()
+            .apply(EditAction::Remove(8), &mut no_members)
+            .unwrap();
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.moved,
+            vec![
+                MovedPerson {
+                    member: 0,
+                    before: Some((0, DrawLoc::Panel)),
+                    after: Some((1, DrawLoc::Panel)),
+                },
+                MovedPerson {
+                    member: 8,
+                    before: Some((0, DrawLoc::Team(Team::Co))),
+                    after: None,
+                },
+            ]
+        );
+        assert_eq!(diff.changed_rooms, vec![0, 1]);
+
+        let same = before.diff(&before);
+        assert!(same.moved.is_empty() && same.changed_rooms.is_empty());
+    }
+
+    #[test]
+    fn test_replace_member() {
+        let mut draw = one_room();
+        draw.generate_map();
+        draw.rooms[0].chair = Some(0);
+        let idx = draw.id_map[&0];
+
+        assert!(!draw.replace_member(100, 101));
+        assert!(draw.replace_member(0, 100));
+        assert!(draw.rooms[0].panel.contains(&100));
+        assert!(!draw.rooms[0].panel.contains(&0));
+        assert_eq!(draw.rooms[0].chair, Some(100));
+        assert_eq!(draw.id_map[&100], idx);
+
+        // if both people are in the draw, the duplicate is removed
+        assert!(draw.replace_member(2, 3));
+        assert_eq!(
+            draw.rooms[0].teams[&Team::Og],
+            [3].into_iter().collect::<HashSet<_>>()
+        );
+        assert!(!draw.id_map.contains_key(&2));
+    }
 }
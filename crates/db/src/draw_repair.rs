@@ -0,0 +1,609 @@
+//! Repairs a draw which has already been released, when some people do not
+//! turn up (or turn up late).
+//!
+//! The aim is to change as little of the draw as possible, as everyone will
+//! already have been told where they are. In order, we
+//!
+//! 1. remove the people who are absent
+//! 2. place the late arrivals (speakers fill teams which are short of
+//!    speakers, and judges join the smallest panels)
+//! 3. fix rooms which no longer have a valid number of teams, by moving whole
+//!    teams between rooms (merging rooms where this empties one of them),
+//!    or turning rooms into half-rooms (with only the opening teams)
+//! 4. move judges into rooms which no longer have any
+//!
+//! Rooms are never renumbered: a room which is merged into another one is
+//! left empty (see [`Repair::closed_rooms`]).
+
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+use crate::draft_draw::{DraftDrawData, DraftDrawRoom, Team};
+
+/// Someone who arrived after the draw was released.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LateArrival {
+    pub member: i64,
+    /// Whether they should be placed as a judge (otherwise they are placed as
+    /// a speaker).
+    pub as_judge: bool,
+}
+
+/// A single change made when repairing the draw.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RepairStep {
+    Removed {
+        member: i64,
+        room: usize,
+    },
+    /// The chair of the room was removed (or moved), so somebody else on the
+    /// panel now chairs it.
+    NewChair {
+        member: i64,
+        room: usize,
+    },
+    AddedSpeaker {
+        member: i64,
+        room: usize,
+        team: Team,
+    },
+    AddedJudge {
+        member: i64,
+        room: usize,
+    },
+    MovedJudge {
+        member: i64,
+        from: usize,
+        to: usize,
+    },
+    MovedTeam {
+        from: (usize, Team),
+        to: (usize, Team),
+    },
+    /// There was an odd number of teams left over, so the speakers of this
+    /// team now judge instead.
+    TeamToPanel {
+        room: usize,
+        team: Team,
+    },
+    /// Everyone left in the room has been moved elsewhere.
+    ClosedRoom {
+        room: usize,
+    },
+    /// It was not possible to place this late arrival anywhere.
+    CouldNotPlace {
+        member: i64,
+    },
+    /// There are no spare judges to move into this room.
+    NoJudge {
+        room: usize,
+    },
+}
+
+impl RepairStep {
+    pub fn describe(&self, name_of: &dyn Fn(i64) -> String) -> String {
+        match self {
+            RepairStep::Removed { member, room } => {
+                format!("Removed {} from room {room}", name_of(*member))
+            }
+            RepairStep::NewChair { member, room } => {
+                format!("{} now chairs room {room}", name_of(*member))
+            }
+            RepairStep::AddedSpeaker { member, room, team } => format!(
+                "Added {} to {} in room {room}",
+                name_of(*member),
+                team.abbreviation()
+            ),
+            RepairStep::AddedJudge { member, room } => format!(
+                "Added {} to the panel of room {room}",
+                name_of(*member)
+            ),
+            RepairStep::MovedJudge { member, from, to } => format!(
+                "Moved {} from the panel of room {from} to the panel of room \
+                 {to}",
+                name_of(*member)
+            ),
+            RepairStep::MovedTeam { from, to } => format!(
+                "Moved {} of room {} to {} of room {}",
+                from.1.abbreviation(),
+                from.0,
+                to.1.abbreviation(),
+                to.0
+            ),
+            RepairStep::TeamToPanel { room, team } => format!(
+                "The speakers of {} in room {room} will judge instead",
+                team.abbreviation()
+            ),
+            RepairStep::ClosedRoom { room } => {
+                format!("Room {room} no longer runs")
+            }
+            RepairStep::CouldNotPlace { member } => {
+                format!("Could not find anywhere to put {}", name_of(*member))
+            }
+            RepairStep::NoJudge { room } => {
+                format!("There are no spare judges for room {room}")
+            }
+        }
+    }
+
+    /// Whether this step means that the repaired draw still needs attention.
+    pub fn is_unresolved(&self) -> bool {
+        matches!(
+            self,
+            RepairStep::CouldNotPlace { .. } | RepairStep::NoJudge { .. }
+        )
+    }
+}
+
+/// A proposed repair of a draw (see [`propose_repair`]).
+#[derive(Clone, Debug)]
+pub struct Repair {
+    pub steps: Vec<RepairStep>,
+    pub data: DraftDrawData,
+}
+
+impl Repair {
+    /// The rooms which no longer have anybody in them.
+    pub fn closed_rooms(&self) -> Vec<usize> {
+        self.data
+            .rooms
+            .iter()
+            .enumerate()
+            .filter(|(_, room)| room.is_empty())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+impl DraftDrawRoom {
+    fn is_empty(&self) -> bool {
+        self.panel.is_empty() && self.teams.values().all(|t| t.is_empty())
+    }
+
+    fn non_empty_teams(&self) -> Vec<Team> {
+        Team::ALL
+            .into_iter()
+            .filter(|team| self.teams.get(team).is_some_and(|t| !t.is_empty()))
+            .collect()
+    }
+
+    fn empty_teams(&self) -> Vec<Team> {
+        Team::ALL
+            .into_iter()
+            .filter(|team| self.teams.get(team).is_none_or(|t| t.is_empty()))
+            .collect()
+    }
+
+    /// Removes a judge from the panel, choosing a new chair if they were
+    /// chairing the room.
+    fn remove_judge(
+        &mut self,
+        member: i64,
+        room: usize,
+        steps: &mut Vec<RepairStep>,
+    ) {
+        self.panel.remove(&member);
+        if self.chair == Some(member) {
+            self.chair = self.panel.iter().min().copied();
+            if let Some(chair) = self.chair {
+                steps.push(RepairStep::NewChair {
+                    member: chair,
+                    room,
+                });
+            }
+        }
+    }
+}
+
+/// Proposes the smallest repair we can find of a released draw, given the
+/// people who are absent and those who have arrived late.
+pub fn propose_repair(
+    data: &DraftDrawData,
+    absent: &HashSet<i64>,
+    arrivals: &[LateArrival],
+    speakers_per_team: usize,
+) -> Repair {
+    let mut data = data.clone();
+    let mut steps = Vec::new();
+
+    for (idx, room) in data.rooms.iter_mut().enumerate() {
+        for member in room.panel.clone().into_iter().sorted() {
+            if absent.contains(&member) {
+                steps.push(RepairStep::Removed { member, room: idx });
+                room.remove_judge(member, idx, &mut steps);
+            }
+        }
+        for team in Team::ALL {
+            let Some(speakers) = room.teams.get_mut(&team) else {
+                continue;
+            };
+            for member in speakers.clone().into_iter().sorted() {
+                if absent.contains(&member) {
+                    speakers.remove(&member);
+                    steps.push(RepairStep::Removed { member, room: idx });
+                }
+            }
+        }
+    }
+
+    place_arrivals(&mut data, arrivals, speakers_per_team, &mut steps);
+    fix_teams(&mut data, &mut steps);
+    fix_panels(&mut data, &mut steps);
+
+    Repair { steps, data }
+}
+
+fn place_arrivals(
+    data: &mut DraftDrawData,
+    arrivals: &[LateArrival],
+    speakers_per_team: usize,
+    steps: &mut Vec<RepairStep>,
+) {
+    for arrival in arrivals.iter().sorted_by_key(|arrival| arrival.member) {
+        let member = arrival.member;
+        if arrival.as_judge {
+            // prefer rooms without any judges, and then the smallest panels
+            let room = data
+                .rooms
+                .iter()
+                .enumerate()
+                .filter(|(_, room)| !room.non_empty_teams().is_empty())
+                .min_by_key(|(idx, room)| (room.panel.len(), *idx))
+                .map(|(idx, _)| idx);
+            match room {
+                Some(idx) => {
+                    data.rooms[idx].panel.insert(member);
+                    steps.push(RepairStep::AddedJudge { member, room: idx });
+                }
+                None => steps.push(RepairStep::CouldNotPlace { member }),
+            }
+        } else {
+            // prefer teams which have lost all their speakers (so that the
+            // room does not have to be changed), and then the smallest teams
+            let slot = data
+                .rooms
+                .iter()
+                .enumerate()
+                .filter(|(_, room)| !room.non_empty_teams().is_empty())
+                .flat_map(|(idx, room)| {
+                    Team::ALL.into_iter().map(move |team| {
+                        let size = room.teams.get(&team).map_or(0, |t| t.len());
+                        (idx, team, size)
+                    })
+                })
+                .filter(|(_, _, size)| *size < speakers_per_team)
+                .min_by_key(|(idx, team, size)| (*size, *idx, *team))
+                .map(|(idx, team, _)| (idx, team));
+            match slot {
+                Some((idx, team)) => {
+                    data.rooms[idx]
+                        .teams
+                        .entry(team)
+                        .or_default()
+                        .insert(member);
+                    steps.push(RepairStep::AddedSpeaker {
+                        member,
+                        room: idx,
+                        team,
+                    });
+                }
+                None => steps.push(RepairStep::CouldNotPlace { member }),
+            }
+        }
+    }
+}
+
+fn move_team(
+    data: &mut DraftDrawData,
+    from: (usize, Team),
+    to: (usize, Team),
+    steps: &mut Vec<RepairStep>,
+) {
+    let speakers = data.rooms[from.0]
+        .teams
+        .get_mut(&from.1)
+        .map(std::mem::take)
+        .unwrap_or_default();
+    data.rooms[to.0]
+        .teams
+        .entry(to.1)
+        .or_default()
+        .extend(speakers);
+    steps.push(RepairStep::MovedTeam { from, to });
+}
+
+/// Moves everyone on the panel of a room which no longer has any teams to
+/// the panel of another room (`to`, or the rooms with the smallest panels if
+/// this is `None`).
+fn close_room(
+    data: &mut DraftDrawData,
+    idx: usize,
+    to: Option<usize>,
+    steps: &mut Vec<RepairStep>,
+) {
+    for member in data.rooms[idx].panel.clone().into_iter().sorted() {
+        let target = to.or_else(|| {
+            data.rooms
+                .iter()
+                .enumerate()
+                .filter(|(other, room)| {
+                    *other != idx && !room.non_empty_teams().is_empty()
+                })
+                .min_by_key(|(other, room)| (room.panel.len(), *other))
+                .map(|(other, _)| other)
+        });
+        let Some(target) = target else {
+            // there is nowhere else to go
+            return;
+        };
+        data.rooms[idx].remove_judge(member, idx, steps);
+        data.rooms[target].panel.insert(member);
+        steps.push(RepairStep::MovedJudge {
+            member,
+            from: idx,
+            to: target,
+        });
+    }
+    data.rooms[idx].chair = None;
+    steps.push(RepairStep::ClosedRoom { room: idx });
+}
+
+/// Makes sure that every room has either four teams, or two teams (in the
+/// opening positions).
+fn fix_teams(data: &mut DraftDrawData, steps: &mut Vec<RepairStep>) {
+    // rooms which have lost all of their teams
+    for idx in 0..data.rooms.len() {
+        let room = &data.rooms[idx];
+        if room.non_empty_teams().is_empty() && !room.panel.is_empty() {
+            close_room(data, idx, None, steps);
+        }
+    }
+
+    // rooms with an odd number of teams are paired up
+    let odd = (0..data.rooms.len())
+        .filter(|idx| data.rooms[*idx].non_empty_teams().len() % 2 == 1)
+        .collect::<Vec<_>>();
+    for pair in odd.chunks(2) {
+        match *pair {
+            [a, b] => {
+                let (a, b) = if data.rooms[a].non_empty_teams().len()
+                    >= data.rooms[b].non_empty_teams().len()
+                {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                // `a` has at least as many teams as `b`, so we move a team
+                // from `b` to `a`
+                let from = *data.rooms[b].non_empty_teams().last().unwrap();
+                let to = data.rooms[a].empty_teams()[0];
+                move_team(data, (b, from), (a, to), steps);
+                if data.rooms[b].non_empty_teams().is_empty() {
+                    // the rooms have been merged
+                    close_room(data, b, Some(a), steps);
+                }
+            }
+            [a] => {
+                // there is an odd number of teams in total, so one of them
+                // has to judge
+                let team = *data.rooms[a].non_empty_teams().last().unwrap();
+                let speakers = data.rooms[a]
+                    .teams
+                    .get_mut(&team)
+                    .map(std::mem::take)
+                    .unwrap_or_default();
+                data.rooms[a].panel.extend(speakers);
+                steps.push(RepairStep::TeamToPanel { room: a, team });
+                if data.rooms[a].non_empty_teams().is_empty() {
+                    close_room(data, a, None, steps);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // half-rooms use the opening positions
+    for idx in 0..data.rooms.len() {
+        let teams = data.rooms[idx].non_empty_teams();
+        if teams.len() != 2 {
+            continue;
+        }
+        for (from, to) in teams.into_iter().zip([Team::Og, Team::Oo]) {
+            if from != to {
+                move_team(data, (idx, from), (idx, to), steps);
+            }
+        }
+    }
+}
+
+/// Makes sure that every room which is running has at least one judge.
+fn fix_panels(data: &mut DraftDrawData, steps: &mut Vec<RepairStep>) {
+    for idx in 0..data.rooms.len() {
+        let room = &data.rooms[idx];
+        if !room.panel.is_empty() || room.non_empty_teams().is_empty() {
+            continue;
+        }
+
+        // take a judge (other than the chair, where possible) from the
+        // largest panel which can spare one
+        let donor = data
+            .rooms
+            .iter()
+            .enumerate()
+            .filter(|(_, room)| room.panel.len() > 1)
+            .max_by_key(|(other, room)| (room.panel.len(), usize::MAX - other))
+            .map(|(other, _)| other);
+        let Some(donor) = donor else {
+            steps.push(RepairStep::NoJudge { room: idx });
+            continue;
+        };
+        let chair = data.rooms[donor].chair;
+        let member = data.rooms[donor]
+            .panel
+            .iter()
+            .copied()
+            .filter(|member| Some(*member) != chair)
+            .min()
+            .unwrap();
+        data.rooms[donor].remove_judge(member, donor, steps);
+        data.rooms[idx].panel.insert(member);
+        steps.push(RepairStep::MovedJudge {
+            member,
+            from: donor,
+            to: idx,
+        });
+        data.rooms[idx].chair = Some(member);
+        steps.push(RepairStep::NewChair { member, room: idx });
+    }
+}
+
+#[cfg(test)]
+mod test_draw_repair {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{propose_repair, LateArrival, RepairStep};
+    use crate::draft_draw::{DraftDrawData, DraftDrawRoom, Team};
+
+    /// A room whose judges are `first..first + judges`, followed by two
+    /// speakers in each of the teams.
+    fn room(first: i64, judges: i64) -> DraftDrawRoom {
+        let mut next = first;
+        let mut take = |n: i64| {
+            let set = (next..next + n).collect::<HashSet<_>>();
+            next += n;
+            set
+        };
+        let panel = take(judges);
+        let teams = Team::ALL
+            .into_iter()
+            .map(|team| (team, take(2)))
+            .collect::<HashMap<_, _>>();
+        DraftDrawRoom {
+            chair: panel.iter().min().copied(),
+            panel,
+            teams,
+        }
+    }
+
+    fn draw(rooms: Vec<DraftDrawRoom>) -> DraftDrawData {
+        let mut data = DraftDrawData {
+            rooms,
+            id_map: HashMap::new(),
+        };
+        data.generate_map();
+        data
+    }
+
+    fn absent(members: &[i64]) -> HashSet<i64> {
+        members.iter().copied().collect()
+    }
+
+    #[test]
+    fn nothing_to_do() {
+        let data = draw(vec![room(0, 1), room(100, 1)]);
+        let repair = propose_repair(&data, &HashSet::new(), &[], 2);
+        assert!(repair.steps.is_empty());
+        assert_eq!(repair.data, data);
+    }
+
+    #[test]
+    fn late_speaker_fills_gap() {
+        // speakers 1 and 2 are OG in the first room
+        let data = draw(vec![room(0, 1), room(100, 1)]);
+        let repair = propose_repair(
+            &data,
+            &absent(&[1]),
+            &[LateArrival {
+                member: 50,
+                as_judge: false,
+            }],
+            2,
+        );
+        assert_eq!(
+            repair.steps,
+            vec![
+                RepairStep::Removed { member: 1, room: 0 },
+                RepairStep::AddedSpeaker {
+                    member: 50,
+                    room: 0,
+                    team: Team::Og
+                },
+            ]
+        );
+        assert!(repair.data.rooms[0].teams[&Team::Og].contains(&50));
+    }
+
+    #[test]
+    fn half_room() {
+        // both closing teams of the first room are missing
+        let data = draw(vec![room(0, 1), room(100, 1)]);
+        let repair = propose_repair(&data, &absent(&[5, 6, 7, 8]), &[], 2);
+        assert!(repair.data.rooms[0].teams[&Team::Cg].is_empty());
+        assert!(repair.data.rooms[0].teams[&Team::Co].is_empty());
+        assert_eq!(repair.data.rooms[1], data.rooms[1]);
+        assert!(repair.closed_rooms().is_empty());
+    }
+
+    #[test]
+    fn half_room_uses_opening_positions() {
+        // both opening teams of the first room are missing, so the closing
+        // teams move up
+        let data = draw(vec![room(0, 1), room(100, 1)]);
+        let repair = propose_repair(&data, &absent(&[1, 2, 3, 4]), &[], 2);
+        let half = &repair.data.rooms[0];
+        assert_eq!(half.teams[&Team::Og], data.rooms[0].teams[&Team::Cg]);
+        assert_eq!(half.teams[&Team::Oo], data.rooms[0].teams[&Team::Co]);
+        assert!(half.teams[&Team::Cg].is_empty());
+        assert!(half.teams[&Team::Co].is_empty());
+        assert!(repair.steps.contains(&RepairStep::MovedTeam {
+            from: (0, Team::Cg),
+            to: (0, Team::Og)
+        }));
+    }
+
+    #[test]
+    fn merge_rooms() {
+        // the first room loses three teams, so its remaining team (and its
+        // judge) move into the second room, which has lost a team
+        let data = draw(vec![room(0, 1), room(100, 1)]);
+        let repair = propose_repair(
+            &data,
+            &absent(&[1, 2, 3, 4, 5, 6, 107, 108]),
+            &[],
+            2,
+        );
+        assert_eq!(repair.closed_rooms(), vec![0]);
+        let merged = &repair.data.rooms[1];
+        assert!(merged.teams[&Team::Co].contains(&7));
+        assert!(merged.teams[&Team::Co].contains(&8));
+        assert!(merged.panel.contains(&0));
+        assert!(merged.panel.contains(&100));
+        assert!(repair.steps.contains(&RepairStep::ClosedRoom { room: 0 }));
+    }
+
+    #[test]
+    fn judge_moved_in() {
+        // the only judge of the first room is absent, and the second room has
+        // three judges
+        let data = draw(vec![room(0, 1), room(100, 3)]);
+        let repair = propose_repair(&data, &absent(&[0]), &[], 2);
+        assert_eq!(repair.data.rooms[0].panel.len(), 1);
+        assert_eq!(repair.data.rooms[1].panel.len(), 2);
+        // the chair of the second room stays where they are
+        assert_eq!(repair.data.rooms[1].chair, Some(100));
+        assert!(repair.data.rooms[1].panel.contains(&100));
+        assert_eq!(repair.data.rooms[0].chair, Some(101));
+    }
+
+    #[test]
+    fn no_spare_judges() {
+        let data = draw(vec![room(0, 1), room(100, 1)]);
+        let repair = propose_repair(&data, &absent(&[0]), &[], 2);
+        assert!(repair
+            .steps
+            .iter()
+            .any(|step| *step == RepairStep::NoJudge { room: 0 }));
+        assert!(repair.steps.iter().any(|step| step.is_unresolved()));
+    }
+}
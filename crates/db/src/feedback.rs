@@ -0,0 +1,32 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+
+#[derive(Queryable, Serialize, Debug, Clone, Eq, PartialEq)]
+/// A private link which a speaker can use to give feedback on the
+/// adjudicators in their room.
+pub struct SpeakerFeedbackLink {
+    pub id: i64,
+    pub public_id: String,
+    pub link: String,
+    pub speaker_id: i64,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, Eq, PartialEq)]
+/// Feedback given by a speaker on one of the adjudicators in their room. Each
+/// speaker can give at most one piece of feedback on each adjudicator
+/// (resubmitting replaces the previous feedback).
+pub struct AdjudicatorFeedback {
+    pub id: i64,
+    pub public_id: String,
+    pub adjudicator_id: i64,
+    pub speaker_id: i64,
+    /// A score out of [`MAX_FEEDBACK_SCORE`].
+    pub score: Option<i64>,
+    pub comments: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+pub const MAX_FEEDBACK_SCORE: i64 = 10;
@@ -3,10 +3,13 @@
 pub mod ballot;
 pub mod config;
 pub mod draft_draw;
+pub mod draw_repair;
 pub mod email;
+pub mod feedback;
 pub mod group;
 pub mod invite;
 pub mod magic_link;
+pub mod merge_members;
 pub mod room;
 /// Database schema
 pub mod schema;
@@ -31,11 +34,26 @@ use diesel::{
 };
 use diesel::{sql_query, RunQueryDsl};
 use rocket::{Build, Rocket};
-use rocket_sync_db_pools::{database, Config, PoolResult, Poolable};
+use rocket_sync_db_pools::{
+    database, Config, ConnectionPool, PoolResult, Poolable,
+};
 
 #[database("database")]
 pub struct DbConn(DbWrapper);
 
+/// The pool which [`DbConn`]s are taken from. This can be cloned and kept
+/// around by tasks which run outside of a request (these should take a new
+/// connection each time they need one, rather than holding one forever).
+pub type DbPool = ConnectionPool<DbConn, DbWrapper>;
+
+impl DbConn {
+    /// Takes a connection from the pool (returns `None` if no connection
+    /// became available before the configured timeout).
+    pub async fn from_pool(pool: &DbPool) -> Option<DbConn> {
+        pool.get().await.map(DbConn)
+    }
+}
+
 pub struct DbWrapper(SqliteConnection);
 
 impl SimpleConnection for DbWrapper {
@@ -0,0 +1,307 @@
+//! Merges two members of a spar series which are really the same person (for
+//! example, someone who was added by an administrator and then also had a
+//! join request approved, or who was added twice with different spellings
+//! of their name).
+//!
+//! Everything which refers to the member being merged (`from`) is changed to
+//! refer to the member being kept (`into`), and then `from` is deleted.
+
+use std::collections::HashSet;
+
+use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
+
+use crate::{
+    draft_draw::{DraftDraw, DraftDrawData, DraftDrawVersion},
+    schema::{
+        adjudicator_ballot_entries, draft_draw_versions, draft_draws,
+        spar_adjudicator_ballot_links, spar_adjudicators, spar_rooms,
+        spar_series_members, spar_signups, spar_speakers, spar_teams, spars,
+    },
+    spar::{Spar, SparSeriesMember, SparSignup},
+};
+
+/// The rows which will be changed by merging two members.
+#[derive(Debug, Clone)]
+pub struct MergePreview {
+    /// Signups which will be moved to `into`.
+    pub signups: usize,
+    /// Spars which both members signed up to (the signups are combined).
+    pub combined_signups: usize,
+    /// Signups of other people who asked to be paired with `from`.
+    pub partner_preferences: usize,
+    pub speakers: usize,
+    pub adjudicators: usize,
+    /// Speaker scores given to `from` (these stay attached to the speaker
+    /// record, which is moved to `into`).
+    pub ballot_entries: usize,
+    pub ballot_links: usize,
+    /// Draft draws (including old versions) which include `from`.
+    pub draft_draws: usize,
+    /// Spars in which both members are in the draw. Members can only be
+    /// merged if this is empty (as otherwise the same person would be in the
+    /// draw twice).
+    pub conflicts: Vec<Spar>,
+}
+
+impl MergePreview {
+    pub fn can_merge(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Returns the ids of the spars in which the member is in the (confirmed)
+/// draw.
+fn spars_in_draw(
+    member_id: i64,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<HashSet<i64>, diesel::result::Error> {
+    let mut ret = spar_speakers::table
+        .inner_join(spar_teams::table.inner_join(spar_rooms::table))
+        .filter(spar_speakers::member_id.eq(member_id))
+        .select(spar_rooms::spar_id)
+        .load::<i64>(conn)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    ret.extend(
+        spar_adjudicators::table
+            .inner_join(spar_rooms::table)
+            .filter(spar_adjudicators::member_id.eq(member_id))
+            .select(spar_rooms::spar_id)
+            .load::<i64>(conn)?,
+    );
+    Ok(ret)
+}
+
+fn draft_draws_of_series(
+    spar_series_id: i64,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<(Vec<DraftDraw>, Vec<DraftDrawVersion>), diesel::result::Error> {
+    let draws = draft_draws::table
+        .inner_join(spars::table)
+        .filter(spars::spar_series_id.eq(spar_series_id))
+        .select(draft_draws::all_columns)
+        .load::<DraftDraw>(conn)?;
+    let versions = draft_draw_versions::table
+        .filter(
+            draft_draw_versions::draft_draw_id
+                .eq_any(draws.iter().map(|draw| draw.id).collect::<Vec<_>>()),
+        )
+        .load::<DraftDrawVersion>(conn)?;
+    Ok((draws, versions))
+}
+
+/// Returns the draw with `from` replaced by `into`, or `None` if `from` is
+/// not in the draw.
+fn replace_in_draw(data: &str, from: i64, into: i64) -> Option<String> {
+    let mut draw = serde_json::from_str::<DraftDrawData>(data).unwrap();
+    if draw.replace_member(from, into) {
+        Some(serde_json::to_string(&draw).unwrap())
+    } else {
+        None
+    }
+}
+
+/// Works out what [`merge_members`] will change, without changing anything.
+#[tracing::instrument(skip(conn))]
+pub fn preview_merge(
+    from: &SparSeriesMember,
+    into: &SparSeriesMember,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<MergePreview, diesel::result::Error> {
+    assert_eq!(from.spar_series_id, into.spar_series_id);
+    assert_ne!(from.id, into.id);
+
+    let from_signups = spar_signups::table
+        .filter(spar_signups::member_id.eq(from.id))
+        .select(spar_signups::spar_id)
+        .load::<i64>(conn)?;
+    let into_signups = spar_signups::table
+        .filter(spar_signups::member_id.eq(into.id))
+        .select(spar_signups::spar_id)
+        .load::<i64>(conn)?;
+    let combined_signups = from_signups
+        .iter()
+        .filter(|spar| into_signups.contains(spar))
+        .count();
+
+    let conflicting = spars_in_draw(from.id, conn)?
+        .intersection(&spars_in_draw(into.id, conn)?)
+        .copied()
+        .collect::<Vec<_>>();
+    let conflicts = spars::table
+        .filter(spars::id.eq_any(conflicting))
+        .order_by(spars::start_time.asc())
+        .load::<Spar>(conn)?;
+
+    let (draws, versions) = draft_draws_of_series(from.spar_series_id, conn)?;
+    let draft_draws = draws
+        .iter()
+        .filter_map(|draw| draw.data.as_deref())
+        .chain(versions.iter().map(|version| version.data.as_str()))
+        .filter(|data| replace_in_draw(data, from.id, into.id).is_some())
+        .count();
+
+    Ok(MergePreview {
+        signups: from_signups.len() - combined_signups,
+        combined_signups,
+        partner_preferences: spar_signups::table
+            .filter(spar_signups::partner_preference.eq(from.id))
+            .count()
+            .get_result::<i64>(conn)? as usize,
+        speakers: spar_speakers::table
+            .filter(spar_speakers::member_id.eq(from.id))
+            .count()
+            .get_result::<i64>(conn)? as usize,
+        adjudicators: spar_adjudicators::table
+            .filter(spar_adjudicators::member_id.eq(from.id))
+            .count()
+            .get_result::<i64>(conn)? as usize,
+        ballot_entries: adjudicator_ballot_entries::table
+            .inner_join(spar_speakers::table)
+            .filter(spar_speakers::member_id.eq(from.id))
+            .count()
+            .get_result::<i64>(conn)? as usize,
+        ballot_links: spar_adjudicator_ballot_links::table
+            .filter(spar_adjudicator_ballot_links::member_id.eq(from.id))
+            .count()
+            .get_result::<i64>(conn)? as usize,
+        draft_draws,
+        conflicts,
+    })
+}
+
+/// Merges `from` into `into` (see the module documentation). This should be
+/// run inside a transaction.
+///
+/// Where both members signed up to the same spar, the signups are combined
+/// (keeping the signup of `into`, and the roles of both). The group identity
+/// and user account of `from` are kept if `into` does not have one.
+///
+/// Returns an error (without changing anything) if the members cannot be
+/// merged (see [`MergePreview::conflicts`]).
+#[tracing::instrument(skip(conn))]
+pub fn merge_members(
+    from: &SparSeriesMember,
+    into: &SparSeriesMember,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Result<MergePreview, MergePreview>, diesel::result::Error> {
+    let preview = preview_merge(from, into, conn)?;
+    if !preview.can_merge() {
+        return Ok(Err(preview));
+    }
+
+    for signup in spar_signups::table
+        .filter(spar_signups::member_id.eq(from.id))
+        .load::<SparSignup>(conn)?
+    {
+        let existing = spar_signups::table
+            .filter(spar_signups::member_id.eq(into.id))
+            .filter(spar_signups::spar_id.eq(signup.spar_id))
+            .first::<SparSignup>(conn)
+            .optional()?;
+        match existing {
+            Some(existing) => {
+                diesel::update(
+                    spar_signups::table
+                        .filter(spar_signups::id.eq(existing.id)),
+                )
+                .set((
+                    spar_signups::as_judge
+                        .eq(existing.as_judge || signup.as_judge),
+                    spar_signups::as_speaker
+                        .eq(existing.as_speaker || signup.as_speaker),
+                    spar_signups::partner_preference.eq(existing
+                        .partner_preference
+                        .or(signup.partner_preference)),
+                ))
+                .execute(conn)?;
+                diesel::delete(
+                    spar_signups::table.filter(spar_signups::id.eq(signup.id)),
+                )
+                .execute(conn)?;
+            }
+            None => {
+                diesel::update(
+                    spar_signups::table.filter(spar_signups::id.eq(signup.id)),
+                )
+                .set(spar_signups::member_id.eq(into.id))
+                .execute(conn)?;
+            }
+        }
+    }
+
+    diesel::update(
+        spar_signups::table
+            .filter(spar_signups::partner_preference.eq(from.id)),
+    )
+    .set(spar_signups::partner_preference.eq(into.id))
+    .execute(conn)?;
+    // nobody can be their own partner
+    diesel::update(
+        spar_signups::table
+            .filter(spar_signups::member_id.eq(into.id))
+            .filter(spar_signups::partner_preference.eq(into.id)),
+    )
+    .set(spar_signups::partner_preference.eq(None::<i64>))
+    .execute(conn)?;
+
+    diesel::update(
+        spar_speakers::table.filter(spar_speakers::member_id.eq(from.id)),
+    )
+    .set(spar_speakers::member_id.eq(into.id))
+    .execute(conn)?;
+    diesel::update(
+        spar_adjudicators::table
+            .filter(spar_adjudicators::member_id.eq(from.id)),
+    )
+    .set(spar_adjudicators::member_id.eq(into.id))
+    .execute(conn)?;
+    diesel::update(
+        spar_adjudicator_ballot_links::table
+            .filter(spar_adjudicator_ballot_links::member_id.eq(from.id)),
+    )
+    .set(spar_adjudicator_ballot_links::member_id.eq(into.id))
+    .execute(conn)?;
+
+    let (draws, versions) = draft_draws_of_series(from.spar_series_id, conn)?;
+    for draw in draws {
+        if let Some(data) = draw
+            .data
+            .as_deref()
+            .and_then(|data| replace_in_draw(data, from.id, into.id))
+        {
+            diesel::update(
+                draft_draws::table.filter(draft_draws::id.eq(draw.id)),
+            )
+            .set(draft_draws::data.eq(data))
+            .execute(conn)?;
+        }
+    }
+    for version in versions {
+        if let Some(data) = replace_in_draw(&version.data, from.id, into.id) {
+            diesel::update(
+                draft_draw_versions::table
+                    .filter(draft_draw_versions::id.eq(version.id)),
+            )
+            .set(draft_draw_versions::data.eq(data))
+            .execute(conn)?;
+        }
+    }
+
+    // note: the triggers on this table keep the full text index up to date
+    diesel::delete(
+        spar_series_members::table.filter(spar_series_members::id.eq(from.id)),
+    )
+    .execute(conn)?;
+    diesel::update(
+        spar_series_members::table.filter(spar_series_members::id.eq(into.id)),
+    )
+    .set((
+        spar_series_members::identity_id
+            .eq(into.identity_id.or(from.identity_id)),
+        spar_series_members::user_id.eq(into.user_id.or(from.user_id)),
+    ))
+    .execute(conn)?;
+
+    Ok(Ok(preview))
+}
@@ -10,7 +10,7 @@ use diesel::sqlite::Sqlite;
 use fuzzcheck::DefaultMutator;
 use serde::Serialize;
 
-use crate::ballot::BallotRepr;
+use crate::ballot::{resolve_canonical_ballot, AdjudicatorBallot, BallotRepr};
 use crate::schema::adjudicator_ballots;
 use crate::schema::spar_adjudicators;
 use crate::schema::spar_series_members;
@@ -54,25 +54,76 @@ impl SparRoom {
         SparRoomRepr::of_id(self.id, conn)
     }
 
+    /// Returns the most recent ballot submitted by each adjudicator on the
+    /// panel, ordered by the time each adjudicator first submitted a ballot.
+    #[tracing::instrument(name = "SparRoom::latest_ballots", skip(conn))]
+    pub fn latest_ballots(
+        &self,
+        conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+    ) -> Result<Vec<BallotRepr>, diesel::result::Error> {
+        let ballots = adjudicator_ballots::table
+            .filter(adjudicator_ballots::room_id.eq(self.id))
+            .order_by((
+                adjudicator_ballots::created_at.asc(),
+                adjudicator_ballots::id.asc(),
+            ))
+            .load::<AdjudicatorBallot>(conn)?;
+
+        let mut latest: Vec<AdjudicatorBallot> = Vec::new();
+        for ballot in ballots {
+            match latest
+                .iter_mut()
+                .find(|prev| prev.adjudicator_id == ballot.adjudicator_id)
+            {
+                Some(prev) => *prev = ballot,
+                None => latest.push(ballot),
+            }
+        }
+
+        latest
+            .into_iter()
+            .map(|ballot| BallotRepr::of_id(ballot.id, conn))
+            .collect()
+    }
+
+    /// Returns the ballot which should be treated as the result of this room.
+    ///
+    /// If an administrator has pinned a ballot, then that ballot is used.
+    /// Otherwise see [`resolve_canonical_ballot`] for how we resolve conflicts
+    /// between adjudicators on the same panel.
     #[tracing::instrument(name = "SparRoom::canonical_ballot", skip(conn))]
     pub fn canonical_ballot(
         &self,
         conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
     ) -> Result<Option<BallotRepr>, diesel::result::Error> {
-        let id = adjudicator_ballots::table
+        let pinned = adjudicator_ballots::table
             .filter(adjudicator_ballots::room_id.eq(self.id))
-            .order_by(adjudicator_ballots::created_at)
+            .filter(adjudicator_ballots::is_pinned.eq(true))
             .select(adjudicator_ballots::id)
             .first::<i64>(conn)
             .optional()?;
-
-        match id {
-            Some(id) => Ok(Some(BallotRepr::of_id(id, conn)?)),
-            None => Ok(None),
+        if let Some(pinned) = pinned {
+            return Ok(Some(BallotRepr::of_id(pinned, conn)?));
         }
+
+        let ballots = self.latest_ballots(conn)?;
+
+        let chair = spar_adjudicators::table
+            .filter(spar_adjudicators::room_id.eq(self.id))
+            .filter(spar_adjudicators::status.eq(CHAIR))
+            .select(spar_adjudicators::id)
+            .first::<i64>(conn)
+            .optional()?;
+
+        Ok(resolve_canonical_ballot(ballots, chair))
     }
 }
 
+/// The status of the adjudicator who chairs a room.
+pub const CHAIR: &str = "chair";
+/// The status of an adjudicator who is a panellist (i.e. not the chair).
+pub const PANELLIST: &str = "panellist";
+
 #[derive(Debug, Clone)]
 pub struct SparRoomRepr {
     pub inner: SparRoom,
@@ -81,6 +132,7 @@ pub struct SparRoomRepr {
     /// The teams will be stored here in the order they speak.
     ///
     /// For BP teams[0] = og, teams[1] = oo, teams[2] = cg, teams[3] = co
+    /// (in half-rooms the closing teams have no speakers).
     pub teams: Vec<TeamRepr>,
     /// Maps speakers to relevant records.
     pub speakers: HashMap<i64, SparRoomTeamSpeaker>,
@@ -165,6 +217,12 @@ impl SparRoomRepr {
                 .collect(),
         })
     }
+
+    /// Whether only the opening teams are debating in this room (which is
+    /// the case if the closing teams have no speakers).
+    pub fn is_half_room(&self) -> bool {
+        self.teams[2..].iter().all(|team| team.speakers.is_empty())
+    }
 }
 
 #[derive(Debug, Clone)]
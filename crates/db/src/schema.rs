@@ -21,6 +21,17 @@ diesel::table! {
         team_id -> BigInt,
         speak -> BigInt,
         position -> BigInt,
+        comments -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    adjudicator_ballot_team_comments (id) {
+        id -> BigInt,
+        public_id -> Text,
+        ballot_id -> BigInt,
+        team_id -> BigInt,
+        comments -> Text,
     }
 }
 
@@ -31,6 +42,21 @@ diesel::table! {
         adjudicator_id -> BigInt,
         room_id -> BigInt,
         created_at -> Timestamp,
+        source -> Text,
+        submitted_by -> Nullable<BigInt>,
+        is_pinned -> Bool,
+    }
+}
+
+diesel::table! {
+    adjudicator_feedback (id) {
+        id -> BigInt,
+        public_id -> Text,
+        adjudicator_id -> BigInt,
+        speaker_id -> BigInt,
+        score -> Nullable<BigInt>,
+        comments -> Nullable<Text>,
+        created_at -> Timestamp,
     }
 }
 
@@ -43,6 +69,32 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    draft_draw_candidates (id) {
+        id -> BigInt,
+        draft_draw_id -> BigInt,
+        generation_id -> Text,
+        preset -> Text,
+        max_team_gap -> Nullable<Double>,
+        mean_team_gap -> Nullable<Double>,
+        partner_preferences_honoured -> Nullable<BigInt>,
+        partner_preferences_requested -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    draft_draw_versions (id) {
+        id -> BigInt,
+        public_id -> Text,
+        draft_draw_id -> BigInt,
+        version -> BigInt,
+        data -> Text,
+        command -> Nullable<Text>,
+        created_by -> Nullable<BigInt>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     draft_draws (id) {
         id -> BigInt,
@@ -64,6 +116,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    group_member_identities (id) {
+        id -> BigInt,
+        public_id -> Text,
+        group_id -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     group_members (id) {
         id -> BigInt,
@@ -104,6 +165,10 @@ diesel::table! {
         member_id -> BigInt,
         created_at -> Timestamp,
         expires_at -> Timestamp,
+        sent_at -> Nullable<Timestamp>,
+        opened_at -> Nullable<Timestamp>,
+        reminded_at -> Nullable<Timestamp>,
+        auto_reminded_at -> Nullable<Timestamp>,
     }
 }
 
@@ -117,6 +182,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    spar_reopenings (id) {
+        id -> BigInt,
+        public_id -> Text,
+        spar_id -> BigInt,
+        reopened_by -> BigInt,
+        reason -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     spar_rooms (id) {
         id -> BigInt,
@@ -139,6 +215,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    spar_series_ballot_settings (id) {
+        id -> BigInt,
+        spar_series_id -> BigInt,
+        reminder_after_hours -> Nullable<BigInt>,
+        auto_complete -> Bool,
+    }
+}
+
 diesel::table! {
     spar_series_join_requests (id) {
         id -> BigInt,
@@ -158,6 +243,20 @@ diesel::table! {
         email -> Text,
         spar_series_id -> BigInt,
         created_at -> Timestamp,
+        identity_id -> Nullable<BigInt>,
+        user_id -> Nullable<BigInt>,
+        is_active -> Bool,
+    }
+}
+
+diesel::table! {
+    spar_series_score_rules (id) {
+        id -> BigInt,
+        spar_series_id -> BigInt,
+        min_score -> Nullable<BigInt>,
+        max_score -> Nullable<BigInt>,
+        step -> Nullable<BigInt>,
+        enforcement -> Text,
     }
 }
 
@@ -204,6 +303,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    speaker_feedback_links (id) {
+        id -> BigInt,
+        public_id -> Text,
+        link -> Text,
+        speaker_id -> BigInt,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> BigInt,
@@ -222,9 +332,18 @@ diesel::joinable!(account_invites -> users (sent_by));
 diesel::joinable!(adjudicator_ballot_entries -> adjudicator_ballots (ballot_id));
 diesel::joinable!(adjudicator_ballot_entries -> spar_speakers (speaker_id));
 diesel::joinable!(adjudicator_ballot_entries -> spar_teams (team_id));
+diesel::joinable!(adjudicator_ballot_team_comments -> adjudicator_ballots (ballot_id));
+diesel::joinable!(adjudicator_ballot_team_comments -> spar_teams (team_id));
 diesel::joinable!(adjudicator_ballots -> spar_adjudicators (adjudicator_id));
 diesel::joinable!(adjudicator_ballots -> spar_rooms (room_id));
+diesel::joinable!(adjudicator_ballots -> users (submitted_by));
+diesel::joinable!(adjudicator_feedback -> spar_adjudicators (adjudicator_id));
+diesel::joinable!(adjudicator_feedback -> spar_speakers (speaker_id));
+diesel::joinable!(draft_draw_candidates -> draft_draws (draft_draw_id));
+diesel::joinable!(draft_draw_versions -> draft_draws (draft_draw_id));
+diesel::joinable!(draft_draw_versions -> users (created_by));
 diesel::joinable!(draft_draws -> spars (spar_id));
+diesel::joinable!(group_member_identities -> groups (group_id));
 diesel::joinable!(group_members -> groups (group_id));
 diesel::joinable!(group_members -> users (user_id));
 diesel::joinable!(magic_links -> users (user_id));
@@ -232,35 +351,51 @@ diesel::joinable!(spar_adjudicator_ballot_links -> spar_rooms (room_id));
 diesel::joinable!(spar_adjudicator_ballot_links -> spar_series_members (member_id));
 diesel::joinable!(spar_adjudicators -> spar_rooms (room_id));
 diesel::joinable!(spar_adjudicators -> spar_series_members (member_id));
+diesel::joinable!(spar_reopenings -> spars (spar_id));
+diesel::joinable!(spar_reopenings -> users (reopened_by));
 diesel::joinable!(spar_rooms -> spars (spar_id));
 diesel::joinable!(spar_series -> groups (group_id));
+diesel::joinable!(spar_series_ballot_settings -> spar_series (spar_series_id));
 diesel::joinable!(spar_series_join_requests -> spar_series (spar_series_id));
+diesel::joinable!(spar_series_members -> group_member_identities (identity_id));
 diesel::joinable!(spar_series_members -> spar_series (spar_series_id));
+diesel::joinable!(spar_series_members -> users (user_id));
+diesel::joinable!(spar_series_score_rules -> spar_series (spar_series_id));
 diesel::joinable!(spar_signups -> spars (spar_id));
 diesel::joinable!(spar_speakers -> spar_series_members (member_id));
 diesel::joinable!(spar_speakers -> spar_teams (team_id));
 diesel::joinable!(spar_teams -> spar_rooms (room_id));
 diesel::joinable!(spars -> spar_series (spar_series_id));
+diesel::joinable!(speaker_feedback_links -> spar_speakers (speaker_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     account_invites,
     adjudicator_ballot_entries,
+    adjudicator_ballot_team_comments,
     adjudicator_ballots,
+    adjudicator_feedback,
     config,
+    draft_draw_candidates,
+    draft_draw_versions,
     draft_draws,
     emails,
+    group_member_identities,
     group_members,
     groups,
     magic_links,
     spar_adjudicator_ballot_links,
     spar_adjudicators,
+    spar_reopenings,
     spar_rooms,
     spar_series,
+    spar_series_ballot_settings,
     spar_series_join_requests,
     spar_series_members,
+    spar_series_score_rules,
     spar_signups,
     spar_speakers,
     spar_teams,
     spars,
+    speaker_feedback_links,
     users,
 );
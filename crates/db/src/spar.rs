@@ -65,57 +65,26 @@ pub struct Spar {
     pub created_at: NaiveDateTime,
 }
 
-allow_columns_to_appear_in_same_group_by_clause!(
-    adjudicator_ballots::id,
-    spar_rooms::id
-);
-
 impl Spar {
-    /// Returns the canonical ballot for each room in this spar.
-    ///
-    /// Currently we resolve conflicting ballots by assuming that the most
-    /// recently submitted ballot is correct.
+    /// Returns the canonical ballot for each room in this spar (see
+    /// [`SparRoom::canonical_ballot`] for how we resolve conflicting ballots).
+    /// Rooms for which no ballots have been submitted are skipped.
     #[tracing::instrument(skip(conn))]
     pub fn canonical_ballots(
         &self,
         conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
     ) -> Result<Vec<BallotRepr>, diesel::result::Error> {
-        let (b1, b2) = diesel::alias!(
-            adjudicator_ballots as b1,
-            adjudicator_ballots as b2
-        );
-
-        let ballots = b1
-            .inner_join(spar_rooms::table)
+        let rooms = spar_rooms::table
             .filter(spar_rooms::spar_id.eq(self.id))
-            .group_by(spar_rooms::id)
-            .filter(
-                b1.field(adjudicator_ballots::created_at).nullable().eq(b2
-                    .filter(
-                        b2.field(adjudicator_ballots::room_id)
-                            .eq(b1.field(adjudicator_ballots::room_id)),
-                    )
-                    .order_by(b2.field(adjudicator_ballots::created_at).desc())
-                    .limit(1)
-                    .select(b2.field(adjudicator_ballots::created_at))
-                    .single_value()),
-            )
-            .select((
-                spar_rooms::id,
-                diesel::dsl::max(b1.field(adjudicator_ballots::id)),
-            ))
-            .load::<(i64, Option<i64>)>(conn)?;
-
-        let mut ret = Vec::with_capacity(ballots.len());
+            .order_by(spar_rooms::public_id)
+            .load::<SparRoom>(conn)?;
 
-        for (_, ballot_id) in ballots {
-            let ballot_id = ballot_id.expect(
-                "error: the query should always
-                 return at least one ballot for each room (no records should be
-                 returned if there are no ballots)",
-            );
+        let mut ret = Vec::with_capacity(rooms.len());
 
-            ret.push(BallotRepr::of_id(ballot_id, conn)?);
+        for room in rooms {
+            if let Some(ballot) = room.canonical_ballot(conn)? {
+                ret.push(ballot);
+            }
         }
 
         Ok(ret)
@@ -143,6 +112,18 @@ impl Spar {
     }
 }
 
+#[derive(Queryable, Serialize, Debug, Clone)]
+/// A record of a completed spar being reopened.
+pub struct SparReopening {
+    pub id: i64,
+    pub public_id: String,
+    pub spar_id: i64,
+    /// The user who reopened the spar.
+    pub reopened_by: i64,
+    pub reason: String,
+    pub created_at: NaiveDateTime,
+}
+
 #[derive(Debug, Queryable, Serialize, Clone, Arbitrary, DefaultMutator)]
 pub struct SparSignup {
     pub id: i64,
@@ -201,7 +182,7 @@ pub struct SparRoomTeam {
 
 use crate::ballot::BallotRepr;
 use crate::room::SparRoomRepr;
-use crate::schema::{adjudicator_ballots, spar_rooms, spar_series_members};
+use crate::schema::{spar_rooms, spar_series_members};
 
 #[derive(
     Queryable,
@@ -228,6 +209,29 @@ pub struct SparSeriesMember {
     pub spar_series_id: i64,
     #[field_mutator(NaiveDateTimeMutator = { naive_date_time_mutator() })]
     pub created_at: NaiveDateTime,
+    /// The group-level identity this member has been linked to (if any). All
+    /// members sharing an identity are the same person, which allows their
+    /// rating to be carried over between spar series.
+    pub identity_id: Option<i64>,
+    /// The user account this member has been linked to (if any). Linked
+    /// members can submit their ballots while logged in, rather than through
+    /// the link they are emailed.
+    pub user_id: Option<i64>,
+    /// Whether this member still takes part in the series. Inactive members
+    /// cannot sign up for spars, but their past signups, draws and ballots
+    /// are kept.
+    pub is_active: bool,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone)]
+/// A request (made by someone who is not yet a member) to join a spar series.
+pub struct SparSeriesJoinRequest {
+    pub id: i64,
+    pub public_id: String,
+    pub name: String,
+    pub email: String,
+    pub spar_series_id: i64,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(
@@ -256,7 +260,8 @@ pub struct SparRoomAdjudicator {
     pub public_id: String,
     pub member_id: i64,
     pub room_id: i64,
-    // one of "chair", "panelist", "trainee"
+    // one of "chair", "panellist", "trainee" (see [`crate::room::CHAIR`] and
+    // [`crate::room::PANELLIST`])
     pub status: String,
 }
 
@@ -1,18 +1,34 @@
 use db::{
     group::Group,
-    schema::{group_members, groups},
+    schema::{
+        group_members, groups, spar_adjudicators, spar_rooms, spar_series,
+        spar_series_members, spars,
+    },
+    spar::{Spar, SparSeries, SparSeriesMember},
     user::User,
     DbConn,
 };
 use diesel::prelude::*;
 use diesel::{Connection, QueryDsl};
 use maud::Markup;
+use rocket::{
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
 use tracing::Instrument;
 
-use crate::{html::page_of_body, request_ids::TracingSpan};
+use crate::{
+    html::page_of_body_and_flash_msg, request_ids::TracingSpan, util::tx,
+};
 
 #[get("/user")]
-pub async fn account_page(user: User, db: DbConn, span: TracingSpan) -> Markup {
+pub async fn account_page(
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Markup {
+    let msg = msg.map(|msg| msg.message().to_string());
     let span1 = span.0.clone();
     db.run(move |conn| {
         let _guard = span1.enter();
@@ -23,6 +39,48 @@ pub async fn account_page(user: User, db: DbConn, span: TracingSpan) -> Markup {
                 .select(groups::all_columns)
                 .load::<Group>(conn)?;
 
+            let linked_members = spar_series_members::table
+                .filter(spar_series_members::user_id.eq(user.id))
+                .inner_join(spar_series::table)
+                .select((
+                    spar_series_members::all_columns,
+                    spar_series::all_columns,
+                ))
+                .load::<(SparSeriesMember, SparSeries)>(conn)?;
+
+            // members can only be linked to an account with the same
+            // (verified) email address
+            let linkable_members = if user.email_verified {
+                spar_series_members::table
+                    .filter(spar_series_members::email.eq(&user.email))
+                    .filter(spar_series_members::user_id.is_null())
+                    .inner_join(spar_series::table)
+                    .select((
+                        spar_series_members::all_columns,
+                        spar_series::all_columns,
+                    ))
+                    .load::<(SparSeriesMember, SparSeries)>(conn)?
+            } else {
+                vec![]
+            };
+
+            let adjudicating = spar_adjudicators::table
+                .inner_join(spar_series_members::table)
+                .filter(spar_series_members::user_id.eq(user.id))
+                .inner_join(
+                    spar_rooms::table
+                        .inner_join(spars::table.inner_join(spar_series::table)),
+                )
+                .filter(spars::release_draw.eq(true))
+                .filter(spars::is_complete.eq(false))
+                .order_by(spars::start_time.asc())
+                .select((
+                    spar_rooms::public_id,
+                    spars::all_columns,
+                    spar_series::title,
+                ))
+                .load::<(String, Spar, String)>(conn)?;
+
             let markup = maud::html!(
                 h1 {"Hello " (user.username.clone().unwrap_or("Unnamed user".to_string()))}
 
@@ -39,6 +97,82 @@ pub async fn account_page(user: User, db: DbConn, span: TracingSpan) -> Markup {
                     }
                 }
 
+                @if !adjudicating.is_empty() {
+                    h3 {"Rooms you are adjudicating"}
+                    table class="table" {
+                        thead {
+                            tr {
+                                th scope="col" {"Spar series"}
+                                th scope="col" {"Spar"}
+                                th scope="col" {"Ballot"}
+                            }
+                        }
+                        tbody {
+                            @for (room_id, spar, series_title) in &adjudicating {
+                                tr {
+                                    th scope="row" {(series_title)}
+                                    td {(spar.start_time.format("%Y-%m-%d %H:%M"))}
+                                    td {a href=(format!("/rooms/{room_id}/ballots/submit")) {"Submit ballot"}}
+                                }
+                            }
+                        }
+                    }
+                }
+
+                h3 {"My spar series"}
+                @if linked_members.is_empty() {
+                    p class="text-muted" {
+                        "Your account is not linked to any spar series. Once it
+                         is, you can submit ballots from this page rather than
+                         by using the link you are emailed."
+                    }
+                } @else {
+                    table class="table" {
+                        thead {
+                            tr {
+                                th scope="col" {"Spar series"}
+                                th scope="col" {"Member name"}
+                                th scope="col" {"Actions"}
+                            }
+                        }
+                        tbody {
+                            @for (member, series) in &linked_members {
+                                tr {
+                                    th scope="row" {(series.title)}
+                                    td {(member.name)}
+                                    td {
+                                        form method="post" action=(format!("/user/members/{}/unlink", member.public_id)) {
+                                            button type="submit" class="btn btn-sm btn-outline-danger" {"Unlink"}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                @if !linkable_members.is_empty() {
+                    p {
+                        "The following spar series have a member with your
+                         email address. Link them to your account to submit
+                         your ballots while logged in."
+                    }
+                    ul {
+                        @for (member, series) in &linkable_members {
+                            li {
+                                (series.title) " (as " (member.name) ") "
+                                form method="post" class="d-inline" action=(format!("/user/members/{}/link", member.public_id)) {
+                                    button type="submit" class="btn btn-sm btn-primary" {"Link"}
+                                }
+                            }
+                        }
+                    }
+                } @else if !user.email_verified {
+                    p class="text-muted" {
+                        "Verify your email address to link your account to the
+                         spar series you are a member of."
+                    }
+                }
+
                 h3 {"My groups"}
                 table class="table" {
                     thead {
@@ -58,10 +192,84 @@ pub async fn account_page(user: User, db: DbConn, span: TracingSpan) -> Markup {
                 }
             );
 
-            Ok(page_of_body(markup, Some(user)))
+            Ok(page_of_body_and_flash_msg(markup, msg, Some(user)))
         })
         .unwrap()
     })
     .instrument(span.0)
     .await
 }
+
+#[post("/user/members/<member_id>/link")]
+/// Links a spar series member to the current user. This is only permitted if
+/// the member has the same email address as the (verified) email address of
+/// the user.
+pub async fn do_link_member_to_account(
+    member_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Flash<Redirect> {
+    let member_id = member_id.to_string();
+    tx(span, db, move |conn| {
+        let redirect_to = Redirect::to("/user");
+
+        if !user.email_verified {
+            return Flash::error(
+                redirect_to,
+                "Error: please verify your email address first.",
+            );
+        }
+
+        let n = diesel::update(
+            spar_series_members::table
+                .filter(spar_series_members::public_id.eq(&member_id))
+                .filter(spar_series_members::email.eq(&user.email))
+                .filter(spar_series_members::user_id.is_null()),
+        )
+        .set(spar_series_members::user_id.eq(user.id))
+        .execute(conn)
+        .unwrap();
+
+        if n == 1 {
+            Flash::success(
+                redirect_to,
+                "Linked the spar series to your account.",
+            )
+        } else {
+            Flash::error(
+                redirect_to,
+                "Error: that spar series member cannot be linked to your \
+                 account.",
+            )
+        }
+    })
+    .await
+}
+
+#[post("/user/members/<member_id>/unlink")]
+/// Unlinks a spar series member from the current user.
+pub async fn do_unlink_member_from_account(
+    member_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Flash<Redirect> {
+    let member_id = member_id.to_string();
+    tx(span, db, move |conn| {
+        diesel::update(
+            spar_series_members::table
+                .filter(spar_series_members::public_id.eq(&member_id))
+                .filter(spar_series_members::user_id.eq(user.id)),
+        )
+        .set(spar_series_members::user_id.eq(None::<i64>))
+        .execute(conn)
+        .unwrap();
+
+        Flash::success(
+            Redirect::to("/user"),
+            "Unlinked the spar series from your account.",
+        )
+    })
+    .await
+}
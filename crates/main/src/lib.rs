@@ -2,7 +2,9 @@
 
 use std::collections::HashMap;
 
-use accounts::account_page;
+use accounts::{
+    account_page, do_link_member_to_account, do_unlink_member_from_account,
+};
 use admin::{
     config::{config_page, do_upsert_config, edit_existing_config_item_page},
     invite::{
@@ -55,15 +57,28 @@ use spar_generation::{
         results_of_spar_page, results_of_spar_series_page,
     },
     individual_spars::{
-        admin_overview::{set_is_open, single_spar_overview_for_admin_page},
+        admin_overview::{
+            do_make_chair, set_is_open, single_spar_overview_for_admin_page,
+        },
         draw_management::{
+            candidates::draw_candidates_page,
             confirm_draft::{confirm_draw_page, do_confirm_draw},
+            diff::draw_diff_page,
             draft_management::{do_edit_draw, view_draft_draw},
             edit::show_draw_to_admin_page,
+            editor::do_edit_draw_rooms,
             generate::generate_draw,
+            history::{
+                do_redo_draft_edit, do_restore_draft_version,
+                do_undo_draft_edit,
+            },
             release::do_release_draw,
         },
         participant_overview::single_spar_overview_for_participants_page,
+        room_ballots::{
+            admin_enter_ballot_page, do_admin_enter_ballot, do_pin_ballot,
+            room_ballots_page,
+        },
         signup_routes::{
             do_register_for_spar, do_spar_signup_search,
             register_for_spar_page, spar_signup_search_page,
@@ -71,17 +86,48 @@ use spar_generation::{
     },
     spar_series::admin_routes::{
         add_member_page, do_add_member, do_make_session, internal_page,
-        make_session_page, set_member_email, set_member_email_page,
+        make_session_page, set_member_active, set_member_email,
+        set_member_email_page,
     },
 };
 use spar_generation::{
-    ballots::{do_submit_ballot, submit_ballot_page, view_ballot},
+    ballots::{
+        do_submit_ballot, do_submit_ballot_as_user, submit_ballot_as_user_page,
+        submit_ballot_page, view_ballot,
+    },
+    exports::{export_series, export_spar},
+    feedback::{
+        adjudicator_feedback_page, do_submit_feedback, feedback_history_page,
+        submit_feedback_page,
+    },
+    individual_spars::adjust_draw::{adjust_draw_page, do_adjust_draw},
+    individual_spars::ballot_links::{
+        ballot_links_page, do_extend_ballot_links, do_resend_ballot_link,
+        do_rotate_ballot_link,
+    },
+    individual_spars::ballot_status::{
+        do_remind_judges, run_periodic_ballot_tasks,
+    },
     individual_spars::complete_spar::do_mark_spar_complete,
+    individual_spars::printing::{print_ballots, print_draw},
+    individual_spars::projector::{projector_draw, projector_page},
+    individual_spars::reopen_spar::do_reopen_spar,
     spar_series::admin_routes::{
         approve_join_request, do_request2join_spar_series, join_requests_page,
         member_overview_page, request2join_spar_series_page,
         spar_series_member_overview,
     },
+    spar_series::ballot_settings::{
+        ballot_settings_page, do_set_ballot_settings,
+    },
+    spar_series::identities::{
+        do_link_members, do_unlink_member, member_identities_page,
+    },
+    spar_series::import_members::{
+        do_import_members, import_members_page, preview_import_members,
+    },
+    spar_series::merge_members::{do_merge_member, merge_member_page},
+    spar_series::score_rules::{do_set_score_rules, score_rules_page},
 };
 
 pub mod accounts;
@@ -305,6 +351,16 @@ pub fn make_rocket(default_db: &str) -> Rocket<Build> {
                 Err(_) => Err(rocket),
             }
         }))
+        .attach(AdHoc::on_liftoff("periodic ballot tasks", |rocket| {
+            Box::pin(async move {
+                #[allow(unexpected_cfgs)]
+                let fuzzing = cfg!(fuzzing);
+                if !fuzzing {
+                    let pool = DbConn::pool(rocket).unwrap().clone();
+                    rocket::tokio::spawn(run_periodic_ballot_tasks(pool));
+                }
+            })
+        }))
         .mount(
             "/",
             routes![
@@ -340,7 +396,11 @@ pub fn make_rocket(default_db: &str) -> Rocket<Build> {
                 single_spar_overview_for_participants_page,
                 submit_ballot_page,
                 do_submit_ballot,
+                submit_ballot_as_user_page,
+                do_submit_ballot_as_user,
                 account_page,
+                do_link_member_to_account,
+                do_unlink_member_from_account,
                 view_ballot,
                 results_of_spar_series_page,
                 results_of_spar_page,
@@ -368,13 +428,55 @@ pub fn make_rocket(default_db: &str) -> Rocket<Build> {
                 do_set_password,
                 set_member_email_page,
                 set_member_email,
+                set_member_active,
                 confirm_draw_page,
                 do_confirm_draw,
                 view_draft_draw,
                 generate_draw,
                 do_edit_draw,
+                do_edit_draw_rooms,
+                draw_diff_page,
+                draw_candidates_page,
+                do_undo_draft_edit,
+                do_redo_draft_edit,
+                do_restore_draft_version,
                 break_slides_page,
-                do_gen_break_slides
+                do_gen_break_slides,
+                member_identities_page,
+                do_link_members,
+                do_unlink_member,
+                do_make_chair,
+                room_ballots_page,
+                do_pin_ballot,
+                admin_enter_ballot_page,
+                do_admin_enter_ballot,
+                score_rules_page,
+                do_set_score_rules,
+                submit_feedback_page,
+                do_submit_feedback,
+                adjudicator_feedback_page,
+                feedback_history_page,
+                ballot_links_page,
+                do_resend_ballot_link,
+                do_rotate_ballot_link,
+                do_extend_ballot_links,
+                do_remind_judges,
+                ballot_settings_page,
+                do_set_ballot_settings,
+                do_reopen_spar,
+                adjust_draw_page,
+                do_adjust_draw,
+                projector_page,
+                projector_draw,
+                print_draw,
+                print_ballots,
+                export_spar,
+                export_series,
+                import_members_page,
+                preview_import_members,
+                do_import_members,
+                merge_member_page,
+                do_merge_member
             ],
         )
         .attach(RequestIdFairing)
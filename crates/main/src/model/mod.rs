@@ -492,4 +492,102 @@ mod regressions {
         .unwrap();
         (runner)(&actions)
     }
+
+    #[test]
+    fn merge_members_signed_up_to_same_spar() {
+        let runner = make_test_runner();
+        let actions = serde_json::from_str(
+            r#"
+            [
+              {
+                "Setup": {
+                  "id": 0,
+                  "public_id": "",
+                  "username": "UcIf",
+                  "email": "r@e.com",
+                  "email_verified": false,
+                  "password_hash": "person0",
+                  "created_at": "1974-07-22T10:28:52",
+                  "is_superuser": true,
+                  "may_create_resources": false
+                }
+              },
+              { "Login": 0 },
+              {
+                "CreateGroup": {
+                  "id": 0,
+                  "public_id": "",
+                  "name": "perso1",
+                  "website": null,
+                  "created_at": "1905-10-22T01:06:56"
+                }
+              },
+              {
+                "CreateSparSeries": {
+                  "id": 0,
+                  "public_id": "",
+                  "title": "Iuf",
+                  "description": null,
+                  "speakers_per_team": 2,
+                  "group_id": 0,
+                  "created_at": "2016-10-27T01:31:51",
+                  "allow_join_requests": true,
+                  "auto_approve_join_requests": false
+                }
+              },
+              {
+                "AddMember": {
+                  "id": 0,
+                  "public_id": "",
+                  "name": "Alice",
+                  "email": "alice@example.com",
+                  "spar_series_id": 0,
+                  "created_at": "1974-10-05T00:11:03"
+                }
+              },
+              {
+                "AddMember": {
+                  "id": 0,
+                  "public_id": "",
+                  "name": "Alicia",
+                  "email": "alicia@example.com",
+                  "spar_series_id": 0,
+                  "created_at": "1974-10-05T00:11:03"
+                }
+              },
+              {
+                "CreateSpar": {
+                  "id": 0,
+                  "public_id": "",
+                  "start_time": "2030-01-01T18:00:00",
+                  "is_open": true,
+                  "release_draw": false,
+                  "spar_series_id": 0,
+                  "is_complete": false,
+                  "created_at": "2029-12-01T00:00:00"
+                }
+              },
+              {
+                "Signup": {
+                  "member_idx": 0,
+                  "spar_idx": 0,
+                  "as_judge": false,
+                  "as_speaker": true
+                }
+              },
+              {
+                "Signup": {
+                  "member_idx": 1,
+                  "spar_idx": 0,
+                  "as_judge": true,
+                  "as_speaker": false
+                }
+              },
+              { "MergeMembers": { "from": 1, "into": 0 } }
+            ]
+            "#,
+        )
+        .unwrap();
+        (runner)(&actions)
+    }
 }
@@ -5,6 +5,7 @@ use chrono::Utc;
 use db::{
     ballot::{
         AdjudicatorBallot, AdjudicatorBallotEntry, AdjudicatorBallotLink,
+        Score, SOURCE_LINK,
     },
     group::{Group, GroupMember},
     schema::users,
@@ -31,7 +32,12 @@ use crate::{
     spar_generation::{
         ballots::BpBallotForm,
         individual_spars::signup_routes::SignupForSpar,
-        spar_series::admin_routes::{AddMemberForm, MakeSessionForm},
+        spar_series::{
+            admin_routes::{
+                AddMemberForm, MakeSessionForm, SetMemberActiveForm,
+            },
+            merge_members::MergeMembersForm,
+        },
     },
 };
 
@@ -93,8 +99,14 @@ impl State {
         conn.transaction(|conn| -> Result<_, diesel::result::Error> {
             diesel::delete(db::schema::adjudicator_ballots::table)
                 .execute(conn)?;
+            diesel::delete(db::schema::adjudicator_feedback::table)
+                .execute(conn)?;
+            diesel::delete(db::schema::speaker_feedback_links::table)
+                .execute(conn)?;
             diesel::delete(db::schema::adjudicator_ballot_entries::table)
                 .execute(conn)?;
+            diesel::delete(db::schema::adjudicator_ballot_team_comments::table)
+                .execute(conn)?;
             diesel::delete(db::schema::spar_adjudicator_ballot_links::table)
                 .execute(conn)?;
             diesel::delete(db::schema::spar_speakers::table).execute(conn)?;
@@ -104,9 +116,16 @@ impl State {
             diesel::delete(db::schema::spar_teams::table).execute(conn)?;
             diesel::delete(db::schema::spar_rooms::table).execute(conn)?;
             diesel::delete(db::schema::spar_signups::table).execute(conn)?;
+            diesel::delete(db::schema::spar_reopenings::table).execute(conn)?;
             diesel::delete(db::schema::spars::table).execute(conn)?;
             diesel::delete(db::schema::spar_series_members::table)
                 .execute(conn)?;
+            diesel::delete(db::schema::group_member_identities::table)
+                .execute(conn)?;
+            diesel::delete(db::schema::spar_series_score_rules::table)
+                .execute(conn)?;
+            diesel::delete(db::schema::spar_series_ballot_settings::table)
+                .execute(conn)?;
             diesel::delete(db::schema::spar_series::table).execute(conn)?;
             diesel::delete(db::schema::group_members::table).execute(conn)?;
             diesel::delete(db::schema::groups::table).execute(conn)?;
@@ -328,6 +347,7 @@ impl State {
             assert_eq!(member.public_id, db_member.public_id);
             assert_eq!(member.name, db_member.name);
             assert_eq!(member.email, db_member.email);
+            assert_eq!(member.is_active, db_member.is_active);
         }
     }
 
@@ -812,7 +832,7 @@ impl State {
                 }
                 let member = member.unwrap();
 
-                if !spar.is_open {
+                if !spar.is_open || !member.is_active {
                     return;
                 }
 
@@ -941,6 +961,9 @@ impl State {
                     adjudicator_id: adj.id,
                     room_id: room.id,
                     created_at: Utc::now().naive_utc(),
+                    source: SOURCE_LINK.to_string(),
+                    submitted_by: None,
+                    is_pinned: false,
                 });
 
                 let entries = [
@@ -961,8 +984,9 @@ impl State {
                         ballot_id,
                         speaker_id: speaker.id,
                         team_id,
-                        speak: score,
+                        speak: Score::of_half_points(score),
                         position,
+                        comments: None,
                     });
                 }
             }
@@ -1004,8 +1028,164 @@ impl State {
                 member.spar_series_id = spar_series.id;
                 member.id = self.spar_series_members.len() as i64;
                 member.public_id = last_id().unwrap().to_string();
+                member.identity_id = None;
+                member.user_id = None;
+                member.is_active = true;
                 self.spar_series_members.push(member);
             }
+            Action::SetMemberActive {
+                member_idx,
+                is_active,
+            } => {
+                let user = match &self.active_user {
+                    Some(u) => u,
+                    None => return,
+                };
+
+                let member_idx = (*member_idx)
+                    .clamp(0, self.spar_series_members.len().saturating_sub(1));
+                let member = match self.spar_series_members.get(member_idx) {
+                    Some(m) => m,
+                    None => return,
+                };
+
+                let series = &self.spar_series[member.spar_series_id as usize];
+                let group = &self.groups[series.group_id as usize];
+                let membership = match self
+                    .group_members
+                    .get(&(user.id as usize, group.clone()))
+                {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !membership.is_admin && !membership.is_superuser {
+                    return;
+                }
+
+                self.spar_series_members[member_idx].is_active = *is_active;
+            }
+            Action::MergeMembers { from, into } => {
+                let user = match &self.active_user {
+                    Some(u) => u,
+                    None => return,
+                };
+
+                let n = self.spar_series_members.len();
+                let from_idx = (*from).clamp(0, n.saturating_sub(1));
+                let into_idx = (*into).clamp(0, n.saturating_sub(1));
+                if from_idx == into_idx {
+                    return;
+                }
+                let from_member = &self.spar_series_members[from_idx];
+                let into_member = &self.spar_series_members[into_idx];
+                if from_member.spar_series_id != into_member.spar_series_id {
+                    return;
+                }
+
+                let series =
+                    &self.spar_series[from_member.spar_series_id as usize];
+                let group = &self.groups[series.group_id as usize];
+                let membership = match self
+                    .group_members
+                    .get(&(user.id as usize, group.clone()))
+                {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !membership.is_admin && !membership.is_superuser {
+                    return;
+                }
+
+                // members who are both in the draw for the same spar cannot
+                // be merged
+                let spars_in_draw = |member_id: i64| {
+                    let spar_of_room =
+                        |room_id: i64| self.rooms[room_id as usize].spar_id;
+                    self.speakers
+                        .iter()
+                        .filter(|speaker| speaker.member_id == member_id)
+                        .map(|speaker| {
+                            spar_of_room(
+                                self.teams[speaker.team_id as usize].room_id,
+                            )
+                        })
+                        .chain(
+                            self.adjs
+                                .iter()
+                                .filter(|adj| adj.member_id == member_id)
+                                .map(|adj| spar_of_room(adj.room_id)),
+                        )
+                        .collect::<HashSet<_>>()
+                };
+                if !spars_in_draw(from_member.id)
+                    .is_disjoint(&spars_in_draw(into_member.id))
+                {
+                    return;
+                }
+
+                let from_id = from_member.id;
+                let into_id = into_member.id;
+
+                let from_signups = self
+                    .spar_signups
+                    .iter()
+                    .filter(|signup| signup.member_id == from_id)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                self.spar_signups
+                    .retain(|signup| signup.member_id != from_id);
+                for signup in from_signups {
+                    match self.spar_signups.iter_mut().find(|existing| {
+                        existing.member_id == into_id
+                            && existing.spar_id == signup.spar_id
+                    }) {
+                        Some(existing) => {
+                            existing.as_judge |= signup.as_judge;
+                            existing.as_speaker |= signup.as_speaker;
+                            existing.partner_preference = existing
+                                .partner_preference
+                                .or(signup.partner_preference);
+                        }
+                        None => self.spar_signups.push(SparSignup {
+                            member_id: into_id,
+                            ..signup
+                        }),
+                    }
+                }
+
+                // the member ids are indices into `spar_series_members`, so
+                // everything after the removed member moves down by one
+                let reindex = |id: i64| {
+                    let id = if id == from_id { into_id } else { id };
+                    if id > from_id {
+                        id - 1
+                    } else {
+                        id
+                    }
+                };
+                self.spar_series_members.remove(from_idx);
+                for (i, member) in
+                    self.spar_series_members.iter_mut().enumerate()
+                {
+                    member.id = i as i64;
+                }
+                for signup in &mut self.spar_signups {
+                    signup.member_id = reindex(signup.member_id);
+                    signup.partner_preference = signup
+                        .partner_preference
+                        .map(reindex)
+                        .filter(|partner| *partner != signup.member_id);
+                }
+                for speaker in &mut self.speakers {
+                    speaker.member_id = reindex(speaker.member_id);
+                }
+                for adj in &mut self.adjs {
+                    adj.member_id = reindex(adj.member_id);
+                }
+                for link in &mut self.ballot_links {
+                    link.member_id = reindex(link.member_id);
+                }
+            }
             Action::ReleaseDraw(spar_idx) => {
                 let user_opt = &self.active_user;
                 if user_opt.is_none() {
@@ -1269,21 +1449,41 @@ impl State {
                         let ballot = BpBallotForm {
                             force: true,
                             pm: resolve_public_id(&ballot.pm),
-                            pm_score: ballot.pm_score,
+                            pm_score: Score::of_half_points(ballot.pm_score),
                             dpm: resolve_public_id(&ballot.dpm),
-                            dpm_score: ballot.dpm_score,
+                            dpm_score: Score::of_half_points(ballot.dpm_score),
                             lo: resolve_public_id(&ballot.lo),
-                            lo_score: ballot.lo_score,
+                            lo_score: Score::of_half_points(ballot.lo_score),
                             dlo: resolve_public_id(&ballot.dlo),
-                            dlo_score: ballot.dlo_score,
-                            mg: resolve_public_id(&ballot.mg),
-                            mg_score: ballot.mg_score,
-                            gw: resolve_public_id(&ballot.gw),
-                            gw_score: ballot.gw_score,
-                            mo: resolve_public_id(&ballot.mo),
-                            mo_score: ballot.mo_score,
-                            ow: resolve_public_id(&ballot.ow),
-                            ow_score: ballot.ow_score,
+                            dlo_score: Score::of_half_points(ballot.dlo_score),
+                            mg: Some(resolve_public_id(&ballot.mg)),
+                            mg_score: Some(Score::of_half_points(
+                                ballot.mg_score,
+                            )),
+                            gw: Some(resolve_public_id(&ballot.gw)),
+                            gw_score: Some(Score::of_half_points(
+                                ballot.gw_score,
+                            )),
+                            mo: Some(resolve_public_id(&ballot.mo)),
+                            mo_score: Some(Score::of_half_points(
+                                ballot.mo_score,
+                            )),
+                            ow: Some(resolve_public_id(&ballot.ow)),
+                            ow_score: Some(Score::of_half_points(
+                                ballot.ow_score,
+                            )),
+                            pm_comments: None,
+                            dpm_comments: None,
+                            lo_comments: None,
+                            dlo_comments: None,
+                            mg_comments: None,
+                            gw_comments: None,
+                            mo_comments: None,
+                            ow_comments: None,
+                            og_comments: None,
+                            oo_comments: None,
+                            cg_comments: None,
+                            co_comments: None,
                         };
                         self.client
                             .post(format!("/ballots/{}/submit", key.link))
@@ -1313,6 +1513,65 @@ impl State {
                         .dispatch();
                 }
             }
+            Action::SetMemberActive {
+                member_idx,
+                is_active,
+            } => {
+                let member_idx = (*member_idx)
+                    .clamp(0, self.spar_series_members.len().saturating_sub(1));
+                if let Some(member) = self.spar_series_members.get(member_idx) {
+                    if let Some(series) =
+                        self.spar_series.get(member.spar_series_id as usize)
+                    {
+                        self.client
+                            .post(format!(
+                                "/spar_series/{}/members/{}/set_active",
+                                series.public_id, member.public_id
+                            ))
+                            .header(ContentType::Form)
+                            .body(
+                                serde_urlencoded::to_string(
+                                    &SetMemberActiveForm {
+                                        is_active: *is_active,
+                                    },
+                                )
+                                .unwrap(),
+                            )
+                            .dispatch();
+                    }
+                }
+            }
+            Action::MergeMembers { from, into } => {
+                let n = self.spar_series_members.len();
+                let from_idx = (*from).clamp(0, n.saturating_sub(1));
+                let into_idx = (*into).clamp(0, n.saturating_sub(1));
+                if let (Some(from), Some(into)) = (
+                    self.spar_series_members.get(from_idx),
+                    self.spar_series_members.get(into_idx),
+                ) {
+                    // the model does not store the public ids of the
+                    // series, so we look up the series by index
+                    if let Some(series) =
+                        self.spar_series.get(from.spar_series_id as usize)
+                    {
+                        self.client
+                            .post(format!(
+                                "/spar_series/{}/members/{}/merge",
+                                series.public_id, from.public_id
+                            ))
+                            .header(ContentType::Form)
+                            .body(
+                                serde_urlencoded::to_string(
+                                    &MergeMembersForm {
+                                        into: into.public_id.clone(),
+                                    },
+                                )
+                                .unwrap(),
+                            )
+                            .dispatch();
+                    }
+                }
+            }
             Action::SetSparIsOpen { spar, state } => {
                 let spar_idx =
                     (*spar).clamp(0, self.spars.len().saturating_sub(1));
@@ -1389,6 +1648,26 @@ pub enum Action {
     /// Submit a ballot in the nth room. Requires that the logged in user is
     /// allocated as a judge for that room.
     SubmitBallot(FuzzerBpBallotForm, usize, usize),
+    /// Mark the nth spar series member as active or inactive.
+    SetMemberActive {
+        #[field_mutator(
+            UsizeWithinRangeMutator = { usize_within_range_mutator(0..10) }
+        )]
+        member_idx: usize,
+        is_active: bool,
+    },
+    /// Merge the `from`th member of a spar series into the `into`th member
+    /// (both must be members of the same series).
+    MergeMembers {
+        #[field_mutator(
+            UsizeWithinRangeMutator = { usize_within_range_mutator(0..10) }
+        )]
+        from: usize,
+        #[field_mutator(
+            UsizeWithinRangeMutator = { usize_within_range_mutator(0..10) }
+        )]
+        into: usize,
+    },
     SetSparIsOpen {
         // todo: weightedusizemutator which
         #[field_mutator(
@@ -1400,6 +1679,8 @@ pub enum Action {
 }
 
 #[derive(Debug, DefaultMutator, Clone, Serialize, Deserialize, Arbitrary)]
+/// A ballot, where the speakers are indices into the list of speakers, and the
+/// scores are a whole number of half points (see [`db::ballot::Score`]).
 pub struct FuzzerBpBallotForm {
     pub pm: usize,
     pub pm_score: i64,
@@ -1,7 +1,16 @@
-use db::{group::GroupMember, schema::group_members, user::User};
-use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
+use db::{
+    group::GroupMember,
+    schema::{
+        group_members, spar_adjudicators, spar_rooms, spar_series_members,
+        spars,
+    },
+    user::User,
+};
+use diesel::{
+    connection::LoadConnection, dsl::exists, prelude::*, select, sqlite::Sqlite,
+};
 
-use crate::resources::GroupRef;
+use crate::resources::{GroupRef, SparRoomRef};
 
 #[derive(Debug)]
 /// A permission for a given resource on the system.
@@ -14,6 +23,9 @@ pub enum Permission {
     RegisterAsNewUser,
     /// Edit the site-wide configuration.
     ModifyGlobalConfig,
+    /// Submit a ballot for a room (as one of the adjudicators of that room),
+    /// without using the link that was emailed to the adjudicator.
+    SubmitBallotForRoom(SparRoomRef),
 }
 
 /// Returns whether a requester has the requisite permission on the given
@@ -44,9 +56,39 @@ pub fn has_permission(
             Some(user) => user.is_superuser,
             None => false,
         },
+        Permission::SubmitBallotForRoom(SparRoomRef(room_id)) => {
+            check_submit_ballot_for_room(user, conn, room_id)
+        }
     }
 }
 
+/// Users may submit a ballot for a room if their account is linked to one of
+/// the adjudicators in that room, and the draw has been released (but the spar
+/// has not yet been completed).
+#[tracing::instrument(skip(conn))]
+fn check_submit_ballot_for_room(
+    user: Option<&User>,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+    room_id: &i64,
+) -> bool {
+    let user = match user {
+        Some(user) => user,
+        None => return false,
+    };
+
+    select(exists(
+        spar_adjudicators::table
+            .filter(spar_adjudicators::room_id.eq(room_id))
+            .inner_join(spar_series_members::table)
+            .filter(spar_series_members::user_id.eq(user.id))
+            .inner_join(spar_rooms::table.inner_join(spars::table))
+            .filter(spars::release_draw.eq(true))
+            .filter(spars::is_complete.eq(false)),
+    ))
+    .get_result::<bool>(conn)
+    .unwrap()
+}
+
 #[tracing::instrument(skip(conn))]
 fn check_if_registrations_are_open(
     conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
@@ -1,5 +1,6 @@
 //! Algorithms for spar allocation.
 
+pub mod presets;
 pub mod ratings;
 pub mod results;
 pub mod solve_allocation;
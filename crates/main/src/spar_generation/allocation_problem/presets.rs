@@ -0,0 +1,209 @@
+//! Weight presets used to generate several candidate draws, and the metrics
+//! used to compare them.
+
+use std::collections::HashMap;
+
+use db::{
+    draft_draw::{DraftDrawData, Team},
+    spar::SparSignup,
+};
+
+use crate::spar_generation::allocation_problem::solve_allocation::ObjectiveWeights;
+
+pub struct DrawPreset {
+    /// Stored in `draft_draw_candidates.preset`, so this should not be
+    /// changed.
+    pub name: &'static str,
+    pub description: &'static str,
+    pub weights: ObjectiveWeights,
+}
+
+/// The presets used to generate candidate draws (one candidate is generated
+/// for each preset).
+pub static DRAW_PRESETS: [DrawPreset; 3] = [
+    DrawPreset {
+        name: "balanced",
+        description: "The default weights.",
+        weights: ObjectiveWeights::DEFAULT,
+    },
+    DrawPreset {
+        name: "even teams",
+        description: "Tries harder to make the teams in each room equally \
+                      strong.",
+        weights: ObjectiveWeights {
+            difference_between_teams: -4.0 * 10.0,
+            ..ObjectiveWeights::DEFAULT
+        },
+    },
+    DrawPreset {
+        name: "even panels",
+        description: "Tries harder to spread judges evenly between rooms.",
+        weights: ObjectiveWeights {
+            judge_penalty: -6.0 * 10.0,
+            ..ObjectiveWeights::DEFAULT
+        },
+    },
+];
+
+pub fn preset_of_name(name: &str) -> Option<&'static DrawPreset> {
+    DRAW_PRESETS.iter().find(|preset| preset.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawMetrics {
+    pub max_team_gap: f64,
+    pub mean_team_gap: f64,
+    pub partner_preferences_honoured: i64,
+    pub partner_preferences_requested: i64,
+}
+
+/// Computes the metrics of a generated draw.
+pub fn metrics_of_draw(
+    data: &DraftDrawData,
+    signups: &HashMap<i64, SparSignup>,
+    elo_scores: &HashMap<i64, f64>,
+) -> DrawMetrics {
+    let mut team_of = HashMap::new();
+    let mut gaps = Vec::new();
+
+    for (room_idx, room) in data.rooms.iter().enumerate() {
+        let averages = Team::ALL
+            .iter()
+            .filter_map(|team| {
+                let speakers = room.teams.get(team)?;
+                for speaker in speakers {
+                    team_of.insert(*speaker, (room_idx, *team));
+                }
+                (!speakers.is_empty()).then(|| {
+                    speakers
+                        .iter()
+                        .map(|speaker| {
+                            elo_scores.get(speaker).copied().unwrap_or(25.0)
+                        })
+                        .sum::<f64>()
+                        / speakers.len() as f64
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let (Some(max), Some(min)) = (
+            averages.iter().copied().max_by(f64::total_cmp),
+            averages.iter().copied().min_by(f64::total_cmp),
+        ) {
+            gaps.push(max - min);
+        }
+    }
+
+    let mut partner_preferences_honoured = 0;
+    let mut partner_preferences_requested = 0;
+    for signup in signups.values() {
+        if let Some(partner) = signup.partner_preference {
+            if !signups.contains_key(&partner) {
+                continue;
+            }
+            partner_preferences_requested += 1;
+            if team_of.get(&signup.member_id).is_some()
+                && team_of.get(&signup.member_id) == team_of.get(&partner)
+            {
+                partner_preferences_honoured += 1;
+            }
+        }
+    }
+
+    DrawMetrics {
+        max_team_gap: gaps.iter().copied().fold(0.0, f64::max),
+        mean_team_gap: if gaps.is_empty() {
+            0.0
+        } else {
+            gaps.iter().sum::<f64>() / gaps.len() as f64
+        },
+        partner_preferences_honoured,
+        partner_preferences_requested,
+    }
+}
+
+#[cfg(test)]
+mod test_metrics {
+    use std::collections::{HashMap, HashSet};
+
+    use db::{
+        draft_draw::{DraftDrawData, DraftDrawRoom, Team},
+        spar::SparSignup,
+    };
+
+    use super::metrics_of_draw;
+
+    /// A room whose judge is `first`, followed by two speakers in each team.
+    fn room(first: i64) -> DraftDrawRoom {
+        DraftDrawRoom {
+            panel: HashSet::from([first]),
+            teams: Team::ALL
+                .into_iter()
+                .enumerate()
+                .map(|(i, team)| {
+                    let a = first + 1 + 2 * i as i64;
+                    (team, HashSet::from([a, a + 1]))
+                })
+                .collect(),
+            chair: None,
+        }
+    }
+
+    fn signup(member_id: i64, partner: Option<i64>) -> (i64, SparSignup) {
+        (
+            member_id,
+            SparSignup {
+                id: member_id,
+                public_id: member_id.to_string(),
+                member_id,
+                spar_id: 0,
+                as_judge: false,
+                as_speaker: true,
+                partner_preference: partner,
+            },
+        )
+    }
+
+    #[test]
+    fn team_gaps_and_partners() {
+        let mut data = DraftDrawData {
+            rooms: vec![room(0), room(100)],
+            id_map: HashMap::new(),
+        };
+        data.generate_map();
+
+        // in the first room, OG averages 30 and OO averages 20 (everyone
+        // else has the default score of 25); the second room is even
+        let elo_scores =
+            HashMap::from([(1, 30.0), (2, 30.0), (3, 20.0), (4, 20.0)]);
+
+        let signups = HashMap::from([
+            // on the same team
+            signup(1, Some(2)),
+            signup(2, None),
+            // on different teams
+            signup(3, Some(5)),
+            signup(5, None),
+            // the partner has not signed up, so this is not counted
+            signup(7, Some(99)),
+        ]);
+
+        let metrics = metrics_of_draw(&data, &signups, &elo_scores);
+        assert_eq!(metrics.max_team_gap, 10.0);
+        assert_eq!(metrics.mean_team_gap, 5.0);
+        assert_eq!(metrics.partner_preferences_requested, 2);
+        assert_eq!(metrics.partner_preferences_honoured, 1);
+    }
+
+    #[test]
+    fn empty_draw() {
+        let data = DraftDrawData {
+            rooms: vec![],
+            id_map: HashMap::new(),
+        };
+        let metrics = metrics_of_draw(&data, &HashMap::new(), &HashMap::new());
+        assert_eq!(metrics.max_team_gap, 0.0);
+        assert_eq!(metrics.mean_team_gap, 0.0);
+        assert_eq!(metrics.partner_preferences_requested, 0);
+    }
+}
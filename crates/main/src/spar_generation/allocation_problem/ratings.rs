@@ -11,13 +11,21 @@
 //! The advantage of not releasing the scores is that it is also possible to
 //! change the rankings algorithm used at any time, without causing a noticeable
 //! difference in perception for the end user.
+//!
+//! Members of different spar series (run by the same group) can be linked to
+//! a single group-level identity, in which case their rating is carried over
+//! (with a widened uncertainty) from one series to the next.
 
 use std::collections::HashMap;
 
+use chrono::NaiveDateTime;
 use db::{
     ballot::BpTeam,
-    schema::{adjudicator_ballots, spar_rooms, spar_series_members, spars},
-    spar::SparRoom,
+    schema::{
+        adjudicator_ballots, spar_rooms, spar_series, spar_series_members,
+        spars,
+    },
+    spar::{SparRoom, SparSeries},
 };
 use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
 use skillratings::{
@@ -25,28 +33,135 @@ use skillratings::{
     MultiTeamOutcome,
 };
 
+/// How much the uncertainty of a rating is widened when it is carried over
+/// from a previous spar series (people improve, or get rusty, between terms).
+const CARRY_OVER_UNCERTAINTY_INCREASE: f64 = 25.0 / 6.0;
+
 /// Compute scores for each player.
+///
+/// Members of this spar series who have been linked to a group-level identity
+/// start from the rating they finished the most recent previous series (run by
+/// the same group) with, rather than from scratch.
 #[tracing::instrument(skip(conn))]
 pub fn compute_scores(
     series_id: i64,
     conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
 ) -> Result<HashMap<i64, f64>, diesel::result::Error> {
     tracing::trace!("Computing ELO scores");
+
+    let series = spar_series::table
+        .filter(spar_series::id.eq(series_id))
+        .first::<SparSeries>(conn)?;
+
+    // all the previous spar series run by this group which have members that
+    // have been linked to an identity, oldest first
+    let previous_series = spar_series::table
+        .inner_join(spar_series_members::table)
+        .filter(spar_series::group_id.eq(series.group_id))
+        .filter(spar_series::id.ne(series.id))
+        .filter(spar_series::created_at.le(series.created_at))
+        .filter(spar_series_members::identity_id.is_not_null())
+        .select((spar_series::created_at, spar_series::id))
+        .distinct()
+        .order_by((spar_series::created_at.asc(), spar_series::id.asc()))
+        .load::<(NaiveDateTime, i64)>(conn)?;
+
+    // maps identity ids to the most recent rating of that person
+    let mut identity_ratings: HashMap<i64, WengLinRating> = HashMap::new();
+    for (_, previous_series_id) in previous_series {
+        let ratings = compute_ratings_for_series(
+            previous_series_id,
+            &identity_ratings,
+            conn,
+        )?;
+
+        let linked_members = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(previous_series_id))
+            .filter(spar_series_members::identity_id.is_not_null())
+            .select((
+                spar_series_members::id,
+                spar_series_members::identity_id.assume_not_null(),
+            ))
+            .load::<(i64, i64)>(conn)?;
+
+        record_identity_ratings(
+            &mut identity_ratings,
+            &ratings,
+            &linked_members,
+        );
+    }
+
+    Ok(
+        compute_ratings_for_series(series.id, &identity_ratings, conn)?
+            .into_iter()
+            .map(|(id, score)| (id, score.rating))
+            .collect(),
+    )
+}
+
+/// Widens the uncertainty of a rating from a previous spar series (this is
+/// never allowed to exceed the uncertainty of a brand new player).
+fn carry_over(rating: WengLinRating) -> WengLinRating {
+    WengLinRating {
+        rating: rating.rating,
+        uncertainty: (rating.uncertainty + CARRY_OVER_UNCERTAINTY_INCREASE)
+            .min(WengLinRating::new().uncertainty),
+    }
+}
+
+/// Records the final rating of each member of a series (`ratings`, which is
+/// keyed by member id) against the identity they are linked to, replacing
+/// the rating from any earlier series.
+fn record_identity_ratings(
+    identity_ratings: &mut HashMap<i64, WengLinRating>,
+    ratings: &HashMap<i64, WengLinRating>,
+    linked_members: &[(i64, i64)],
+) {
+    for (member_id, identity_id) in linked_members {
+        identity_ratings.insert(*identity_id, ratings[member_id]);
+    }
+}
+
+/// The rating a member of a series starts with: a carried over rating if
+/// they are linked to an identity which has one, and otherwise the rating of
+/// a new player.
+fn initial_rating(
+    identity_id: Option<i64>,
+    identity_ratings: &HashMap<i64, WengLinRating>,
+) -> WengLinRating {
+    identity_id
+        .and_then(|identity_id| identity_ratings.get(&identity_id))
+        .map(|rating| carry_over(*rating))
+        .unwrap_or_else(WengLinRating::new)
+}
+
+/// Computes the rating of every member of the given spar series, using the
+/// ratings in `identity_ratings` (which maps identity ids to ratings) as the
+/// starting point for any members who have been linked to an identity.
+fn compute_ratings_for_series(
+    series_id: i64,
+    identity_ratings: &HashMap<i64, WengLinRating>,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<HashMap<i64, WengLinRating>, diesel::result::Error> {
     let rooms_with_results = spar_rooms::table
         .inner_join(spars::table)
         .filter(spars::spar_series_id.eq(series_id))
         .inner_join(adjudicator_ballots::table)
         .order_by(spars::created_at)
         .select(spar_rooms::all_columns)
+        // a room appears once for every ballot submitted by the panel
+        .distinct()
         .load::<SparRoom>(conn)?;
 
     // maps speaker ids (as in the database) to their respective scores
     let mut member_ids_to_scores_map = spar_series_members::table
         .filter(spar_series_members::spar_series_id.eq(series_id))
-        .select(spar_series_members::id)
-        .load::<i64>(conn)?
+        .select((spar_series_members::id, spar_series_members::identity_id))
+        .load::<(i64, Option<i64>)>(conn)?
         .into_iter()
-        .map(|speaker_id| (speaker_id, WengLinRating::new()))
+        .map(|(speaker_id, identity_id)| {
+            (speaker_id, initial_rating(identity_id, identity_ratings))
+        })
         .collect::<HashMap<_, _>>();
 
     for room in rooms_with_results {
@@ -110,7 +225,7 @@ pub fn compute_scores(
 
         let ranking = ballot.bp_ranking();
 
-        let teams_and_ranks = vec![
+        let mut teams_and_ranks = vec![
             (
                 &og[..],
                 MultiTeamOutcome::new(
@@ -129,7 +244,10 @@ pub fn compute_scores(
                         .expect("must have a position for the team og"),
                 ),
             ),
-            (
+        ];
+        // only the opening teams debate in half-rooms
+        if !room_repr.is_half_room() {
+            teams_and_ranks.push((
                 &cg[..],
                 MultiTeamOutcome::new(
                     ranking
@@ -137,8 +255,8 @@ pub fn compute_scores(
                         .position(|t| *t == BpTeam::Cg)
                         .expect("must have a position for the team cg"),
                 ),
-            ),
-            (
+            ));
+            teams_and_ranks.push((
                 &co[..],
                 MultiTeamOutcome::new(
                     ranking
@@ -146,8 +264,8 @@ pub fn compute_scores(
                         .position(|t| *t == BpTeam::Co)
                         .expect("must have a position for the team co"),
                 ),
-            ),
-        ];
+            ));
+        }
 
         let new_teams =
             weng_lin_multi_team(&teams_and_ranks, &WengLinConfig::default());
@@ -186,41 +304,124 @@ pub fn compute_scores(
             }
         };
 
-        let _update_cg = {
-            let new_cg = &new_teams[2];
-            let cg_speakers = &teams[2].speakers;
-            member_ids_to_scores_map.insert(
-                room_repr.speakers[&cg_speakers[0]].member_id,
-                new_cg[0],
-            );
-            if cg_speakers.len() > 1 {
+        if !room_repr.is_half_room() {
+            let _update_cg = {
+                let new_cg = &new_teams[2];
+                let cg_speakers = &teams[2].speakers;
                 member_ids_to_scores_map.insert(
-                    room_repr.speakers[&cg_speakers[1]].member_id,
-                    new_cg[1],
+                    room_repr.speakers[&cg_speakers[0]].member_id,
+                    new_cg[0],
                 );
-            }
-        };
+                if cg_speakers.len() > 1 {
+                    member_ids_to_scores_map.insert(
+                        room_repr.speakers[&cg_speakers[1]].member_id,
+                        new_cg[1],
+                    );
+                }
+            };
 
-        let _update_co = {
-            let new_co = &new_teams[2];
-            let co_speakers = &teams[2].speakers;
-            member_ids_to_scores_map.insert(
-                room_repr.speakers[&co_speakers[0]].member_id,
-                new_co[0],
-            );
-            if co_speakers.len() > 1 {
+            let _update_co = {
+                let new_co = &new_teams[2];
+                let co_speakers = &teams[2].speakers;
                 member_ids_to_scores_map.insert(
-                    room_repr.speakers[&co_speakers[1]].member_id,
-                    new_co[1],
+                    room_repr.speakers[&co_speakers[0]].member_id,
+                    new_co[0],
                 );
-            }
-        };
+                if co_speakers.len() > 1 {
+                    member_ids_to_scores_map.insert(
+                        room_repr.speakers[&co_speakers[1]].member_id,
+                        new_co[1],
+                    );
+                }
+            };
+        }
 
         tracing::trace!("After, scores are {og:?}, {oo:?}, {cg:?}, {co:?}");
     }
 
-    Ok(member_ids_to_scores_map
-        .into_iter()
-        .map(|(id, score)| (id, score.rating))
-        .collect())
+    Ok(member_ids_to_scores_map)
+}
+
+#[cfg(test)]
+mod test_carry_over {
+    use std::collections::HashMap;
+
+    use skillratings::{
+        weng_lin::{weng_lin_multi_team, WengLinConfig, WengLinRating},
+        MultiTeamOutcome,
+    };
+
+    use super::{
+        carry_over, initial_rating, record_identity_ratings,
+        CARRY_OVER_UNCERTAINTY_INCREASE,
+    };
+
+    #[test]
+    fn uncertainty_is_widened() {
+        let rating = WengLinRating {
+            rating: 30.0,
+            uncertainty: 2.0,
+        };
+        let carried = carry_over(rating);
+        assert_eq!(carried.rating, 30.0);
+        assert_eq!(carried.uncertainty, 2.0 + CARRY_OVER_UNCERTAINTY_INCREASE);
+    }
+
+    #[test]
+    fn uncertainty_is_capped() {
+        let new = WengLinRating::new();
+        let rating = WengLinRating {
+            rating: 30.0,
+            uncertainty: new.uncertainty - 1.0,
+        };
+        let carried = carry_over(rating);
+        assert_eq!(carried.rating, 30.0);
+        assert_eq!(carried.uncertainty, new.uncertainty);
+
+        // a new player's rating is unchanged
+        assert_eq!(carry_over(new), new);
+    }
+
+    #[test]
+    fn ratings_follow_identity() {
+        // in the first series, member 1 (linked to identity 100) wins a
+        // debate against member 2 (who is not linked to an identity)
+        let new = WengLinRating::new();
+        let results = weng_lin_multi_team(
+            &[
+                (&[new][..], MultiTeamOutcome::new(1)),
+                (&[new][..], MultiTeamOutcome::new(2)),
+            ],
+            &WengLinConfig::default(),
+        );
+        let first_series =
+            HashMap::from([(1, results[0][0]), (2, results[1][0])]);
+        assert!(first_series[&1].rating > new.rating);
+
+        let mut identity_ratings = HashMap::new();
+        record_identity_ratings(
+            &mut identity_ratings,
+            &first_series,
+            &[(1, 100)],
+        );
+        assert_eq!(identity_ratings, HashMap::from([(100, first_series[&1])]));
+
+        // in the next series, the member linked to identity 100 starts from
+        // where member 1 finished, and everyone else starts from scratch
+        assert_eq!(
+            initial_rating(Some(100), &identity_ratings),
+            carry_over(first_series[&1])
+        );
+        assert_eq!(initial_rating(Some(200), &identity_ratings), new);
+        assert_eq!(initial_rating(None, &identity_ratings), new);
+
+        // a later series replaces the rating of the identity
+        let second_series = HashMap::from([(3, new)]);
+        record_identity_ratings(
+            &mut identity_ratings,
+            &second_series,
+            &[(3, 100)],
+        );
+        assert_eq!(initial_rating(Some(100), &identity_ratings), new);
+    }
 }
@@ -13,12 +13,56 @@ use good_lp::{
 };
 use serde::{Deserialize, Serialize};
 
+/// The weights of each term of the objective function used by
+/// [`solve_lp_with_weights`]. Each weight is multiplied by the maximum number
+/// of rooms (so the weights are independent of the size of the spar).
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectiveWeights {
+    pub judge_penalty: f64,
+    pub difference_between_teams: f64,
+    pub difference_between_speakers: f64,
+    pub fewer_rooms: f64,
+    pub partner_preferences: f64,
+}
+
+impl ObjectiveWeights {
+    pub const DEFAULT: ObjectiveWeights = ObjectiveWeights {
+        // todo: work out what the right multipliers are
+        judge_penalty: -2.0 * 10.0,
+        difference_between_teams: -1.0 * 10.0,
+        difference_between_speakers: 1.0,
+        fewer_rooms: -1.0 * 50.0,
+        // todo: should this grow faster than linear in the number of rooms?
+        partner_preferences: 1000.0,
+    };
+}
+
+impl Default for ObjectiveWeights {
+    fn default() -> Self {
+        ObjectiveWeights::DEFAULT
+    }
+}
+
+/// Solves the allocation problem using the default weights (see
+/// [`ObjectiveWeights::DEFAULT`]).
+pub fn solve_lp(
+    person_and_signup_data: Arc<HashMap<i64, SparSignup>>,
+    elo_scores: HashMap<i64, f64>,
+) -> HashMap<i64, Assignment> {
+    solve_lp_with_weights(
+        person_and_signup_data,
+        elo_scores,
+        ObjectiveWeights::DEFAULT,
+    )
+}
+
 /// Always remember: if it runs in polynomial time, it's efficient (for
 /// constructing the problem instance).
 #[tracing::instrument]
-pub fn solve_lp(
+pub fn solve_lp_with_weights(
     person_and_signup_data: Arc<HashMap<i64, SparSignup>>,
     elo_scores: HashMap<i64, f64>,
+    weights: ObjectiveWeights,
 ) -> HashMap<i64, Assignment> {
     // maximum number of rooms (where everyone is assigned to speak)
     // todo: this number can be reduced
@@ -414,14 +458,15 @@ pub fn solve_lp(
 
     let mut problem = vars
         .maximise(
-            // todo: work out what the right multipliers are
-            ((-2.0 * (r_max as f64) * 10.0) * judge_penalty)
-                + ((-1.0 * (r_max as f64) * 10.0) * difference_between_teams)
-                + (r_max as f64) * (difference_between_speakers)
-                + ((-1.0 * (r_max as f64) * 50.0) * fewer_rooms_objective)
-                // todo: should this grow faster than linear in the number of
-                // rooms?
-                + ((r_max as f64) * 1000.0) * partner_preferences,
+            ((weights.judge_penalty * r_max as f64) * judge_penalty)
+                + ((weights.difference_between_teams * r_max as f64)
+                    * difference_between_teams)
+                + ((weights.difference_between_speakers * r_max as f64)
+                    * difference_between_speakers)
+                + ((weights.fewer_rooms * r_max as f64)
+                    * fewer_rooms_objective)
+                + ((weights.partner_preferences * r_max as f64)
+                    * partner_preferences),
         )
         .using(good_lp::solvers::highs::highs);
 
@@ -1,14 +1,15 @@
 use arbitrary::Arbitrary;
 use db::{
     ballot::{
-        AdjudicatorBallot, AdjudicatorBallotLink, BallotRepr, BpTeam,
-        Scoresheet, SpeakerScoresheet, TeamScoresheet,
+        AdjudicatorBallot, AdjudicatorBallotLink, BallotRepr, BpTeam, Score,
+        ScoreRules, Scoresheet, SpeakerScoresheet, TeamScoresheet,
+        SOURCE_ACCOUNT, SOURCE_LINK,
     },
     room::SparRoomRepr,
     schema::{
-        adjudicator_ballot_entries, adjudicator_ballots,
-        spar_adjudicator_ballot_links, spar_adjudicators, spar_rooms,
-        spar_series, spar_series_members, spar_speakers, spars,
+        adjudicator_ballot_entries, adjudicator_ballot_team_comments,
+        adjudicator_ballots, spar_adjudicator_ballot_links, spar_adjudicators,
+        spar_rooms, spar_series, spar_series_members, spar_speakers, spars,
     },
     spar::Spar,
     user::User,
@@ -17,15 +18,20 @@ use db::{
 use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
 use fuzzcheck::DefaultMutator;
 use maud::Markup;
-use rocket::{form::Form, response::Redirect};
+use rocket::{
+    form::Form,
+    response::{Flash, Redirect},
+};
 use serde::{Deserialize, Serialize};
 use tracing::Instrument;
 
 use crate::{
-    html::{error_404, page_of_body},
+    html::{error_403, error_404, page_of_body},
     model::sync::id::gen_uuid,
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
+    resources::SparRoomRef,
+    util::tx,
 };
 
 #[get("/ballots/submit/<key>")]
@@ -61,24 +67,33 @@ pub async fn submit_ballot_page(
                 }
             };
 
-            let room = SparRoomRepr::of_id(key.room_id, conn)?;
+            if key.opened_at.is_none() {
+                diesel::update(
+                    spar_adjudicator_ballot_links::table
+                        .filter(spar_adjudicator_ballot_links::id.eq(key.id)),
+                )
+                .set(
+                    spar_adjudicator_ballot_links::opened_at
+                        .eq(diesel::dsl::now),
+                )
+                .execute(conn)?;
+            }
 
-            let previous_ballot_id = adjudicator_ballots::table
-                .filter(adjudicator_ballots::room_id.eq(room.inner.id))
-                .inner_join(spar_adjudicators::table)
-                .filter(spar_adjudicators::member_id.eq(key.member_id))
-                .select(adjudicator_ballots::id)
-                .first::<i64>(conn)
-                .optional()?;
+            let room = SparRoomRepr::of_id(key.room_id, conn)?;
 
             let previous_ballot =
-                if let Some(previous_ballot_id) = previous_ballot_id {
-                    Some(BallotRepr::of_id(previous_ballot_id, conn)?)
-                } else {
-                    None
-                };
+                previous_ballot_of(room.inner.id, key.member_id, conn)?;
+
+            let rules = score_rules_of_room(&room, conn)?;
 
-            Ok(render_ballot_form(previous_ballot, room, None, user, false))
+            Ok(render_ballot_form(
+                previous_ballot,
+                room,
+                None,
+                user,
+                false,
+                rules.as_ref(),
+            ))
         })
         .unwrap()
     })
@@ -151,48 +166,51 @@ pub fn render_ballot(room: &SparRoomRepr, prev: &BallotRepr) -> Markup {
                 }
             }
 
-            div class="col-6 list-group mb-3" {
-                li class="list-group-item" {
-                    strong {"MG "}
-                    (room.members[&room.speakers[&prev.scoresheet.teams[2].speakers[0].speaker_id].member_id].name)
-                    span class="float-end badge text-bg-secondary" {
-                        (prev.scoresheet.teams[2].speakers[0].score)
+            // only the opening teams debate in half-rooms
+            @if prev.scoresheet.teams.len() == 4 {
+                div class="col-6 list-group mb-3" {
+                    li class="list-group-item" {
+                        strong {"MG "}
+                        (room.members[&room.speakers[&prev.scoresheet.teams[2].speakers[0].speaker_id].member_id].name)
+                        span class="float-end badge text-bg-secondary" {
+                            (prev.scoresheet.teams[2].speakers[0].score)
+                        }
                     }
-                }
-                li class="list-group-item" {
-                    strong {"GW "}
-                    (room.members[&room.speakers[&prev.scoresheet.teams[2].speakers[1].speaker_id].member_id].name)
-                    span class="float-end badge text-bg-secondary" {
-                        (prev.scoresheet.teams[2].speakers[1].score)
+                    li class="list-group-item" {
+                        strong {"GW "}
+                        (room.members[&room.speakers[&prev.scoresheet.teams[2].speakers[1].speaker_id].member_id].name)
+                        span class="float-end badge text-bg-secondary" {
+                            (prev.scoresheet.teams[2].speakers[1].score)
+                        }
                     }
-                }
-                li class=((get_style)(BpTeam::Cg)) {
-                    em {"Total for Closing Government"}
-                    span class="float-end badge text-bg-secondary" {
-                        (prev.scoresheet.teams[2].speakers[0].score + prev.scoresheet.teams[2].speakers[1].score)
+                    li class=((get_style)(BpTeam::Cg)) {
+                        em {"Total for Closing Government"}
+                        span class="float-end badge text-bg-secondary" {
+                            (prev.scoresheet.teams[2].speakers[0].score + prev.scoresheet.teams[2].speakers[1].score)
+                        }
                     }
                 }
-            }
 
-            div class="col-6 list-group mb-3" {
-                li class="list-group-item" {
-                    strong {"MO "}
-                    (room.members[&room.speakers[&prev.scoresheet.teams[3].speakers[0].speaker_id].member_id].name)
-                    span class="float-end badge text-bg-secondary" {
-                        (prev.scoresheet.teams[3].speakers[0].score)
+                div class="col-6 list-group mb-3" {
+                    li class="list-group-item" {
+                        strong {"MO "}
+                        (room.members[&room.speakers[&prev.scoresheet.teams[3].speakers[0].speaker_id].member_id].name)
+                        span class="float-end badge text-bg-secondary" {
+                            (prev.scoresheet.teams[3].speakers[0].score)
+                        }
                     }
-                }
-                li class="list-group-item" {
-                    strong {"OW "}
-                    (room.members[&room.speakers[&prev.scoresheet.teams[3].speakers[1].speaker_id].member_id].name)
-                    span class="float-end badge text-bg-secondary" {
-                        (prev.scoresheet.teams[3].speakers[1].score)
+                    li class="list-group-item" {
+                        strong {"OW "}
+                        (room.members[&room.speakers[&prev.scoresheet.teams[3].speakers[1].speaker_id].member_id].name)
+                        span class="float-end badge text-bg-secondary" {
+                            (prev.scoresheet.teams[3].speakers[1].score)
+                        }
                     }
-                }
-                li class=((get_style)(BpTeam::Co)) {
-                    em {"Total for Closing Opposition"}
-                    span class="float-end badge text-bg-secondary" {
-                        (prev.scoresheet.teams[3].speakers[0].score + prev.scoresheet.teams[3].speakers[1].score)
+                    li class=((get_style)(BpTeam::Co)) {
+                        em {"Total for Closing Opposition"}
+                        span class="float-end badge text-bg-secondary" {
+                            (prev.scoresheet.teams[3].speakers[0].score + prev.scoresheet.teams[3].speakers[1].score)
+                        }
                     }
                 }
             }
@@ -200,28 +218,30 @@ pub fn render_ballot(room: &SparRoomRepr, prev: &BallotRepr) -> Markup {
     }
 }
 
-/// Renders the ballot submission form.
+/// Renders the inputs used to enter the speakers and speaker scores of each
+/// team (the names of the inputs correspond to the fields of
+/// [`BpBallotForm`]).
 ///
-/// The `force_submit` variable should be set to allow the user to submit a
-/// ballot that is different to the previously submitted ballot.
-///
-/// TODO: embed Javascript to automatically display scores on the webpage and
-/// warn if the ballot is invalid
-fn render_ballot_form(
-    prev: Option<BallotRepr>,
-    room: SparRoomRepr,
-    error: Option<&str>,
-    user: Option<User>,
-    force_submit: bool,
+/// If the series has strict score rules then we also ask the browser to
+/// enforce them (warnings are handled on the server, as the adjudicator must
+/// be able to submit the ballot anyway).
+pub(crate) fn render_ballot_inputs(
+    room: &SparRoomRepr,
+    rules: Option<&ScoreRules>,
 ) -> Markup {
-    let prev = prev.map(|prev| {
-        maud::html! {
-            div class="alert alert-danger" role="alert" {
-                p { b { "You have already submitted the following ballot:" } }
-                (render_ballot(&room, &prev))
-            }
+    let (min, max, step) = match rules {
+        Some(rules) if rules.is_strict() => {
+            (rules.min_score, rules.max_score, rules.step)
         }
-    });
+        Some(_) => (None, None, None),
+        None => (
+            Some(Score::of_points(50)),
+            Some(Score::of_points(100)),
+            None,
+        ),
+    };
+    // half points can be used unless the rules say otherwise
+    let step = step.unwrap_or(Score::of_half_points(1));
 
     let teams = room.teams.iter().enumerate().map(|(i, team)| {
         let speaker_names_and_public_ids = team.speakers.iter().map(|speaker_id| {
@@ -231,11 +251,11 @@ fn render_ballot_form(
             (member.name.clone(), speaker_record.public_id.clone())
         }).collect::<Vec<_>>();
 
-        let (s1, s2) = match i {
-            0 => ("pm", "dpm"),
-            1 => ("lo", "dlo"),
-            2 => ("mg", "gw"),
-            3 => ("mo", "ow"),
+        let (s1, s2, t) = match i {
+            0 => ("pm", "dpm", "og"),
+            1 => ("lo", "dlo", "oo"),
+            2 => ("mg", "gw", "cg"),
+            3 => ("mo", "ow", "co"),
             _ => unreachable!(),
         };
 
@@ -251,7 +271,11 @@ fn render_ballot_form(
             }
             div class="mb-3" {
                 label for=(s1.to_string() + "_score") class="form-label" {"Speaker Score"}
-                input type="number" min="50" max="100" name=(s1.to_string() + "_score") id=(s1.to_string() + "_score") class="form-control" {}
+                input type="number" min=[min] max=[max] step=(step) name=(s1.to_string() + "_score") id=(s1.to_string() + "_score") class="form-control" {}
+            }
+            div class="mb-3" {
+                label for=(s1.to_string() + "_comments") class="form-label" {"Feedback for this speaker (optional)"}
+                textarea name=(s1.to_string() + "_comments") id=(s1.to_string() + "_comments") class="form-control" rows="2" {}
             }
             hr {}
             p {b {(s2)}}
@@ -265,11 +289,67 @@ fn render_ballot_form(
             }
             div class="mb-3" {
                 label for=(s2.to_string() + "_score") class="form-label" {"Speaker Score"}
-                input type="number" min="50" max="100" name=(s2.to_string() + "_score") id=(s2.to_string() + "_score") class="form-control" {}
+                input type="number" min=[min] max=[max] step=(step) name=(s2.to_string() + "_score") id=(s2.to_string() + "_score") class="form-control" {}
+            }
+            div class="mb-3" {
+                label for=(s2.to_string() + "_comments") class="form-label" {"Feedback for this speaker (optional)"}
+                textarea name=(s2.to_string() + "_comments") id=(s2.to_string() + "_comments") class="form-control" rows="2" {}
+            }
+            div class="mb-3" {
+                label for=(t.to_string() + "_comments") class="form-label" {"Feedback for this team (optional)"}
+                textarea name=(t.to_string() + "_comments") id=(t.to_string() + "_comments") class="form-control" rows="2" {}
             }
         }
     }).collect::<Vec<_>>();
 
+    maud::html! {
+        div class="row" {
+            div class="col" {
+                (teams[0])
+            }
+            div class="col" {
+                (teams[1])
+            }
+        }
+        // the closing teams are left off the ballot in half-rooms
+        @if !room.is_half_room() {
+            div class="row" {
+                div class="col" {
+                    (teams[2])
+                }
+                div class="col" {
+                    (teams[3])
+                }
+            }
+        }
+    }
+}
+
+/// Renders the ballot submission form.
+///
+/// The `force_submit` variable should be set to allow the user to submit a
+/// ballot that is different to the previously submitted ballot (or which
+/// breaks the score rules of the series, if they are not strict).
+///
+/// TODO: embed Javascript to automatically display scores on the webpage and
+/// warn if the ballot is invalid
+fn render_ballot_form(
+    prev: Option<BallotRepr>,
+    room: SparRoomRepr,
+    error: Option<&str>,
+    user: Option<User>,
+    force_submit: bool,
+    rules: Option<&ScoreRules>,
+) -> Markup {
+    let prev = prev.map(|prev| {
+        maud::html! {
+            div class="alert alert-danger" role="alert" {
+                p { b { "You have already submitted the following ballot:" } }
+                (render_ballot(&room, &prev))
+            }
+        }
+    });
+
     let markup = maud::html! {
         h1 {"Ballot submission"}
         @if let Some(error) = error {
@@ -281,22 +361,7 @@ fn render_ballot_form(
             (prev)
         }
         form method="post" {
-            div class="row" {
-                div class="col" {
-                    (teams[0])
-                }
-                div class="col" {
-                    (teams[1])
-                }
-            }
-            div class="row" {
-                div class="col" {
-                    (teams[2])
-                }
-                div class="col" {
-                    (teams[3])
-                }
-            }
+            (render_ballot_inputs(&room, rules))
             @if force_submit {
                 input type="checkbox" name="force" hidden checked {}
             } @else {
@@ -314,22 +379,86 @@ fn render_ballot_form(
 )]
 pub struct BpBallotForm {
     pub pm: String,
-    pub pm_score: i64,
+    pub pm_score: Score,
     pub dpm: String,
-    pub dpm_score: i64,
+    pub dpm_score: Score,
     pub lo: String,
-    pub lo_score: i64,
+    pub lo_score: Score,
     pub dlo: String,
-    pub dlo_score: i64,
-    pub mg: String,
-    pub mg_score: i64,
-    pub gw: String,
-    pub gw_score: i64,
-    pub mo: String,
-    pub mo_score: i64,
-    pub ow: String,
-    pub ow_score: i64,
+    pub dlo_score: Score,
+    /// The closing teams are left out of ballots for half-rooms.
+    pub mg: Option<String>,
+    pub mg_score: Option<Score>,
+    pub gw: Option<String>,
+    pub gw_score: Option<Score>,
+    pub mo: Option<String>,
+    pub mo_score: Option<Score>,
+    pub ow: Option<String>,
+    pub ow_score: Option<Score>,
     pub force: bool,
+    /// Optional written feedback for each speaker.
+    pub pm_comments: Option<String>,
+    pub dpm_comments: Option<String>,
+    pub lo_comments: Option<String>,
+    pub dlo_comments: Option<String>,
+    pub mg_comments: Option<String>,
+    pub gw_comments: Option<String>,
+    pub mo_comments: Option<String>,
+    pub ow_comments: Option<String>,
+    /// Optional written feedback for each team.
+    pub og_comments: Option<String>,
+    pub oo_comments: Option<String>,
+    pub cg_comments: Option<String>,
+    pub co_comments: Option<String>,
+}
+
+impl BpBallotForm {
+    /// Returns the speaker (as a public id) and score of each speech given by
+    /// the closing teams (in speaking order), or `None` if any of them are
+    /// missing (as they are on ballots for half-rooms).
+    fn closing_speeches(&self) -> Option<[(&str, Score); 4]> {
+        Some([
+            (self.mg.as_deref()?, self.mg_score?),
+            (self.gw.as_deref()?, self.gw_score?),
+            (self.mo.as_deref()?, self.mo_score?),
+            (self.ow.as_deref()?, self.ow_score?),
+        ])
+    }
+
+    /// Returns the comments for each speaker (in speaking order), treating
+    /// blank comments as missing.
+    fn speaker_comments(&self) -> [Option<&str>; 8] {
+        [
+            &self.pm_comments,
+            &self.dpm_comments,
+            &self.lo_comments,
+            &self.dlo_comments,
+            &self.mg_comments,
+            &self.gw_comments,
+            &self.mo_comments,
+            &self.ow_comments,
+        ]
+        .map(non_blank)
+    }
+
+    /// Returns the comments for each team (in speaking order), treating blank
+    /// comments as missing.
+    fn team_comments(&self) -> [Option<&str>; 4] {
+        [
+            &self.og_comments,
+            &self.oo_comments,
+            &self.cg_comments,
+            &self.co_comments,
+        ]
+        .map(non_blank)
+    }
+}
+
+fn non_blank(comments: &Option<String>) -> Option<&str> {
+    comments
+        .as_deref()
+        .map(str::trim)
+        .filter(|comments| !comments.is_empty())
 }
 
 #[post("/ballots/submit/<key>", data = "<ballot>")]
@@ -365,357 +494,524 @@ pub async fn do_submit_ballot(
                 }
             };
 
-            fn id_of_speaker_uuid(
-                uid: &str,
-                conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
-            ) -> Result<i64, diesel::result::Error> {
-                spar_speakers::table
-                    .filter(spar_speakers::public_id.eq(uid))
-                    .select(spar_speakers::id)
-                    .first::<i64>(conn)
+            let room = SparRoomRepr::of_id(key.room_id, conn)?;
+
+            match process_ballot_submission(
+                room,
+                key.member_id,
+                &ballot,
+                user,
+                SOURCE_LINK,
+                None,
+                conn,
+            )? {
+                Some(page) => Ok(Err(page)),
+                // todo: build this page
+                None => Ok(Ok(Redirect::to("/ballots/submit/thanks"))),
             }
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
 
-            let room = SparRoomRepr::of_id(key.room_id, conn)?;
+/// Finds the adjudicator (in the given room) who is linked to the current
+/// user, checking that they are permitted to submit a ballot for the room.
+fn load_room_for_adjudicator(
+    room_id: &str,
+    user: &User,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<Result<(SparRoomRepr, i64), Markup>>, diesel::result::Error>
+{
+    let room_id = match spar_rooms::table
+        .filter(spar_rooms::public_id.eq(room_id))
+        .select(spar_rooms::id)
+        .first::<i64>(conn)
+        .optional()?
+    {
+        Some(room_id) => room_id,
+        None => return Ok(None),
+    };
 
-            let ballot_error = (|| -> Result<_, diesel::result::Error> {
-                // check that all speakers are valid
-
-                // todo: warn if unexpected ironman
-                let og = &room.teams[0].speakers;
-                let pm_i64 = id_of_speaker_uuid(&ballot.pm, conn)?;
-                let dpm_i64 = id_of_speaker_uuid(&ballot.dpm, conn)?;
-                if !(og.contains(&pm_i64) && og.contains(&dpm_i64)) {
-                    return Ok(Some(
-                        "Error: the ballot submitted specifies a speaker who is
-                        not assigned to this spar (either PM or DPM is
-                        incorrect).",
-                    ));
-                }
+    if !has_permission(
+        Some(user),
+        &Permission::SubmitBallotForRoom(SparRoomRef(room_id)),
+        conn,
+    ) {
+        return Ok(Some(Err(error_403(
+            Some(
+                "Error: you are not an adjudicator in this room (or ballots \
+                 for this room can no longer be submitted).",
+            ),
+            Some(user.clone()),
+        ))));
+    }
 
-                let oo = &room.teams[1].speakers;
-                let lo_i64 = id_of_speaker_uuid(&ballot.lo, conn)?;
-                let dlo_i64 = id_of_speaker_uuid(&ballot.dlo, conn)?;
-                if !(oo.contains(&lo_i64) && oo.contains(&dlo_i64)) {
-                    return Ok(Some(
-                        "Error: the ballot submitted specifies a speaker who is
-                        not assigned to this spar (either LO or DLO is
-                        incorrect).",
-                    ));
-                }
+    let member_id = spar_adjudicators::table
+        .filter(spar_adjudicators::room_id.eq(room_id))
+        .inner_join(spar_series_members::table)
+        .filter(spar_series_members::user_id.eq(user.id))
+        .select(spar_adjudicators::member_id)
+        .first::<i64>(conn)?;
 
-                let cg = &room.teams[2].speakers;
-                let mg_i64 = id_of_speaker_uuid(&ballot.mg, conn)?;
-                let gw_i64 = id_of_speaker_uuid(&ballot.gw, conn)?;
-                if !(cg.contains(&mg_i64) && cg.contains(&gw_i64)) {
-                    return Ok(Some(
-                        "Error: the ballot submitted specifies a speaker who is
-                        not assigned to this spar (either MG or GW is
-                        incorrect).",
-                    ));
-                }
+    Ok(Some(Ok((SparRoomRepr::of_id(room_id, conn)?, member_id))))
+}
 
-                let co = &room.teams[3].speakers;
-                let mo_i64 = id_of_speaker_uuid(&ballot.mo, conn)?;
-                let ow_i64 = id_of_speaker_uuid(&ballot.ow, conn)?;
-                if !(co.contains(&mo_i64) && co.contains(&ow_i64)) {
-                    return Ok(Some(
-                        "Error: the ballot submitted specifies a speaker who is
-                        not assigned to this spar (either MO or OW is
-                        incorrect).",
-                    ));
-                }
+#[get("/rooms/<room_id>/ballots/submit")]
+/// Allows an adjudicator whose account is linked to their spar series
+/// membership to submit their ballot without using the emailed link.
+pub async fn submit_ballot_as_user_page(
+    room_id: &str,
+    db: DbConn,
+    user: User,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let room_id = room_id.to_string();
+    tx(span, db, move |conn| {
+        let (room, member_id) =
+            match load_room_for_adjudicator(&room_id, &user, conn).unwrap()? {
+                Ok(t) => t,
+                Err(page) => return Some(page),
+            };
 
-                let og_score = ballot.pm_score + ballot.dpm_score;
-                let oo_score = ballot.lo_score + ballot.dlo_score;
-                let cg_score = ballot.mg_score + ballot.gw_score;
-                let co_score = ballot.mo_score + ballot.ow_score;
+        let previous_ballot =
+            previous_ballot_of(room.inner.id, member_id, conn).unwrap();
+        let rules = score_rules_of_room(&room, conn).unwrap();
 
-                if og_score == oo_score {
-                    return Ok(Some(
-                        "Error: OG and OO have the same sum of speaks.",
-                    ));
-                }
+        Some(render_ballot_form(
+            previous_ballot,
+            room,
+            None,
+            Some(user),
+            false,
+            rules.as_ref(),
+        ))
+    })
+    .await
+}
 
-                if og_score == cg_score {
-                    return Ok(Some(
-                        "Error: OG and CG have the same sum of speaks.",
-                    ));
-                }
+#[post("/rooms/<room_id>/ballots/submit", data = "<ballot>")]
+pub async fn do_submit_ballot_as_user(
+    room_id: &str,
+    db: DbConn,
+    user: User,
+    ballot: Form<BpBallotForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let room_id = room_id.to_string();
+    tx(span, db, move |conn| {
+        let (room, member_id) =
+            match load_room_for_adjudicator(&room_id, &user, conn).unwrap()? {
+                Ok(t) => t,
+                Err(page) => return Some(Err(page)),
+            };
 
-                if og_score == co_score {
-                    return Ok(Some(
-                        "Error: OG and CO have the same sum of speaks.",
-                    ));
-                }
+        let user_id = user.id;
+        match process_ballot_submission(
+            room,
+            member_id,
+            &ballot,
+            Some(user),
+            SOURCE_ACCOUNT,
+            Some(user_id),
+            conn,
+        )
+        .unwrap()
+        {
+            Some(page) => Some(Err(page)),
+            None => Some(Ok(Flash::success(
+                Redirect::to("/user"),
+                "Your ballot has been submitted.",
+            ))),
+        }
+    })
+    .await
+}
 
-                if oo_score == cg_score {
-                    return Ok(Some(
-                        "Error: OO and CG have the same sum of speaks.",
-                    ));
-                }
+/// Returns the most recent ballot which the given adjudicator has submitted
+/// for the given room (if they have submitted one).
+fn previous_ballot_of(
+    room_id: i64,
+    member_id: i64,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<BallotRepr>, diesel::result::Error> {
+    let previous_ballot_id = adjudicator_ballots::table
+        .filter(adjudicator_ballots::room_id.eq(room_id))
+        .inner_join(spar_adjudicators::table)
+        .filter(spar_adjudicators::member_id.eq(member_id))
+        .order_by(adjudicator_ballots::id.desc())
+        .select(adjudicator_ballots::id)
+        .first::<i64>(conn)
+        .optional()?;
 
-                if oo_score == co_score {
-                    return Ok(Some(
-                        "Error: OO and CO have the same sum of speaks.",
-                    ));
-                }
+    match previous_ballot_id {
+        Some(previous_ballot_id) => {
+            Ok(Some(BallotRepr::of_id(previous_ballot_id, conn)?))
+        }
+        None => Ok(None),
+    }
+}
 
-                if cg_score == co_score {
-                    return Ok(Some(
-                        "Error: CG and CO have the same sum of speaks.",
-                    ));
-                }
+/// Validates and records a ballot submitted by one of the adjudicators in a
+/// room. If the ballot cannot be accepted (yet), returns the ballot form
+/// (with an explanation) which should be shown to the adjudicator.
+fn process_ballot_submission(
+    room: SparRoomRepr,
+    member_id: i64,
+    ballot: &BpBallotForm,
+    user: Option<User>,
+    source: &str,
+    submitted_by: Option<i64>,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<Markup>, diesel::result::Error> {
+    let ballot_error = check_ballot(&room, ballot, conn)?;
+    let rules = score_rules_of_room(&room, conn)?;
 
-                Ok(None)
-            })()?;
-
-            let previous_ballot = {
-                let previous_ballot_id = adjudicator_ballots::table
-                    .filter(adjudicator_ballots::room_id.eq(room.inner.id))
-                    .inner_join(spar_adjudicators::table)
-                    .filter(spar_adjudicators::member_id.eq(key.member_id))
-                    .select(adjudicator_ballots::id)
-                    .first::<i64>(conn)
-                    .optional()?;
-                if let Some(previous_ballot_id) = previous_ballot_id {
-                    Some(BallotRepr::of_id(previous_ballot_id, conn)?)
-                } else {
-                    None
-                }
-            };
+    let previous_ballot = previous_ballot_of(room.inner.id, member_id, conn)?;
 
-            if let Some(ballot_error) = ballot_error {
-                return Ok(Err(render_ballot_form(
+    if let Some(ballot_error) = ballot_error {
+        return Ok(Some(render_ballot_form(
+            previous_ballot,
+            room,
+            Some(ballot_error),
+            user,
+            false,
+            rules.as_ref(),
+        )));
+    }
+
+    if let Some(rules) = &rules {
+        if let Some(msg) = score_rules_message(ballot, rules) {
+            if rules.is_strict() || !ballot.force {
+                return Ok(Some(render_ballot_form(
                     previous_ballot,
                     room,
-                    Some(ballot_error),
+                    Some(&msg),
                     user,
-                    false,
+                    !rules.is_strict(),
+                    Some(rules),
                 )));
             }
+        }
+    }
 
-            // if this is the first time that the ballot is being submitted, we
-            // check whether it is contrary to ballots submitted by other
-            // in this room
-            if !ballot.force {
-                if let Some(canonical_ballot) =
-                    room.inner.canonical_ballot(conn)?
-                {
-                    let submitted_scoresheet = Scoresheet {
-                        teams: vec![
-                            TeamScoresheet {
-                                speakers: vec![
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.pm, conn,
-                                        )?,
-                                        score: ballot.pm_score,
-                                    },
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.dpm,
-                                            conn,
-                                        )?,
-                                        score: ballot.dpm_score,
-                                    },
-                                ],
-                            },
-                            TeamScoresheet {
-                                speakers: vec![
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.lo, conn,
-                                        )?,
-                                        score: ballot.lo_score,
-                                    },
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.dlo,
-                                            conn,
-                                        )?,
-                                        score: ballot.dlo_score,
-                                    },
-                                ],
-                            },
-                            TeamScoresheet {
-                                speakers: vec![
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.mg, conn,
-                                        )?,
-                                        score: ballot.mg_score,
-                                    },
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.gw, conn,
-                                        )?,
-                                        score: ballot.gw_score,
-                                    },
-                                ],
-                            },
-                            TeamScoresheet {
-                                speakers: vec![
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.mo, conn,
-                                        )?,
-                                        score: ballot.mo_score,
-                                    },
-                                    SpeakerScoresheet {
-                                        // todo: we have already computed these above
-                                        speaker_id: id_of_speaker_uuid(
-                                            &ballot.ow, conn,
-                                        )?,
-                                        score: ballot.ow_score,
-                                    },
-                                ],
-                            },
-                        ],
-                    };
-
-                    if submitted_scoresheet != canonical_ballot.scoresheet {
-                        return Ok(Err(render_ballot_form(
-                            previous_ballot,
-                            room,
-                            Some(
-                                "Note: a ballot with a different result has
-                                   already been submitted for this form.",
-                            ),
-                            user,
-                            false,
-                        )));
-                    }
-                }
-            }
+    // if this is the first time that the ballot is being submitted, we
+    // check whether it is contrary to ballots submitted by other
+    // in this room
+    if !ballot.force {
+        if let Some(canonical_ballot) = room.inner.canonical_ballot(conn)? {
+            let submitted_scoresheet = scoresheet_of_form(ballot, conn)?;
 
-            let adjudicator_id = spar_adjudicators::table
-                // todo: this should point to the spar_adjudicators table
-                .filter(spar_adjudicators::member_id.eq(key.member_id))
-                .filter(spar_adjudicators::room_id.eq(key.room_id))
-                .select(spar_adjudicators::id)
-                .first::<i64>(conn)?;
-
-            let ballot_id = diesel::insert_into(adjudicator_ballots::table)
-                .values({
-                    (
-                        adjudicator_ballots::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballots::adjudicator_id.eq(adjudicator_id),
-                        adjudicator_ballots::room_id.eq(room.inner.id),
-                        adjudicator_ballots::created_at.eq(diesel::dsl::now),
-                    )
-                })
-                .returning(adjudicator_ballots::id)
-                .get_result::<i64>(conn)?;
-
-            let n = diesel::insert_into(adjudicator_ballot_entries::table)
-                .values(vec![
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.pm, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[0].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.pm_score),
-                        adjudicator_ballot_entries::position.eq(0),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.dpm, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[0].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.dpm_score),
-                        adjudicator_ballot_entries::position.eq(1),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.lo, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[1].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.lo_score),
-                        adjudicator_ballot_entries::position.eq(0),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.dlo, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[1].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.dlo_score),
-                        adjudicator_ballot_entries::position.eq(1),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.mg, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[2].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.mg_score),
-                        adjudicator_ballot_entries::position.eq(0),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.gw, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[2].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.gw_score),
-                        adjudicator_ballot_entries::position.eq(1),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.mo, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[3].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.mo_score),
-                        adjudicator_ballot_entries::position.eq(0),
-                    ),
-                    (
-                        adjudicator_ballot_entries::public_id
-                            .eq(gen_uuid().to_string()),
-                        adjudicator_ballot_entries::ballot_id.eq(ballot_id),
-                        adjudicator_ballot_entries::speaker_id
-                            .eq(id_of_speaker_uuid(&ballot.ow, conn)?),
-                        adjudicator_ballot_entries::team_id
-                            .eq(room.teams[3].inner.id),
-                        adjudicator_ballot_entries::speak.eq(ballot.ow_score),
-                        adjudicator_ballot_entries::position.eq(1),
+            if submitted_scoresheet != canonical_ballot.scoresheet {
+                return Ok(Some(render_ballot_form(
+                    previous_ballot,
+                    room,
+                    Some(
+                        "Note: a ballot with a different result has
+                               already been submitted for this form. If
+                               your ballot is correct, please enter it
+                               again.",
                     ),
-                ])
-                .execute(conn)?;
-            assert_eq!(n, 8);
+                    user,
+                    true,
+                    rules.as_ref(),
+                )));
+            }
+        }
+    }
 
-            // todo: build this page
-            return Ok(Ok(Redirect::to("/ballots/submit/thanks")));
-        })
-        .unwrap()
+    let adjudicator_id = spar_adjudicators::table
+        // todo: this should point to the spar_adjudicators table
+        .filter(spar_adjudicators::member_id.eq(member_id))
+        .filter(spar_adjudicators::room_id.eq(room.inner.id))
+        .select(spar_adjudicators::id)
+        .first::<i64>(conn)?;
+
+    insert_ballot(&room, adjudicator_id, ballot, source, submitted_by, conn)?;
+
+    Ok(None)
+}
+
+fn id_of_speaker_uuid(
+    uid: &str,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<i64, diesel::result::Error> {
+    spar_speakers::table
+        .filter(spar_speakers::public_id.eq(uid))
+        .select(spar_speakers::id)
+        .first::<i64>(conn)
+}
+
+/// Checks that a submitted ballot is valid for the given room (i.e. that each
+/// speaker is on the correct team, and that no two teams have the same total
+/// number of speaker points). Ballots for half-rooms should only include the
+/// opening teams. Returns a description of the problem if the ballot is
+/// invalid.
+pub(crate) fn check_ballot(
+    room: &SparRoomRepr,
+    ballot: &BpBallotForm,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<&'static str>, diesel::result::Error> {
+    // check that all speakers are valid
+
+    // todo: warn if unexpected ironman
+    let og = &room.teams[0].speakers;
+    let pm_i64 = id_of_speaker_uuid(&ballot.pm, conn)?;
+    let dpm_i64 = id_of_speaker_uuid(&ballot.dpm, conn)?;
+    if !(og.contains(&pm_i64) && og.contains(&dpm_i64)) {
+        return Ok(Some(
+            "Error: the ballot submitted specifies a speaker who is
+            not assigned to this spar (either PM or DPM is
+            incorrect).",
+        ));
+    }
+
+    let oo = &room.teams[1].speakers;
+    let lo_i64 = id_of_speaker_uuid(&ballot.lo, conn)?;
+    let dlo_i64 = id_of_speaker_uuid(&ballot.dlo, conn)?;
+    if !(oo.contains(&lo_i64) && oo.contains(&dlo_i64)) {
+        return Ok(Some(
+            "Error: the ballot submitted specifies a speaker who is
+            not assigned to this spar (either LO or DLO is
+            incorrect).",
+        ));
+    }
+
+    let og_score = ballot.pm_score + ballot.dpm_score;
+    let oo_score = ballot.lo_score + ballot.dlo_score;
+
+    if og_score == oo_score {
+        return Ok(Some("Error: OG and OO have the same sum of speaks."));
+    }
+
+    let [mg, gw, mo, ow] =
+        match (ballot.closing_speeches(), room.is_half_room()) {
+            (Some(closing), false) => closing,
+            (None, true) => return Ok(None),
+            (Some(_), true) => {
+                return Ok(Some(
+                    "Error: this is a half-room, so the ballot should not
+                    include the closing teams.",
+                ))
+            }
+            (None, false) => {
+                return Ok(Some(
+                    "Error: the ballot is missing a speaker or a score for
+                    one of the closing teams.",
+                ))
+            }
+        };
+
+    let cg = &room.teams[2].speakers;
+    let mg_i64 = id_of_speaker_uuid(mg.0, conn)?;
+    let gw_i64 = id_of_speaker_uuid(gw.0, conn)?;
+    if !(cg.contains(&mg_i64) && cg.contains(&gw_i64)) {
+        return Ok(Some(
+            "Error: the ballot submitted specifies a speaker who is
+            not assigned to this spar (either MG or GW is
+            incorrect).",
+        ));
+    }
+
+    let co = &room.teams[3].speakers;
+    let mo_i64 = id_of_speaker_uuid(mo.0, conn)?;
+    let ow_i64 = id_of_speaker_uuid(ow.0, conn)?;
+    if !(co.contains(&mo_i64) && co.contains(&ow_i64)) {
+        return Ok(Some(
+            "Error: the ballot submitted specifies a speaker who is
+            not assigned to this spar (either MO or OW is
+            incorrect).",
+        ));
+    }
+
+    let cg_score = mg.1 + gw.1;
+    let co_score = mo.1 + ow.1;
+
+    if og_score == cg_score {
+        return Ok(Some("Error: OG and CG have the same sum of speaks."));
+    }
+
+    if og_score == co_score {
+        return Ok(Some("Error: OG and CO have the same sum of speaks."));
+    }
+
+    if oo_score == cg_score {
+        return Ok(Some("Error: OO and CG have the same sum of speaks."));
+    }
+
+    if oo_score == co_score {
+        return Ok(Some("Error: OO and CO have the same sum of speaks."));
+    }
+
+    if cg_score == co_score {
+        return Ok(Some("Error: CG and CO have the same sum of speaks."));
+    }
+
+    Ok(None)
+}
+
+/// Loads the score rules (if any) of the series which this room belongs to.
+pub(crate) fn score_rules_of_room(
+    room: &SparRoomRepr,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<ScoreRules>, diesel::result::Error> {
+    let spar_series_id = spars::table
+        .filter(spars::id.eq(room.inner.spar_id))
+        .select(spars::spar_series_id)
+        .first::<i64>(conn)?;
+    ScoreRules::of_series(spar_series_id, conn)
+}
+
+/// Checks the speaker scores on this ballot against the score rules of the
+/// series, returning a message to show to the person submitting the ballot
+/// if any of the scores break the rules.
+pub(crate) fn score_rules_message(
+    ballot: &BpBallotForm,
+    rules: &ScoreRules,
+) -> Option<String> {
+    let mut scores = vec![
+        ("PM", ballot.pm_score),
+        ("DPM", ballot.dpm_score),
+        ("LO", ballot.lo_score),
+        ("DLO", ballot.dlo_score),
+    ];
+    if let Some([mg, gw, mo, ow]) = ballot.closing_speeches() {
+        scores.extend([("MG", mg.1), ("GW", gw.1), ("MO", mo.1), ("OW", ow.1)]);
+    }
+    let violations = rules.violations(&scores);
+
+    if violations.is_empty() {
+        return None;
+    }
+
+    Some(if rules.is_strict() {
+        format!("Error: {}", violations.join(" "))
+    } else {
+        format!(
+            "Warning: {} If these scores are correct, please enter the \
+             ballot again to confirm.",
+            violations.join(" ")
+        )
     })
-    .instrument(span.0)
-    .await
+}
+
+/// Converts a submitted ballot into a [`Scoresheet`] (this should only be
+/// called on ballots which have been checked using [`check_ballot`]).
+fn scoresheet_of_form(
+    ballot: &BpBallotForm,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Scoresheet, diesel::result::Error> {
+    let speeches = speeches_of_form(ballot);
+    let teams = speeches
+        .chunks(2)
+        .map(|speeches| {
+            Ok(TeamScoresheet {
+                speakers: speeches
+                    .iter()
+                    .map(|(speaker, score)| {
+                        Ok(SpeakerScoresheet {
+                            speaker_id: id_of_speaker_uuid(speaker, conn)?,
+                            score: *score,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, diesel::result::Error>>()?,
+            })
+        })
+        .collect::<Result<Vec<_>, diesel::result::Error>>()?;
+    Ok(Scoresheet { teams })
+}
+
+/// Returns the speaker (as a public id) and score of each speech on the
+/// ballot, in speaking order (the closing teams are left out of ballots for
+/// half-rooms).
+fn speeches_of_form(ballot: &BpBallotForm) -> Vec<(&str, Score)> {
+    let mut speeches = vec![
+        (ballot.pm.as_str(), ballot.pm_score),
+        (ballot.dpm.as_str(), ballot.dpm_score),
+        (ballot.lo.as_str(), ballot.lo_score),
+        (ballot.dlo.as_str(), ballot.dlo_score),
+    ];
+    if let Some(closing) = ballot.closing_speeches() {
+        speeches.extend(closing);
+    }
+    speeches
+}
+
+/// Records a new version of an adjudicator's ballot (ballots are never
+/// modified once they have been submitted). Returns the id of the new ballot.
+pub(crate) fn insert_ballot(
+    room: &SparRoomRepr,
+    adjudicator_id: i64,
+    ballot: &BpBallotForm,
+    source: &str,
+    submitted_by: Option<i64>,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<i64, diesel::result::Error> {
+    let ballot_id = diesel::insert_into(adjudicator_ballots::table)
+        .values({
+            (
+                adjudicator_ballots::public_id.eq(gen_uuid().to_string()),
+                adjudicator_ballots::adjudicator_id.eq(adjudicator_id),
+                adjudicator_ballots::room_id.eq(room.inner.id),
+                adjudicator_ballots::created_at.eq(diesel::dsl::now),
+                adjudicator_ballots::source.eq(source),
+                adjudicator_ballots::submitted_by.eq(submitted_by),
+            )
+        })
+        .returning(adjudicator_ballots::id)
+        .get_result::<i64>(conn)?;
+
+    let speaker_comments = ballot.speaker_comments();
+    let speeches = speeches_of_form(ballot);
+    let entries = speeches
+        .iter()
+        .enumerate()
+        .map(|(i, (speaker, score))| {
+            Ok((
+                adjudicator_ballot_entries::public_id
+                    .eq(gen_uuid().to_string()),
+                adjudicator_ballot_entries::ballot_id.eq(ballot_id),
+                adjudicator_ballot_entries::speaker_id
+                    .eq(id_of_speaker_uuid(speaker, conn)?),
+                adjudicator_ballot_entries::team_id
+                    .eq(room.teams[i / 2].inner.id),
+                adjudicator_ballot_entries::speak.eq(*score),
+                adjudicator_ballot_entries::comments.eq(speaker_comments[i]),
+                adjudicator_ballot_entries::position.eq((i % 2) as i64),
+            ))
+        })
+        .collect::<Result<Vec<_>, diesel::result::Error>>()?;
+    let n = diesel::insert_into(adjudicator_ballot_entries::table)
+        .values(entries)
+        .execute(conn)?;
+    assert_eq!(n, speeches.len());
+
+    let team_comments = ballot
+        .team_comments()
+        .into_iter()
+        .zip(&room.teams)
+        // (the closing teams of half-rooms have no speakers to comment on)
+        .filter(|(_, team)| !team.speakers.is_empty())
+        .filter_map(|(comments, team)| {
+            comments.map(|comments| {
+                (
+                    adjudicator_ballot_team_comments::public_id
+                        .eq(gen_uuid().to_string()),
+                    adjudicator_ballot_team_comments::ballot_id.eq(ballot_id),
+                    adjudicator_ballot_team_comments::team_id.eq(team.inner.id),
+                    adjudicator_ballot_team_comments::comments.eq(comments),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    if !team_comments.is_empty() {
+        diesel::insert_into(adjudicator_ballot_team_comments::table)
+            .values(team_comments)
+            .execute(conn)?;
+    }
+
+    Ok(ballot_id)
 }
 
 #[get("/ballots/view/<ballot_id>")]
@@ -13,20 +13,23 @@ use crate::{
     auth::register::RegisterForm, groups::CreateGroupForm, make_rocket,
 };
 
-use db::ballot::AdjudicatorBallotLink;
+use db::ballot::{AdjudicatorBallotLink, BpTeam, Score};
 use db::draft_draw::DraftDraw;
 use db::email::EmailRow;
 use db::schema::{
-    draft_draws, emails, spar_adjudicator_ballot_links, spar_rooms,
-    spar_series, spar_series_join_requests, spar_series_members, spars,
+    adjudicator_ballot_team_comments, draft_draws, emails,
+    spar_adjudicator_ballot_links, spar_rooms, spar_series,
+    spar_series_join_requests, spar_series_members, spars,
+};
+use db::spar::{
+    Spar, SparRoom, SparSeries, SparSeriesJoinRequest, SparSeriesMember,
 };
-use db::spar::{Spar, SparRoom, SparSeries, SparSeriesMember};
 use db::{group::Group, schema::groups};
 use diesel::connection::LoadConnection;
 use diesel::prelude::*;
 use diesel::sqlite::Sqlite;
 use diesel::SqliteConnection;
-use rocket::http::ContentType;
+use rocket::http::{ContentType, Status};
 use rocket::local::blocking::Client;
 use uuid::Uuid;
 
@@ -197,7 +200,7 @@ fn basic_test_sequence() {
     let _approve_member_join_requests = {
         let join_requests = spar_series_join_requests::table
             .order_by(spar_series_join_requests::created_at.desc())
-            .load::<SparSeriesMember>(&mut conn)
+            .load::<SparSeriesJoinRequest>(&mut conn)
             .unwrap();
         assert_eq!(join_requests.len(), 9);
         for req in join_requests {
@@ -401,6 +404,44 @@ fn basic_test_sequence() {
 
     submit_ballot(&rocket, &mut conn);
 
+    // (4)(d) confirming the draw again replaces the rooms (and so also the
+    // ballot, including its comments)
+
+    assert_eq!(
+        adjudicator_ballot_team_comments::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap(),
+        1
+    );
+
+    let response = rocket
+        .post(format!(
+            "/spars/{}/draws/{}/confirm",
+            spar.public_id, draft_of_room_1.public_id
+        ))
+        .header(ContentType::Form)
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+
+    assert_eq!(
+        spar_rooms::table
+            .filter(spar_rooms::spar_id.eq(spar.id))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        adjudicator_ballot_team_comments::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap(),
+        0
+    );
+
+    submit_ballot(&rocket, &mut conn);
+
     // (5) conclude spar
 
     mark_spar_complete(&rocket, spar);
@@ -426,10 +467,16 @@ fn basic_test_sequence() {
     let og = &submitted_ballot.scoresheet.teams[0];
     let pm = &og.speakers[0];
     let dpm = &og.speakers[1];
-    assert_eq!(pm.score, 80);
+    assert_eq!(pm.score, Score::of_points(80));
     assert_eq!(repr.speakers[&pm.speaker_id].public_id, pm_id);
-    assert_eq!(dpm.score, 78);
+    assert_eq!(dpm.score, Score::of_points(78));
     assert_eq!(repr.speakers[&dpm.speaker_id].public_id, dpm_id);
+    assert_eq!(
+        submitted_ballot.scoresheet.teams[2].speakers[0]
+            .score
+            .to_string(),
+        "77.5"
+    );
 
     // (6) create second spar
 
@@ -503,6 +550,7 @@ fn basic_test_sequence() {
     let mut draft = draft_draws::table
         .order_by(draft_draws::created_at.desc())
         .filter(draft_draws::id.ne(draft_of_room_1.id))
+        .filter(draft_draws::spar_id.eq(spar.id))
         .first::<DraftDraw>(&mut conn)
         .optional()
         .unwrap();
@@ -514,6 +562,7 @@ fn basic_test_sequence() {
         draft = draft_draws::table
             .order_by(draft_draws::created_at.desc())
             .filter(draft_draws::id.ne(draft_of_room_1.id))
+            .filter(draft_draws::spar_id.eq(spar.id))
             .first::<DraftDraw>(&mut conn)
             .optional()
             .unwrap();
@@ -551,6 +600,32 @@ fn basic_test_sequence() {
             .unwrap(),
         2
     );
+
+    // (7) the closing teams of the second spar do not turn up, so its room
+    // becomes a half-room (and only the opening teams are on the ballot)
+
+    let room = spar_rooms::table
+        .filter(spar_rooms::spar_id.eq(spar.id))
+        .first::<SparRoom>(&mut conn)
+        .unwrap();
+    let repr = room.repr(&mut conn).unwrap();
+    let absent = repr.teams[2..]
+        .iter()
+        .flat_map(|team| &team.speakers)
+        .map(|speaker| format!("absent={}", repr.speakers[speaker].member_id))
+        .collect::<Vec<_>>();
+    let response = rocket
+        .post(format!("/spars/{}/adjust", spar.public_id))
+        .header(ContentType::Form)
+        .body(absent.join("&"))
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert!(room.repr(&mut conn).unwrap().is_half_room());
+
+    submit_half_room_ballot(&rocket, &mut conn, &room);
+    let ballot = room.canonical_ballot(&mut conn).unwrap().unwrap();
+    assert_eq!(ballot.scoresheet.teams.len(), 2);
+    assert_eq!(ballot.bp_ranking(), vec![BpTeam::Oo, BpTeam::Og]);
 }
 
 fn mark_spar_complete(rocket: &Client, spar: Spar) {
@@ -578,22 +653,35 @@ fn submit_ballot(
     let dpm_id = repr.speakers[&teams[0].speakers[1]].public_id.clone();
     let submission = BpBallotForm {
         pm: pm_id.clone(),
-        pm_score: 80,
+        pm_score: Score::of_points(80),
         dpm: dpm_id.clone(),
-        dpm_score: 78,
+        dpm_score: Score::of_points(78),
         lo: repr.speakers[&teams[1].speakers[0]].public_id.clone(),
-        lo_score: 76,
+        lo_score: Score::of_points(76),
         dlo: repr.speakers[&teams[1].speakers[1]].public_id.clone(),
-        dlo_score: 75,
-        mg: repr.speakers[&teams[2].speakers[0]].public_id.clone(),
-        mg_score: 77,
-        gw: repr.speakers[&teams[2].speakers[1]].public_id.clone(),
-        gw_score: 73,
-        mo: repr.speakers[&teams[3].speakers[0]].public_id.clone(),
-        mo_score: 73,
-        ow: repr.speakers[&teams[3].speakers[1]].public_id.clone(),
-        ow_score: 72,
+        dlo_score: Score::of_points(75),
+        mg: Some(repr.speakers[&teams[2].speakers[0]].public_id.clone()),
+        // half points can be used
+        mg_score: Some("77.5".parse().unwrap()),
+        gw: Some(repr.speakers[&teams[2].speakers[1]].public_id.clone()),
+        gw_score: Some(Score::of_points(73)),
+        mo: Some(repr.speakers[&teams[3].speakers[0]].public_id.clone()),
+        mo_score: Some(Score::of_points(73)),
+        ow: Some(repr.speakers[&teams[3].speakers[1]].public_id.clone()),
+        ow_score: Some(Score::of_points(72)),
         force: false,
+        pm_comments: Some("Good extension".to_string()),
+        dpm_comments: None,
+        lo_comments: None,
+        dlo_comments: None,
+        mg_comments: None,
+        gw_comments: None,
+        mo_comments: None,
+        ow_comments: None,
+        og_comments: Some("Clear case".to_string()),
+        oo_comments: None,
+        cg_comments: None,
+        co_comments: None,
     };
 
     rocket
@@ -602,3 +690,57 @@ fn submit_ballot(
         .body(&serde_urlencoded::to_string(&submission).unwrap())
         .dispatch();
 }
+
+/// Submits a ballot for a half-room (so only the opening teams are included).
+fn submit_half_room_ballot(
+    rocket: &Client,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+    room: &SparRoom,
+) {
+    let ballot_link = spar_adjudicator_ballot_links::table
+        .filter(spar_adjudicator_ballot_links::room_id.eq(room.id))
+        .first::<AdjudicatorBallotLink>(conn)
+        .unwrap();
+
+    let repr = room.repr(conn).unwrap();
+    let teams = repr.teams;
+
+    let submission = BpBallotForm {
+        pm: repr.speakers[&teams[0].speakers[0]].public_id.clone(),
+        pm_score: Score::of_points(75),
+        dpm: repr.speakers[&teams[0].speakers[1]].public_id.clone(),
+        dpm_score: Score::of_points(74),
+        lo: repr.speakers[&teams[1].speakers[0]].public_id.clone(),
+        lo_score: Score::of_points(78),
+        dlo: repr.speakers[&teams[1].speakers[1]].public_id.clone(),
+        dlo_score: Score::of_points(77),
+        mg: None,
+        mg_score: None,
+        gw: None,
+        gw_score: None,
+        mo: None,
+        mo_score: None,
+        ow: None,
+        ow_score: None,
+        force: false,
+        pm_comments: None,
+        dpm_comments: None,
+        lo_comments: None,
+        dlo_comments: None,
+        mg_comments: None,
+        gw_comments: None,
+        mo_comments: None,
+        ow_comments: None,
+        og_comments: None,
+        oo_comments: None,
+        cg_comments: None,
+        co_comments: None,
+    };
+
+    let response = rocket
+        .post(format!("/ballots/submit/{}", ballot_link.link))
+        .header(ContentType::Form)
+        .body(&serde_urlencoded::to_string(&submission).unwrap())
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+}
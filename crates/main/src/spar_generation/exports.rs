@@ -0,0 +1,741 @@
+//! Machine-readable exports of the data stored about spars.
+//!
+//! Each export can be downloaded for a single spar (from
+//! `/spars/<spar_id>/export/<kind>`) or for every spar in a series (from
+//! `/spar_series/<spar_series_id>/export/<kind>`), where `<kind>` is one of
+//! `signups`, `draw`, `ballots` or `attendance`. Add `?format=json` to
+//! download JSON instead of CSV.
+//!
+//! The CSV files have a header row, and one row per record (the columns are
+//! listed in `COLUMNS` for each type of row). The JSON files have the format
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "kind": "signups",
+//!   "spar_series": "<public id of the series>",
+//!   "rows": [ ... ]
+//! }
+//! ```
+//!
+//! where each element of `rows` is an object with the same fields as the
+//! columns in the CSV file. Empty values are `null` in JSON (and empty
+//! strings in CSV). All identifiers are the public IDs used elsewhere in the
+//! site (e.g. in URLs), and times are in UTC in the format
+//! `YYYY-MM-DD HH:MM:SS`.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use db::{
+    ballot::{AdjudicatorBallot, BallotRepr, Score},
+    room::CHAIR,
+    schema::{
+        adjudicator_ballots, spar_adjudicators, spar_series,
+        spar_series_members, spar_signups, spar_speakers, spars,
+    },
+    spar::{Spar, SparSeries, SparSeriesMember, SparSignup},
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use maud::{html, Markup};
+use rocket::http::{ContentType, Header};
+use serde::Serialize;
+
+use crate::{
+    html::error_403,
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    util::tx,
+};
+
+/// Incremented whenever the fields of the exported rows change in a way
+/// which is not backwards compatible.
+const EXPORT_VERSION: u32 = 1;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportKind {
+    Signups,
+    Draw,
+    Ballots,
+    Attendance,
+}
+
+impl ExportKind {
+    fn of_name(name: &str) -> Option<Self> {
+        match name {
+            "signups" => Some(ExportKind::Signups),
+            "draw" => Some(ExportKind::Draw),
+            "ballots" => Some(ExportKind::Ballots),
+            "attendance" => Some(ExportKind::Attendance),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ExportKind::Signups => "signups",
+            ExportKind::Draw => "draw",
+            ExportKind::Ballots => "ballots",
+            ExportKind::Attendance => "attendance",
+        }
+    }
+}
+
+/// A row of one of the exports. `COLUMNS` must list the (serialized) names
+/// of the fields in the order that they should appear in the CSV file.
+trait ExportRow: Serialize {
+    const COLUMNS: &'static [&'static str];
+}
+
+#[derive(Serialize)]
+/// One person who signed up to a spar.
+struct SignupRow {
+    spar_id: String,
+    spar_start_time: String,
+    member_id: String,
+    name: String,
+    email: String,
+    as_speaker: bool,
+    as_judge: bool,
+    /// The member who this person asked to be paired with, if any.
+    partner_id: Option<String>,
+    partner_name: Option<String>,
+}
+
+impl ExportRow for SignupRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "spar_id",
+        "spar_start_time",
+        "member_id",
+        "name",
+        "email",
+        "as_speaker",
+        "as_judge",
+        "partner_id",
+        "partner_name",
+    ];
+}
+
+#[derive(Serialize)]
+/// One person's place in the confirmed draw.
+struct DrawRow {
+    spar_id: String,
+    spar_start_time: String,
+    /// The number of the room (as shown on the draw, starting from zero).
+    room: usize,
+    room_id: String,
+    /// One of `speaker`, `chair` or `panellist`.
+    role: &'static str,
+    /// One of `OG`, `OO`, `CG` or `CO` (only set for speakers).
+    team: Option<&'static str>,
+    member_id: String,
+    name: String,
+}
+
+impl ExportRow for DrawRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "spar_id",
+        "spar_start_time",
+        "room",
+        "room_id",
+        "role",
+        "team",
+        "member_id",
+        "name",
+    ];
+}
+
+#[derive(Serialize)]
+/// The score given to one speaker on one ballot. Every ballot which was
+/// submitted is included (including ballots which were later replaced), so
+/// use `is_canonical` to find the results of each room.
+struct BallotRow {
+    spar_id: String,
+    spar_start_time: String,
+    room: usize,
+    room_id: String,
+    ballot_id: String,
+    /// Whether this ballot is used as the result of the room.
+    is_canonical: bool,
+    submitted_at: String,
+    /// One of `link`, `account` or `admin`.
+    source: String,
+    adjudicator_id: String,
+    adjudicator_name: String,
+    /// One of `OG`, `OO`, `CG` or `CO`.
+    team: &'static str,
+    /// The rank of the team on this ballot (1 is first, 4 is last, or 2 in a
+    /// half-room).
+    team_rank: usize,
+    speaker_id: String,
+    speaker_name: String,
+    score: Score,
+}
+
+impl ExportRow for BallotRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "spar_id",
+        "spar_start_time",
+        "room",
+        "room_id",
+        "ballot_id",
+        "is_canonical",
+        "submitted_at",
+        "source",
+        "adjudicator_id",
+        "adjudicator_name",
+        "team",
+        "team_rank",
+        "speaker_id",
+        "speaker_name",
+        "score",
+    ];
+}
+
+#[derive(Serialize)]
+/// Whether someone who signed up to (or was placed in the draw for) a spar
+/// took part in it.
+struct AttendanceRow {
+    spar_id: String,
+    spar_start_time: String,
+    member_id: String,
+    name: String,
+    signed_up: bool,
+    /// One of `speaker` or `judge`, or empty if the person was not in the
+    /// draw.
+    attended_as: Option<&'static str>,
+}
+
+impl ExportRow for AttendanceRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "spar_id",
+        "spar_start_time",
+        "member_id",
+        "name",
+        "signed_up",
+        "attended_as",
+    ];
+}
+
+const POSITIONS: [&str; 4] = ["OG", "OO", "CG", "CO"];
+
+fn signup_rows(
+    spar: &Spar,
+    conn: &mut DbWrapper,
+) -> Result<Vec<SignupRow>, diesel::result::Error> {
+    let signups = spar_signups::table
+        .inner_join(
+            spar_series_members::table
+                .on(spar_series_members::id.eq(spar_signups::member_id)),
+        )
+        .filter(spar_signups::spar_id.eq(spar.id))
+        .order_by(spar_series_members::name.asc())
+        .load::<(SparSignup, SparSeriesMember)>(conn)?;
+
+    let partners = spar_series_members::table
+        .filter(
+            spar_series_members::id.eq_any(
+                signups
+                    .iter()
+                    .filter_map(|(signup, _)| signup.partner_preference)
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .load::<SparSeriesMember>(conn)?
+        .into_iter()
+        .map(|member| (member.id, member))
+        .collect::<HashMap<_, _>>();
+
+    Ok(signups
+        .into_iter()
+        .map(|(signup, member)| {
+            let partner =
+                signup.partner_preference.and_then(|id| partners.get(&id));
+            SignupRow {
+                spar_id: spar.public_id.clone(),
+                spar_start_time: spar
+                    .start_time
+                    .format(TIME_FORMAT)
+                    .to_string(),
+                member_id: member.public_id,
+                name: member.name,
+                email: member.email,
+                as_speaker: signup.as_speaker,
+                as_judge: signup.as_judge,
+                partner_id: partner.map(|p| p.public_id.clone()),
+                partner_name: partner.map(|p| p.name.clone()),
+            }
+        })
+        .collect())
+}
+
+fn draw_rows(
+    spar: &Spar,
+    conn: &mut DbWrapper,
+) -> Result<Vec<DrawRow>, diesel::result::Error> {
+    let mut rows = Vec::new();
+    for (number, room) in spar.rooms(conn)?.into_iter().enumerate() {
+        let row = |role, team, member: &SparSeriesMember| DrawRow {
+            spar_id: spar.public_id.clone(),
+            spar_start_time: spar.start_time.format(TIME_FORMAT).to_string(),
+            room: number,
+            room_id: room.inner.public_id.clone(),
+            role,
+            team,
+            member_id: member.public_id.clone(),
+            name: member.name.clone(),
+        };
+
+        for (team, position) in room.teams.iter().zip(POSITIONS) {
+            for speaker in &team.speakers {
+                let member = &room.members[&room.speakers[speaker].member_id];
+                rows.push(row("speaker", Some(position), member));
+            }
+        }
+        for judge in &room.judges {
+            let role = if judge.status == CHAIR {
+                "chair"
+            } else {
+                "panellist"
+            };
+            rows.push(row(role, None, &room.members[&judge.member_id]));
+        }
+    }
+    Ok(rows)
+}
+
+fn ballot_rows(
+    spar: &Spar,
+    conn: &mut DbWrapper,
+) -> Result<Vec<BallotRow>, diesel::result::Error> {
+    let mut rows = Vec::new();
+    for (number, room) in spar.rooms(conn)?.into_iter().enumerate() {
+        let canonical = room
+            .inner
+            .canonical_ballot(conn)?
+            .map(|ballot| ballot.inner.id);
+
+        let ballots = adjudicator_ballots::table
+            .filter(adjudicator_ballots::room_id.eq(room.inner.id))
+            .order_by((
+                adjudicator_ballots::created_at.asc(),
+                adjudicator_ballots::id.asc(),
+            ))
+            .load::<AdjudicatorBallot>(conn)?;
+
+        for ballot in ballots {
+            let adjudicator = spar_adjudicators::table
+                .filter(spar_adjudicators::id.eq(ballot.adjudicator_id))
+                .inner_join(spar_series_members::table)
+                .select(spar_series_members::all_columns)
+                .first::<SparSeriesMember>(conn)?;
+            let repr = BallotRepr::of_id(ballot.id, conn)?;
+            let ranking = repr.bp_ranking();
+
+            for (i, (team, position)) in
+                repr.scoresheet.teams.iter().zip(POSITIONS).enumerate()
+            {
+                let team_rank = ranking
+                    .iter()
+                    .position(|t| *t as usize == i)
+                    .map(|rank| rank + 1)
+                    .unwrap_or_default();
+                for speaker in &team.speakers {
+                    let member = match room.speakers.get(&speaker.speaker_id) {
+                        Some(s) => room.members[&s.member_id].clone(),
+                        None => member_of_speaker(speaker.speaker_id, conn)?,
+                    };
+                    rows.push(BallotRow {
+                        spar_id: spar.public_id.clone(),
+                        spar_start_time: spar
+                            .start_time
+                            .format(TIME_FORMAT)
+                            .to_string(),
+                        room: number,
+                        room_id: room.inner.public_id.clone(),
+                        ballot_id: ballot.public_id.clone(),
+                        is_canonical: canonical == Some(ballot.id),
+                        submitted_at: ballot
+                            .created_at
+                            .format(TIME_FORMAT)
+                            .to_string(),
+                        source: ballot.source.clone(),
+                        adjudicator_id: adjudicator.public_id.clone(),
+                        adjudicator_name: adjudicator.name.clone(),
+                        team: position,
+                        team_rank,
+                        speaker_id: member.public_id,
+                        speaker_name: member.name,
+                        score: speaker.score,
+                    });
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn member_of_speaker(
+    speaker_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<SparSeriesMember, diesel::result::Error> {
+    spar_speakers::table
+        .filter(spar_speakers::id.eq(speaker_id))
+        .inner_join(spar_series_members::table)
+        .select(spar_series_members::all_columns)
+        .first::<SparSeriesMember>(conn)
+}
+
+fn attendance_rows(
+    spar: &Spar,
+    conn: &mut DbWrapper,
+) -> Result<Vec<AttendanceRow>, diesel::result::Error> {
+    let signups = spar_signups::table
+        .inner_join(
+            spar_series_members::table
+                .on(spar_series_members::id.eq(spar_signups::member_id)),
+        )
+        .filter(spar_signups::spar_id.eq(spar.id))
+        .select(spar_series_members::all_columns)
+        .load::<SparSeriesMember>(conn)?;
+
+    // people who took part without signing up (e.g. who were added to the
+    // draw when they arrived late) are also included
+    let mut people: Vec<(SparSeriesMember, bool, Option<&'static str>)> =
+        signups
+            .into_iter()
+            .map(|member| (member, true, None))
+            .collect();
+    for room in spar.rooms(conn)? {
+        let speakers = room.speakers.values().map(|s| (s.member_id, "speaker"));
+        let judges = room.judges.iter().map(|j| (j.member_id, "judge"));
+        for (member_id, role) in speakers.chain(judges) {
+            match people.iter_mut().find(|(m, _, _)| m.id == member_id) {
+                Some((_, _, attended_as)) => *attended_as = Some(role),
+                None => people.push((
+                    room.members[&member_id].clone(),
+                    false,
+                    Some(role),
+                )),
+            }
+        }
+    }
+    people.sort_by(|(a, _, _), (b, _, _)| a.name.cmp(&b.name));
+
+    Ok(people
+        .into_iter()
+        .map(|(member, signed_up, attended_as)| AttendanceRow {
+            spar_id: spar.public_id.clone(),
+            spar_start_time: spar.start_time.format(TIME_FORMAT).to_string(),
+            member_id: member.public_id,
+            name: member.name,
+            signed_up,
+            attended_as,
+        })
+        .collect())
+}
+
+/// Formats a single CSV field, quoting it if necessary (as described in
+/// RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Prefixes text which a spreadsheet would otherwise interpret as a formula
+/// with a `'` (names and emails are entered by members of the public, and the
+/// export is likely to be opened in a spreadsheet).
+fn escape_formula(text: &str) -> Cow<'_, str> {
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{text}"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn to_csv<R: ExportRow>(rows: &[R]) -> String {
+    let mut ret = R::COLUMNS.join(",");
+    ret.push_str("\r\n");
+    for row in rows {
+        let value = serde_json::to_value(row).unwrap();
+        let fields = R::COLUMNS
+            .iter()
+            .map(|column| match &value[column] {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(s) => csv_field(&escape_formula(s)),
+                other => csv_field(&other.to_string()),
+            })
+            .collect::<Vec<_>>();
+        ret.push_str(&fields.join(","));
+        ret.push_str("\r\n");
+    }
+    ret
+}
+
+fn to_json<R: ExportRow>(
+    kind: ExportKind,
+    series: &SparSeries,
+    rows: &[R],
+) -> String {
+    serde_json::to_string_pretty(&serde_json::json!({
+        "version": EXPORT_VERSION,
+        "kind": kind.name(),
+        "spar_series": series.public_id,
+        "rows": rows,
+    }))
+    .unwrap()
+}
+
+fn render<R: ExportRow>(
+    kind: ExportKind,
+    format: ExportFormat,
+    series: &SparSeries,
+    rows: &[R],
+) -> String {
+    match format {
+        ExportFormat::Csv => to_csv(rows),
+        ExportFormat::Json => to_json(kind, series, rows),
+    }
+}
+
+/// Produces the export for all of the given spars (which should all be part
+/// of `series`).
+fn export(
+    kind: ExportKind,
+    format: ExportFormat,
+    series: &SparSeries,
+    spars: &[Spar],
+    conn: &mut DbWrapper,
+) -> Result<String, diesel::result::Error> {
+    macro_rules! rows_of {
+        ($f:ident) => {{
+            let mut rows = Vec::new();
+            for spar in spars {
+                rows.extend($f(spar, conn)?);
+            }
+            render(kind, format, series, &rows)
+        }};
+    }
+
+    Ok(match kind {
+        ExportKind::Signups => rows_of!(signup_rows),
+        ExportKind::Draw => rows_of!(draw_rows),
+        ExportKind::Ballots => rows_of!(ballot_rows),
+        ExportKind::Attendance => rows_of!(attendance_rows),
+    })
+}
+
+/// Links to download each of the exports, where `base` is the URL of the
+/// spar or series (e.g. `/spars/<spar_id>`).
+pub fn render_export_links(base: &str) -> Markup {
+    let kinds = [
+        (ExportKind::Signups, "Signups"),
+        (ExportKind::Draw, "Draw"),
+        (ExportKind::Ballots, "Ballots"),
+        (ExportKind::Attendance, "Attendance"),
+    ];
+    html! {
+        table class="table table-sm w-auto" {
+            tbody {
+                @for (kind, label) in kinds {
+                    tr {
+                        td { (label) }
+                        td {
+                            a href=(format!("{base}/export/{}", kind.name())) { "CSV" }
+                        }
+                        td {
+                            a href=(format!("{base}/export/{}?format=json", kind.name())) { "JSON" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Responder)]
+/// A file which the browser should download (rather than display).
+pub struct Download {
+    inner: (ContentType, String),
+    disposition: Header<'static>,
+}
+
+impl Download {
+    fn new(filename: String, format: ExportFormat, contents: String) -> Self {
+        let (content_type, extension) = match format {
+            ExportFormat::Csv => (ContentType::CSV, "csv"),
+            ExportFormat::Json => (ContentType::JSON, "json"),
+        };
+        Download {
+            inner: (content_type, contents),
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{filename}.{extension}\""),
+            ),
+        }
+    }
+}
+
+#[get("/spars/<spar_id>/export/<kind>?<format>")]
+/// Downloads one of the exports (see the module documentation) for a single
+/// spar.
+pub async fn export_spar(
+    spar_id: &str,
+    kind: &str,
+    format: Option<ExportFormat>,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<Download, Markup>> {
+    let spar_id = spar_id.to_string();
+    let kind = ExportKind::of_name(kind)?;
+    let format = format.unwrap_or_default();
+    tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+        let series = spar_series::table
+            .filter(spar_series::id.eq(spar.spar_series_id))
+            .first::<SparSeries>(conn)
+            .unwrap();
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+            conn,
+        ) {
+            return Some(Err(error_403(
+                Some("Error: you are not authorized to export this spar!"),
+                Some(user),
+            )));
+        }
+
+        let contents =
+            export(kind, format, &series, std::slice::from_ref(&spar), conn)
+                .unwrap();
+        let filename = format!(
+            "spar-{}-{}",
+            spar.start_time.format("%Y-%m-%d"),
+            kind.name()
+        );
+        Some(Ok(Download::new(filename, format, contents)))
+    })
+    .await
+}
+
+#[get("/spar_series/<spar_series_id>/export/<kind>?<format>")]
+/// Downloads one of the exports (see the module documentation) for every
+/// spar in the series.
+pub async fn export_series(
+    spar_series_id: &str,
+    kind: &str,
+    format: Option<ExportFormat>,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<Download, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let kind = ExportKind::of_name(kind)?;
+    let format = format.unwrap_or_default();
+    tx(span, db, move |conn| {
+        let series = spar_series::table
+            .filter(spar_series::public_id.eq(&spar_series_id))
+            .first::<SparSeries>(conn)
+            .optional()
+            .unwrap()?;
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+            conn,
+        ) {
+            return Some(Err(error_403(
+                Some("Error: you are not authorized to export this series!"),
+                Some(user),
+            )));
+        }
+
+        let spars = spars::table
+            .filter(spars::spar_series_id.eq(series.id))
+            .order_by(spars::start_time.asc())
+            .load::<Spar>(conn)
+            .unwrap();
+        let contents = export(kind, format, &series, &spars, conn).unwrap();
+        let filename = format!("{}-{}", series.title, kind.name())
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        Some(Ok(Download::new(filename, format, contents)))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test_csv {
+    use serde::Serialize;
+
+    use super::{to_csv, ExportRow};
+
+    #[derive(Serialize)]
+    struct Row {
+        name: String,
+        score: Option<i64>,
+    }
+
+    impl ExportRow for Row {
+        const COLUMNS: &'static [&'static str] = &["name", "score"];
+    }
+
+    #[test]
+    fn test_quotes_fields() {
+        let rows = [
+            Row {
+                name: "Plain".to_string(),
+                score: Some(75),
+            },
+            Row {
+                name: "Smith, \"Jo\"".to_string(),
+                score: None,
+            },
+        ];
+        assert_eq!(
+            to_csv(&rows),
+            "name,score\r\nPlain,75\r\n\"Smith, \"\"Jo\"\"\",\r\n"
+        );
+    }
+
+    #[test]
+    fn test_escapes_formulas() {
+        let rows = ["=1+1", "+44 20", "-2", "@SUM(A1)", "\tTab", "\rReturn"]
+            .into_iter()
+            .map(|name| Row {
+                name: name.to_string(),
+                score: Some(-1),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            to_csv(&rows),
+            "name,score\r\n\
+             '=1+1,-1\r\n\
+             '+44 20,-1\r\n\
+             '-2,-1\r\n\
+             '@SUM(A1),-1\r\n\
+             '\tTab,-1\r\n\
+             \"'\rReturn\",-1\r\n"
+        );
+    }
+}
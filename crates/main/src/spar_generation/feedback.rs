@@ -0,0 +1,641 @@
+//! Feedback between speakers and adjudicators.
+//!
+//! Once a spar has been marked as complete, every speaker is emailed the
+//! written feedback that their adjudicators left on their ballots, along with
+//! a private link. The link can be used to view all the feedback they have
+//! received in the spar series, and to give feedback on each of their
+//! adjudicators (an optional score, and some comments). Feedback on
+//! adjudicators is only visible to the administrators of the spar series.
+
+use std::collections::HashMap;
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use db::{
+    feedback::{AdjudicatorFeedback, SpeakerFeedbackLink, MAX_FEEDBACK_SCORE},
+    room::{SparRoom, SparRoomRepr},
+    schema::{
+        adjudicator_ballot_entries, adjudicator_ballot_team_comments,
+        adjudicator_feedback, spar_adjudicators, spar_rooms, spar_series,
+        spar_series_members, spar_speakers, spar_teams, spars,
+        speaker_feedback_links,
+    },
+    spar::{
+        SparRoomAdjudicator, SparRoomTeamSpeaker, SparSeries, SparSeriesMember,
+    },
+    user::User,
+    DbConn,
+};
+use diesel::{
+    connection::LoadConnection, dsl::insert_into, prelude::*, sqlite::Sqlite,
+};
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    html::{error_403, error_404, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    util::tx,
+};
+
+/// How long speakers have to submit feedback after the spar is completed.
+const FEEDBACK_LINK_VALIDITY_DAYS: i64 = 7;
+
+/// Creates a feedback link for every speaker in the given spar (who does not
+/// already have one), returning the newly created links along with the
+/// speaker and member they were created for.
+pub fn create_feedback_links(
+    spar_id: i64,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<
+    Vec<(SparRoomTeamSpeaker, SparSeriesMember, String)>,
+    diesel::result::Error,
+> {
+    let speakers = spar_speakers::table
+        .inner_join(spar_teams::table.inner_join(spar_rooms::table))
+        .inner_join(spar_series_members::table)
+        .filter(spar_rooms::spar_id.eq(spar_id))
+        .select((spar_speakers::all_columns, spar_series_members::all_columns))
+        .load::<(SparRoomTeamSpeaker, SparSeriesMember)>(conn)?;
+
+    let already_have_links = speaker_feedback_links::table
+        .filter(
+            speaker_feedback_links::speaker_id.eq_any(
+                speakers
+                    .iter()
+                    .map(|(speaker, _)| speaker.id)
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .select(speaker_feedback_links::speaker_id)
+        .load::<i64>(conn)?;
+
+    let mut ret = Vec::with_capacity(speakers.len());
+    for (speaker, member) in speakers {
+        if already_have_links.contains(&speaker.id) {
+            continue;
+        }
+
+        let link = Uuid::new_v4().to_string();
+        insert_into(speaker_feedback_links::table)
+            .values((
+                speaker_feedback_links::public_id
+                    .eq(Uuid::now_v7().to_string()),
+                speaker_feedback_links::link.eq(&link),
+                speaker_feedback_links::speaker_id.eq(speaker.id),
+                speaker_feedback_links::created_at.eq(diesel::dsl::now),
+                speaker_feedback_links::expires_at.eq(Utc::now()
+                    .naive_utc()
+                    .checked_add_signed(TimeDelta::days(
+                        FEEDBACK_LINK_VALIDITY_DAYS,
+                    ))
+                    .unwrap()),
+            ))
+            .execute(conn)?;
+        ret.push((speaker, member, link));
+    }
+
+    Ok(ret)
+}
+
+/// Written feedback given to a speaker (and their team) by one of the
+/// adjudicators in their room.
+pub struct ReceivedComments {
+    /// The name of the adjudicator.
+    pub adjudicator: String,
+    pub speaker: Option<String>,
+    pub team: Option<String>,
+}
+
+/// Collects the written feedback given to the speaker with the given id by
+/// each adjudicator in their room (using the latest ballot submitted by each
+/// adjudicator).
+pub fn comments_for_speaker(
+    speaker_id: i64,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Vec<ReceivedComments>, diesel::result::Error> {
+    let (team_id, room) = spar_speakers::table
+        .inner_join(spar_teams::table.inner_join(spar_rooms::table))
+        .filter(spar_speakers::id.eq(speaker_id))
+        .select((spar_teams::id, spar_rooms::all_columns))
+        .first::<(i64, SparRoom)>(conn)?;
+
+    let mut ret = Vec::new();
+    for ballot in room.latest_ballots(conn)? {
+        let speaker = adjudicator_ballot_entries::table
+            .filter(adjudicator_ballot_entries::ballot_id.eq(ballot.inner.id))
+            .filter(adjudicator_ballot_entries::speaker_id.eq(speaker_id))
+            .select(adjudicator_ballot_entries::comments)
+            .first::<Option<String>>(conn)
+            .optional()?
+            .flatten();
+        let team = adjudicator_ballot_team_comments::table
+            .filter(
+                adjudicator_ballot_team_comments::ballot_id.eq(ballot.inner.id),
+            )
+            .filter(adjudicator_ballot_team_comments::team_id.eq(team_id))
+            .select(adjudicator_ballot_team_comments::comments)
+            .first::<String>(conn)
+            .optional()?;
+
+        if speaker.is_none() && team.is_none() {
+            continue;
+        }
+
+        let adjudicator = spar_adjudicators::table
+            .inner_join(spar_series_members::table)
+            .filter(spar_adjudicators::id.eq(ballot.inner.adjudicator_id))
+            .select(spar_series_members::name)
+            .first::<String>(conn)?;
+
+        ret.push(ReceivedComments {
+            adjudicator,
+            speaker,
+            team,
+        });
+    }
+
+    Ok(ret)
+}
+
+/// Displays the written feedback given to a speaker in a single spar.
+pub fn render_received_comments(comments: &[ReceivedComments]) -> Markup {
+    html! {
+        @if comments.is_empty() {
+            p { "Your adjudicators did not leave any written feedback." }
+        }
+        @for comment in comments {
+            h5 { "From " (comment.adjudicator) }
+            @if let Some(speaker) = &comment.speaker {
+                p { b { "For you: " } (speaker) }
+            }
+            @if let Some(team) = &comment.team {
+                p { b { "For your team: " } (team) }
+            }
+        }
+    }
+}
+
+/// Plain-text version of [`render_received_comments`] (for emails).
+pub fn text_of_received_comments(comments: &[ReceivedComments]) -> String {
+    if comments.is_empty() {
+        return "Your adjudicators did not leave any written feedback."
+            .to_string();
+    }
+
+    comments
+        .iter()
+        .map(|comment| {
+            let mut text = format!("From {}:", comment.adjudicator);
+            if let Some(speaker) = &comment.speaker {
+                text.push_str(&format!("\n  For you: {speaker}"));
+            }
+            if let Some(team) = &comment.team {
+                text.push_str(&format!("\n  For your team: {team}"));
+            }
+            text
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Computes the mean feedback score given to each member of the spar series
+/// when they adjudicated (members who have not been given any scores are
+/// omitted). Returns a map from member ids to `(mean score, number of
+/// scores)`.
+pub fn judge_quality_scores(
+    spar_series_id: i64,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<HashMap<i64, (f64, usize)>, diesel::result::Error> {
+    let scores = adjudicator_feedback::table
+        .inner_join(
+            spar_adjudicators::table
+                .inner_join(spar_rooms::table.inner_join(spars::table)),
+        )
+        .filter(spars::spar_series_id.eq(spar_series_id))
+        .filter(adjudicator_feedback::score.is_not_null())
+        .select((spar_adjudicators::member_id, adjudicator_feedback::score))
+        .load::<(i64, Option<i64>)>(conn)?;
+
+    let mut totals: HashMap<i64, (i64, usize)> = HashMap::new();
+    for (member_id, score) in scores {
+        let entry = totals.entry(member_id).or_default();
+        entry.0 += score.unwrap();
+        entry.1 += 1;
+    }
+
+    Ok(totals
+        .into_iter()
+        .map(|(member_id, (total, n))| {
+            (member_id, (total as f64 / n as f64, n))
+        })
+        .collect())
+}
+
+/// Loads the (unexpired) feedback link with the given key, along with the
+/// room that the speaker spoke in.
+fn load_feedback_link(
+    key: &str,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<(SpeakerFeedbackLink, SparRoomRepr)>, diesel::result::Error>
+{
+    let link = match speaker_feedback_links::table
+        .filter(speaker_feedback_links::link.eq(key))
+        .filter(speaker_feedback_links::expires_at.gt(diesel::dsl::now))
+        .first::<SpeakerFeedbackLink>(conn)
+        .optional()?
+    {
+        Some(link) => link,
+        None => return Ok(None),
+    };
+
+    let room_id = spar_speakers::table
+        .inner_join(spar_teams::table)
+        .filter(spar_speakers::id.eq(link.speaker_id))
+        .select(spar_teams::room_id)
+        .first::<i64>(conn)?;
+
+    Ok(Some((link, SparRoomRepr::of_id(room_id, conn)?)))
+}
+
+#[get("/feedback/submit/<key>")]
+/// Allows a speaker to give feedback on each of the adjudicators in their
+/// room.
+pub async fn submit_feedback_page(
+    key: &str,
+    user: Option<User>,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Markup {
+    let key = key.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let (link, room) = match load_feedback_link(&key, conn).unwrap() {
+            Some(t) => t,
+            None => {
+                return error_404(
+                    Some("Error: no such link (perhaps it has expired?)"),
+                    user,
+                )
+            }
+        };
+
+        let existing = adjudicator_feedback::table
+            .filter(adjudicator_feedback::speaker_id.eq(link.speaker_id))
+            .load::<AdjudicatorFeedback>(conn)
+            .unwrap()
+            .into_iter()
+            .map(|feedback| (feedback.adjudicator_id, feedback))
+            .collect::<HashMap<_, _>>();
+
+        let markup = html! {
+            (page_title("Adjudicator feedback"))
+            p {
+                "Your feedback is only visible to the people running this
+                 spar series (the adjudicators cannot see it)."
+            }
+            @if room.judges.is_empty() {
+                p { "There were no adjudicators in your room." }
+            }
+            @for judge in &room.judges {
+                @let previous = existing.get(&judge.id);
+                div class="card mb-3" {
+                    div class="card-body" {
+                        h5 class="card-title" {
+                            (room.members[&judge.member_id].name)
+                        }
+                        @if previous.is_some() {
+                            p class="text-muted" {
+                                "You have already given feedback on this
+                                 adjudicator. Submitting this form again will
+                                 replace it."
+                            }
+                        }
+                        form method="post" {
+                            input type="hidden" name="adjudicator" value=(judge.public_id) {}
+                            div class="mb-3" {
+                                label class="form-label" for=(format!("score-{}", judge.public_id)) {
+                                    "Score (optional, out of " (MAX_FEEDBACK_SCORE) ")"
+                                }
+                                select class="form-select" name="score" id=(format!("score-{}", judge.public_id)) {
+                                    option value="" { "No score" }
+                                    @for score in 1..=MAX_FEEDBACK_SCORE {
+                                        option value=(score) selected[previous.and_then(|p| p.score) == Some(score)] {
+                                            (score)
+                                        }
+                                    }
+                                }
+                            }
+                            div class="mb-3" {
+                                label class="form-label" for=(format!("comments-{}", judge.public_id)) {
+                                    "Comments"
+                                }
+                                textarea class="form-control" name="comments" id=(format!("comments-{}", judge.public_id)) rows="3" {
+                                    (previous.and_then(|p| p.comments.clone()).unwrap_or_default())
+                                }
+                            }
+                            button type="submit" class="btn btn-primary" { "Submit feedback" }
+                        }
+                    }
+                }
+            }
+        };
+
+        page_of_body_and_flash_msg(markup, msg, user)
+    })
+    .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct FeedbackForm {
+    /// The public id of the `spar_adjudicators` row.
+    pub adjudicator: String,
+    pub score: Option<i64>,
+    pub comments: String,
+}
+
+#[post("/feedback/submit/<key>", data = "<form>")]
+pub async fn do_submit_feedback(
+    key: &str,
+    user: Option<User>,
+    db: DbConn,
+    form: Form<FeedbackForm>,
+    span: TracingSpan,
+) -> Result<Flash<Redirect>, Markup> {
+    let key = key.to_string();
+    tx(span, db, move |conn| {
+        let (link, room) = match load_feedback_link(&key, conn).unwrap() {
+            Some(t) => t,
+            None => {
+                return Err(error_404(
+                    Some("Error: no such link (perhaps it has expired?)"),
+                    user,
+                ))
+            }
+        };
+
+        let redirect_to = Redirect::to(format!("/feedback/submit/{key}"));
+
+        let judge = match room
+            .judges
+            .iter()
+            .find(|judge| judge.public_id == form.adjudicator)
+        {
+            Some(judge) => judge,
+            None => {
+                return Ok(Flash::error(
+                    redirect_to,
+                    "Error: that adjudicator was not in your room.",
+                ))
+            }
+        };
+
+        if let Some(score) = form.score {
+            if !(1..=MAX_FEEDBACK_SCORE).contains(&score) {
+                return Ok(Flash::error(
+                    redirect_to,
+                    format!(
+                        "Error: scores must be between 1 and \
+                         {MAX_FEEDBACK_SCORE}."
+                    ),
+                ));
+            }
+        }
+
+        let comments =
+            Some(form.comments.trim()).filter(|comments| !comments.is_empty());
+
+        diesel::delete(
+            adjudicator_feedback::table
+                .filter(adjudicator_feedback::adjudicator_id.eq(judge.id))
+                .filter(adjudicator_feedback::speaker_id.eq(link.speaker_id)),
+        )
+        .execute(conn)
+        .unwrap();
+
+        insert_into(adjudicator_feedback::table)
+            .values((
+                adjudicator_feedback::public_id.eq(Uuid::now_v7().to_string()),
+                adjudicator_feedback::adjudicator_id.eq(judge.id),
+                adjudicator_feedback::speaker_id.eq(link.speaker_id),
+                adjudicator_feedback::score.eq(form.score),
+                adjudicator_feedback::comments.eq(comments),
+                adjudicator_feedback::created_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        Ok(Flash::success(
+            redirect_to,
+            format!(
+                "Thanks! Your feedback on {} has been recorded.",
+                room.members[&judge.member_id].name
+            ),
+        ))
+    })
+    .await
+}
+
+#[get("/feedback/history/<key>")]
+/// Shows a speaker all the written feedback their adjudicators have given
+/// them in completed spars in this spar series.
+///
+/// Unlike [`submit_feedback_page`], this can still be viewed once the link
+/// has expired.
+pub async fn feedback_history_page(
+    key: &str,
+    user: Option<User>,
+    db: DbConn,
+    span: TracingSpan,
+) -> Markup {
+    let key = key.to_string();
+    tx(span, db, move |conn| {
+        let member = match speaker_feedback_links::table
+            .filter(speaker_feedback_links::link.eq(&key))
+            .inner_join(
+                spar_speakers::table.inner_join(spar_series_members::table),
+            )
+            .select(spar_series_members::all_columns)
+            .first::<SparSeriesMember>(conn)
+            .optional()
+            .unwrap()
+        {
+            Some(member) => member,
+            None => return error_404(Some("Error: no such link"), user),
+        };
+
+        let spars_spoken_in = spar_speakers::table
+            .inner_join(
+                spar_teams::table
+                    .inner_join(spar_rooms::table.inner_join(spars::table)),
+            )
+            .filter(spar_speakers::member_id.eq(member.id))
+            .filter(spars::is_complete.eq(true))
+            .order_by(spars::start_time.desc())
+            .select((spar_speakers::id, spars::start_time))
+            .load::<(i64, NaiveDateTime)>(conn)
+            .unwrap();
+
+        let history = spars_spoken_in
+            .into_iter()
+            .map(|(speaker_id, start_time)| {
+                (start_time, comments_for_speaker(speaker_id, conn).unwrap())
+            })
+            .collect::<Vec<_>>();
+
+        page_of_body_and_flash_msg(
+            html! {
+                (page_title(format!("Feedback for {}", member.name)))
+                @if history.is_empty() {
+                    p { "You have not spoken in any completed spars yet." }
+                }
+                @for (start_time, comments) in &history {
+                    h3 { (start_time.format("%Y-%m-%d %H:%M")) }
+                    (render_received_comments(comments))
+                }
+            },
+            None,
+            user,
+        )
+    })
+    .await
+}
+
+#[get("/spar_series/<spar_series_id>/adjudicator_feedback")]
+/// Shows series administrators all of the feedback that speakers have given
+/// on adjudicators in this spar series.
+pub async fn adjudicator_feedback_page(
+    spar_series_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_series_id = spar_series_id.to_string();
+    tx(span, db, move |conn| {
+        let series = spar_series::table
+            .filter(spar_series::public_id.eq(&spar_series_id))
+            .first::<SparSeries>(conn)
+            .optional()
+            .unwrap()?;
+
+        let required_permission = Permission::ModifyResourceInGroup(
+            crate::resources::GroupRef(series.group_id),
+        );
+        if !has_permission(Some(&user), &required_permission, conn) {
+            return Some(error_403(
+                Some("Error: you are not authorized to view this page!"),
+                Some(user),
+            ));
+        }
+
+        let members = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(series.id))
+            .load::<SparSeriesMember>(conn)
+            .unwrap()
+            .into_iter()
+            .map(|member| (member.id, member))
+            .collect::<HashMap<_, _>>();
+
+        let feedback = adjudicator_feedback::table
+            .inner_join(
+                spar_adjudicators::table
+                    .inner_join(spar_rooms::table.inner_join(spars::table)),
+            )
+            .filter(spars::spar_series_id.eq(series.id))
+            .order_by(spars::start_time.desc())
+            .select((
+                adjudicator_feedback::all_columns,
+                spar_adjudicators::all_columns,
+                spars::start_time,
+            ))
+            .load::<(AdjudicatorFeedback, SparRoomAdjudicator, NaiveDateTime)>(
+                conn,
+            )
+            .unwrap();
+
+        let speaker_members = spar_speakers::table
+            .filter(
+                spar_speakers::id.eq_any(
+                    feedback
+                        .iter()
+                        .map(|(feedback, _, _)| feedback.speaker_id)
+                        .collect::<Vec<_>>(),
+                ),
+            )
+            .select((spar_speakers::id, spar_speakers::member_id))
+            .load::<(i64, i64)>(conn)
+            .unwrap()
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        let mut by_judge: HashMap<i64, Vec<_>> = HashMap::new();
+        for (feedback, judge, start_time) in &feedback {
+            by_judge
+                .entry(judge.member_id)
+                .or_default()
+                .push((feedback, start_time));
+        }
+        let mut judges = by_judge.keys().copied().collect::<Vec<_>>();
+        judges.sort_by_key(|id| members[id].name.clone());
+
+        let quality = judge_quality_scores(series.id, conn).unwrap();
+
+        Some(page_of_body_and_flash_msg(
+            html! {
+                (page_title(format!("Adjudicator feedback for {}", series.title)))
+                @if judges.is_empty() {
+                    p { "No feedback has been submitted yet." }
+                }
+                @for judge in &judges {
+                    h3 { (members[judge].name) }
+                    p {
+                        @if let Some((mean, n)) = quality.get(judge) {
+                            "Mean score: " (format!("{mean:.1}")) " (from "
+                            (n) " scores)"
+                        } @else {
+                            "No scores given."
+                        }
+                    }
+                    table class="table" {
+                        thead {
+                            tr {
+                                th scope="col" { "Spar" }
+                                th scope="col" { "Speaker" }
+                                th scope="col" { "Score" }
+                                th scope="col" { "Comments" }
+                            }
+                        }
+                        tbody {
+                            @for (feedback, start_time) in &by_judge[judge] {
+                                tr {
+                                    td { (start_time.format("%Y-%m-%d %H:%M")) }
+                                    td { (members[&speaker_members[&feedback.speaker_id]].name) }
+                                    td {
+                                        @if let Some(score) = feedback.score {
+                                            (score)
+                                        }
+                                    }
+                                    td {
+                                        @if let Some(comments) = &feedback.comments {
+                                            (comments)
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            None,
+            Some(user),
+        ))
+    })
+    .await
+}
@@ -0,0 +1,764 @@
+//! Adjusts a draw after it has been released, when some people do not turn
+//! up (or turn up late). The repair itself is worked out by
+//! [`db::draw_repair::propose_repair`]; this module shows the proposal to the
+//! administrator and then applies it to the rooms of the spar.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use chrono::{TimeDelta, Utc};
+use db::{
+    ballot::AdjudicatorBallotLink,
+    draft_draw::{DraftDrawData, DraftDrawRoom, DrawLoc, Team},
+    draw_repair::{propose_repair, LateArrival, Repair},
+    room::{SparRoomRepr, CHAIR, PANELLIST},
+    schema::{
+        adjudicator_ballots, spar_adjudicator_ballot_links, spar_adjudicators,
+        spar_rooms, spar_series, spar_series_members, spar_speakers, spars,
+    },
+    spar::{Spar, SparRoom, SparSeries, SparSeriesMember},
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use email::send_mail;
+use itertools::Itertools;
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    spar_generation::individual_spars::{
+        ballot_links::mail_ballot_link,
+        draw_management::validation::member_names,
+    },
+    util::tx,
+};
+
+/// How long new (or reissued) ballot links last for.
+const ADJUSTED_LINK_HOURS: i64 = 5;
+
+#[derive(FromForm, Serialize, Default)]
+pub struct AdjustDrawForm {
+    /// Members (in the draw) who are absent.
+    pub absent: Vec<i64>,
+    /// Members (not in the draw) who have arrived late and will judge.
+    pub late_judges: Vec<i64>,
+    /// Members (not in the draw) who have arrived late and will speak.
+    pub late_speakers: Vec<i64>,
+}
+
+impl AdjustDrawForm {
+    fn is_empty(&self) -> bool {
+        self.absent.is_empty()
+            && self.late_judges.is_empty()
+            && self.late_speakers.is_empty()
+    }
+}
+
+/// Loads the rooms of the spar (in the order they are shown to
+/// administrators), along with the draw in the same form as a draft draw.
+pub fn released_draw(
+    spar_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<(Vec<SparRoomRepr>, DraftDrawData), diesel::result::Error> {
+    let rooms = spar_rooms::table
+        .filter(spar_rooms::spar_id.eq(spar_id))
+        .order_by(spar_rooms::public_id)
+        .load::<SparRoom>(conn)?
+        .into_iter()
+        .map(|room| SparRoomRepr::of_id(room.id, conn))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut data = DraftDrawData {
+        rooms: rooms
+            .iter()
+            .map(|room| DraftDrawRoom {
+                panel: room
+                    .judges
+                    .iter()
+                    .map(|judge| judge.member_id)
+                    .collect(),
+                teams: room
+                    .teams
+                    .iter()
+                    .zip(Team::ALL)
+                    .map(|(team, position)| {
+                        (
+                            position,
+                            team.speakers
+                                .iter()
+                                .map(|speaker| room.speakers[speaker].member_id)
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+                chair: room
+                    .judges
+                    .iter()
+                    .find(|judge| judge.status == CHAIR)
+                    .map(|judge| judge.member_id),
+            })
+            .collect(),
+        id_map: HashMap::new(),
+    };
+    data.generate_map();
+
+    Ok((rooms, data))
+}
+
+/// Loads the spar (and its series), if the user is allowed to adjust its
+/// draw.
+fn load_spar_for_adjustment(
+    spar_id: &str,
+    user: &User,
+    conn: &mut DbWrapper,
+) -> Option<Result<(Spar, SparSeries), Markup>> {
+    let spar = spars::table
+        .filter(spars::public_id.eq(spar_id))
+        .first::<Spar>(conn)
+        .optional()
+        .unwrap()?;
+
+    let series = spar_series::table
+        .filter(spar_series::id.eq(spar.spar_series_id))
+        .first::<SparSeries>(conn)
+        .unwrap();
+    if !has_permission(
+        Some(user),
+        &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+        conn,
+    ) {
+        return Some(Err(error_403(
+            Some("Error: you are not authorized to adjust this draw!"),
+            Some(user.clone()),
+        )));
+    }
+
+    Some(Ok((spar, series)))
+}
+
+/// Works out the repair for the absences and late arrivals in the form
+/// (ignoring any members who are not part of the spar series, or who are
+/// already in the draw).
+fn repair_of_form(
+    form: &AdjustDrawForm,
+    data: &DraftDrawData,
+    series: &SparSeries,
+    members: &[SparSeriesMember],
+) -> Repair {
+    let in_draw = data.id_map.keys().copied().collect::<HashSet<_>>();
+    let absent = form
+        .absent
+        .iter()
+        .copied()
+        .filter(|member| in_draw.contains(member))
+        .collect::<HashSet<_>>();
+    let arrivals = form
+        .late_judges
+        .iter()
+        .map(|member| (*member, true))
+        .chain(form.late_speakers.iter().map(|member| (*member, false)))
+        .filter(|(member, _)| {
+            !in_draw.contains(member) && members.iter().any(|m| m.id == *member)
+        })
+        .unique_by(|(member, _)| *member)
+        .map(|(member, as_judge)| LateArrival { member, as_judge })
+        .collect::<Vec<_>>();
+
+    propose_repair(data, &absent, &arrivals, series.speakers_per_team as usize)
+}
+
+/// Returns the rooms (by index) which the repair changes, and which already
+/// have ballots (these cannot be adjusted).
+fn changed_rooms_with_ballots(
+    rooms: &[SparRoomRepr],
+    changed_rooms: &[usize],
+    conn: &mut DbWrapper,
+) -> Vec<usize> {
+    changed_rooms
+        .iter()
+        .copied()
+        .filter(|idx| {
+            diesel::select(diesel::dsl::exists(
+                adjudicator_ballots::table.filter(
+                    adjudicator_ballots::room_id.eq(rooms[*idx].inner.id),
+                ),
+            ))
+            .get_result::<bool>(conn)
+            .unwrap()
+        })
+        .collect()
+}
+
+fn describe_loc(loc: DrawLoc) -> String {
+    match loc {
+        DrawLoc::Panel => "judging".to_string(),
+        DrawLoc::Team(team) => format!("speaking in {}", team.abbreviation()),
+    }
+}
+
+fn render_room(
+    idx: usize,
+    room: &DraftDrawRoom,
+    names: &HashMap<i64, String>,
+) -> Markup {
+    let name_of = |member: &i64| {
+        names
+            .get(member)
+            .cloned()
+            .unwrap_or_else(|| "(unknown member)".to_string())
+    };
+
+    html! {
+        tr {
+            td { (idx) }
+            @for team in Team::ALL {
+                td {
+                    @for member in room.teams.get(&team).into_iter().flatten().sorted() {
+                        div { (name_of(member)) }
+                    }
+                }
+            }
+            td {
+                @for member in room.panel.iter().sorted() {
+                    div {
+                        (name_of(member))
+                        @if room.chair == Some(*member) { " (chair)" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_rooms(
+    rooms: &[(usize, &DraftDrawRoom)],
+    names: &HashMap<i64, String>,
+) -> Markup {
+    html! {
+        table class="table table-sm table-bordered" {
+            thead {
+                tr {
+                    th { "Room" }
+                    th { "OG" }
+                    th { "OO" }
+                    th { "CG" }
+                    th { "CO" }
+                    th { "Panel" }
+                }
+            }
+            tbody {
+                @for (idx, room) in rooms {
+                    (render_room(*idx, room, names))
+                }
+            }
+        }
+    }
+}
+
+fn render_proposal(
+    spar: &Spar,
+    form: &AdjustDrawForm,
+    data: &DraftDrawData,
+    repair: &Repair,
+    with_ballots: &[usize],
+    names: &HashMap<i64, String>,
+) -> Markup {
+    let name_of = |member: i64| {
+        names
+            .get(&member)
+            .cloned()
+            .unwrap_or_else(|| "(unknown member)".to_string())
+    };
+    let diff = data.diff(&repair.data);
+    let closed = repair.closed_rooms();
+
+    html! {
+        h3 { "Proposed changes" }
+        @if repair.steps.is_empty() {
+            div class="alert alert-info" { "The draw does not need to be changed." }
+        } @else {
+            ol {
+                @for step in &repair.steps {
+                    li class=[step.is_unresolved().then_some("text-danger")] {
+                        (step.describe(&name_of))
+                    }
+                }
+            }
+
+            @if !with_ballots.is_empty() {
+                div class="alert alert-danger" {
+                    "This would change rooms in which ballots have already been
+                     submitted ("
+                    (with_ballots.iter().join(", "))
+                    "), so it cannot be applied."
+                }
+            }
+
+            h4 { "Before" }
+            (render_rooms(&diff.changed_rooms.iter().map(|idx| (*idx, &data.rooms[*idx])).collect::<Vec<_>>(), names))
+            h4 { "After" }
+            (render_rooms(&diff.changed_rooms.iter().filter(|idx| !closed.contains(idx)).map(|idx| (*idx, &repair.data.rooms[*idx])).collect::<Vec<_>>(), names))
+            @if !closed.is_empty() {
+                p { "These rooms will no longer run: " (closed.iter().join(", ")) }
+            }
+
+            p class="text-muted" {
+                "Applying these changes replaces the ballot links of every
+                 judge in the changed rooms (and emails them the new links),
+                 and emails everyone who has been moved to tell them where
+                 they are now."
+            }
+            form method="post" action={"/spars/" (spar.public_id) "/adjust"} {
+                @for member in &form.absent {
+                    input type="hidden" name="absent" value=(member);
+                }
+                @for member in &form.late_judges {
+                    input type="hidden" name="late_judges" value=(member);
+                }
+                @for member in &form.late_speakers {
+                    input type="hidden" name="late_speakers" value=(member);
+                }
+                button type="submit" class="btn btn-primary" disabled[!with_ballots.is_empty()] {
+                    "Apply changes"
+                }
+            }
+        }
+    }
+}
+
+#[get("/spars/<spar_id>/adjust?<adjustment..>")]
+/// Allows administrators to mark people as absent (or as having arrived
+/// late) after the draw has been released, and shows the changes to the draw
+/// which this requires.
+pub async fn adjust_draw_page(
+    spar_id: &str,
+    adjustment: AdjustDrawForm,
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Result<Markup, Flash<Redirect>>> {
+    let spar_id = spar_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let (spar, series) =
+            match load_spar_for_adjustment(&spar_id, &user, conn)? {
+                Ok(t) => t,
+                Err(page) => return Some(Ok(page)),
+            };
+
+        if !spar.release_draw || spar.is_complete {
+            return Some(Err(Flash::error(
+                Redirect::to(format!("/spars/{spar_id}?tab=draw")),
+                "Error: the draw can only be adjusted once it has been \
+                 released (and before the spar is complete).",
+            )));
+        }
+
+        let (rooms, data) = released_draw(spar.id, conn).unwrap();
+        let names = member_names(spar.spar_series_id, conn);
+        let members = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(series.id))
+            .order_by(spar_series_members::name.asc())
+            .load::<SparSeriesMember>(conn)
+            .unwrap();
+        let not_in_draw = members
+            .iter()
+            .filter(|member| {
+                member.is_active && !data.id_map.contains_key(&member.id)
+            })
+            .collect::<Vec<_>>();
+
+        let proposal = if adjustment.is_empty() {
+            None
+        } else {
+            let repair = repair_of_form(&adjustment, &data, &series, &members);
+            let with_ballots = changed_rooms_with_ballots(
+                &rooms,
+                &data.diff(&repair.data).changed_rooms,
+                conn,
+            );
+            Some(render_proposal(
+                &spar,
+                &adjustment,
+                &data,
+                &repair,
+                &with_ballots,
+                &names,
+            ))
+        };
+
+        let markup = html! {
+            a href=(format!("/spars/{}?tab=draw", spar.public_id)) class="btn btn-secondary mb-3" {
+                "← Back to spar"
+            }
+            (page_title("Adjust the draw"))
+            p class="text-muted" {
+                "Mark the people who have not turned up, and add anyone who
+                 has arrived late. We will then suggest the smallest change to
+                 the draw which we can find."
+            }
+            form method="get" {
+                h4 { "Absent" }
+                div class="row mb-3" {
+                    @for (idx, room) in data.rooms.iter().enumerate() {
+                        div class="col-md-3" {
+                            b { "Room " (idx) }
+                            @for member in room.panel.iter().chain(room.teams.values().flatten()).sorted_by_key(|member| names.get(member)) {
+                                div class="form-check" {
+                                    input class="form-check-input" type="checkbox" name="absent" value=(member) id={"absent-" (member)} checked[adjustment.absent.contains(member)];
+                                    label class="form-check-label" for={"absent-" (member)} {
+                                        (names.get(member).map(|name| name.as_str()).unwrap_or("(unknown member)"))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                h4 { "Late arrivals" }
+                div class="row mb-3" {
+                    div class="col-md-6" {
+                        label for="late_speakers" class="form-label" { "Speaking" }
+                        select class="form-select" multiple name="late_speakers" id="late_speakers" {
+                            @for member in &not_in_draw {
+                                option value=(member.id) selected[adjustment.late_speakers.contains(&member.id)] { (member.name) }
+                            }
+                        }
+                    }
+                    div class="col-md-6" {
+                        label for="late_judges" class="form-label" { "Judging" }
+                        select class="form-select" multiple name="late_judges" id="late_judges" {
+                            @for member in &not_in_draw {
+                                option value=(member.id) selected[adjustment.late_judges.contains(&member.id)] { (member.name) }
+                            }
+                        }
+                    }
+                }
+                button type="submit" class="btn btn-primary mb-4" { "Suggest changes" }
+            }
+            @if let Some(proposal) = proposal {
+                (proposal)
+            }
+        };
+
+        Some(Ok(page_of_body_and_flash_msg(markup, msg, Some(user))))
+    })
+    .await
+}
+
+/// Makes the rooms of the spar match `new` (which must be the repaired
+/// version of the room). Returns false if the room has been closed (and
+/// therefore deleted).
+fn apply_room(
+    room: &SparRoomRepr,
+    new: &DraftDrawRoom,
+    conn: &mut DbWrapper,
+) -> Result<bool, diesel::result::Error> {
+    if new.panel.is_empty() && new.teams.values().all(|t| t.is_empty()) {
+        // the teams, speakers, adjudicators and ballot links of the room are
+        // deleted along with it
+        diesel::delete(
+            spar_rooms::table.filter(spar_rooms::id.eq(room.inner.id)),
+        )
+        .execute(conn)?;
+        return Ok(false);
+    }
+
+    for (team, position) in room.teams.iter().zip(Team::ALL) {
+        let wanted = new.teams.get(&position).cloned().unwrap_or_default();
+        let mut existing = HashSet::new();
+        for speaker in &team.speakers {
+            let speaker = &room.speakers[speaker];
+            if wanted.contains(&speaker.member_id) {
+                existing.insert(speaker.member_id);
+            } else {
+                diesel::delete(
+                    spar_speakers::table
+                        .filter(spar_speakers::id.eq(speaker.id)),
+                )
+                .execute(conn)?;
+            }
+        }
+        for member in wanted.difference(&existing).sorted() {
+            insert_into(spar_speakers::table)
+                .values((
+                    spar_speakers::public_id.eq(Uuid::now_v7().to_string()),
+                    spar_speakers::member_id.eq(member),
+                    spar_speakers::team_id.eq(team.inner.id),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    for judge in &room.judges {
+        if !new.panel.contains(&judge.member_id) {
+            diesel::delete(
+                spar_adjudicators::table
+                    .filter(spar_adjudicators::id.eq(judge.id)),
+            )
+            .execute(conn)?;
+        }
+    }
+    for member in new.panel.iter().sorted() {
+        let status = if new.chair == Some(*member) {
+            CHAIR
+        } else {
+            PANELLIST
+        };
+        if room.judges.iter().any(|judge| judge.member_id == *member) {
+            diesel::update(
+                spar_adjudicators::table
+                    .filter(spar_adjudicators::room_id.eq(room.inner.id))
+                    .filter(spar_adjudicators::member_id.eq(member)),
+            )
+            .set(spar_adjudicators::status.eq(status))
+            .execute(conn)?;
+        } else {
+            insert_into(spar_adjudicators::table)
+                .values((
+                    spar_adjudicators::public_id.eq(Uuid::now_v7().to_string()),
+                    spar_adjudicators::member_id.eq(member),
+                    spar_adjudicators::room_id.eq(room.inner.id),
+                    spar_adjudicators::status.eq(status),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    // everyone on the panel gets a new link (the old links may have been
+    // passed on to people who are no longer judging this room)
+    diesel::delete(
+        spar_adjudicator_ballot_links::table
+            .filter(spar_adjudicator_ballot_links::room_id.eq(room.inner.id)),
+    )
+    .execute(conn)?;
+    let expires_at =
+        Utc::now().naive_utc() + TimeDelta::hours(ADJUSTED_LINK_HOURS);
+    for member in new.panel.iter().sorted() {
+        insert_into(spar_adjudicator_ballot_links::table)
+            .values((
+                spar_adjudicator_ballot_links::public_id
+                    .eq(Uuid::now_v7().to_string()),
+                spar_adjudicator_ballot_links::link
+                    .eq(Uuid::new_v4().to_string()),
+                spar_adjudicator_ballot_links::room_id.eq(room.inner.id),
+                spar_adjudicator_ballot_links::member_id.eq(member),
+                spar_adjudicator_ballot_links::created_at.eq(diesel::dsl::now),
+                spar_adjudicator_ballot_links::expires_at.eq(expires_at),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(true)
+}
+
+/// An email which is sent once the adjustment has been committed (so that
+/// nobody is told about changes which are then rolled back).
+enum Notification {
+    BallotLink(AdjudicatorBallotLink, SparSeriesMember),
+    Moved {
+        member: SparSeriesMember,
+        position: String,
+        spar_id: String,
+    },
+}
+
+impl Notification {
+    fn send(&self, db: Arc<DbConn>) {
+        match self {
+            Notification::BallotLink(link, member) => {
+                mail_ballot_link(link, member, db)
+            }
+            Notification::Moved {
+                member,
+                position,
+                spar_id,
+            } => send_mail(
+                vec![(&member.name, &member.email)],
+                "Change to the draw",
+                &maud::html! {
+                    p { "The draw has been changed, and you are now " (position) "." }
+                    p { a href=(format!("https://eldemite.net/spars/{spar_id}")) { "View the draw" } }
+                }
+                .into_string(),
+                &format!(
+                    "The draw has been changed, and you are now {position}. \
+                     You can view the draw at \
+                     https://eldemite.net/spars/{spar_id}"
+                ),
+                db,
+            ),
+        }
+    }
+}
+
+#[post("/spars/<spar_id>/adjust", data = "<form>")]
+/// Applies the repair to the draw, reissues the ballot links of the rooms
+/// which changed and tells everyone who moved where they are now.
+pub async fn do_adjust_draw(
+    spar_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<AdjustDrawForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_id = spar_id.to_string();
+    let span1 = span.0.clone();
+    let db = Arc::new(db);
+    db.clone()
+        .run(move |conn| {
+            let _guard = span1.enter();
+            conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+                let (spar, series) =
+                    match load_spar_for_adjustment(&spar_id, &user, conn) {
+                        Some(Ok(t)) => t,
+                        Some(Err(page)) => return Ok(Some(Err(page))),
+                        None => return Ok(None),
+                    };
+
+                let adjust_page =
+                    Redirect::to(format!("/spars/{spar_id}/adjust"));
+                if !spar.release_draw || spar.is_complete {
+                    return Ok(Some(Ok((
+                        vec![],
+                        Flash::error(
+                            adjust_page,
+                            "Error: the draw can only be adjusted once it \
+                             has been released (and before the spar is \
+                             complete).",
+                        ),
+                    ))));
+                }
+
+                let (rooms, data) = released_draw(spar.id, conn)?;
+                let members = spar_series_members::table
+                    .filter(spar_series_members::spar_series_id.eq(series.id))
+                    .load::<SparSeriesMember>(conn)?;
+                let repair = repair_of_form(&form, &data, &series, &members);
+                let diff = data.diff(&repair.data);
+
+                let with_ballots = changed_rooms_with_ballots(
+                    &rooms,
+                    &diff.changed_rooms,
+                    conn,
+                );
+                if !with_ballots.is_empty() {
+                    return Ok(Some(Ok((
+                        vec![],
+                        Flash::error(
+                            adjust_page,
+                            format!(
+                                "Error: ballots have already been submitted \
+                                 in rooms {}, so they cannot be changed.",
+                                with_ballots.iter().join(", ")
+                            ),
+                        ),
+                    ))));
+                }
+
+                let mut open_rooms = Vec::new();
+                for idx in &diff.changed_rooms {
+                    if apply_room(&rooms[*idx], &repair.data.rooms[*idx], conn)?
+                    {
+                        open_rooms.push(rooms[*idx].inner.id);
+                    }
+                }
+
+                let links = spar_adjudicator_ballot_links::table
+                    .filter(
+                        spar_adjudicator_ballot_links::room_id
+                            .eq_any(&open_rooms),
+                    )
+                    .inner_join(spar_series_members::table)
+                    .select((
+                        spar_adjudicator_ballot_links::all_columns,
+                        spar_series_members::all_columns,
+                    ))
+                    .load::<(AdjudicatorBallotLink, SparSeriesMember)>(conn)?;
+                diesel::update(spar_adjudicator_ballot_links::table)
+                    .filter(
+                        spar_adjudicator_ballot_links::id.eq_any(
+                            links
+                                .iter()
+                                .map(|(link, _)| link.id)
+                                .collect::<Vec<_>>(),
+                        ),
+                    )
+                    .set(
+                        spar_adjudicator_ballot_links::sent_at
+                            .eq(diesel::dsl::now),
+                    )
+                    .execute(conn)?;
+                let reissued = links.len();
+                let mut notifications = links
+                    .into_iter()
+                    .map(|(link, member)| {
+                        Notification::BallotLink(link, member)
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut notified = 0;
+                for moved in &diff.moved {
+                    let Some((room, loc)) = moved.after else {
+                        // they are absent
+                        continue;
+                    };
+                    let Some(member) =
+                        members.iter().find(|member| member.id == moved.member)
+                    else {
+                        continue;
+                    };
+                    notifications.push(Notification::Moved {
+                        member: member.clone(),
+                        position: format!(
+                            "{} in room {room}",
+                            describe_loc(loc)
+                        ),
+                        spar_id: spar.public_id.clone(),
+                    });
+                    notified += 1;
+                }
+
+                Ok(Some(Ok((
+                    notifications,
+                    Flash::success(
+                        Redirect::to(format!("/spars/{spar_id}?tab=draw")),
+                        format!(
+                            "Adjusted the draw ({} rooms changed, {} \
+                             people notified and {} ballot links reissued).",
+                            diff.changed_rooms.len(),
+                            notified,
+                            reissued
+                        ),
+                    ),
+                ))))
+            })
+            .unwrap()
+            // the changes have now been committed, so it is safe to tell
+            // people about them
+            .map(|result| {
+                result.map(|(notifications, flash)| {
+                    for notification in &notifications {
+                        notification.send(db.clone());
+                    }
+                    flash
+                })
+            })
+        })
+        .instrument(span.0)
+        .await
+}
@@ -1,26 +1,37 @@
 use db::{
     group::Group,
-    room::SparRoomRepr,
+    room::{SparRoomRepr, CHAIR, PANELLIST},
     schema::{
-        groups, spar_rooms, spar_series, spar_series_members, spar_signups,
-        spars,
+        groups, spar_adjudicators, spar_rooms, spar_series,
+        spar_series_members, spar_signups, spars,
+    },
+    spar::{
+        Spar, SparRoom, SparRoomAdjudicator, SparSeries, SparSignup,
+        SparSignupSerializer,
     },
-    spar::{Spar, SparRoom, SparSeries, SparSignup, SparSignupSerializer},
     user::User,
     DbConn,
 };
 use diesel::prelude::*;
 use maud::{Markup, PreEscaped};
 use qrcode::{render::svg, EcLevel, QrCode};
-use rocket::{request::FlashMessage, response::status::Unauthorized};
+use rocket::{
+    request::FlashMessage,
+    response::{status::Unauthorized, Flash, Redirect},
+};
 use tracing::Instrument;
 
 use crate::{
     html::page_of_body_and_flash_msg,
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
-    spar_generation::individual_spars::draw_management::util::{
-        ballots_of_rooms, render_draw,
+    spar_generation::exports::render_export_links,
+    spar_generation::individual_spars::{
+        ballot_status::{ballot_status_of_spar, render_ballot_status},
+        draw_management::util::{
+            ballots_of_rooms, render_ballot_conflicts, render_draw,
+        },
+        reopen_spar::{render_reopenings, reopenings_of_spar},
     },
 };
 
@@ -122,6 +133,12 @@ pub async fn single_spar_overview_for_admin_page(
                         .collect::<Result<Vec<SparRoomRepr>, _>>()?;
 
                     let ballots = ballots_of_rooms(&draw, conn)?;
+                    let conflicts = render_ballot_conflicts(&spar.public_id, &draw, conn)?;
+                    let ballot_status = if spar.release_draw && !draw.is_empty() {
+                        Some(ballot_status_of_spar(spar.id, conn)?)
+                    } else {
+                        None
+                    };
 
                     let release_unrelease_link = if !draw.is_empty() && spar.release_draw {
                         maud::html! {
@@ -148,9 +165,15 @@ pub async fn single_spar_overview_for_admin_page(
                     };
 
                     maud::html! {
+                        @if let Some(conflicts) = conflicts {
+                            (conflicts)
+                        }
                         @if !draw.is_empty() {
                             h3 {"Existing draw"}
-                            (render_draw(draw, ballots))
+                            (render_draw(draw, ballots, true))
+                        }
+                        @if let Some(ballot_status) = &ballot_status {
+                            (render_ballot_status(&spar, ballot_status))
                         }
 
                         div class="d-flex gap-3 mt-3" {
@@ -162,6 +185,31 @@ pub async fn single_spar_overview_for_admin_page(
                             div {
                                 (release_unrelease_link)
                             }
+                            @if spar.release_draw && !spar.is_complete && !draw.is_empty() {
+                                a href=(format!("/spars/{}/adjust", spar.public_id)) class="btn btn-outline-secondary" {
+                                    "Adjust for absences"
+                                }
+                            }
+                            @if !draw.is_empty() {
+                                a href=(format!("/spars/{}/projector", spar.public_id)) target="_blank" class="btn btn-outline-secondary" {
+                                    "Projector view"
+                                }
+                            }
+                        }
+                        @if !draw.is_empty() {
+                            form method="get" class="row g-2 align-items-center mt-3" {
+                                div class="col-md-6" {
+                                    input type="text" name="motion" class="form-control" placeholder="Motion (optional)" {}
+                                }
+                                div class="col-auto" {
+                                    button type="submit" formaction=(format!("/spars/{}/print/draw", spar.public_id)) class="btn btn-outline-secondary me-2" {
+                                        "Print draw"
+                                    }
+                                    button type="submit" formaction=(format!("/spars/{}/print/ballots", spar.public_id)) class="btn btn-outline-secondary" {
+                                        "Print ballots"
+                                    }
+                                }
+                            }
                         }
                     }
                 },
@@ -280,10 +328,19 @@ pub async fn single_spar_overview_for_admin_page(
                              configure (this page is here in case the
                              developers need to add settings later)."
                         }
+                        h3 {"Export"}
+                        p class="text-muted" {
+                            "Download the data for this spar (to download the
+                             data for every spar in the series, use the links
+                             on the series page)."
+                        }
+                        (render_export_links(&format!("/spars/{}", spar.public_id)))
                     }
                 }
             };
 
+            let reopenings = reopenings_of_spar(spar.id, conn)?;
+
             let draw_is_active = if matches!(tab, Some(SparAdminTab::Draw) | None) {"active"} else {""};
             let signups_is_active = if matches!(tab, Some(SparAdminTab::Signups)) {"active"} else {""};
             let settings_is_active = if matches!(tab, Some(SparAdminTab::Settings)) {"active"} else {""};
@@ -300,6 +357,13 @@ pub async fn single_spar_overview_for_admin_page(
                                     "Mark Complete"
                                 }
                             }
+                        } @else {
+                            form method="post" action=(format!("/spars/{}/reopen", spar.public_id)) class="d-flex gap-2" {
+                                input type="text" name="reason" class="form-control" placeholder="Reason for reopening" required {}
+                                button class="btn btn-warning text-nowrap" type="submit" {
+                                    "Reopen spar"
+                                }
+                            }
                         }
                     }
 
@@ -309,6 +373,8 @@ pub async fn single_spar_overview_for_admin_page(
                         span class="render-date" { (spar.start_time) }
                     }
 
+                    (render_reopenings(&reopenings))
+
                     ul class = "my-3 bg-primary-subtle nav nav-pills flex-column flex-sm-row" {
                         li class = "nav-item" {
                             a href=(format!("/spars/{spar_id}?tab=draw")) class=(format!("nav-link {draw_is_active}")) {
@@ -405,3 +471,81 @@ pub async fn set_is_open(
     .instrument(span.0)
     .await
 }
+
+#[post("/spars/<spar_id>/adjudicators/<adjudicator_id>/make_chair")]
+/// Designates an adjudicator as the chair of their room (and demotes any
+/// existing chair to a panellist). If the chair has submitted a ballot, then
+/// it is used as the result of the room.
+pub async fn do_make_chair(
+    db: DbConn,
+    user: User,
+    spar_id: &str,
+    adjudicator_id: &str,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Unauthorized<()>>> {
+    let spar_id = spar_id.to_string();
+    let adjudicator_id = adjudicator_id.to_string();
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let spar = match spars::table
+                .filter(spars::public_id.eq(&spar_id))
+                .first::<Spar>(conn)
+                .optional()?
+            {
+                Some(spar) => spar,
+                None => return Ok(None),
+            };
+
+            let spar_series = spar_series::table
+                .filter(spar_series::id.eq(spar.spar_series_id))
+                .first::<SparSeries>(conn)?;
+
+            if !has_permission(
+                Some(&user),
+                &Permission::ModifyResourceInGroup(crate::resources::GroupRef(
+                    spar_series.group_id,
+                )),
+                conn,
+            ) {
+                return Ok(Some(Err(Unauthorized(()))));
+            }
+
+            let adjudicator = match spar_adjudicators::table
+                .inner_join(spar_rooms::table)
+                .filter(spar_rooms::spar_id.eq(spar.id))
+                .filter(spar_adjudicators::public_id.eq(&adjudicator_id))
+                .select(spar_adjudicators::all_columns)
+                .first::<SparRoomAdjudicator>(conn)
+                .optional()?
+            {
+                Some(adjudicator) => adjudicator,
+                None => return Ok(None),
+            };
+
+            diesel::update(
+                spar_adjudicators::table
+                    .filter(spar_adjudicators::room_id.eq(adjudicator.room_id))
+                    .filter(spar_adjudicators::status.eq(CHAIR)),
+            )
+            .set(spar_adjudicators::status.eq(PANELLIST))
+            .execute(conn)?;
+
+            diesel::update(
+                spar_adjudicators::table
+                    .filter(spar_adjudicators::id.eq(adjudicator.id)),
+            )
+            .set(spar_adjudicators::status.eq(CHAIR))
+            .execute(conn)?;
+
+            Ok(Some(Ok(Flash::success(
+                Redirect::to(format!("/spars/{}?tab=draw", spar.public_id)),
+                "Updated the chair of the room.",
+            ))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
@@ -0,0 +1,370 @@
+//! Allows administrators to manage the links which adjudicators use to submit
+//! their ballots (e.g. if an adjudicator did not receive their link, if a
+//! link has been shared with somebody else, or if the spar is running late).
+
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use db::{
+    ballot::AdjudicatorBallotLink,
+    schema::{
+        adjudicator_ballots, spar_adjudicator_ballot_links, spar_adjudicators,
+        spar_series_members,
+    },
+    spar::SparSeriesMember,
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use email::send_mail;
+use maud::Markup;
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    html::{page_of_body_and_flash_msg, page_title},
+    request_ids::TracingSpan,
+    spar_generation::individual_spars::room_ballots::load_room_for_admin,
+    util::tx,
+};
+
+/// Emails an adjudicator their ballot link (without recording that it has
+/// been sent, see [`send_ballot_link`]).
+pub fn mail_ballot_link(
+    link: &AdjudicatorBallotLink,
+    member: &SparSeriesMember,
+    db: Arc<DbConn>,
+) {
+    let ballot_link =
+        format!("https://eldemite.net/ballots/submit/{}", link.link);
+    send_mail(
+        vec![(&member.name, &member.email)],
+        "Ballot link",
+        &maud::html! {
+            "Please use " a href=(ballot_link) { "this link" } " to submit your ballot."
+        }
+        .into_string(),
+        &format!("Please use this link to submit your ballot: {ballot_link}"),
+        db,
+    );
+}
+
+/// Emails an adjudicator their ballot link, and records that it has been
+/// sent.
+pub fn send_ballot_link(
+    link: &AdjudicatorBallotLink,
+    member: &SparSeriesMember,
+    db: Arc<DbConn>,
+    conn: &mut DbWrapper,
+) -> Result<(), diesel::result::Error> {
+    mail_ballot_link(link, member, db);
+
+    diesel::update(
+        spar_adjudicator_ballot_links::table
+            .filter(spar_adjudicator_ballot_links::id.eq(link.id)),
+    )
+    .set(spar_adjudicator_ballot_links::sent_at.eq(diesel::dsl::now))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+fn format_time(time: Option<NaiveDateTime>) -> String {
+    time.map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "never".to_string())
+}
+
+#[get("/rooms/<room_id>/ballot_links")]
+/// Shows the status of the ballot link of each adjudicator in this room.
+pub async fn ballot_links_page(
+    room_id: &str,
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let room_id = room_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let (room, spar) = match load_room_for_admin(&room_id, &user, conn)? {
+            Ok(t) => t,
+            Err(page) => return Some(page),
+        };
+
+        let links = spar_adjudicator_ballot_links::table
+            .filter(spar_adjudicator_ballot_links::room_id.eq(room.id))
+            .inner_join(spar_series_members::table)
+            .order_by(spar_series_members::name.asc())
+            .select((
+                spar_adjudicator_ballot_links::all_columns,
+                spar_series_members::all_columns,
+            ))
+            .load::<(AdjudicatorBallotLink, SparSeriesMember)>(conn)
+            .unwrap();
+
+        let submitted = adjudicator_ballots::table
+            .filter(adjudicator_ballots::room_id.eq(room.id))
+            .inner_join(spar_adjudicators::table)
+            .select(spar_adjudicators::member_id)
+            .distinct()
+            .load::<i64>(conn)
+            .unwrap();
+
+        let now = Utc::now().naive_utc();
+
+        let markup = maud::html! {
+            a href=(format!("/spars/{}?tab=draw", spar.public_id)) class="btn btn-secondary mb-3" {
+                "← Back to spar"
+            }
+            (page_title("Ballot links for room"))
+            table class="table" {
+                thead {
+                    tr {
+                        th scope="col" { "Adjudicator" }
+                        th scope="col" { "Sent" }
+                        th scope="col" { "Opened" }
+                        th scope="col" { "Expires" }
+                        th scope="col" { "Ballot submitted" }
+                        th scope="col" { "Actions" }
+                    }
+                }
+                tbody {
+                    @for (link, member) in &links {
+                        tr {
+                            td { (member.name) }
+                            td { (format_time(link.sent_at)) }
+                            td { (format_time(link.opened_at)) }
+                            td {
+                                (format_time(Some(link.expires_at)))
+                                @if link.expires_at <= now {
+                                    " "
+                                    span class="badge text-bg-danger" { "expired" }
+                                }
+                            }
+                            td {
+                                @if submitted.contains(&member.id) { "Yes" } @else { "No" }
+                            }
+                            td {
+                                form method="post" class="d-inline" action=(format!("/rooms/{}/ballot_links/{}/resend", room.public_id, link.public_id)) {
+                                    button type="submit" class="btn btn-sm btn-primary me-1" { "Resend" }
+                                }
+                                form method="post" class="d-inline" action=(format!("/rooms/{}/ballot_links/{}/rotate", room.public_id, link.public_id)) {
+                                    button type="submit" class="btn btn-sm btn-danger" { "Replace link" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            p class="text-muted" {
+                "Replacing a link stops the old link from working (e.g. if it
+                 has been shared with somebody else) and emails a new link to
+                 the adjudicator."
+            }
+            form method="post" action=(format!("/rooms/{}/ballot_links/extend", room.public_id)) class="row g-2 align-items-center" {
+                div class="col-auto" {
+                    label for="hours" class="col-form-label" { "Extend all links in this room by" }
+                }
+                div class="col-auto" {
+                    input type="number" min="1" name="hours" id="hours" value="2" class="form-control" {}
+                }
+                div class="col-auto" { "hours" }
+                div class="col-auto" {
+                    button type="submit" class="btn btn-primary" { "Extend" }
+                }
+            }
+        };
+
+        Some(page_of_body_and_flash_msg(markup, msg, Some(user)))
+    })
+    .await
+}
+
+/// Loads the ballot link with the given public id (which must belong to the
+/// given room) along with the adjudicator it belongs to.
+fn load_link(
+    room_id: i64,
+    link_id: &str,
+    conn: &mut DbWrapper,
+) -> Option<(AdjudicatorBallotLink, SparSeriesMember)> {
+    spar_adjudicator_ballot_links::table
+        .filter(spar_adjudicator_ballot_links::public_id.eq(link_id))
+        .filter(spar_adjudicator_ballot_links::room_id.eq(room_id))
+        .inner_join(spar_series_members::table)
+        .select((
+            spar_adjudicator_ballot_links::all_columns,
+            spar_series_members::all_columns,
+        ))
+        .first::<(AdjudicatorBallotLink, SparSeriesMember)>(conn)
+        .optional()
+        .unwrap()
+}
+
+#[post("/rooms/<room_id>/ballot_links/<link_id>/resend")]
+/// Emails the adjudicator their (existing) ballot link again.
+pub async fn do_resend_ballot_link(
+    room_id: &str,
+    link_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let room_id = room_id.to_string();
+    let link_id = link_id.to_string();
+    let span1 = span.0.clone();
+    let db = Arc::new(db);
+    db.clone()
+        .run(move |conn| {
+            let _guard = span1.enter();
+            conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+                let (room, _) = match load_room_for_admin(&room_id, &user, conn)
+                {
+                    Some(Ok(t)) => t,
+                    Some(Err(page)) => return Ok(Some(Err(page))),
+                    None => return Ok(None),
+                };
+
+                let (link, member) = match load_link(room.id, &link_id, conn) {
+                    Some(t) => t,
+                    None => return Ok(None),
+                };
+
+                send_ballot_link(&link, &member, db.clone(), conn)?;
+
+                Ok(Some(Ok(Flash::success(
+                    Redirect::to(format!("/rooms/{room_id}/ballot_links")),
+                    format!("Sent the ballot link to {} again.", member.name),
+                ))))
+            })
+            .unwrap()
+        })
+        .instrument(span.0)
+        .await
+}
+
+#[post("/rooms/<room_id>/ballot_links/<link_id>/rotate")]
+/// Replaces an adjudicator's ballot link with a new one (so that the old link
+/// stops working), and emails them the new link.
+pub async fn do_rotate_ballot_link(
+    room_id: &str,
+    link_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let room_id = room_id.to_string();
+    let link_id = link_id.to_string();
+    let span1 = span.0.clone();
+    let db = Arc::new(db);
+    db.clone()
+        .run(move |conn| {
+            let _guard = span1.enter();
+            conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+                let (room, _) = match load_room_for_admin(&room_id, &user, conn)
+                {
+                    Some(Ok(t)) => t,
+                    Some(Err(page)) => return Ok(Some(Err(page))),
+                    None => return Ok(None),
+                };
+
+                let (link, member) = match load_link(room.id, &link_id, conn) {
+                    Some(t) => t,
+                    None => return Ok(None),
+                };
+
+                let link = diesel::update(
+                    spar_adjudicator_ballot_links::table
+                        .filter(spar_adjudicator_ballot_links::id.eq(link.id)),
+                )
+                .set((
+                    spar_adjudicator_ballot_links::link
+                        .eq(Uuid::new_v4().to_string()),
+                    spar_adjudicator_ballot_links::opened_at
+                        .eq(None::<NaiveDateTime>),
+                ))
+                .returning(spar_adjudicator_ballot_links::all_columns)
+                .get_result::<AdjudicatorBallotLink>(conn)?;
+
+                send_ballot_link(&link, &member, db.clone(), conn)?;
+
+                Ok(Some(Ok(Flash::success(
+                    Redirect::to(format!("/rooms/{room_id}/ballot_links")),
+                    format!(
+                        "Replaced the ballot link for {} (and emailed them \
+                         the new link).",
+                        member.name
+                    ),
+                ))))
+            })
+            .unwrap()
+        })
+        .instrument(span.0)
+        .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct ExtendBallotLinksForm {
+    pub hours: i64,
+}
+
+#[post("/rooms/<room_id>/ballot_links/extend", data = "<form>")]
+/// Extends the expiry of every ballot link in this room (links which have
+/// already expired are extended from the current time).
+pub async fn do_extend_ballot_links(
+    room_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<ExtendBallotLinksForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let room_id = room_id.to_string();
+    tx(span, db, move |conn| {
+        let (room, _) = match load_room_for_admin(&room_id, &user, conn)? {
+            Ok(t) => t,
+            Err(page) => return Some(Err(page)),
+        };
+
+        let redirect_to =
+            Redirect::to(format!("/rooms/{room_id}/ballot_links"));
+
+        if !(1..=24 * 7).contains(&form.hours) {
+            return Some(Ok(Flash::error(
+                redirect_to,
+                "Error: links can be extended by between 1 hour and 1 week.",
+            )));
+        }
+
+        let now = Utc::now().naive_utc();
+        let links = spar_adjudicator_ballot_links::table
+            .filter(spar_adjudicator_ballot_links::room_id.eq(room.id))
+            .load::<AdjudicatorBallotLink>(conn)
+            .unwrap();
+        for link in &links {
+            let expires_at =
+                link.expires_at.max(now) + TimeDelta::hours(form.hours);
+            diesel::update(
+                spar_adjudicator_ballot_links::table
+                    .filter(spar_adjudicator_ballot_links::id.eq(link.id)),
+            )
+            .set(spar_adjudicator_ballot_links::expires_at.eq(expires_at))
+            .execute(conn)
+            .unwrap();
+        }
+
+        Some(Ok(Flash::success(
+            redirect_to,
+            format!(
+                "Extended {} ballot links by {} hours.",
+                links.len(),
+                form.hours
+            ),
+        )))
+    })
+    .await
+}
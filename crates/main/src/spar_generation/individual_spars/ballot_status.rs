@@ -0,0 +1,469 @@
+//! Tracks which adjudicators have submitted their ballots for a spar, and
+//! reminds those who have not (either when an administrator asks us to, or
+//! automatically some time after the start of the spar - see
+//! [`db::ballot::BallotSettings`]).
+//!
+//! This module also runs the background task which sends these reminders and
+//! marks spars as complete once all their ballots are in.
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use chrono::{TimeDelta, Utc};
+use db::{
+    ballot::{AdjudicatorBallotLink, BallotSettings},
+    room::{SparRoom, CHAIR},
+    schema::{
+        adjudicator_ballots, spar_adjudicator_ballot_links, spar_adjudicators,
+        spar_rooms, spar_series, spar_series_ballot_settings,
+        spar_series_members, spars,
+    },
+    spar::{Spar, SparRoomAdjudicator, SparSeriesMember},
+    user::User,
+    DbConn, DbPool, DbWrapper,
+};
+use diesel::prelude::*;
+use email::send_mail;
+use maud::Markup;
+use rocket::response::{Flash, Redirect};
+use tracing::Instrument;
+
+use crate::{
+    html::{error_403, error_404},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    spar_generation::individual_spars::complete_spar::complete_ready_spars,
+};
+
+/// How often we check whether any automatic reminders need to be sent (or
+/// spars need to be marked as complete).
+const REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub struct JudgeBallotStatus {
+    pub adjudicator: SparRoomAdjudicator,
+    pub member: SparSeriesMember,
+    pub submitted: bool,
+    /// The link the adjudicator can use to submit their ballot (this should
+    /// exist for every adjudicator once the draw has been released).
+    pub link: Option<AdjudicatorBallotLink>,
+}
+
+pub struct RoomBallotStatus {
+    pub room: SparRoom,
+    pub judges: Vec<JudgeBallotStatus>,
+}
+
+/// Returns whether each adjudicator in each room of the spar has submitted a
+/// ballot. Rooms are returned in the same order as they are displayed in the
+/// draw.
+pub fn ballot_status_of_spar(
+    spar_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<Vec<RoomBallotStatus>, diesel::result::Error> {
+    let rooms = spar_rooms::table
+        .filter(spar_rooms::spar_id.eq(spar_id))
+        .order_by(spar_rooms::public_id)
+        .load::<SparRoom>(conn)?;
+
+    let judges = spar_adjudicators::table
+        .inner_join(spar_rooms::table)
+        .filter(spar_rooms::spar_id.eq(spar_id))
+        .inner_join(spar_series_members::table)
+        .order_by(spar_series_members::name.asc())
+        .select((
+            spar_adjudicators::all_columns,
+            spar_series_members::all_columns,
+        ))
+        .load::<(SparRoomAdjudicator, SparSeriesMember)>(conn)?;
+
+    let submitted = adjudicator_ballots::table
+        .inner_join(spar_rooms::table)
+        .filter(spar_rooms::spar_id.eq(spar_id))
+        .select(adjudicator_ballots::adjudicator_id)
+        .distinct()
+        .load::<i64>(conn)?
+        .into_iter()
+        .collect::<HashSet<i64>>();
+
+    let links = spar_adjudicator_ballot_links::table
+        .inner_join(spar_rooms::table)
+        .filter(spar_rooms::spar_id.eq(spar_id))
+        .select(spar_adjudicator_ballot_links::all_columns)
+        .load::<AdjudicatorBallotLink>(conn)?;
+
+    let mut ret = rooms
+        .into_iter()
+        .map(|room| RoomBallotStatus {
+            room,
+            judges: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+
+    for (adjudicator, member) in judges {
+        let link = links
+            .iter()
+            .find(|link| {
+                link.room_id == adjudicator.room_id
+                    && link.member_id == adjudicator.member_id
+            })
+            .cloned();
+        let room = ret
+            .iter_mut()
+            .find(|room| room.room.id == adjudicator.room_id)
+            .unwrap();
+        room.judges.push(JudgeBallotStatus {
+            submitted: submitted.contains(&adjudicator.id),
+            adjudicator,
+            member,
+            link,
+        });
+    }
+
+    Ok(ret)
+}
+
+pub fn render_ballot_status(spar: &Spar, rooms: &[RoomBallotStatus]) -> Markup {
+    let outstanding = rooms
+        .iter()
+        .flat_map(|room| &room.judges)
+        .filter(|judge| !judge.submitted)
+        .count();
+    let rooms_without_ballots = rooms
+        .iter()
+        .filter(|room| !room.judges.iter().any(|judge| judge.submitted))
+        .count();
+
+    maud::html! {
+        h3 { "Ballots" }
+        p {
+            (rooms.len() - rooms_without_ballots) " of " (rooms.len())
+            " rooms have at least one ballot, and " (outstanding)
+            " adjudicators have not yet submitted their ballot."
+        }
+        table class="table table-sm" {
+            thead {
+                tr {
+                    th scope="col" { "Room" }
+                    th scope="col" { "Adjudicators" }
+                }
+            }
+            tbody {
+                @for (i, room) in rooms.iter().enumerate() {
+                    tr {
+                        td {
+                            a href=(format!("/rooms/{}/ballots", room.room.public_id)) { (i) }
+                        }
+                        td {
+                            @for judge in &room.judges {
+                                span class="me-3" {
+                                    (judge.member.name)
+                                    @if judge.adjudicator.status == CHAIR {
+                                        " (c)"
+                                    }
+                                    " "
+                                    @if judge.submitted {
+                                        span class="badge text-bg-success" { "submitted" }
+                                    } @else {
+                                        span class="badge text-bg-warning" { "outstanding" }
+                                        @if let Some(reminded_at) = judge.link.as_ref().and_then(|link| link.reminded_at) {
+                                            " "
+                                            span class="text-muted small" {
+                                                "reminded " (reminded_at.format("%H:%M"))
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        @if outstanding > 0 && !spar.is_complete {
+            form method="post" action=(format!("/spars/{}/remind_judges", spar.public_id)) {
+                button class="btn btn-outline-primary" type="submit" {
+                    "Remind outstanding judges"
+                }
+            }
+        }
+    }
+}
+
+/// A reminder which should be emailed to an adjudicator once the transaction
+/// recording it has been committed.
+pub struct Reminder {
+    link: AdjudicatorBallotLink,
+    member: SparSeriesMember,
+}
+
+/// Emails an adjudicator to remind them to submit their ballot.
+fn send_reminder(reminder: &Reminder, db: Arc<DbConn>) {
+    let Reminder { link, member } = reminder;
+    let ballot_link =
+        format!("https://eldemite.net/ballots/submit/{}", link.link);
+    let account_note = if member.user_id.is_some() {
+        " You can also submit it from your account page \
+         (https://eldemite.net/user)."
+    } else {
+        ""
+    };
+    send_mail(
+        vec![(&member.name, &member.email)],
+        "Reminder: please submit your ballot",
+        &maud::html! {
+            p {
+                "We have not yet received your ballot. Please use "
+                a href=(ballot_link) { "this link" } " to submit it."
+                (account_note)
+            }
+        }
+        .into_string(),
+        &format!(
+            "We have not yet received your ballot. Please use this link to \
+             submit it: {ballot_link}.{account_note}"
+        ),
+        db,
+    );
+}
+
+/// Records a reminder for every adjudicator in the spar who has not submitted
+/// their ballot. If `automatic` is set, adjudicators who have already been
+/// sent an automatic reminder are skipped (reminders sent by administrators
+/// do not count, as these are tracked separately). Returns the reminders
+/// which should be sent once the transaction has been committed, and the
+/// number of adjudicators who could not be reminded because their link has
+/// expired.
+fn remind_outstanding_judges(
+    spar_id: i64,
+    automatic: bool,
+    conn: &mut DbWrapper,
+) -> Result<(Vec<Reminder>, usize), diesel::result::Error> {
+    let now = Utc::now().naive_utc();
+    let mut reminders = Vec::new();
+    let mut expired = 0;
+    for room in ballot_status_of_spar(spar_id, conn)? {
+        for judge in room.judges {
+            let link = match (&judge.link, judge.submitted) {
+                (Some(link), false) => link,
+                _ => continue,
+            };
+            if automatic && link.auto_reminded_at.is_some() {
+                continue;
+            }
+            if link.expires_at <= now {
+                expired += 1;
+                continue;
+            }
+
+            let update = diesel::update(
+                spar_adjudicator_ballot_links::table
+                    .filter(spar_adjudicator_ballot_links::id.eq(link.id)),
+            );
+            if automatic {
+                update
+                    .set((
+                        spar_adjudicator_ballot_links::reminded_at
+                            .eq(diesel::dsl::now),
+                        spar_adjudicator_ballot_links::auto_reminded_at
+                            .eq(diesel::dsl::now),
+                    ))
+                    .execute(conn)?;
+            } else {
+                update
+                    .set(
+                        spar_adjudicator_ballot_links::reminded_at
+                            .eq(diesel::dsl::now),
+                    )
+                    .execute(conn)?;
+            }
+
+            reminders.push(Reminder {
+                link: link.clone(),
+                member: judge.member,
+            });
+        }
+    }
+    Ok((reminders, expired))
+}
+
+#[post("/spars/<spar_id>/remind_judges")]
+/// Emails each adjudicator who has not yet submitted a ballot for this spar.
+pub async fn do_remind_judges(
+    spar_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Result<Flash<Redirect>, Markup> {
+    let spar_id = spar_id.to_string();
+    let span1 = span.0.clone();
+    let db = Arc::new(db);
+    db.clone()
+        .run(move |conn| {
+            let _guard = span1.enter();
+            conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+                let spar = match spars::table
+                    .filter(spars::public_id.eq(&spar_id))
+                    .first::<Spar>(conn)
+                    .optional()?
+                {
+                    Some(spar) => spar,
+                    None => {
+                        return Ok(Err(error_404(
+                            Some("No such spar!".to_string()),
+                            Some(user),
+                        )))
+                    }
+                };
+
+                let group_id = spar_series::table
+                    .filter(spar_series::id.eq(spar.spar_series_id))
+                    .select(spar_series::group_id)
+                    .first::<i64>(conn)?;
+                if !has_permission(
+                    Some(&user),
+                    &Permission::ModifyResourceInGroup(GroupRef(group_id)),
+                    conn,
+                ) {
+                    return Ok(Err(error_403(
+                        Some("Error: you don't have permission to do that"),
+                        Some(user),
+                    )));
+                }
+
+                let redirect_to =
+                    Redirect::to(format!("/spars/{spar_id}?tab=draw"));
+
+                if !spar.release_draw || spar.is_complete {
+                    return Ok(Ok((
+                        vec![],
+                        Flash::error(
+                            redirect_to,
+                            "Error: reminders can only be sent once the draw \
+                             has been released (and before the spar is \
+                             complete).",
+                        ),
+                    )));
+                }
+
+                let (reminders, expired) =
+                    remind_outstanding_judges(spar.id, false, conn)?;
+                let sent = reminders.len();
+
+                let msg = if expired > 0 {
+                    format!(
+                        "Sent {sent} reminders. {expired} adjudicators were \
+                         not reminded because their ballot link has expired \
+                         (you can extend it from the ballot links page of \
+                         their room)."
+                    )
+                } else {
+                    format!("Sent {sent} reminders.")
+                };
+
+                Ok(Ok((reminders, Flash::success(redirect_to, msg))))
+            })
+            .unwrap()
+            .map(|(reminders, flash)| {
+                // the reminders have now been recorded, so it is safe to send
+                // them
+                for reminder in &reminders {
+                    send_reminder(reminder, db.clone());
+                }
+                flash
+            })
+        })
+        .instrument(span.0)
+        .await
+}
+
+/// Records a reminder for each adjudicator who has not yet submitted their
+/// ballot (and who has not already been automatically reminded) in spars which
+/// started longer ago than the reminder period configured for their spar
+/// series. Returns the reminders, which should be sent once the transaction
+/// has been committed.
+pub fn due_reminders(
+    conn: &mut DbWrapper,
+) -> Result<Vec<Reminder>, diesel::result::Error> {
+    let now = Utc::now().naive_utc();
+
+    let all_settings = spar_series_ballot_settings::table
+        .filter(spar_series_ballot_settings::reminder_after_hours.is_not_null())
+        .load::<BallotSettings>(conn)?;
+
+    let mut reminders = Vec::new();
+    for settings in all_settings {
+        let hours = settings.reminder_after_hours.unwrap();
+        let due = spars::table
+            .filter(spars::spar_series_id.eq(settings.spar_series_id))
+            .filter(spars::release_draw.eq(true))
+            .filter(spars::is_complete.eq(false))
+            .filter(spars::start_time.le(now - TimeDelta::hours(hours)))
+            .load::<Spar>(conn)?;
+
+        for spar in due {
+            let (mut spar_reminders, _) =
+                remind_outstanding_judges(spar.id, true, conn)?;
+            reminders.append(&mut spar_reminders);
+        }
+    }
+
+    Ok(reminders)
+}
+
+/// Periodically sends any automatic ballot reminders which are due, and marks
+/// any spars whose ballots are all in as complete (for series which have
+/// enabled this). This runs for the lifetime of the application, and takes a
+/// connection from the pool each time it runs.
+pub async fn run_periodic_ballot_tasks(pool: DbPool) {
+    loop {
+        rocket::tokio::time::sleep(REMINDER_CHECK_INTERVAL).await;
+
+        let db = match DbConn::from_pool(&pool).await {
+            Some(db) => Arc::new(db),
+            None => {
+                tracing::error!(
+                    "Failed to get a database connection for the periodic \
+                     ballot tasks."
+                );
+                continue;
+            }
+        };
+
+        let result = db
+            .clone()
+            .run(move |conn| {
+                conn.transaction(|conn| {
+                    let reminders = due_reminders(conn)?;
+                    let (completed, emails) = complete_ready_spars(conn)?;
+                    Ok::<_, diesel::result::Error>((
+                        reminders, completed, emails,
+                    ))
+                })
+            })
+            .await;
+
+        match result {
+            Ok((reminders, completed, emails)) => {
+                for reminder in &reminders {
+                    send_reminder(reminder, db.clone());
+                }
+                for email in &emails {
+                    email.send(db.clone());
+                }
+
+                let sent = reminders.len();
+                if sent > 0 {
+                    tracing::info!("Sent {sent} automatic ballot reminders.");
+                }
+                if completed > 0 {
+                    tracing::info!(
+                        "Automatically completed {completed} spars."
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to run periodic ballot tasks: {e:?}")
+            }
+        }
+    }
+}
@@ -1,19 +1,29 @@
+use std::sync::Arc;
+
 use db::{
-    schema::{adjudicator_ballots, spar_rooms, spar_series, spars},
-    spar::Spar,
+    schema::{
+        group_members, spar_reopenings, spar_rooms, spar_series,
+        spar_series_ballot_settings, spars, users,
+    },
+    spar::{Spar, SparSeries},
     user::User,
     DbConn,
 };
-use diesel::prelude::*;
+use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
 
+use email::send_mail;
 use maud::Markup;
 use rocket::response::Redirect;
+use tracing::Instrument;
 
 use crate::{
     html::{error_403, page_of_body},
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
-    util::tx,
+    spar_generation::feedback::{
+        comments_for_speaker, create_feedback_links, render_received_comments,
+        text_of_received_comments,
+    },
 };
 
 #[post("/spars/<spar_id>/mark_complete?<force>")]
@@ -24,128 +34,307 @@ pub async fn do_mark_spar_complete(
     // whether we should over-ride issues (e.g. missing ballots, no spar was
     // actually conducted)
     //
-    // todo: should people be able to "un-mark" spars?
+    // note: completed spars can be reopened (see `reopen_spar.rs`)
     force: bool,
     span: TracingSpan,
 ) -> Option<Result<Redirect, Markup>> {
     let spar_id = spar_id.to_string();
-    tx(span, db, move |conn| {
-        let spar = spars::table
-            .filter(spars::public_id.eq(spar_id))
-            .first::<Spar>(conn)
-            .optional()
-            .unwrap();
-        let spar = match spar {
-            Some(spar) => spar,
-            None => return None,
-        };
-
-        let user_has_permission = has_permission(
-            Some(&user),
-            &Permission::ModifyResourceInGroup(crate::resources::GroupRef(
-                spar_series::table
-                    .filter(spar_series::id.eq(spar.spar_series_id))
-                    .select(spar_series::group_id)
-                    .first::<i64>(conn).unwrap(),
-            )),
-            conn,
-        );
-
-        if !user_has_permission {
-            return Some(Err(error_403(
-                Some("Error: you do not have permission to do that!"),
-                Some(user),
-            )));
-        }
-
-        if !force {
-            #[derive(Debug)]
-            enum Problem {
-                MissingBallots { count: usize },
-                NoSparStarted,
-            }
-
-            let mut problems = Vec::with_capacity(2);
-
-            let rooms_with_ballots = spar_rooms::table
-                .filter(spar_rooms::spar_id.eq(spar.id))
-                .inner_join(adjudicator_ballots::table)
-                .select(spar_rooms::all_columns)
-                .count()
-                .get_result::<i64>(conn)
-                .unwrap();
-
-            let total_rooms = spar_rooms::table
-                .filter(spar_rooms::spar_id.eq(spar.id))
-                .count()
-                .get_result::<i64>(conn)
+    let span1 = span.0.clone();
+    let db = Arc::new(db);
+    db.clone().run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let spar = spars::table
+                .filter(spars::public_id.eq(spar_id))
+                .first::<Spar>(conn)
+                .optional()
                 .unwrap();
+            let spar = match spar {
+                Some(spar) => spar,
+                None => return Ok(None),
+            };
 
-            assert!(
-                rooms_with_ballots <= total_rooms,
-                "error: rooms_without_ballots={rooms_with_ballots} and
-                            total_rooms={total_rooms}"
+            let user_has_permission = has_permission(
+                Some(&user),
+                &Permission::ModifyResourceInGroup(crate::resources::GroupRef(
+                    spar_series::table
+                        .filter(spar_series::id.eq(spar.spar_series_id))
+                        .select(spar_series::group_id)
+                        .first::<i64>(conn).unwrap(),
+                )),
+                conn,
             );
-            assert!(
-                rooms_with_ballots >= 0,
-                "rooms_without_ballots={rooms_with_ballots}"
-            );
-
-            let rooms_without_ballots = total_rooms - rooms_with_ballots;
 
-            if rooms_without_ballots > 0 {
-                problems.push(Problem::MissingBallots {
-                    count: rooms_without_ballots as usize,
-                });
+            if !user_has_permission {
+                return Ok(Some(Err(error_403(
+                    Some("Error: you do not have permission to do that!"),
+                    Some(user),
+                ))));
             }
 
-            if total_rooms == 0 {
-                problems.push(Problem::NoSparStarted);
-            }
+            if !force {
+                let problems = completion_problems(&spar, conn)?;
 
-            if !problems.is_empty() {
-                return Some(Err(page_of_body(
-                    maud::html! {
-                        h1 { "Warning: problems found" }
-                        p {
-                            "Some issues were found when marking this spar as complete:"
-                        }
-                        ul {
-                            @for problem in &problems {
-                                li {
-                                    @match problem {
-                                        Problem::MissingBallots { count } => {
-                                            "Missing ballots: " (count) " rooms don't have ballots submitted"
-                                        }
-                                        Problem::NoSparStarted => {
-                                            "No draw was generated for this spar"
+                if !problems.is_empty() {
+                    return Ok(Some(Err(page_of_body(
+                        maud::html! {
+                            h1 { "Warning: problems found" }
+                            p {
+                                "Some issues were found when marking this spar as complete:"
+                            }
+                            ul {
+                                @for problem in &problems {
+                                    li {
+                                        @match problem {
+                                            CompletionProblem::MissingBallots { count } => {
+                                                "Missing ballots: " (count) " rooms don't have ballots submitted"
+                                            }
+                                            CompletionProblem::NoSparStarted => {
+                                                "No draw was generated for this spar"
+                                            }
                                         }
                                     }
                                 }
                             }
-                        }
-                        p {
-                            "You can still mark this spar as complete by using the form below, but you may want to address these issues first."
-                        }
-                        form method="post" action=(format!("/spars/{}/mark_complete?force=true", spar.public_id)) {
-                            button class="btn btn-danger" type="submit" {
-                                "Mark as complete anyway"
+                            p {
+                                "You can still mark this spar as complete by using the form below, but you may want to address these issues first."
                             }
-                        }
-                        a href=(format!("/spars/{}", spar.public_id)) class="btn btn-secondary" {
-                            "Cancel"
-                        }
-                    },
-                    Some(user),
-                )));
+                            form method="post" action=(format!("/spars/{}/mark_complete?force=true", spar.public_id)) {
+                                button class="btn btn-danger" type="submit" {
+                                    "Mark as complete anyway"
+                                }
+                            }
+                            a href=(format!("/spars/{}", spar.public_id)) class="btn btn-secondary" {
+                                "Cancel"
+                            }
+                        },
+                        Some(user),
+                    ))));
+                }
+            }
+
+            let emails = complete_spar(&spar, conn)?;
+
+            Ok(Some(Ok((
+                emails,
+                Redirect::to(format!("/spars/{}", spar.public_id)),
+            ))))
+        })
+        .unwrap()
+        .map(|result| {
+            result.map(|(emails, redirect)| {
+                // the spar has now been marked as complete, so it is safe to
+                // send the emails
+                for email in &emails {
+                    email.send(db.clone());
+                }
+                redirect
+            })
+        })
+    })
+    .instrument(span.0)
+    .await
+}
+
+#[derive(Debug)]
+/// A reason why a spar might not be ready to be marked as complete.
+pub enum CompletionProblem {
+    MissingBallots { count: usize },
+    NoSparStarted,
+}
+
+/// Checks whether the spar is ready to be marked as complete (i.e. that a
+/// draw was generated, and that every room has a ballot).
+pub fn completion_problems(
+    spar: &Spar,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Vec<CompletionProblem>, diesel::result::Error> {
+    let mut problems = Vec::with_capacity(2);
+
+    let rooms_with_ballots = spar.canonical_ballots(conn)?.len();
+
+    let total_rooms = spar_rooms::table
+        .filter(spar_rooms::spar_id.eq(spar.id))
+        .count()
+        .get_result::<i64>(conn)? as usize;
+
+    assert!(
+        rooms_with_ballots <= total_rooms,
+        "error: rooms_with_ballots={rooms_with_ballots} and \
+         total_rooms={total_rooms}"
+    );
+
+    let rooms_without_ballots = total_rooms - rooms_with_ballots;
+
+    if rooms_without_ballots > 0 {
+        problems.push(CompletionProblem::MissingBallots {
+            count: rooms_without_ballots,
+        });
+    }
+
+    if total_rooms == 0 {
+        problems.push(CompletionProblem::NoSparStarted);
+    }
+
+    Ok(problems)
+}
+
+/// An email which should be sent once a spar has been marked as complete (the
+/// caller should only send these once the transaction which completed the
+/// spar has been committed).
+pub struct CompletionEmail {
+    to: Vec<(String, String)>,
+    subject: &'static str,
+    html: String,
+    text: String,
+}
+
+impl CompletionEmail {
+    pub fn send(&self, db: Arc<DbConn>) {
+        send_mail(
+            self.to
+                .iter()
+                .map(|(name, email)| (name.as_str(), email.as_str()))
+                .collect(),
+            self.subject,
+            &self.html,
+            &self.text,
+            db,
+        );
+    }
+}
+
+/// Marks the spar as complete. Returns the emails which give each speaker the
+/// feedback from their adjudicators (along with a link they can use to give
+/// feedback on their adjudicators).
+pub fn complete_spar(
+    spar: &Spar,
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Vec<CompletionEmail>, diesel::result::Error> {
+    let n = diesel::update(spars::table.filter(spars::id.eq(spar.id)))
+        .set((spars::is_open.eq(false), spars::is_complete.eq(true)))
+        .execute(conn)?;
+    assert_eq!(n, 1);
+
+    // send speakers the feedback from their adjudicators, and ask
+    // them for feedback on their adjudicators
+    let links = create_feedback_links(spar.id, conn)?;
+    let mut emails = Vec::with_capacity(links.len());
+    for (speaker, member, link) in links {
+        let comments = comments_for_speaker(speaker.id, conn)?;
+        let feedback_link =
+            format!("https://eldemite.net/feedback/submit/{link}");
+        let history_link =
+            format!("https://eldemite.net/feedback/history/{link}");
+        let text_comments = text_of_received_comments(&comments);
+        emails.push(CompletionEmail {
+            to: vec![(member.name, member.email)],
+            subject: "Feedback from your spar",
+            html: maud::html! {
+                p { "Thanks for taking part in the spar!" }
+                (render_received_comments(&comments))
+                p {
+                    "Please use " a href=(feedback_link) { "this link" }
+                    " to give feedback on the adjudicators in your
+                     room. You can see all the feedback you have
+                     received " a href=(history_link) { "here" } "."
+                }
             }
+            .into_string(),
+            text: format!(
+                "Thanks for taking part in the spar!\n\n\
+                 {text_comments}\n\n\
+                 Please use this link to give feedback on the \
+                 adjudicators in your room: {feedback_link}\n\n\
+                 You can see all the feedback you have received \
+                 here: {history_link}"
+            ),
+        });
+    }
+
+    Ok(emails)
+}
+
+/// Marks every spar as complete which belongs to a series that has automatic
+/// completion enabled (see [`db::ballot::BallotSettings`]), has had its draw
+/// released, has a ballot for every room, and has never been reopened.
+/// Returns the number of spars which were completed, along with the emails
+/// which should be sent (these include a notification to the administrators
+/// of the group for each spar which was completed).
+pub fn complete_ready_spars(
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<(usize, Vec<CompletionEmail>), diesel::result::Error> {
+    let ready = spar_series_ballot_settings::table
+        .filter(spar_series_ballot_settings::auto_complete.eq(true))
+        .inner_join(spar_series::table.inner_join(spars::table))
+        .filter(spars::release_draw.eq(true))
+        .filter(spars::is_complete.eq(false))
+        .select((spars::all_columns, spar_series::all_columns))
+        .load::<(Spar, SparSeries)>(conn)?;
+
+    // spars which have been reopened are left for an administrator to
+    // complete (otherwise they would be completed again straight away)
+    let reopened = spar_reopenings::table
+        .select(spar_reopenings::spar_id)
+        .distinct()
+        .load::<i64>(conn)?;
+
+    let mut completed = 0;
+    let mut emails = Vec::new();
+    for (spar, series) in ready {
+        if reopened.contains(&spar.id)
+            || !completion_problems(&spar, conn)?.is_empty()
+        {
+            continue;
+        }
+
+        emails.append(&mut complete_spar(&spar, conn)?);
+        completed += 1;
+
+        let admins = users::table
+            .inner_join(group_members::table)
+            .filter(group_members::group_id.eq(series.group_id))
+            .filter(
+                group_members::is_admin
+                    .eq(true)
+                    .or(group_members::has_signing_power.eq(true)),
+            )
+            .select(users::all_columns)
+            .load::<User>(conn)?;
+        if admins.is_empty() {
+            continue;
         }
 
-        let n = diesel::update(spars::table.filter(spars::id.eq(spar.id)))
-            .set((spars::is_open.eq(false), spars::is_complete.eq(true)))
-            .execute(conn)
-            .unwrap();
-        assert_eq!(n, 1);
-        Some(Ok(Redirect::to(format!("/spars/{}", spar.public_id))))
-    }).await
+        let spar_link =
+            format!("https://eldemite.net/spars/{}", spar.public_id);
+        let start_time = spar.start_time.format("%Y-%m-%d %H:%M");
+        emails.push(CompletionEmail {
+            to: admins
+                .into_iter()
+                .map(|admin| {
+                    (
+                        admin.username.unwrap_or_else(|| admin.email.clone()),
+                        admin.email,
+                    )
+                })
+                .collect(),
+            subject: "Spar marked as complete",
+            html: maud::html! {
+                p {
+                    "Every room in the " (series.title) " spar at "
+                    (start_time) " now has a ballot, so "
+                    a href=(spar_link) { "the spar" }
+                    " has been marked as complete."
+                }
+            }
+            .into_string(),
+            text: format!(
+                "Every room in the {} spar at {start_time} now has a ballot, \
+                 so the spar has been marked as complete: {spar_link}",
+                series.title
+            ),
+        });
+    }
+
+    Ok((completed, emails))
 }
@@ -0,0 +1,153 @@
+//! Compares the candidate draws produced by a single request to generate a
+//! draw (see [`crate::spar_generation::allocation_problem::presets`]).
+
+use db::{
+    draft_draw::{DraftDraw, DraftDrawCandidate},
+    schema::{draft_draw_candidates, draft_draws, spar_series, spars},
+    spar::Spar,
+    user::User,
+    DbConn,
+};
+use diesel::prelude::*;
+use maud::{html, Markup};
+use rocket::request::FlashMessage;
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    spar_generation::allocation_problem::presets::preset_of_name,
+    util::tx,
+};
+
+#[get("/spars/<spar_id>/draw_candidates/<generation_id>")]
+/// Shows the candidate draws side by side, so that the admin can pick the
+/// best one.
+pub async fn draw_candidates_page(
+    spar_id: &str,
+    generation_id: &str,
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_id = spar_id.to_string();
+    let generation_id = generation_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+
+        let group_id = spar_series::table
+            .filter(spar_series::id.eq(spar.spar_series_id))
+            .select(spar_series::group_id)
+            .first::<i64>(conn)
+            .unwrap();
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(group_id)),
+            conn,
+        ) {
+            return Some(error_403(
+                Some("Error: you are not authorized to view this spar!"),
+                Some(user),
+            ));
+        }
+
+        let candidates = draft_draw_candidates::table
+            .inner_join(draft_draws::table)
+            .filter(draft_draw_candidates::generation_id.eq(&generation_id))
+            .filter(draft_draws::spar_id.eq(spar.id))
+            .order_by(draft_draw_candidates::id.asc())
+            .select((
+                draft_draw_candidates::all_columns,
+                draft_draws::all_columns,
+            ))
+            .load::<(DraftDrawCandidate, DraftDraw)>(conn)
+            .unwrap();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let pending = candidates.iter().any(|(_, draw)| draw.data.is_none());
+        let best_gap = candidates
+            .iter()
+            .filter_map(|(candidate, _)| candidate.max_team_gap)
+            .min_by(f64::total_cmp);
+
+        let markup = html! {
+            @if pending {
+                meta http-equiv="refresh" content="5";
+            }
+            (page_title("Candidate draws"))
+            p {
+                a href={"/spars/" (spar.public_id)} { "Back to spar" }
+            }
+            p class="text-muted" {
+                "Each of these draws was generated using different weights
+                 (if several weights produce the same draw, it is only shown
+                 once). Pick one to edit and confirm (the others can be
+                 ignored)."
+            }
+            table class="table table-striped" {
+                thead {
+                    tr {
+                        th { "Weights" }
+                        th { "Largest gap between teams" }
+                        th { "Average gap between teams" }
+                        th { "Partner preferences honoured" }
+                        th {}
+                    }
+                }
+                tbody {
+                    @for (candidate, draw) in &candidates {
+                        @let draw_url = format!("/spars/{}/draws/{}", spar.public_id, draw.public_id);
+                        tr {
+                            td {
+                                b { (candidate.preset) }
+                                @if let Some(preset) = preset_of_name(&candidate.preset) {
+                                    div class="small text-muted" { (preset.description) }
+                                }
+                            }
+                            @if draw.data.is_none() {
+                                td colspan="3" class="text-muted" { "Generating..." }
+                            } @else {
+                                td {
+                                    @if let Some(gap) = candidate.max_team_gap {
+                                        (format!("{gap:.1}"))
+                                        @if Some(gap) == best_gap {
+                                            " " span class="badge bg-success" { "best" }
+                                        }
+                                    }
+                                }
+                                td {
+                                    @if let Some(gap) = candidate.mean_team_gap {
+                                        (format!("{gap:.1}"))
+                                    }
+                                }
+                                td {
+                                    @if let (Some(honoured), Some(requested)) = (candidate.partner_preferences_honoured, candidate.partner_preferences_requested) {
+                                        (honoured) " / " (requested)
+                                    }
+                                }
+                            }
+                            td {
+                                @if draw.data.is_some() {
+                                    a class="btn btn-sm btn-outline-primary me-1" href=(draw_url) { "View and edit" }
+                                    a class="btn btn-sm btn-success" href={(draw_url) "/confirm"} { "Confirm" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        Some(page_of_body_and_flash_msg(markup, msg, Some(user)))
+    })
+    .await
+}
@@ -1,16 +1,15 @@
-use std::collections::HashMap;
-
 use chrono::{TimeDelta, Utc};
 use db::{
     draft_draw::{DraftDraw, DraftDrawData, Team},
+    room::{CHAIR, PANELLIST},
     schema::{
-        draft_draws, spar_adjudicator_ballot_links, spar_adjudicators,
-        spar_rooms, spar_series, spar_series_members, spar_signups,
+        draft_draw_versions, draft_draws, spar_adjudicator_ballot_links,
+        spar_adjudicators, spar_rooms, spar_series, spar_series_members,
         spar_speakers,
         spar_teams::{self},
         spars,
     },
-    spar::{Spar, SparSeriesMember, SparSignup},
+    spar::{Spar, SparSeriesMember},
     user::User,
     DbConn,
 };
@@ -29,7 +28,12 @@ use crate::{
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
     resources::GroupRef,
-    spar_generation::individual_spars::draw_management::draft_management::render_draw_data,
+    spar_generation::individual_spars::draw_management::{
+        draft_management::render_draw_data,
+        validation::{
+            describe_problem, member_names, render_problems, validate_draft,
+        },
+    },
 };
 
 #[get("/spars/<spar_id>/draws/<draw_id>/confirm")]
@@ -95,6 +99,10 @@ pub async fn confirm_draw_page(
                 }, Some(user)))),
             };
 
+            let problems = validate_draft(&spar, &data, conn);
+            let names = member_names(spar.spar_series_id, conn);
+            let has_errors = problems.iter().any(|problem| problem.is_error());
+
             let preexisting_draw = select(exists(
                 spar_rooms::table.filter(spar_rooms::spar_id.eq(spar.id))
             ))
@@ -106,6 +114,7 @@ pub async fn confirm_draw_page(
                     h3 {
                         "Would you like to confirm this draw?"
                     }
+                    (render_problems(&problems, &names, false))
                     (render_draw_data(&data, conn))
 
                     @if let Ok(true) = preexisting_draw {
@@ -118,7 +127,7 @@ pub async fn confirm_draw_page(
 
                     form method="post" action={ "/spars/" (spar_id) "/draws/" (draw_id) "/confirm" } {
                         div .d-flex.mt-4 {
-                            button .btn.btn-primary.me-2 type="submit" { "Confirm Draw" }
+                            button .btn.btn-primary.me-2 type="submit" disabled[has_errors] { "Confirm Draw" }
                             a .btn.btn-secondary href={ "/spars/" (spar_id) } { "Cancel" }
                         }
                     }
@@ -199,12 +208,20 @@ pub async fn do_confirm_draw(
                     ))),
                 };
 
-            let signups = spar_signups::table
-                .filter(spar_signups::spar_id.eq(spar.id))
-                .load::<SparSignup>(conn)?
-                .into_iter()
-                .map(|signup| (signup.member_id, signup))
-                .collect::<HashMap<i64, SparSignup>>();
+            let problems = validate_draft(&spar, &data, conn);
+            if let Some(error) =
+                problems.iter().find(|problem| problem.is_error())
+            {
+                let names = member_names(spar.spar_series_id, conn);
+                return Ok(Some(Flash::error(
+                    Redirect::to(format!("/spars/{spar_id}/draws/{draw_id}")),
+                    format!(
+                        "Error: this draw cannot be confirmed because {} (see \
+                         the draft draw page for all the problems).",
+                        describe_problem(error, &names)
+                    ),
+                )));
+            }
 
             diesel::delete(
                 spar_rooms::table.filter(spar_rooms::spar_id.eq(spar.id)),
@@ -221,24 +238,27 @@ pub async fn do_confirm_draw(
                     .returning(spar_rooms::id)
                     .get_result::<i64>(conn)?;
 
+                // note: members in the draw need not have signed up (they can
+                // be added to a draft draw by name)
                 for adj in room.panel {
-                    let adj_signup = &signups[&adj];
                     diesel::insert_into(spar_adjudicators::table)
                         .values((
                             spar_adjudicators::public_id
                                 .eq(Uuid::now_v7().to_string()),
-                            spar_adjudicators::member_id
-                                .eq(adj_signup.member_id),
+                            spar_adjudicators::member_id.eq(adj),
                             spar_adjudicators::room_id.eq(spar_room_id),
-                            // todo: eventually allocate chairs
-                            spar_adjudicators::status.eq("panellist"),
+                            spar_adjudicators::status.eq(
+                                if room.chair == Some(adj) {
+                                    CHAIR
+                                } else {
+                                    PANELLIST
+                                },
+                            ),
                         ))
                         .execute(conn)?;
 
                     let member = spar_series_members::table
-                        .filter(
-                            spar_series_members::id.eq(adj_signup.member_id),
-                        )
+                        .filter(spar_series_members::id.eq(adj))
                         .first::<SparSeriesMember>(conn)?;
 
                     // todo: when deleting the records for previous rooms, we
@@ -285,12 +305,11 @@ pub async fn do_confirm_draw(
                         .get_result::<i64>(conn)?;
 
                     for speaker in speakers {
-                        let signup = &signups[&speaker];
                         insert_into(spar_speakers::table)
                             .values((
                                 spar_speakers::public_id
                                     .eq(Uuid::now_v7().to_string()),
-                                spar_speakers::member_id.eq(signup.member_id),
+                                spar_speakers::member_id.eq(speaker),
                                 spar_speakers::team_id.eq(team_id),
                             ))
                             .execute(conn)?;
@@ -298,6 +317,15 @@ pub async fn do_confirm_draw(
                 }
             }
 
+            // the history of the draft is no longer needed once it has been
+            // confirmed
+            diesel::delete(
+                draft_draw_versions::table
+                    .filter(draft_draw_versions::draft_draw_id.eq(draw.id))
+                    .filter(draft_draw_versions::version.ne(draw.version)),
+            )
+            .execute(conn)?;
+
             Ok(Some(Flash::success(
                 Redirect::to(format!("/spars/{}/", spar_id)),
                 "Attached that draft draw to this spar!",
@@ -0,0 +1,292 @@
+//! Shows what changed between two versions of the draft draws of a spar
+//! (either two versions of the same draft, or two different drafts).
+
+use std::collections::{HashMap, HashSet};
+
+use db::{
+    draft_draw::{DraftDraw, DraftDrawData, DraftDrawRoom, DrawLoc, Team},
+    schema::{draft_draws, spar_series, spars},
+    spar::Spar,
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use itertools::Itertools;
+use maud::{html, Markup};
+
+use crate::{
+    html::{error_403, page_of_body, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    spar_generation::individual_spars::draw_management::{
+        history::versions_of_draft, validation::member_names,
+    },
+    util::tx,
+};
+
+/// A version of a draft draw which can be compared against another.
+struct DrawChoice {
+    /// Either the public id of a draft draw (for its current version), or the
+    /// public id of a version of a draft draw.
+    value: String,
+    label: String,
+    data: Option<String>,
+}
+
+fn choices_of_spar(spar: &Spar, conn: &mut DbWrapper) -> Vec<DrawChoice> {
+    let drafts = draft_draws::table
+        .filter(draft_draws::spar_id.eq(spar.id))
+        .order_by(draft_draws::created_at.desc())
+        .load::<DraftDraw>(conn)
+        .unwrap();
+
+    let mut choices = Vec::new();
+    for draft in drafts {
+        let versions = versions_of_draft(draft.id, conn).unwrap();
+        choices.push(DrawChoice {
+            value: draft.public_id.clone(),
+            label: format!(
+                "Draft {} (current, version {})",
+                draft.public_id, draft.version
+            ),
+            data: draft.data.clone(),
+        });
+        for (version, _) in versions {
+            if version.version == draft.version {
+                continue;
+            }
+            choices.push(DrawChoice {
+                value: version.public_id,
+                label: format!(
+                    "Draft {}, version {}{}",
+                    draft.public_id,
+                    version.version,
+                    version
+                        .command
+                        .map(|command| format!(" ({command})"))
+                        .unwrap_or_default()
+                ),
+                data: Some(version.data),
+            });
+        }
+    }
+    choices
+}
+
+fn describe_position(position: Option<(usize, DrawLoc)>) -> String {
+    match position {
+        None => "not in the draw".to_string(),
+        Some((room, DrawLoc::Panel)) => format!("room {room}, panel"),
+        Some((room, DrawLoc::Team(team))) => {
+            format!("room {room}, {}", team.abbreviation())
+        }
+    }
+}
+
+fn render_room(
+    room: Option<&DraftDrawRoom>,
+    moved: &HashSet<i64>,
+    names: &HashMap<i64, String>,
+) -> Markup {
+    let name_of = |member: &i64| {
+        names
+            .get(member)
+            .cloned()
+            .unwrap_or_else(|| "(unknown member)".to_string())
+    };
+
+    html! {
+        @if let Some(room) = room {
+            ul class="list-unstyled mb-0" {
+                @for team in Team::ALL {
+                    li {
+                        b { (team.abbreviation()) ": " }
+                        @for (i, member) in room.teams[&team].iter().sorted().enumerate() {
+                            @if i > 0 { ", " }
+                            @if moved.contains(member) {
+                                mark { (name_of(member)) }
+                            } @else {
+                                (name_of(member))
+                            }
+                        }
+                    }
+                }
+                li {
+                    b { "Panel: " }
+                    @for (i, member) in room.panel.iter().sorted().enumerate() {
+                        @if i > 0 { ", " }
+                        @if moved.contains(member) {
+                            mark { (name_of(member)) }
+                        } @else {
+                            (name_of(member))
+                        }
+                        @if room.chair == Some(*member) { " (chair)" }
+                    }
+                }
+            }
+        } @else {
+            p class="text-muted mb-0" { "(this room does not exist)" }
+        }
+    }
+}
+
+fn render_diff(
+    before: &DraftDrawData,
+    after: &DraftDrawData,
+    names: &HashMap<i64, String>,
+) -> Markup {
+    let diff = before.diff(after);
+    let moved = diff
+        .moved
+        .iter()
+        .map(|person| person.member)
+        .collect::<HashSet<_>>();
+
+    html! {
+        @if diff.moved.is_empty() && diff.changed_rooms.is_empty() {
+            div class="alert alert-info" { "These versions of the draw are the same." }
+        } @else {
+            h3 { "Moved people" }
+            table class="table table-sm table-striped" {
+                thead {
+                    tr {
+                        th { "Name" }
+                        th { "Before" }
+                        th { "After" }
+                    }
+                }
+                tbody {
+                    @for person in &diff.moved {
+                        tr {
+                            td { (names.get(&person.member).map(|name| name.as_str()).unwrap_or("(unknown member)")) }
+                            td { (describe_position(person.before)) }
+                            td { (describe_position(person.after)) }
+                        }
+                    }
+                }
+            }
+
+            h3 { "Changed rooms" }
+            @for room in &diff.changed_rooms {
+                div class="row mb-3" {
+                    div class="col-md-6" {
+                        div class="card" {
+                            div class="card-header" { "Room " (room) " (before)" }
+                            div class="card-body" {
+                                (render_room(before.rooms.get(*room), &moved, names))
+                            }
+                        }
+                    }
+                    div class="col-md-6" {
+                        div class="card" {
+                            div class="card-header" { "Room " (room) " (after)" }
+                            div class="card-body" {
+                                (render_room(after.rooms.get(*room), &moved, names))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[get("/spars/<spar_id>/draw_diff?<from>&<to>")]
+/// Compares two versions of the draft draws of a spar.
+pub async fn draw_diff_page(
+    spar_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_id = spar_id.to_string();
+    let from = from.map(|from| from.to_string());
+    let to = to.map(|to| to.to_string());
+    tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+
+        let group_id = spar_series::table
+            .filter(spar_series::id.eq(spar.spar_series_id))
+            .select(spar_series::group_id)
+            .first::<i64>(conn)
+            .unwrap();
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(group_id)),
+            conn,
+        ) {
+            return Some(error_403(
+                Some("Error: you are not authorized to view this spar!"),
+                Some(user),
+            ));
+        }
+
+        let choices = choices_of_spar(&spar, conn);
+        let names = member_names(spar.spar_series_id, conn);
+
+        let data_of = |value: &Option<String>| -> Option<DraftDrawData> {
+            let value = value.as_ref()?;
+            let choice =
+                choices.iter().find(|choice| &choice.value == value)?;
+            choice
+                .data
+                .as_ref()
+                .map(|data| serde_json::from_str(data).unwrap())
+        };
+        let comparison = match (data_of(&from), data_of(&to)) {
+            (Some(before), Some(after)) => {
+                Some(render_diff(&before, &after, &names))
+            }
+            _ => None,
+        };
+
+        let select = |name: &str, selected: &Option<String>| {
+            html! {
+                select class="form-select" name=(name) id=(name) {
+                    @for choice in &choices {
+                        option value=(choice.value) selected[selected.as_ref() == Some(&choice.value)] {
+                            (choice.label)
+                        }
+                    }
+                }
+            }
+        };
+
+        let markup = html! {
+            (page_title("Compare draws"))
+            p {
+                a href={"/spars/" (spar.public_id)} { "Back to spar" }
+            }
+            form method="get" class="row g-3 mb-4" {
+                div class="col-md-5" {
+                    label for="from" class="form-label" { "Before" }
+                    (select("from", &from))
+                }
+                div class="col-md-5" {
+                    label for="to" class="form-label" { "After" }
+                    (select("to", &to))
+                }
+                div class="col-md-2 d-flex align-items-end" {
+                    button type="submit" class="btn btn-primary" { "Compare" }
+                }
+            }
+            @if let Some(comparison) = comparison {
+                (comparison)
+            } @else if from.is_some() || to.is_some() {
+                div class="alert alert-warning" {
+                    "Could not find (or load the data of) one of those draws."
+                }
+            }
+        };
+
+        Some(page_of_body(markup, Some(user)))
+    })
+    .await
+}
@@ -1,26 +1,35 @@
 //! Management for draft draws.
 
 use db::{
-    draft_draw::{DraftDraw, DraftDrawData, EditAction, Team},
-    schema::{draft_draws, spar_series, spar_series_members, spars},
+    draft_draw::{
+        DraftDraw, DraftDrawData, DraftDrawVersion, EditAction, EditError, Team,
+    },
+    schema::{
+        draft_draw_candidates, draft_draws, spar_series, spar_series_members,
+        spars,
+    },
     spar::{Spar, SparSeriesMember},
     user::User,
-    DbConn,
+    DbConn, DbWrapper,
 };
 use diesel::{connection::LoadConnection, prelude::*, sqlite::Sqlite};
-use itertools::Itertools;
 use maud::Markup;
 use rocket::{
     form::Form,
+    request::FlashMessage,
     response::{Flash, Redirect},
 };
 use tracing::Instrument;
 
 use crate::{
-    html::page_of_body,
+    html::page_of_body_and_flash_msg,
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
     resources::GroupRef,
+    spar_generation::individual_spars::draw_management::{
+        editor::render_draw_editor,
+        history::{record_edit, render_history, versions_of_draft},
+    },
     util::tx,
 };
 
@@ -30,10 +39,12 @@ pub async fn view_draft_draw(
     draw_id: &str,
     db: DbConn,
     user: User,
+    msg: Option<FlashMessage<'_>>,
     span: TracingSpan,
 ) -> Option<Markup> {
     let draw_id = draw_id.to_string();
     let spar_id = spar_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
     tx(span, db, move |conn| {
         let spar = match spars::table
             .filter(spars::public_id.eq(&spar_id))
@@ -88,12 +99,24 @@ pub async fn view_draft_draw(
             .as_ref()
             .map(|draw| serde_json::from_str(&draw).unwrap());
 
+        let versions = versions_of_draft(draw.id, conn).unwrap();
+
+        let generation_id = draft_draw_candidates::table
+            .filter(draft_draw_candidates::draft_draw_id.eq(draw.id))
+            .select(draft_draw_candidates::generation_id)
+            .first::<String>(conn)
+            .optional()
+            .unwrap();
+
         Some(render_draft_management_page(
             &draw,
             draw_data,
             &other_draws_of_same_spar,
+            &versions,
+            generation_id.as_deref(),
             &spar,
             user,
+            msg,
             conn,
         ))
     })
@@ -181,123 +204,10 @@ pub fn render_draw_data(
                             td {
                                 @for member_id in &room.panel {
                                     @let member = get_member(*member_id);
-                                    div { (member.name) }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
-
-/// Renders the drag and drop interface to edit the draw.
-#[tracing::instrument(skip(draw_data, conn, current_draw, spar))]
-pub fn render_drag_n_drop_draw_data(
-    draw_data: DraftDrawData,
-    spar: &Spar,
-    current_draw: &DraftDraw,
-    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
-) -> Markup {
-    // todo: compute this upfront
-    let mut get_member = |id: i64| {
-        spar_series_members::table
-            .filter(spar_series_members::id.eq(id))
-            .first::<SparSeriesMember>(conn)
-            .unwrap()
-    };
-
-    use maud::{html, PreEscaped};
-
-    html! {
-        script src="https://cdn.jsdelivr.net/npm/sortablejs@1.15.6/Sortable.min.js" {}
-
-        div class="table-responsive" {
-            form id="speaker-swap-form" method="post" action={"/spars/" (spar.public_id) "/draws/" (current_draw.public_id) "/edit"} {
-                input type="hidden" id="swap-query" name="query" value="";
-
-                table class="table table-striped table-bordered" {
-                    thead class="table-dark" {
-                        tr {
-                            th { "Room" }
-                            th { "Opening Government" }
-                            th { "Opening Opposition" }
-                            th { "Closing Government" }
-                            th { "Closing Opposition" }
-                            th { "Panel" }
-                        }
-                    }
-                    tbody {
-                        @for (i, room) in draw_data.rooms.iter().enumerate() {
-                            tr {
-                                td { (i) }
-
-                                // Opening Government
-                                td {
-                                    div class="sortable-team" data-room={(i)} data-position="og" {
-                                        @let team = room.teams.get(&Team::Og).unwrap();
-                                        @for member_id in team.iter().sorted_by_key(|x| *x) {
-                                            @let member = get_member(*member_id);
-                                            @let public_id = draw_data.id_map.get(member_id).unwrap_or(&0);
-                                            div class="sortable-speaker" data-id={(public_id)} data-member-id={(member_id)} {
-                                                (member.name)
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // Opening Opposition
-                                td {
-                                    div class="sortable-team" data-room={(i)} data-position="oo" {
-                                        @let team = room.teams.get(&Team::Oo).unwrap();
-                                        @for member_id in team.iter().sorted_by_key(|x| *x) {
-                                            @let member = get_member(*member_id);
-                                            @let public_id = draw_data.id_map.get(member_id).unwrap_or(&0);
-                                            div class="sortable-speaker" data-id={(public_id)} data-member-id={(member_id)} {
-                                                (member.name)
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // Closing Government
-                                td {
-                                    div class="sortable-team" data-room={(i)} data-position="cg" {
-                                        @let team = room.teams.get(&Team::Cg).unwrap();
-                                        @for member_id in team.iter().sorted_by_key(|x| *x) {
-                                            @let member = get_member(*member_id);
-                                            @let public_id = draw_data.id_map.get(member_id).unwrap_or(&0);
-                                            div class="sortable-speaker" data-id={(public_id)} data-member-id={(member_id)} {
-                                                (member.name)
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // Closing Opposition
-                                td {
-                                    div class="sortable-team" data-room={(i)} data-position="co" {
-                                        @let team = room.teams.get(&Team::Co).unwrap();
-                                        @for member_id in team.iter().sorted_by_key(|x| *x) {
-                                            @let member = get_member(*member_id);
-                                            @let public_id = draw_data.id_map.get(member_id).unwrap_or(&0);
-                                            div class="sortable-speaker" data-id={(public_id)} data-member-id={(member_id)} {
-                                                (member.name)
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // Panel
-                                td {
-                                    div class="sortable-team" data-room={(i)} data-position="panel" {
-                                        @for member_id in room.panel.iter().sorted_by_key(|x| *x)  {
-                                            @let member = get_member(*member_id);
-                                            @let public_id = draw_data.id_map.get(member_id).unwrap_or(&0);
-                                            div class="sortable-speaker" data-id={(public_id)} data-member-id={(member_id)} {
-                                                (member.name)
-                                            }
+                                    div {
+                                        (member.name)
+                                        @if room.chair == Some(*member_id) {
+                                            " (chair)"
                                         }
                                     }
                                 }
@@ -307,114 +217,6 @@ pub fn render_drag_n_drop_draw_data(
                 }
             }
         }
-
-        style {
-            r#"
-            .sortable-speaker {
-                cursor: move;
-                padding: 8px;
-                margin: 4px 0;
-                background: #f8f9fa;
-                border: 1px solid #dee2e6;
-                border-radius: 4px;
-            }
-            .sortable-speaker:hover {
-                background: #e9ecef;
-            }
-            .sortable-speaker.sortable-ghost {
-                opacity: 0.5;
-                background: #cfe2ff;
-            }
-            .sortable-speaker.sortable-chosen {
-                background: #d1e7dd;
-            }
-            .sortable-team {
-                min-height: 40px;
-                padding: 5px;
-            }
-            .selected {
-                background-color: #d4edda !important;
-                border: 2px solid #28a745;
-            }
-            "#
-        }
-
-        (PreEscaped(r#"
-        <script>
-            document.addEventListener('DOMContentLoaded', function() {
-                const teams = document.querySelectorAll('.sortable-team');
-                let selectedSpeakers = [];
-
-                // Initialize Sortable for each team
-                teams.forEach(team => {
-                    new Sortable(team, {
-                        group: 'speakers',
-                        animation: 150,
-                        ghostClass: 'sortable-ghost',
-                        chosenClass: 'sortable-chosen',
-                        onEnd: function(evt) {
-                            if (evt.from !== evt.to || evt.oldIndex !== evt.newIndex) {
-                                const sourceElement = evt.item;
-                                const sourceId = sourceElement.dataset.id;
-
-                                // Get the next sibling in the new position or the previous one if at the end
-                                let targetElement;
-                                if (evt.to.children.length > 1) {
-                                    if (evt.newIndex === evt.to.children.length - 1) {
-                                        targetElement = evt.to.children[evt.newIndex - 1];
-                                    } else {
-                                        targetElement = evt.to.children[evt.newIndex + 1];
-                                    }
-
-                                    const targetId = targetElement.dataset.id;
-                                    const swapQuery = `swap ${sourceId} ${targetId}`;
-
-                                    document.getElementById('swap-query').value = swapQuery;
-                                    document.getElementById('speaker-swap-form').submit();
-                                } else {
-                                    // Single item in list or other edge case
-                                    const swapQuery = `swap ${sourceId} ${sourceId}`;
-                                    document.getElementById('swap-query').value = swapQuery;
-                                    document.getElementById('speaker-swap-form').submit();
-                                }
-                            }
-                        }
-                    });
-                });
-
-                // Click selection logic
-                document.querySelectorAll('.sortable-speaker').forEach(speaker => {
-                    speaker.addEventListener('click', function(e) {
-                        if (e.ctrlKey || e.metaKey) {
-                            e.preventDefault(); // Prevent default selection
-
-                            // Add or remove from selected list
-                            if (this.classList.contains('selected')) {
-                                this.classList.remove('selected');
-                                selectedSpeakers = selectedSpeakers.filter(s => s !== this);
-                            } else {
-                                // Only allow two selections
-                                if (selectedSpeakers.length < 2) {
-                                    this.classList.add('selected');
-                                    selectedSpeakers.push(this);
-
-                                    // If we now have exactly 2 selections, automatically submit the form
-                                    if (selectedSpeakers.length === 2) {
-                                        const id1 = selectedSpeakers[0].dataset.id;
-                                        const id2 = selectedSpeakers[1].dataset.id;
-                                        document.getElementById('swap-query').value = `swap ${id1} ${id2}`;
-                                        document.getElementById('speaker-swap-form').submit();
-                                    }
-                                } else {
-                                    alert('You can only select two speakers at a time for swapping');
-                                }
-                            }
-                        }
-                    });
-                });
-            });
-        </script>
-        "#))
     }
 }
 
@@ -422,22 +224,27 @@ pub fn render_drag_n_drop_draw_data(
     current_draw,
     draw_data,
     all_draws,
+    versions,
     spar,
     user,
+    msg,
     conn
 ))]
 fn render_draft_management_page(
     current_draw: &DraftDraw,
     draw_data: Option<DraftDrawData>,
     all_draws: &[DraftDraw],
+    versions: &[(DraftDrawVersion, Option<User>)],
+    generation_id: Option<&str>,
     spar: &Spar,
     user: User,
-    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+    msg: Option<String>,
+    conn: &mut DbWrapper,
 ) -> Markup {
     use maud::html;
 
     let rendered_data = if let Some(draw_data) = draw_data {
-        render_drag_n_drop_draw_data(draw_data, spar, current_draw, conn)
+        render_draw_editor(&draw_data, spar, current_draw, conn)
     } else {
         maud::html! {
             div class="alert alert-info" role="alert" {
@@ -489,6 +296,13 @@ fn render_draft_management_page(
                         }
                         div class="card-body" {
                             p { "Draw ID: " (current_draw.public_id) }
+                            @if let Some(generation_id) = generation_id {
+                                p {
+                                    a href={"/spars/" (spar.public_id) "/draw_candidates/" (generation_id)} style="color: #E32879;" {
+                                        "Compare with the other candidate draws"
+                                    }
+                                }
+                            }
                             p class="mt-3" {
                                 a href={"/spars/" (spar.public_id) "/draws/" (current_draw.public_id) "/confirm"} class="btn btn-success" {
                                     "Confirm draw"
@@ -509,7 +323,22 @@ fn render_draft_management_page(
                             p { "Spar ID: " (spar.public_id) }
                             p { "Created: " (spar.created_at) }
 
+                            h4 { "Edit with commands" }
+                            form method="post" action={"/spars/" (spar.public_id) "/draws/" (current_draw.public_id) "/edit"} {
+                                textarea class="form-control font-monospace mb-2" name="query" rows="4" placeholder="move 3 to room 1 og" {}
+                                button type="submit" class="btn btn-primary mb-2" { "Apply" }
+                            }
+                            (edit_commands_help())
+
+                            h4 { "History" }
+                            div id="draft-draw-history" {
+                                (render_history(spar, current_draw, versions))
+                            }
+
                             h4 { "All Draws" }
+                            a href={"/spars/" (spar.public_id) "/draw_diff"} style="color: #E32879;" {
+                                "Compare draws"
+                            }
                             ul class="list-group mt-3" {
                                 @for draw in all_draws {
                                     li class="list-group-item" {
@@ -529,7 +358,77 @@ fn render_draft_management_page(
         }
     };
 
-    page_of_body(markup, Some(user))
+    page_of_body_and_flash_msg(markup, msg, Some(user))
+}
+
+/// Lists the commands which can be used to edit a draft draw (see
+/// [`EditAction`]).
+fn edit_commands_help() -> Markup {
+    maud::html! {
+        details class="mb-3" {
+            summary { "Available commands" }
+            p class="small text-muted mt-2" {
+                "People are referred to by the number shown next to their name.
+                 Several commands can be given at once by putting them on
+                 separate lines (or separating them with a semicolon). If any
+                 command fails then none of them are applied."
+            }
+            ul class="small" {
+                li { code { "swap 1 2" } " swaps two people" }
+                li { code { "remove 1" } " removes someone from the draw" }
+                li { code { "move 1 to room 3 og" } " moves someone to a team (" code { "og" } ", " code { "oo" } ", " code { "cg" } ", " code { "co" } ") or the " code { "panel" } }
+                li { code { "add \"Jane Doe\" to room 2 panel" } " adds a member of the spar series who is not in the draw" }
+                li { code { "newroom" } " adds an empty room" }
+                li { code { "delroom 4" } " deletes an (empty) room" }
+                li { code { "swapteams 1 og 2 co" } " swaps two teams" }
+                li { code { "chair 1" } " makes a judge the chair of their panel" }
+            }
+        }
+    }
+}
+
+/// Parses and applies the edit command(s) in `query` to the draft draw, and
+/// records the result as a new version of the draft.
+pub fn apply_edit_to_draft(
+    spar: &Spar,
+    draw: &DraftDraw,
+    data: &DraftDrawData,
+    query: &str,
+    user_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<DraftDrawData, EditError> {
+    let script = EditAction::parse_script(query)?;
+
+    let mut find_member = |name: &str| {
+        let matching = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(spar.spar_series_id))
+            .select((spar_series_members::id, spar_series_members::name))
+            .load::<(i64, String)>(conn)
+            .unwrap()
+            .into_iter()
+            .filter(|(_, member_name)| {
+                member_name.trim().eq_ignore_ascii_case(name.trim())
+            })
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        match matching.as_slice() {
+            [id] => Ok(*id),
+            [] => Err(EditError::NoMemberNamed(name.to_string())),
+            _ => Err(EditError::AmbiguousMemberName(name.to_string())),
+        }
+    };
+    let new_data = data.apply_script(script, &mut find_member)?;
+
+    record_edit(
+        draw,
+        &serde_json::to_string_pretty(&new_data).unwrap(),
+        query.trim(),
+        user_id,
+        conn,
+    )
+    .unwrap();
+
+    Ok(new_data)
 }
 
 #[derive(FromForm)]
@@ -565,6 +464,7 @@ pub async fn do_edit_draw(
 
             let draw = match draft_draws::table
                 .filter(draft_draws::public_id.eq(&draw_id))
+                .filter(draft_draws::spar_id.eq(spar.id))
                 .first::<DraftDraw>(conn)
                 .optional()
                 .unwrap()
@@ -574,7 +474,7 @@ pub async fn do_edit_draw(
             };
 
             // todo: return proper error messages to the user
-            let data: DraftDrawData = match draw.data {
+            let data: DraftDrawData = match &draw.data {
                 Some(data) => match serde_json::from_str(&data) {
                     Ok(data) => data,
                     Err(e) => {
@@ -601,41 +501,20 @@ pub async fn do_edit_draw(
                 return Ok(None);
             }
 
-            let action = match EditAction::parse(&form.query) {
-                Ok(t) => t,
-                Err(e) => {
-                    return Ok(Some(Flash::error(
-                        Redirect::to(format!(
-                            "/spars/{spar_id}/draws/{draw_id}"
-                        )),
-                        e.to_string(),
-                    )))
-                }
-            };
-
-            let new_data = match data.apply(action) {
-                Ok(t) => t,
-                Err(e) => {
-                    return Ok(Some(Flash::error(
-                        Redirect::to(format!(
-                            "/spars/{spar_id}/draws/{draw_id}"
-                        )),
-                        e.to_string(),
-                    )))
-                },
-            };
-
-            let n = diesel::update(draft_draws::table.filter(draft_draws::id.eq(draw.id)))
-                .set(draft_draws::data.eq(serde_json::to_string_pretty(&new_data).unwrap()))
-                .execute(conn)
-                .unwrap();
-            assert_eq!(n, 1);
+            if let Err(e) =
+                apply_edit_to_draft(&spar, &draw, &data, &form.query, user.id, conn)
+            {
+                return Ok(Some(Flash::error(
+                    Redirect::to(format!("/spars/{spar_id}/draws/{draw_id}")),
+                    e.to_string(),
+                )));
+            }
 
             return Ok(Some(Flash::success(
                 Redirect::to(format!(
                     "/spars/{spar_id}/draws/{draw_id}"
                 )),
-                "Applied that action",
+                "Applied those changes to the draw.",
             )))
         })
         .unwrap()
@@ -85,7 +85,7 @@ pub async fn show_draw_to_admin_page(
                 }
             }
 
-            (render_draw(draw_info, ballots))
+            (render_draw(draw_info, ballots, true))
         };
 
         page_of_body(markup, Some(user))
@@ -0,0 +1,327 @@
+//! The drag and drop editor for draft draws.
+//!
+//! Each drop is sent (using htmx) as an [`db::draft_draw::EditAction`], and
+//! the response only re-renders the rooms which the action changed (using
+//! out-of-band swaps).
+
+use std::collections::HashMap;
+
+use db::{
+    draft_draw::{DraftDraw, DraftDrawData, DraftDrawRoom, DrawProblem, Team},
+    schema::draft_draws,
+    spar::Spar,
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use itertools::Itertools;
+use maud::{html, Markup, PreEscaped};
+use rocket::form::Form;
+
+use crate::{
+    request_ids::TracingSpan,
+    spar_generation::individual_spars::draw_management::{
+        draft_management::{apply_edit_to_draft, EditDrawForm},
+        history::{load_draft_for_editing, render_history, versions_of_draft},
+        validation::{
+            describe_problem, member_names, render_problems, validate_draft,
+        },
+    },
+    util::tx,
+};
+
+fn edit_url(spar: &Spar, draw: &DraftDraw) -> String {
+    format!(
+        "/spars/{}/draws/{}/edit_rooms",
+        spar.public_id, draw.public_id
+    )
+}
+
+fn render_people<'a>(
+    people: impl Iterator<Item = &'a i64>,
+    room: &DraftDrawRoom,
+    id_map: &HashMap<i64, u64>,
+    names: &HashMap<i64, String>,
+) -> Markup {
+    html! {
+        @for member_id in people.sorted() {
+            @let public_id = id_map.get(member_id).unwrap_or(&0);
+            div class="sortable-speaker" data-id={(public_id)} data-member-id={(member_id)} {
+                span class="badge bg-secondary me-1" { (public_id) }
+                (names.get(member_id).map(|name| name.as_str()).unwrap_or("(unknown)"))
+                @if room.chair == Some(*member_id) {
+                    " (chair)"
+                }
+            }
+        }
+    }
+}
+
+/// Renders a single room of the editor. If `oob` is set, then the room is
+/// marked to be swapped in out-of-band (i.e. when it is part of the response
+/// to an edit).
+fn render_editor_room(
+    idx: usize,
+    room: &DraftDrawRoom,
+    id_map: &HashMap<i64, u64>,
+    names: &HashMap<i64, String>,
+    problems: &[DrawProblem],
+    oob: bool,
+) -> Markup {
+    let problems = problems
+        .iter()
+        .filter(|problem| problem.room() == Some(idx))
+        .collect::<Vec<_>>();
+
+    html! {
+        tbody id={"draft-room-" (idx)} hx-swap-oob=[oob.then_some("true")] {
+            tr {
+                td { (idx) }
+                @for team in Team::ALL {
+                    td {
+                        div class="sortable-team" data-room={(idx)} data-position=(team.abbreviation().to_lowercase()) {
+                            (render_people(room.teams[&team].iter(), room, id_map, names))
+                        }
+                    }
+                }
+                td {
+                    div class="sortable-team" data-room={(idx)} data-position="panel" {
+                        (render_people(room.panel.iter(), room, id_map, names))
+                    }
+                }
+            }
+            @for problem in problems {
+                tr {
+                    td colspan="6" class={"small " (if problem.is_error() { "text-danger" } else { "text-warning-emphasis" })} {
+                        (describe_problem(problem, names))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_editor_table(
+    draw_data: &DraftDrawData,
+    names: &HashMap<i64, String>,
+    problems: &[DrawProblem],
+    edit_url: &str,
+    oob: bool,
+) -> Markup {
+    html! {
+        div id="draw-editor" class="table-responsive" data-edit-url=(edit_url) hx-swap-oob=[oob.then_some("true")] {
+            table class="table table-bordered" {
+                thead class="table-dark" {
+                    tr {
+                        th { "Room" }
+                        th { "Opening Government" }
+                        th { "Opening Opposition" }
+                        th { "Closing Government" }
+                        th { "Closing Opposition" }
+                        th { "Panel" }
+                    }
+                }
+                @for (idx, room) in draw_data.rooms.iter().enumerate() {
+                    (render_editor_room(idx, room, &draw_data.id_map, names, problems, false))
+                }
+            }
+        }
+    }
+}
+
+/// Renders the drag and drop interface to edit the draw.
+#[tracing::instrument(skip(draw_data, conn, current_draw, spar))]
+pub fn render_draw_editor(
+    draw_data: &DraftDrawData,
+    spar: &Spar,
+    current_draw: &DraftDraw,
+    conn: &mut DbWrapper,
+) -> Markup {
+    let names = member_names(spar.spar_series_id, conn);
+    let problems = validate_draft(spar, draw_data, conn);
+
+    html! {
+        script src="https://cdn.jsdelivr.net/npm/sortablejs@1.15.6/Sortable.min.js" {}
+
+        p class="small text-muted" {
+            "Drag people between teams, panels and rooms to move them, or
+             ctrl-click (cmd-click on a Mac) two people to swap them."
+        }
+        (render_problems(&problems, &names, false))
+        div id="draw-editor-status" {}
+        (render_editor_table(draw_data, &names, &problems, &edit_url(spar, current_draw), false))
+
+        style {
+            r#"
+            .sortable-speaker {
+                cursor: move;
+                padding: 8px;
+                margin: 4px 0;
+                background: #f8f9fa;
+                border: 1px solid #dee2e6;
+                border-radius: 4px;
+            }
+            .sortable-speaker:hover {
+                background: #e9ecef;
+            }
+            .sortable-speaker.sortable-ghost {
+                opacity: 0.5;
+                background: #cfe2ff;
+            }
+            .sortable-speaker.sortable-chosen {
+                background: #d1e7dd;
+            }
+            .sortable-team {
+                min-height: 40px;
+                padding: 5px;
+            }
+            .selected {
+                background-color: #d4edda !important;
+                border: 2px solid #28a745;
+            }
+            "#
+        }
+
+        (PreEscaped(r#"
+        <script>
+            (function() {
+                function sendEdit(query) {
+                    const editor = document.getElementById('draw-editor');
+                    htmx.ajax('POST', editor.dataset.editUrl, {
+                        target: '#draw-editor-status',
+                        swap: 'innerHTML',
+                        values: { query: query }
+                    });
+                }
+
+                // Sortable has to be set up again for rooms which htmx has
+                // re-rendered
+                function initSortables() {
+                    document.querySelectorAll('.sortable-team').forEach(team => {
+                        if (team.dataset.sortableInit) {
+                            return;
+                        }
+                        team.dataset.sortableInit = 'true';
+                        new Sortable(team, {
+                            group: 'speakers',
+                            animation: 150,
+                            ghostClass: 'sortable-ghost',
+                            chosenClass: 'sortable-chosen',
+                            onEnd: function(evt) {
+                                // the order within a team/panel does not matter
+                                if (evt.from === evt.to) {
+                                    return;
+                                }
+                                const id = evt.item.dataset.id;
+                                const room = evt.to.dataset.room;
+                                const position = evt.to.dataset.position;
+                                sendEdit(`move ${id} to room ${room} ${position}`);
+                            }
+                        });
+                    });
+                }
+
+                let selectedSpeakers = [];
+                document.addEventListener('click', function(e) {
+                    const speaker = e.target.closest('.sortable-speaker');
+                    if (!speaker || !(e.ctrlKey || e.metaKey)) {
+                        return;
+                    }
+                    e.preventDefault();
+
+                    if (speaker.classList.contains('selected')) {
+                        speaker.classList.remove('selected');
+                        selectedSpeakers = selectedSpeakers.filter(s => s !== speaker);
+                        return;
+                    }
+
+                    speaker.classList.add('selected');
+                    selectedSpeakers.push(speaker);
+                    if (selectedSpeakers.length === 2) {
+                        const id1 = selectedSpeakers[0].dataset.id;
+                        const id2 = selectedSpeakers[1].dataset.id;
+                        selectedSpeakers.forEach(s => s.classList.remove('selected'));
+                        selectedSpeakers = [];
+                        sendEdit(`swap ${id1} ${id2}`);
+                    }
+                });
+
+                document.addEventListener('DOMContentLoaded', initSortables);
+                document.addEventListener('htmx:afterSettle', initSortables);
+                document.addEventListener('htmx:oobAfterSwap', initSortables);
+            })();
+        </script>
+        "#))
+    }
+}
+
+#[post("/spars/<spar_id>/draws/<draw_id>/edit_rooms", data = "<form>")]
+/// Applies an edit made using the drag and drop editor. The response contains
+/// a status message, and out-of-band swaps for the rooms which changed (and
+/// the history of the draft).
+pub async fn do_edit_draw_rooms(
+    spar_id: &str,
+    draw_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<EditDrawForm>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_id = spar_id.to_string();
+    let draw_id = draw_id.to_string();
+    tx(span, db, move |conn| {
+        let (spar, draw) =
+            load_draft_for_editing(&spar_id, &draw_id, &user, conn)?;
+        let data: DraftDrawData =
+            serde_json::from_str(draw.data.as_ref()?).unwrap();
+        let edit_url = edit_url(&spar, &draw);
+        let names = member_names(spar.spar_series_id, conn);
+
+        let new_data = match apply_edit_to_draft(
+            &spar,
+            &draw,
+            &data,
+            &form.query,
+            user.id,
+            conn,
+        ) {
+            Ok(new_data) => new_data,
+            Err(e) => {
+                // the browser will already have moved the person, so we put
+                // everything back where it was
+                let problems = validate_draft(&spar, &data, conn);
+                return Some(html! {
+                    div class="alert alert-danger" { "Error: " (e) }
+                    (render_editor_table(&data, &names, &problems, &edit_url, true))
+                });
+            }
+        };
+
+        let problems = validate_draft(&spar, &new_data, conn);
+        let draw = draft_draws::table
+            .filter(draft_draws::id.eq(draw.id))
+            .first::<DraftDraw>(conn)
+            .unwrap();
+        let versions = versions_of_draft(draw.id, conn).unwrap();
+
+        Some(html! {
+            div class="alert alert-success" {
+                "Applied " code { (form.query.trim()) }
+            }
+            @if new_data.rooms.len() != data.rooms.len() {
+                (render_editor_table(&new_data, &names, &problems, &edit_url, true))
+            } @else {
+                @for (idx, room) in new_data.rooms.iter().enumerate() {
+                    @if *room != data.rooms[idx] {
+                        (render_editor_room(idx, room, &new_data.id_map, &names, &problems, true))
+                    }
+                }
+            }
+            (render_problems(&problems, &names, true))
+            div id="draft-draw-history" hx-swap-oob="true" {
+                (render_history(&spar, &draw, &versions))
+            }
+        })
+    })
+    .await
+}
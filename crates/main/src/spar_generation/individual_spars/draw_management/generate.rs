@@ -3,7 +3,8 @@ use std::{collections::HashMap, sync::Arc};
 use db::{
     draft_draw::{DraftDrawData, DraftDrawRoom},
     schema::{
-        draft_draws, spar_series, spar_series_members, spar_signups, spars,
+        draft_draw_candidates, draft_draws, spar_series, spar_series_members,
+        spar_signups, spars,
     },
     spar::{Spar, SparSignup},
     user::User,
@@ -23,11 +24,15 @@ use crate::{
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
     resources::GroupRef,
-    spar_generation::allocation_problem::{
-        ratings::compute_scores,
-        solve_allocation::{
-            rooms_of_speaker_assignments, solve_lp, SolverRoom,
+    spar_generation::{
+        allocation_problem::{
+            presets::{metrics_of_draw, DRAW_PRESETS},
+            ratings::compute_scores,
+            solve_allocation::{
+                rooms_of_speaker_assignments, solve_lp_with_weights, SolverRoom,
+            },
         },
+        individual_spars::draw_management::history::record_version,
     },
 };
 
@@ -194,26 +199,41 @@ pub async fn generate_draw(
 
             let elo_scores = compute_scores(spar.spar_series_id, conn)?;
 
-            let draft_id = gen_uuid().to_string();
-
-            let draft_draw_id = insert_into(draft_draws::table)
-                .values((
-                    draft_draws::public_id.eq(&draft_id),
-                    draft_draws::data.eq(None::<String>),
-                    draft_draws::spar_id.eq(spar.id),
-                    draft_draws::version.eq(0),
-                    draft_draws::created_at.eq(diesel::dsl::now),
-                ))
-                .returning(draft_draws::id)
-                .get_result::<i64>(conn)
-                .unwrap();
+            let generation_id = gen_uuid().to_string();
+
+            // one candidate draft is generated for each preset
+            let mut candidates = Vec::new();
+            for preset in DRAW_PRESETS.iter() {
+                let draft_draw_id = insert_into(draft_draws::table)
+                    .values((
+                        draft_draws::public_id.eq(gen_uuid().to_string()),
+                        draft_draws::data.eq(None::<String>),
+                        draft_draws::spar_id.eq(spar.id),
+                        draft_draws::version.eq(0),
+                        draft_draws::created_at.eq(diesel::dsl::now),
+                    ))
+                    .returning(draft_draws::id)
+                    .get_result::<i64>(conn)
+                    .unwrap();
+
+                let candidate_id = insert_into(draft_draw_candidates::table)
+                    .values((
+                        draft_draw_candidates::draft_draw_id.eq(draft_draw_id),
+                        draft_draw_candidates::generation_id.eq(&generation_id),
+                        draft_draw_candidates::preset.eq(preset.name),
+                    ))
+                    .returning(draft_draw_candidates::id)
+                    .get_result::<i64>(conn)
+                    .unwrap();
 
+                candidates.push((draft_draw_id, candidate_id, preset));
+            }
 
-            Ok(Some(Ok((draft_draw_id, draft_id, elo_scores, signups))))
+            Ok(Some(Ok((candidates, generation_id, elo_scores, signups))))
         }).unwrap()
     }).instrument(span.0.clone()).await;
 
-    let (draft_draw_id, draft_public_id, elo_scores, signups) = match ctx {
+    let (candidates, generation_id, elo_scores, signups) = match ctx {
         Some(Ok(t)) => t,
         Some(Err(t)) => return Some(t),
         None => return None,
@@ -224,46 +244,112 @@ pub async fn generate_draw(
     rocket::tokio::task::spawn_blocking(move || {
         let _guard = span2.enter();
         tracing::info_span!("generating draw");
-        let rooms = {
-            let params = solve_lp(signups.clone(), elo_scores);
-            let solver_rooms = rooms_of_speaker_assignments(&params);
-            solver_room_to_draft_draw(solver_rooms)
-        };
-
-        let insertion_span = tracing::trace_span!("inserting generated draw");
-        tokio::task::spawn(
-            async move {
-                let tx_span = tracing::trace_span!("inserting draw tx");
-                db.run(move |conn| {
-                    let _guard = tx_span.enter();
-                    conn.transaction(
-                        |conn| -> Result<_, diesel::result::Error> {
-                            let n = diesel::update(
-                                draft_draws::table
-                                    .filter(draft_draws::id.eq(draft_draw_id)),
-                            )
-                            .set(draft_draws::data.eq(
-                                serde_json::to_string_pretty(&rooms).unwrap(),
-                            ))
-                            .execute(conn)
-                            .unwrap();
-                            assert_eq!(n, 1);
+        // the candidates are generated one after another (so that the first
+        // one is available as soon as possible)
+        let mut generated: Vec<DraftDrawData> = Vec::new();
+        for (draft_draw_id, candidate_id, preset) in candidates {
+            let rooms = {
+                let params = solve_lp_with_weights(
+                    signups.clone(),
+                    elo_scores.clone(),
+                    preset.weights,
+                );
+                let solver_rooms = rooms_of_speaker_assignments(&params);
+                solver_room_to_draft_draw(solver_rooms)
+            };
 
-                            Ok(())
-                        },
-                    )
-                    .unwrap();
-                })
-                .await
+            // different presets can lead the solver to the same draw, in
+            // which case we only show it once
+            if generated.iter().any(|other| other.is_same_draw_as(&rooms)) {
+                tracing::trace!(
+                    "Preset {} produced a duplicate draw",
+                    preset.name
+                );
+                let db = db.clone();
+                tokio::task::spawn(async move {
+                    db.run(move |conn| {
+                        conn.transaction(
+                            |conn| -> Result<_, diesel::result::Error> {
+                                diesel::delete(
+                                    draft_draw_candidates::table.filter(
+                                        draft_draw_candidates::id
+                                            .eq(candidate_id),
+                                    ),
+                                )
+                                .execute(conn)?;
+                                diesel::delete(
+                                    draft_draws::table.filter(
+                                        draft_draws::id.eq(draft_draw_id),
+                                    ),
+                                )
+                                .execute(conn)?;
+                                Ok(())
+                            },
+                        )
+                        .unwrap();
+                    })
+                    .await
+                });
+                continue;
             }
-            .instrument(insertion_span),
-        )
+            generated.push(rooms.clone());
+
+            let metrics = metrics_of_draw(&rooms, &signups, &elo_scores);
+
+            let insertion_span =
+                tracing::trace_span!("inserting generated draw");
+            let db = db.clone();
+            tokio::task::spawn(
+                async move {
+                    let tx_span = tracing::trace_span!("inserting draw tx");
+                    db.run(move |conn| {
+                        let _guard = tx_span.enter();
+                        conn.transaction(
+                            |conn| -> Result<_, diesel::result::Error> {
+                                record_version(
+                                    draft_draw_id,
+                                    0,
+                                    &serde_json::to_string_pretty(&rooms)
+                                        .unwrap(),
+                                    None,
+                                    None,
+                                    conn,
+                                )?;
+
+                                diesel::update(
+                                    draft_draw_candidates::table.filter(
+                                        draft_draw_candidates::id
+                                            .eq(candidate_id),
+                                    ),
+                                )
+                                .set((
+                                    draft_draw_candidates::max_team_gap
+                                        .eq(metrics.max_team_gap),
+                                    draft_draw_candidates::mean_team_gap
+                                        .eq(metrics.mean_team_gap),
+                                    draft_draw_candidates::partner_preferences_honoured
+                                        .eq(metrics.partner_preferences_honoured),
+                                    draft_draw_candidates::partner_preferences_requested
+                                        .eq(metrics.partner_preferences_requested),
+                                ))
+                                .execute(conn)?;
+
+                                Ok(())
+                            },
+                        )
+                        .unwrap();
+                    })
+                    .await
+                }
+                .instrument(insertion_span),
+            );
+        }
     });
 
     return Some(Ok(Flash::success(
         Redirect::to(format!(
-            "/spars/{}/draws/{}",
-            session_id1, draft_public_id
+            "/spars/{}/draw_candidates/{}",
+            session_id1, generation_id
         )),
         "Draw generation now in progress!",
     )));
@@ -279,6 +365,7 @@ pub fn solver_room_to_draft_draw(
             .map(|k| DraftDrawRoom {
                 panel: k.1.panel,
                 teams: k.1.teams,
+                chair: None,
             })
             .collect(),
         id_map: HashMap::new(),
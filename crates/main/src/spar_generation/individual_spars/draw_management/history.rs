@@ -0,0 +1,357 @@
+//! Version history (undo/redo, and restoring old versions) of draft draws.
+//!
+//! Each edit to a draft draw creates a new row in `draft_draw_versions`, and
+//! `draft_draws.version` points to the version which is currently shown.
+//! Undoing/redoing just moves this pointer, whereas making a new edit (or
+//! restoring an old version) discards any versions which had been undone.
+
+use chrono::Utc;
+use db::{
+    draft_draw::{DraftDraw, DraftDrawVersion, MAX_DRAFT_DRAW_VERSIONS},
+    schema::{draft_draw_versions, draft_draws, spar_series, spars, users},
+    spar::Spar,
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use maud::Markup;
+use rocket::response::{Flash, Redirect};
+
+use crate::{
+    model::sync::id::gen_uuid,
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    util::tx,
+};
+
+/// Loads the given draft draw (which must belong to the given spar), if the
+/// user is allowed to edit it.
+pub fn load_draft_for_editing(
+    spar_id: &str,
+    draw_id: &str,
+    user: &User,
+    conn: &mut DbWrapper,
+) -> Option<(Spar, DraftDraw)> {
+    let spar = spars::table
+        .filter(spars::public_id.eq(spar_id))
+        .first::<Spar>(conn)
+        .optional()
+        .unwrap()?;
+
+    let group_id = spar_series::table
+        .filter(spar_series::id.eq(spar.spar_series_id))
+        .select(spar_series::group_id)
+        .first::<i64>(conn)
+        .unwrap();
+    if !has_permission(
+        Some(user),
+        &Permission::ModifyResourceInGroup(GroupRef(group_id)),
+        conn,
+    ) {
+        return None;
+    }
+
+    let draw = draft_draws::table
+        .filter(draft_draws::public_id.eq(draw_id))
+        .filter(draft_draws::spar_id.eq(spar.id))
+        .first::<DraftDraw>(conn)
+        .optional()
+        .unwrap()?;
+
+    Some((spar, draw))
+}
+
+/// Stores `data` as the given version of the draft draw, and makes it the
+/// current version.
+///
+/// Any versions from `version` onwards (i.e. which had been undone) are
+/// discarded, as are the oldest versions once there are more than
+/// [`MAX_DRAFT_DRAW_VERSIONS`].
+pub fn record_version(
+    draft_draw_id: i64,
+    version: i64,
+    data: &str,
+    command: Option<&str>,
+    created_by: Option<i64>,
+    conn: &mut DbWrapper,
+) -> Result<(), diesel::result::Error> {
+    diesel::delete(
+        draft_draw_versions::table
+            .filter(draft_draw_versions::draft_draw_id.eq(draft_draw_id))
+            .filter(draft_draw_versions::version.ge(version)),
+    )
+    .execute(conn)?;
+
+    insert_into(draft_draw_versions::table)
+        .values((
+            draft_draw_versions::public_id.eq(gen_uuid().to_string()),
+            draft_draw_versions::draft_draw_id.eq(draft_draw_id),
+            draft_draw_versions::version.eq(version),
+            draft_draw_versions::data.eq(data),
+            draft_draw_versions::command.eq(command),
+            draft_draw_versions::created_by.eq(created_by),
+            draft_draw_versions::created_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+
+    diesel::delete(
+        draft_draw_versions::table
+            .filter(draft_draw_versions::draft_draw_id.eq(draft_draw_id))
+            .filter(
+                draft_draw_versions::version
+                    .le(version - MAX_DRAFT_DRAW_VERSIONS),
+            ),
+    )
+    .execute(conn)?;
+
+    let n = diesel::update(
+        draft_draws::table.filter(draft_draws::id.eq(draft_draw_id)),
+    )
+    .set((draft_draws::data.eq(data), draft_draws::version.eq(version)))
+    .execute(conn)?;
+    assert_eq!(n, 1);
+
+    Ok(())
+}
+
+/// Records `data` as a new version of the draft draw, which follows on from
+/// the current version.
+pub fn record_edit(
+    draw: &DraftDraw,
+    data: &str,
+    command: &str,
+    user_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<(), diesel::result::Error> {
+    // draws created before versions were recorded will not have an entry for
+    // their current version, so we add one (otherwise it would not be
+    // possible to undo the first edit)
+    let current_is_recorded = diesel::select(diesel::dsl::exists(
+        draft_draw_versions::table
+            .filter(draft_draw_versions::draft_draw_id.eq(draw.id))
+            .filter(draft_draw_versions::version.eq(draw.version)),
+    ))
+    .get_result::<bool>(conn)?;
+    if !current_is_recorded {
+        if let Some(current) = &draw.data {
+            insert_into(draft_draw_versions::table)
+                .values((
+                    draft_draw_versions::public_id.eq(gen_uuid().to_string()),
+                    draft_draw_versions::draft_draw_id.eq(draw.id),
+                    draft_draw_versions::version.eq(draw.version),
+                    draft_draw_versions::data.eq(current),
+                    draft_draw_versions::created_at.eq(draw.created_at),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    record_version(
+        draw.id,
+        draw.version + 1,
+        data,
+        Some(command),
+        Some(user_id),
+        conn,
+    )
+}
+
+/// Returns the retained versions of the draft draw (newest first), along
+/// with the user who created each of them.
+pub fn versions_of_draft(
+    draft_draw_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<Vec<(DraftDrawVersion, Option<User>)>, diesel::result::Error> {
+    draft_draw_versions::table
+        .filter(draft_draw_versions::draft_draw_id.eq(draft_draw_id))
+        .left_join(users::table)
+        .order_by(draft_draw_versions::version.desc())
+        .select((
+            draft_draw_versions::all_columns,
+            users::all_columns.nullable(),
+        ))
+        .load::<(DraftDrawVersion, Option<User>)>(conn)
+}
+
+/// Renders the undo/redo buttons and the list of versions of the draft draw.
+pub fn render_history(
+    spar: &Spar,
+    current_draw: &DraftDraw,
+    versions: &[(DraftDrawVersion, Option<User>)],
+) -> Markup {
+    let draw_url =
+        format!("/spars/{}/draws/{}", spar.public_id, current_draw.public_id);
+    let can_undo = versions
+        .iter()
+        .any(|(version, _)| version.version < current_draw.version);
+    let can_redo = versions
+        .iter()
+        .any(|(version, _)| version.version > current_draw.version);
+
+    maud::html! {
+        div class="d-flex mb-3" {
+            form method="post" action={(draw_url) "/undo"} class="me-2" {
+                button type="submit" class="btn btn-outline-secondary" disabled[!can_undo] { "Undo" }
+            }
+            form method="post" action={(draw_url) "/redo"} {
+                button type="submit" class="btn btn-outline-secondary" disabled[!can_redo] { "Redo" }
+            }
+        }
+        @if !versions.is_empty() {
+            ul class="list-group mb-3" {
+                @for (version, user) in versions {
+                    li class="list-group-item" {
+                        div {
+                            b { "Version " (version.version) }
+                            @if version.version == current_draw.version {
+                                " (current)"
+                            }
+                        }
+                        div class="small" {
+                            @if let Some(command) = &version.command {
+                                code style="white-space: pre-wrap;" { (command) }
+                            } @else {
+                                "Generated draw"
+                            }
+                        }
+                        div class="small text-muted" {
+                            (version.created_at.format("%Y-%m-%d %H:%M:%S"))
+                            @if let Some(user) = user {
+                                " by " (user.username.clone().unwrap_or(user.email.clone()))
+                            }
+                        }
+                        @if version.version != current_draw.version {
+                            form method="post" action={(draw_url) "/versions/" (version.public_id) "/restore"} class="mt-1" {
+                                button type="submit" class="btn btn-sm btn-outline-primary" { "Restore" }
+                                a class="btn btn-sm btn-outline-secondary ms-1" href={"/spars/" (spar.public_id) "/draw_diff?from=" (version.public_id) "&to=" (current_draw.public_id)} {
+                                    "Compare with current"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Moves the current version of the draft draw by `offset` (-1 to undo, or
+/// +1 to redo).
+async fn step_draft_version(
+    spar_id: &str,
+    draw_id: &str,
+    offset: i64,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Flash<Redirect>> {
+    let spar_id = spar_id.to_string();
+    let draw_id = draw_id.to_string();
+    tx(span, db, move |conn| {
+        let (_, draw) =
+            load_draft_for_editing(&spar_id, &draw_id, &user, conn)?;
+        let redirect_to =
+            Redirect::to(format!("/spars/{spar_id}/draws/{draw_id}"));
+
+        let target = match draft_draw_versions::table
+            .filter(draft_draw_versions::draft_draw_id.eq(draw.id))
+            .filter(draft_draw_versions::version.eq(draw.version + offset))
+            .first::<DraftDrawVersion>(conn)
+            .optional()
+            .unwrap()
+        {
+            Some(target) => target,
+            None => {
+                return Some(Flash::error(
+                    redirect_to,
+                    if offset < 0 {
+                        "Error: there is nothing to undo."
+                    } else {
+                        "Error: there is nothing to redo."
+                    },
+                ))
+            }
+        };
+
+        let n = diesel::update(
+            draft_draws::table.filter(draft_draws::id.eq(draw.id)),
+        )
+        .set((
+            draft_draws::data.eq(&target.data),
+            draft_draws::version.eq(target.version),
+        ))
+        .execute(conn)
+        .unwrap();
+        assert_eq!(n, 1);
+
+        Some(Flash::success(
+            redirect_to,
+            format!("Now showing version {} of the draw.", target.version),
+        ))
+    })
+    .await
+}
+
+#[post("/spars/<spar_id>/draws/<draw_id>/undo")]
+pub async fn do_undo_draft_edit(
+    spar_id: &str,
+    draw_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Flash<Redirect>> {
+    step_draft_version(spar_id, draw_id, -1, user, db, span).await
+}
+
+#[post("/spars/<spar_id>/draws/<draw_id>/redo")]
+pub async fn do_redo_draft_edit(
+    spar_id: &str,
+    draw_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Flash<Redirect>> {
+    step_draft_version(spar_id, draw_id, 1, user, db, span).await
+}
+
+#[post("/spars/<spar_id>/draws/<draw_id>/versions/<version_id>/restore")]
+/// Makes a copy of an old version of the draft draw the current version.
+pub async fn do_restore_draft_version(
+    spar_id: &str,
+    draw_id: &str,
+    version_id: &str,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Flash<Redirect>> {
+    let spar_id = spar_id.to_string();
+    let draw_id = draw_id.to_string();
+    let version_id = version_id.to_string();
+    tx(span, db, move |conn| {
+        let (_, draw) =
+            load_draft_for_editing(&spar_id, &draw_id, &user, conn)?;
+
+        let version = draft_draw_versions::table
+            .filter(draft_draw_versions::public_id.eq(&version_id))
+            .filter(draft_draw_versions::draft_draw_id.eq(draw.id))
+            .first::<DraftDrawVersion>(conn)
+            .optional()
+            .unwrap()?;
+
+        record_edit(
+            &draw,
+            &version.data,
+            &format!("restore version {}", version.version),
+            user.id,
+            conn,
+        )
+        .unwrap();
+
+        Some(Flash::success(
+            Redirect::to(format!("/spars/{spar_id}/draws/{draw_id}")),
+            format!("Restored version {} of the draw.", version.version),
+        ))
+    })
+    .await
+}
@@ -1,6 +1,11 @@
+pub mod candidates;
 pub mod confirm_draft;
+pub mod diff;
 pub mod draft_management;
 pub mod edit;
+pub mod editor;
 pub mod generate;
+pub mod history;
 pub mod release;
 pub mod util;
+pub mod validation;
@@ -14,7 +14,6 @@ use db::{
 };
 use diesel::prelude::*;
 use either::Either;
-use email::send_mail;
 use maud::Markup;
 use rocket::response::Redirect;
 use tracing::Instrument;
@@ -24,6 +23,7 @@ use crate::{
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
     resources::GroupRef,
+    spar_generation::individual_spars::ballot_links::send_ballot_link,
 };
 
 #[post("/spars/<spar_id>/set_released?<released>")]
@@ -93,30 +93,23 @@ pub async fn do_release_draw(
                 },
             }
 
+            // only send links which have not already been sent (so that
+            // toggling the release does not send everyone their link again)
+            if released {
+                let adjudicators = spar_adjudicator_ballot_links::table
+                    .inner_join(spar_rooms::table)
+                    .filter(spar_rooms::spar_id.eq(spar.id))
+                    .filter(spar_adjudicator_ballot_links::sent_at.is_null())
+                    .inner_join(spar_series_members::table)
+                    .select((
+                        spar_adjudicator_ballot_links::all_columns,
+                        spar_series_members::all_columns,
+                    ))
+                    .load::<(AdjudicatorBallotLink, SparSeriesMember)>(conn)?;
 
-
-
-            let adjudicators = spar_adjudicator_ballot_links::table
-                .inner_join(spar_rooms::table)
-                .filter(spar_rooms::spar_id.eq(spar.id))
-                .inner_join(spar_series_members::table)
-                .select((
-                    spar_adjudicator_ballot_links::all_columns,
-                    spar_series_members::all_columns,
-                ))
-                .load::<(AdjudicatorBallotLink, SparSeriesMember)>(conn)?;
-
-            for (adj_link, member) in adjudicators {
-                let ballot_link = format!("https://eldemite.net/ballots/submit/{}", adj_link.link);
-                send_mail(
-                    vec![(&member.name, &member.email)],
-                    "Ballot link",
-                    &maud::html! {
-                        "Please use " a href=(ballot_link) { "this link" } " to submit your ballot."
-                    }.into_string(),
-                    &format!("Please use this link to submit your ballot: {ballot_link}"),
-                    db.clone()
-                );
+                for (adj_link, member) in adjudicators {
+                    send_ballot_link(&adj_link, &member, db.clone(), conn)?;
+                }
             }
 
             Ok(Either::Right(Redirect::to(format!("/spars/{spar_id}"))))
@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 
 use db::{
-    ballot::{AdjudicatorBallot, BallotRepr},
-    room::SparRoomRepr,
+    ballot::{ballots_conflict, AdjudicatorBallot, BallotRepr, BpTeam},
+    room::{SparRoomRepr, CHAIR},
     schema::adjudicator_ballots,
 };
 use diesel::prelude::*;
@@ -37,9 +37,12 @@ pub fn ballots_of_rooms(
 /// - the first (room_info) contains data describing the state of the rooms in
 ///   the draw
 /// - the second (ballots) contains data
+/// - the third (show_admin_links) should be set if the draw is being displayed
+///   to an administrator (this adds links to pages which only they can view)
 pub fn render_draw(
     room_info: Vec<SparRoomRepr>,
     ballots: HashMap<i64, BallotRepr>,
+    show_admin_links: bool,
 ) -> Markup {
     maud::html! {
         table class="table" {
@@ -56,7 +59,20 @@ pub fn render_draw(
             tbody {
                 @for (i, room) in room_info.iter().enumerate() {
                     tr {
-                        td { (i) }
+                        td {
+                            (i)
+                            @if show_admin_links {
+                                " ("
+                                a href=(format!("/rooms/{}/ballots", room.inner.public_id)) {
+                                    "ballots"
+                                }
+                                ", "
+                                a href=(format!("/rooms/{}/ballot_links", room.inner.public_id)) {
+                                    "links"
+                                }
+                                ")"
+                            }
+                        }
                     td {
                         @for speaker in &room.teams[0].speakers {
                             div { (room.members[&room.speakers[&speaker].member_id].name) }
@@ -99,3 +115,82 @@ pub fn render_draw(
         }
     }
 }
+
+pub fn bp_team_name(team: BpTeam) -> &'static str {
+    match team {
+        BpTeam::Og => "OG",
+        BpTeam::Oo => "OO",
+        BpTeam::Cg => "CG",
+        BpTeam::Co => "CO",
+    }
+}
+
+/// Lists the rooms in which the adjudicators on the panel have submitted
+/// ballots with different rankings, along with the ballot that is currently
+/// being treated as the result.
+///
+/// Returns `None` if there are no conflicts.
+pub fn render_ballot_conflicts(
+    spar_public_id: &str,
+    room_info: &[SparRoomRepr],
+    conn: &mut (impl Connection<Backend = Sqlite> + LoadConnection),
+) -> Result<Option<Markup>, diesel::result::Error> {
+    let mut conflicts = Vec::new();
+    for (i, room) in room_info.iter().enumerate() {
+        let ballots = room.inner.latest_ballots(conn)?;
+        if ballots_conflict(&ballots) {
+            let canonical = room.inner.canonical_ballot(conn)?;
+            conflicts.push((i, room, ballots, canonical));
+        }
+    }
+
+    if conflicts.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(maud::html! {
+        div class="alert alert-warning" {
+            h4 { "Conflicting ballots" }
+            p {
+                "The adjudicators in the following rooms submitted ballots with
+                 different rankings. If the chair has submitted a ballot we use
+                 theirs, otherwise we use the ranking submitted by the most
+                 adjudicators."
+            }
+            @for (i, room, ballots, canonical) in &conflicts {
+                h5 { "Room " (i) }
+                ul {
+                    @for ballot in ballots {
+                        @let judge = room
+                            .judges
+                            .iter()
+                            .find(|judge| judge.id == ballot.inner.adjudicator_id)
+                            .unwrap();
+                        li {
+                            (room.members[&judge.member_id].name)
+                            @if judge.status == CHAIR {
+                                " (chair)"
+                            }
+                            ": "
+                            (ballot.bp_ranking().into_iter().map(bp_team_name).collect::<Vec<_>>().join(", "))
+                            " ("
+                            a href=(format!("/ballots/view/{}", ballot.inner.public_id)) {
+                                "view ballot"
+                            }
+                            ")"
+                            @if canonical.as_ref().map(|c| c.inner.id) == Some(ballot.inner.id) {
+                                " "
+                                span class="badge text-bg-success" { "used as result" }
+                            }
+                            @if judge.status != CHAIR {
+                                form method="post" class="d-inline" action=(format!("/spars/{spar_public_id}/adjudicators/{}/make_chair", judge.public_id)) {
+                                    button type="submit" class="btn btn-link btn-sm" { "make chair" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }))
+}
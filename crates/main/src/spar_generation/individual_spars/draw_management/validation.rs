@@ -0,0 +1,88 @@
+//! Checks draft draws for problems before they are confirmed (see
+//! [`DraftDrawData::validate`]).
+
+use std::collections::HashMap;
+
+use db::{
+    draft_draw::{DraftDrawData, DrawProblem},
+    schema::{spar_series_members, spar_signups},
+    spar::{Spar, SparSignup},
+    DbWrapper,
+};
+use diesel::prelude::*;
+use maud::{html, Markup};
+
+/// Returns the names of every member of the spar series.
+pub fn member_names(
+    spar_series_id: i64,
+    conn: &mut DbWrapper,
+) -> HashMap<i64, String> {
+    spar_series_members::table
+        .filter(spar_series_members::spar_series_id.eq(spar_series_id))
+        .select((spar_series_members::id, spar_series_members::name))
+        .load::<(i64, String)>(conn)
+        .unwrap()
+        .into_iter()
+        .collect()
+}
+
+/// Validates the draft draw against the signups for the spar.
+pub fn validate_draft(
+    spar: &Spar,
+    data: &DraftDrawData,
+    conn: &mut DbWrapper,
+) -> Vec<DrawProblem> {
+    let signups = spar_signups::table
+        .filter(spar_signups::spar_id.eq(spar.id))
+        .load::<SparSignup>(conn)
+        .unwrap();
+    data.validate(&signups)
+}
+
+pub fn describe_problem(
+    problem: &DrawProblem,
+    names: &HashMap<i64, String>,
+) -> String {
+    problem.describe(&|member| {
+        names
+            .get(&member)
+            .cloned()
+            .unwrap_or_else(|| "(unknown member)".to_string())
+    })
+}
+
+/// Renders a summary of the problems with the draw. If `oob` is set, the
+/// summary is marked to be swapped in out-of-band (by htmx).
+pub fn render_problems(
+    problems: &[DrawProblem],
+    names: &HashMap<i64, String>,
+    oob: bool,
+) -> Markup {
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        problems.iter().partition(|problem| problem.is_error());
+
+    html! {
+        div id="draw-validation" hx-swap-oob=[oob.then_some("true")] {
+            @if !errors.is_empty() {
+                div class="alert alert-danger" {
+                    p { b { "This draw cannot be confirmed until these problems are fixed:" } }
+                    ul class="mb-0" {
+                        @for problem in &errors {
+                            li { (describe_problem(problem, names)) }
+                        }
+                    }
+                }
+            }
+            @if !warnings.is_empty() {
+                div class="alert alert-warning" {
+                    p { b { "Warnings (the draw can still be confirmed):" } }
+                    ul class="mb-0" {
+                        @for problem in &warnings {
+                            li { (describe_problem(problem, names)) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,12 @@
+pub mod adjust_draw;
 pub mod admin_overview;
+pub mod ballot_links;
+pub mod ballot_status;
 pub mod complete_spar;
 pub mod draw_management;
 pub mod participant_overview;
+pub mod printing;
+pub mod projector;
+pub mod reopen_spar;
+pub mod room_ballots;
 pub mod signup_routes;
@@ -64,7 +64,7 @@ pub async fn single_spar_overview_for_participants_page(
                         }
                     }, user)))
                 } else {
-                    let markup = render_draw(draw_info, ballots);
+                    let markup = render_draw(draw_info, ballots, false);
                     Ok(Some(page_of_body(markup, user)))
                 }
             } else {
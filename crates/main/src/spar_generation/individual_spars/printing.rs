@@ -0,0 +1,280 @@
+//! Printable (PDF) versions of the draw, and of paper ballots for each judge.
+//!
+//! The PDFs are produced by the Typst templates in `printing/` (using
+//! [`slides::compile_pdf`]). Each template is given the draw as `draw.json`
+//! (see [`PrintCtx`]), along with a QR code (as an SVG file) for each ballot
+//! link.
+
+use db::{
+    ballot::AdjudicatorBallotLink,
+    room::{SparRoomRepr, CHAIR},
+    schema::{spar_adjudicator_ballot_links, spar_rooms, spar_series, spars},
+    spar::{Spar, SparSeries},
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use maud::Markup;
+use qrcode::{render::svg, QrCode};
+use rocket::http::ContentType;
+use serde::Serialize;
+
+use crate::{
+    html::{error_403, page_of_body},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    util::tx,
+};
+
+const DRAW_TEMPLATE: &str = include_str!("printing/draw.typst");
+const BALLOTS_TEMPLATE: &str = include_str!("printing/ballots.typst");
+
+#[derive(Serialize)]
+struct PrintCtx {
+    title: String,
+    start_time: String,
+    motion: Option<String>,
+    rooms: Vec<RoomCtx>,
+}
+
+#[derive(Serialize)]
+struct RoomCtx {
+    number: usize,
+    /// Always has four entries (in speaking order). The closing teams of
+    /// half-rooms have no speakers (and are left off the ballots).
+    teams: Vec<TeamCtx>,
+    judges: Vec<JudgeCtx>,
+}
+
+#[derive(Serialize)]
+struct TeamCtx {
+    position: &'static str,
+    speakers: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JudgeCtx {
+    name: String,
+    chair: bool,
+    /// The URL of the judge's ballot link (only included on ballots).
+    link: Option<String>,
+    /// The name of the file containing the QR code of `link`.
+    qr: Option<String>,
+}
+
+fn qr_code_svg(link: &str) -> String {
+    QrCode::new(link.as_bytes())
+        .unwrap()
+        .render()
+        .min_dimensions(200, 200)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build()
+}
+
+/// Loads the draw of the spar, returning the data for the template and the
+/// files (i.e. QR codes) which it refers to. Ballot links are only included
+/// if `with_links` is set.
+fn print_ctx(
+    spar: &Spar,
+    series: &SparSeries,
+    motion: Option<String>,
+    with_links: bool,
+    conn: &mut DbWrapper,
+) -> Result<(PrintCtx, Vec<(String, String)>), diesel::result::Error> {
+    let rooms = spar_rooms::table
+        .filter(spar_rooms::spar_id.eq(spar.id))
+        .order_by(spar_rooms::public_id)
+        .select(spar_rooms::id)
+        .load::<i64>(conn)?
+        .into_iter()
+        .map(|id| SparRoomRepr::of_id(id, conn))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut files = Vec::new();
+    let mut room_ctxs = Vec::with_capacity(rooms.len());
+    for (number, room) in rooms.iter().enumerate() {
+        let links = if with_links {
+            spar_adjudicator_ballot_links::table
+                .filter(
+                    spar_adjudicator_ballot_links::room_id.eq(room.inner.id),
+                )
+                .load::<AdjudicatorBallotLink>(conn)?
+        } else {
+            Vec::new()
+        };
+
+        let judges = room
+            .judges
+            .iter()
+            .map(|judge| {
+                let link = links
+                    .iter()
+                    .find(|link| link.member_id == judge.member_id)
+                    .map(|link| {
+                        (
+                            link.public_id.clone(),
+                            format!(
+                                "https://eldemite.net/ballots/submit/{}",
+                                link.link
+                            ),
+                        )
+                    });
+                let qr = link.as_ref().map(|(public_id, url)| {
+                    let name = format!("qr-{public_id}.svg");
+                    files.push((name.clone(), qr_code_svg(url)));
+                    name
+                });
+                JudgeCtx {
+                    name: room.members[&judge.member_id].name.clone(),
+                    chair: judge.status == CHAIR,
+                    link: link.map(|(_, url)| url),
+                    qr,
+                }
+            })
+            .collect();
+
+        room_ctxs.push(RoomCtx {
+            number,
+            teams: room
+                .teams
+                .iter()
+                .zip(["OG", "OO", "CG", "CO"])
+                .map(|(team, position)| TeamCtx {
+                    position,
+                    speakers: team
+                        .speakers
+                        .iter()
+                        .map(|speaker| {
+                            room.members[&room.speakers[speaker].member_id]
+                                .name
+                                .clone()
+                        })
+                        .collect(),
+                })
+                .collect(),
+            judges,
+        });
+    }
+
+    let ctx = PrintCtx {
+        title: series.title.clone(),
+        start_time: spar.start_time.format("%Y-%m-%d %H:%M").to_string(),
+        motion: motion
+            .map(|motion| motion.trim().to_string())
+            .filter(|motion| !motion.is_empty()),
+        rooms: room_ctxs,
+    };
+    Ok((ctx, files))
+}
+
+/// Produces a PDF of the draw using the given template.
+async fn print_spar(
+    spar_id: &str,
+    motion: Option<&str>,
+    template: &'static str,
+    with_links: bool,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<(ContentType, Vec<u8>), Markup>> {
+    let spar_id = spar_id.to_string();
+    let motion = motion.map(|motion| motion.to_string());
+    let user1 = user.clone();
+    let span1 = span.0.clone();
+    let ctx = tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+        let series = spar_series::table
+            .filter(spar_series::id.eq(spar.spar_series_id))
+            .first::<SparSeries>(conn)
+            .unwrap();
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+            conn,
+        ) {
+            return Some(Err(error_403(
+                Some("Error: you are not authorized to print this draw!"),
+                Some(user),
+            )));
+        }
+
+        Some(Ok(
+            print_ctx(&spar, &series, motion, with_links, conn).unwrap()
+        ))
+    })
+    .await?;
+
+    let (ctx, mut files) = match ctx {
+        Ok(t) => t,
+        Err(page) => return Some(Err(page)),
+    };
+    if ctx.rooms.is_empty() {
+        return Some(Err(page_of_body(
+            maud::html! {
+                div class="alert alert-info" {
+                    "There is no draw to print (the draw has not been confirmed)."
+                }
+            },
+            Some(user1),
+        )));
+    }
+    files.push((
+        "draw.json".to_string(),
+        serde_json::to_string(&ctx).unwrap(),
+    ));
+
+    // compiling the document can take a while
+    let pdf = rocket::tokio::task::spawn_blocking(move || {
+        let _guard = span1.enter();
+        tracing::trace!("Compiling PDF");
+        slides::compile_pdf(template.to_string(), files)
+    })
+    .await
+    .unwrap();
+
+    Some(match pdf {
+        Ok(pdf) => Ok((ContentType::PDF, pdf)),
+        Err(e) => {
+            tracing::error!("Failed to compile PDF: {e}");
+            Err(page_of_body(
+                maud::html! {
+                    div class="alert alert-danger" {
+                        "Error: could not produce the PDF."
+                    }
+                },
+                Some(user1),
+            ))
+        }
+    })
+}
+
+#[get("/spars/<spar_id>/print/draw?<motion>")]
+/// A printable version of the draw.
+pub async fn print_draw(
+    spar_id: &str,
+    motion: Option<&str>,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<(ContentType, Vec<u8>), Markup>> {
+    print_spar(spar_id, motion, DRAW_TEMPLATE, false, user, db, span).await
+}
+
+#[get("/spars/<spar_id>/print/ballots?<motion>")]
+/// Paper ballots for every judge, filled in with the names of the speakers
+/// in their room and a QR code of the judge's ballot link.
+pub async fn print_ballots(
+    spar_id: &str,
+    motion: Option<&str>,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<(ContentType, Vec<u8>), Markup>> {
+    print_spar(spar_id, motion, BALLOTS_TEMPLATE, true, user, db, span).await
+}
@@ -0,0 +1,301 @@
+//! A full-screen version of the draw, which is intended to be shown on a
+//! projector at the venue.
+//!
+//! The rooms are shown in large type, a page at a time (cycling through the
+//! pages if there are too many rooms to fit on the screen). The page polls
+//! the server (using htmx) and updates itself when the draw is released or
+//! changed. We serve our own copy of htmx (rather than using a CDN), so that
+//! this keeps working at venues without a reliable internet connection.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use db::{
+    room::{SparRoomRepr, CHAIR},
+    schema::{spar_rooms, spars},
+    spar::Spar,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use rocket::response::status::NoContent;
+
+use crate::{request_ids::TracingSpan, util::tx};
+
+/// How often the display checks whether the draw has changed.
+const POLL_SECONDS: u64 = 10;
+/// How long each page of rooms is shown for.
+const PAGE_SECONDS: u64 = 15;
+const DEFAULT_ROOMS_PER_PAGE: usize = 6;
+
+fn rooms_of_spar(
+    spar: &Spar,
+    conn: &mut DbWrapper,
+) -> Result<Vec<SparRoomRepr>, diesel::result::Error> {
+    if !spar.release_draw {
+        return Ok(Vec::new());
+    }
+
+    spar_rooms::table
+        .filter(spar_rooms::spar_id.eq(spar.id))
+        .order_by(spar_rooms::public_id)
+        .select(spar_rooms::id)
+        .load::<i64>(conn)?
+        .into_iter()
+        .map(|id| SparRoomRepr::of_id(id, conn))
+        .collect()
+}
+
+fn render_room(idx: usize, room: &SparRoomRepr) -> Markup {
+    let name_of_speaker =
+        |speaker: &i64| &room.members[&room.speakers[speaker].member_id].name;
+
+    html! {
+        div class="projector-room" {
+            h2 { "Room " (idx) }
+            table {
+                @for (team, label) in room.teams.iter().zip(["OG", "OO", "CG", "CO"]) {
+                    @if !team.speakers.is_empty() {
+                        tr {
+                            th { (label) }
+                            td {
+                                (team.speakers.iter().map(name_of_speaker).cloned().collect::<Vec<_>>().join(", "))
+                            }
+                        }
+                    }
+                }
+                tr {
+                    th { "Panel" }
+                    td {
+                        @for (i, judge) in room.judges.iter().enumerate() {
+                            @if i > 0 { ", " }
+                            (room.members[&judge.member_id].name)
+                            @if judge.status == CHAIR { " (c)" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renders the rooms, split into pages.
+fn render_rooms(rooms: &[SparRoomRepr], rooms_per_page: usize) -> Markup {
+    html! {
+        @if rooms.is_empty() {
+            div class="projector-waiting" {
+                "The draw has not been released yet."
+            }
+        } @else {
+            @for (page, chunk) in rooms.chunks(rooms_per_page).enumerate() {
+                div class="projector-page" data-page=(page) hidden[page > 0] {
+                    @for (i, room) in chunk.iter().enumerate() {
+                        (render_room(page * rooms_per_page + i, room))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn fingerprint(rooms: &Markup) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    rooms.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Wraps the rendered rooms in the element which polls for changes. The poll
+/// includes a fingerprint of the rooms, so that the server only sends the
+/// draw again if it has changed.
+fn render_draw(spar: &Spar, rooms: Markup, rooms_per_page: usize) -> Markup {
+    html! {
+        div id="projector-draw"
+            hx-get=(format!(
+                "/spars/{}/projector/draw?version={}&per_page={rooms_per_page}",
+                spar.public_id,
+                fingerprint(&rooms)
+            ))
+            hx-trigger=(format!("every {POLL_SECONDS}s"))
+            hx-swap="outerHTML" {
+            (rooms)
+        }
+    }
+}
+
+fn rooms_per_page(per_page: Option<usize>) -> usize {
+    per_page
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_ROOMS_PER_PAGE)
+}
+
+#[get("/spars/<spar_id>/projector?<per_page>&<countdown>")]
+/// Shows the draw full-screen, for projecting at the venue.
+pub async fn projector_page(
+    spar_id: &str,
+    per_page: Option<usize>,
+    countdown: Option<bool>,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_id = spar_id.to_string();
+    let rooms_per_page = rooms_per_page(per_page);
+    let countdown = countdown.unwrap_or(true);
+    tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+        let rooms = rooms_of_spar(&spar, conn).unwrap();
+
+        Some(html! {
+            (DOCTYPE)
+            html {
+                head {
+                    title { "Draw" }
+                    script src="/static/htmx.js" {}
+                    meta name="viewport" content="width=device-width, initial-scale=1" {}
+                    style {
+                        r#"
+                        body {
+                            margin: 0;
+                            padding: 2vh 2vw;
+                            background: #111;
+                            color: #fff;
+                            font-family: sans-serif;
+                            font-size: 2.2vw;
+                        }
+                        .projector-header {
+                            display: flex;
+                            justify-content: space-between;
+                            align-items: baseline;
+                            border-bottom: 0.3vh solid #E32879;
+                            margin-bottom: 2vh;
+                        }
+                        .projector-header h1 {
+                            margin: 0 0 1vh 0;
+                        }
+                        .projector-page {
+                            display: grid;
+                            grid-template-columns: repeat(auto-fit, minmax(30vw, 1fr));
+                            gap: 2vh 2vw;
+                        }
+                        .projector-page[hidden] {
+                            display: none;
+                        }
+                        .projector-room {
+                            background: #222;
+                            border-radius: 1vh;
+                            padding: 1vh 1.5vw;
+                        }
+                        .projector-room h2 {
+                            margin: 0 0 1vh 0;
+                            color: #E32879;
+                        }
+                        .projector-room th {
+                            text-align: left;
+                            padding-right: 1vw;
+                            vertical-align: top;
+                        }
+                        .projector-waiting {
+                            text-align: center;
+                            margin-top: 30vh;
+                            font-size: 4vw;
+                        }
+                        "#
+                    }
+                }
+                body {
+                    div class="projector-header" {
+                        h1 { "Draw" }
+                        @if countdown {
+                            div id="projector-countdown" data-start=(spar.start_time.format("%Y-%m-%dT%H:%M:%SZ")) {}
+                        }
+                        div id="projector-page-number" {}
+                    }
+                    (render_draw(&spar, render_rooms(&rooms, rooms_per_page), rooms_per_page))
+
+                    (PreEscaped(format!(r#"
+                    <script>
+                        (function() {{
+                            const PAGE_SECONDS = {PAGE_SECONDS};
+                            let page = 0;
+
+                            function showPage() {{
+                                const pages = document.querySelectorAll('.projector-page');
+                                const number = document.getElementById('projector-page-number');
+                                if (pages.length === 0) {{
+                                    number.textContent = '';
+                                    return;
+                                }}
+                                page = page % pages.length;
+                                pages.forEach((p, i) => p.hidden = i !== page);
+                                number.textContent = pages.length > 1
+                                    ? `Page ${{page + 1}} of ${{pages.length}}`
+                                    : '';
+                            }}
+
+                            function updateCountdown() {{
+                                const countdown = document.getElementById('projector-countdown');
+                                if (!countdown) {{
+                                    return;
+                                }}
+                                const seconds = Math.floor((new Date(countdown.dataset.start) - new Date()) / 1000);
+                                if (seconds <= 0) {{
+                                    countdown.textContent = '';
+                                    return;
+                                }}
+                                const minutes = Math.floor(seconds / 60);
+                                const rest = String(seconds % 60).padStart(2, '0');
+                                countdown.textContent = `Starts in ${{minutes}}:${{rest}}`;
+                            }}
+
+                            setInterval(() => {{
+                                page += 1;
+                                showPage();
+                            }}, PAGE_SECONDS * 1000);
+                            setInterval(updateCountdown, 1000);
+                            // keep showing the same page when the draw is
+                            // updated
+                            document.addEventListener('htmx:afterSwap', showPage);
+                            document.addEventListener('DOMContentLoaded', () => {{
+                                showPage();
+                                updateCountdown();
+                            }});
+                        }})();
+                    </script>
+                    "#)))
+                }
+            }
+        })
+    })
+    .await
+}
+
+#[get("/spars/<spar_id>/projector/draw?<version>&<per_page>")]
+/// Returns the draw for the projector display, or nothing (so that htmx
+/// leaves the page alone) if it has not changed since `version`.
+pub async fn projector_draw(
+    spar_id: &str,
+    version: Option<u64>,
+    per_page: Option<usize>,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<Markup, NoContent>> {
+    let spar_id = spar_id.to_string();
+    let rooms_per_page = rooms_per_page(per_page);
+    tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+        let rooms = rooms_of_spar(&spar, conn).unwrap();
+
+        let rooms = render_rooms(&rooms, rooms_per_page);
+        if version == Some(fingerprint(&rooms)) {
+            return Some(Err(NoContent));
+        }
+        Some(Ok(render_draw(&spar, rooms, rooms_per_page)))
+    })
+    .await
+}
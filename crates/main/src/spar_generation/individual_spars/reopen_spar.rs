@@ -0,0 +1,182 @@
+//! Allows a completed spar to be reopened (e.g. so that a ballot can be
+//! corrected). Each reopening is recorded along with who did it and why.
+
+use chrono::{TimeDelta, Utc};
+use db::{
+    schema::{
+        spar_adjudicator_ballot_links, spar_reopenings, spar_rooms,
+        spar_series, spars, users,
+    },
+    spar::{Spar, SparReopening},
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use maud::Markup;
+use rocket::{
+    form::Form,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+
+use crate::{
+    html::error_403,
+    model::sync::id::gen_uuid,
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    util::tx,
+};
+
+/// When a spar is reopened, ballot links which would expire before this many
+/// hours from now are extended so that adjudicators can use them again.
+const REOPENED_LINK_HOURS: i64 = 24;
+
+/// Returns every time the spar has been reopened (oldest first), along with
+/// the user who reopened it.
+pub fn reopenings_of_spar(
+    spar_id: i64,
+    conn: &mut DbWrapper,
+) -> Result<Vec<(SparReopening, User)>, diesel::result::Error> {
+    spar_reopenings::table
+        .filter(spar_reopenings::spar_id.eq(spar_id))
+        .inner_join(users::table)
+        .order_by(spar_reopenings::created_at.asc())
+        .select((spar_reopenings::all_columns, users::all_columns))
+        .load::<(SparReopening, User)>(conn)
+}
+
+pub fn render_reopenings(reopenings: &[(SparReopening, User)]) -> Markup {
+    maud::html! {
+        @if !reopenings.is_empty() {
+            div class="alert alert-secondary" {
+                p { b { "This spar has been reopened after being completed:" } }
+                ul class="mb-0" {
+                    @for (reopening, user) in reopenings {
+                        li {
+                            (reopening.created_at.format("%Y-%m-%d %H:%M"))
+                            " by "
+                            (user.username.clone().unwrap_or(user.email.clone()))
+                            ": "
+                            (reopening.reason)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(FromForm, Serialize)]
+pub struct ReopenSparForm {
+    pub reason: String,
+}
+
+#[post("/spars/<spar_id>/reopen", data = "<form>")]
+/// Marks a completed spar as no longer complete, so that ballots can be
+/// submitted (or corrected) again. This requires signing power, because
+/// the results of the spar may already have been used (e.g. in draws).
+pub async fn do_reopen_spar(
+    spar_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<ReopenSparForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_id = spar_id.to_string();
+    tx(span, db, move |conn| {
+        let spar = spars::table
+            .filter(spars::public_id.eq(&spar_id))
+            .first::<Spar>(conn)
+            .optional()
+            .unwrap()?;
+
+        let group_id = spar_series::table
+            .filter(spar_series::id.eq(spar.spar_series_id))
+            .select(spar_series::group_id)
+            .first::<i64>(conn)
+            .unwrap();
+        if !has_permission(
+            Some(&user),
+            &Permission::DeleteResourceInGroup(GroupRef(group_id)),
+            conn,
+        ) {
+            return Some(Err(error_403(
+                Some(
+                    "Error: only members of the group with signing power can \
+                     reopen a spar.",
+                ),
+                Some(user),
+            )));
+        }
+
+        let redirect_to = Redirect::to(format!("/spars/{spar_id}"));
+
+        if !spar.is_complete {
+            return Some(Ok(Flash::error(
+                redirect_to,
+                "Error: this spar has not been completed.",
+            )));
+        }
+
+        let reason = form.reason.trim();
+        if reason.is_empty() {
+            return Some(Ok(Flash::error(
+                redirect_to,
+                "Error: please give a reason for reopening the spar.",
+            )));
+        }
+
+        let n = diesel::update(spars::table.filter(spars::id.eq(spar.id)))
+            .set(spars::is_complete.eq(false))
+            .execute(conn)
+            .unwrap();
+        assert_eq!(n, 1);
+
+        let expires_at =
+            Utc::now().naive_utc() + TimeDelta::hours(REOPENED_LINK_HOURS);
+        diesel::update(
+            spar_adjudicator_ballot_links::table
+                .filter(
+                    spar_adjudicator_ballot_links::room_id.eq_any(
+                        spar_rooms::table
+                            .filter(spar_rooms::spar_id.eq(spar.id))
+                            .select(spar_rooms::id),
+                    ),
+                )
+                .filter(
+                    spar_adjudicator_ballot_links::expires_at.lt(expires_at),
+                ),
+        )
+        .set(spar_adjudicator_ballot_links::expires_at.eq(expires_at))
+        .execute(conn)
+        .unwrap();
+
+        insert_into(spar_reopenings::table)
+            .values((
+                spar_reopenings::public_id.eq(gen_uuid().to_string()),
+                spar_reopenings::spar_id.eq(spar.id),
+                spar_reopenings::reopened_by.eq(user.id),
+                spar_reopenings::reason.eq(reason),
+                spar_reopenings::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        tracing::info!(
+            "User with id {} reopened spar with id {} (reason: {reason})",
+            user.id,
+            spar.id
+        );
+
+        Some(Ok(Flash::success(
+            redirect_to,
+            format!(
+                "Reopened the spar. Ballot links will now work for at least \
+                 {REOPENED_LINK_HOURS} hours (you can resend them from the \
+                 ballot links page of each room)."
+            ),
+        )))
+    })
+    .await
+}
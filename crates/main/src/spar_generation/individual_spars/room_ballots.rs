@@ -0,0 +1,517 @@
+//! Shows administrators every version of every ballot submitted for a room,
+//! and allows them to pin a specific version as the official result. This also
+//! allows administrators to enter ballots on behalf of adjudicators (e.g. when
+//! they have been handed a paper ballot).
+
+use db::{
+    ballot::{
+        AdjudicatorBallot, BallotRepr, Scoresheet, SOURCE_ACCOUNT, SOURCE_ADMIN,
+    },
+    room::SparRoomRepr,
+    schema::{adjudicator_ballots, spar_rooms, spar_series, spars, users},
+    spar::{Spar, SparRoom, SparRoomAdjudicator},
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use maud::Markup;
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    spar_generation::{
+        ballots::{
+            check_ballot, insert_ballot, render_ballot, render_ballot_inputs,
+            score_rules_message, score_rules_of_room, BpBallotForm,
+        },
+        individual_spars::draw_management::util::bp_team_name,
+    },
+    util::tx,
+};
+
+const SPEAKER_POSITIONS: [[&str; 2]; 4] =
+    [["PM", "DPM"], ["LO", "DLO"], ["MG", "GW"], ["MO", "OW"]];
+
+/// Loads the room with the given public id, provided that the user is allowed
+/// to administer the spar it belongs to.
+pub(crate) fn load_room_for_admin(
+    room_id: &str,
+    user: &User,
+    conn: &mut DbWrapper,
+) -> Option<Result<(SparRoom, Spar), Markup>> {
+    let (room, spar) = spar_rooms::table
+        .filter(spar_rooms::public_id.eq(room_id))
+        .inner_join(spars::table)
+        .first::<(SparRoom, Spar)>(conn)
+        .optional()
+        .unwrap()?;
+
+    let group_id = spar_series::table
+        .filter(spar_series::id.eq(spar.spar_series_id))
+        .select(spar_series::group_id)
+        .first::<i64>(conn)
+        .unwrap();
+
+    if !has_permission(
+        Some(user),
+        &Permission::ModifyResourceInGroup(crate::resources::GroupRef(
+            group_id,
+        )),
+        conn,
+    ) {
+        return Some(Err(error_403(
+            Some("Error: you do not have permission to do that!"),
+            Some(user.clone()),
+        )));
+    }
+
+    Some(Ok((room, spar)))
+}
+
+/// Returns a description of each speaker whose entry differs between two
+/// versions of a ballot.
+fn changes_between(
+    room: &SparRoomRepr,
+    old: &Scoresheet,
+    new: &Scoresheet,
+) -> Vec<String> {
+    let name = |speaker_id: i64| {
+        room.members[&room.speakers[&speaker_id].member_id]
+            .name
+            .clone()
+    };
+
+    let mut changes = Vec::new();
+    for (team, (old_team, new_team)) in
+        old.teams.iter().zip(new.teams.iter()).enumerate()
+    {
+        for (position, (old_speaker, new_speaker)) in old_team
+            .speakers
+            .iter()
+            .zip(new_team.speakers.iter())
+            .enumerate()
+        {
+            if old_speaker != new_speaker {
+                changes.push(format!(
+                    "{}: {} ({}) → {} ({})",
+                    SPEAKER_POSITIONS[team][position],
+                    name(old_speaker.speaker_id),
+                    old_speaker.score,
+                    name(new_speaker.speaker_id),
+                    new_speaker.score
+                ));
+            }
+        }
+    }
+    changes
+}
+
+#[get("/rooms/<room_id>/ballots")]
+/// Lists every version of every ballot submitted for this room.
+pub async fn room_ballots_page(
+    room_id: &str,
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let room_id = room_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let (room, spar) = match load_room_for_admin(&room_id, &user, conn)? {
+            Ok(t) => t,
+            Err(page) => return Some(page),
+        };
+
+        let repr = SparRoomRepr::of_id(room.id, conn).unwrap();
+        let canonical = room.canonical_ballot(conn).unwrap();
+
+        let versions = adjudicator_ballots::table
+            .filter(adjudicator_ballots::room_id.eq(room.id))
+            .left_join(users::table)
+            .order_by((
+                adjudicator_ballots::created_at.asc(),
+                adjudicator_ballots::id.asc(),
+            ))
+            .load::<(AdjudicatorBallot, Option<User>)>(conn)
+            .unwrap()
+            .into_iter()
+            .map(|(ballot, submitter)| {
+                (BallotRepr::of_id(ballot.id, conn).unwrap(), submitter)
+            })
+            .collect::<Vec<_>>();
+
+        let markup = maud::html! {
+            a href=(format!("/spars/{}?tab=draw", spar.public_id)) class="btn btn-secondary mb-3" {
+                "← Back to spar"
+            }
+            (page_title("Ballots for room"))
+            a href=(format!("/rooms/{}/ballots/enter", room.public_id)) class="btn btn-primary mb-3" {
+                "Enter a ballot"
+            }
+            @if versions.is_empty() {
+                p { "No ballots have been submitted for this room yet." }
+            }
+            @for judge in &repr.judges {
+                @let judge_versions = versions
+                    .iter()
+                    .filter(|(ballot, _)| ballot.inner.adjudicator_id == judge.id)
+                    .collect::<Vec<_>>();
+                @if !judge_versions.is_empty() {
+                    h3 { (repr.members[&judge.member_id].name) }
+                    table class="table" {
+                        thead {
+                            tr {
+                                th scope="col" { "Version" }
+                                th scope="col" { "Submitted at" }
+                                th scope="col" { "Submitted by" }
+                                th scope="col" { "Ranking" }
+                                th scope="col" { "Changes from previous version" }
+                                th scope="col" { "Actions" }
+                            }
+                        }
+                        tbody {
+                            @for (i, (ballot, submitter)) in judge_versions.iter().enumerate() {
+                                tr {
+                                    td {
+                                        (i + 1)
+                                        @if canonical.as_ref().map(|c| c.inner.id) == Some(ballot.inner.id) {
+                                            " "
+                                            span class="badge text-bg-success" { "used as result" }
+                                        }
+                                        @if ballot.inner.is_pinned {
+                                            " "
+                                            span class="badge text-bg-primary" { "pinned" }
+                                        }
+                                    }
+                                    td { (ballot.inner.created_at.format("%Y-%m-%d %H:%M:%S")) }
+                                    td {
+                                        @if ballot.inner.source == SOURCE_ADMIN {
+                                            "Administrator "
+                                            @if let Some(submitter) = submitter {
+                                                "("
+                                                (submitter.username.clone().unwrap_or(submitter.email.clone()))
+                                                ")"
+                                            }
+                                        } @else if ballot.inner.source == SOURCE_ACCOUNT {
+                                            "Adjudicator (logged in)"
+                                        } @else {
+                                            "Adjudicator (via their link)"
+                                        }
+                                    }
+                                    td {
+                                        (ballot.bp_ranking().into_iter().map(bp_team_name).collect::<Vec<_>>().join(", "))
+                                    }
+                                    td {
+                                        @if i == 0 {
+                                            "—"
+                                        } @else {
+                                            @let changes = changes_between(
+                                                &repr,
+                                                &judge_versions[i - 1].0.scoresheet,
+                                                &ballot.scoresheet,
+                                            );
+                                            @if changes.is_empty() {
+                                                "No changes"
+                                            } @else {
+                                                ul class="mb-0" {
+                                                    @for change in &changes {
+                                                        li { (change) }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                    td {
+                                        a href=(format!("/ballots/view/{}", ballot.inner.public_id)) { "View" }
+                                        @if ballot.inner.is_pinned {
+                                            form method="post" class="d-inline" action=(format!("/rooms/{}/ballots/{}/pin?pinned=false", room.public_id, ballot.inner.public_id)) {
+                                                button type="submit" class="btn btn-link btn-sm" { "Unpin" }
+                                            }
+                                        } @else {
+                                            form method="post" class="d-inline" action=(format!("/rooms/{}/ballots/{}/pin?pinned=true", room.public_id, ballot.inner.public_id)) {
+                                                button type="submit" class="btn btn-link btn-sm" { "Pin as result" }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        Some(page_of_body_and_flash_msg(markup, msg, Some(user)))
+    })
+    .await
+}
+
+#[post("/rooms/<room_id>/ballots/<ballot_id>/pin?<pinned>")]
+/// Pins (or unpins) a specific version of a ballot as the official result of
+/// the room. At most one ballot may be pinned per room.
+pub async fn do_pin_ballot(
+    room_id: &str,
+    ballot_id: &str,
+    pinned: bool,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let room_id = room_id.to_string();
+    let ballot_id = ballot_id.to_string();
+    tx(span, db, move |conn| {
+        let (room, _) = match load_room_for_admin(&room_id, &user, conn)? {
+            Ok(t) => t,
+            Err(page) => return Some(Err(page)),
+        };
+
+        let ballot = adjudicator_ballots::table
+            .filter(adjudicator_ballots::public_id.eq(&ballot_id))
+            .filter(adjudicator_ballots::room_id.eq(room.id))
+            .first::<AdjudicatorBallot>(conn)
+            .optional()
+            .unwrap()?;
+
+        diesel::update(
+            adjudicator_ballots::table
+                .filter(adjudicator_ballots::room_id.eq(room.id)),
+        )
+        .set(adjudicator_ballots::is_pinned.eq(false))
+        .execute(conn)
+        .unwrap();
+
+        if pinned {
+            diesel::update(
+                adjudicator_ballots::table
+                    .filter(adjudicator_ballots::id.eq(ballot.id)),
+            )
+            .set(adjudicator_ballots::is_pinned.eq(true))
+            .execute(conn)
+            .unwrap();
+        }
+
+        Some(Ok(Flash::success(
+            Redirect::to(format!("/rooms/{}/ballots", room.public_id)),
+            if pinned {
+                "Pinned the ballot as the result of this room."
+            } else {
+                "Unpinned the ballot."
+            },
+        )))
+    })
+    .await
+}
+
+/// Renders the page used by administrators to enter a ballot on behalf of
+/// the given adjudicator (`force_submit` has the same meaning as for the
+/// adjudicator's own ballot form).
+fn render_admin_ballot_entry(
+    room: &SparRoomRepr,
+    judge: &SparRoomAdjudicator,
+    error: Option<&str>,
+    force_submit: bool,
+    user: User,
+    conn: &mut DbWrapper,
+) -> Markup {
+    let rules = score_rules_of_room(room, conn).unwrap();
+
+    let latest = room
+        .inner
+        .latest_ballots(conn)
+        .unwrap()
+        .into_iter()
+        .find(|ballot| ballot.inner.adjudicator_id == judge.id);
+
+    let action = format!(
+        "/rooms/{}/ballots/enter?adjudicator={}",
+        room.inner.public_id, judge.public_id
+    );
+
+    let markup = maud::html! {
+        (page_title(format!("Enter ballot for {}", room.members[&judge.member_id].name)))
+        @if let Some(error) = error {
+            div class="alert alert-danger" role="alert" {
+                p {(error)}
+            }
+        }
+        @if let Some(latest) = &latest {
+            div class="alert alert-info" role="alert" {
+                p {
+                    b { "This adjudicator has already submitted the following ballot" }
+                    " (entering a new ballot will record a new version of it):"
+                }
+                (render_ballot(room, latest))
+            }
+        }
+        form method="post" action=(action) {
+            (render_ballot_inputs(room, rules.as_ref()))
+            @if force_submit {
+                input type="checkbox" name="force" hidden checked {}
+            } @else {
+                input type="checkbox" name="force" hidden {}
+            }
+            button type="submit" class="btn btn-primary me-2" { "Enter ballot" }
+            button type="submit" class="btn btn-secondary" formaction=(format!("{action}&pin=true")) {
+                "Enter ballot and use it as the result of this room"
+            }
+        }
+    };
+
+    page_of_body_and_flash_msg(markup, None, Some(user))
+}
+
+#[get("/rooms/<room_id>/ballots/enter?<adjudicator>")]
+/// Allows an administrator to enter (or correct) a ballot on behalf of one of
+/// the adjudicators in this room. If no adjudicator is selected, we ask the
+/// administrator to pick one.
+pub async fn admin_enter_ballot_page(
+    room_id: &str,
+    adjudicator: Option<&str>,
+    user: User,
+    db: DbConn,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let room_id = room_id.to_string();
+    let adjudicator = adjudicator.map(|a| a.to_string());
+    tx(span, db, move |conn| {
+        let (room, _) = match load_room_for_admin(&room_id, &user, conn)? {
+            Ok(t) => t,
+            Err(page) => return Some(page),
+        };
+
+        let repr = SparRoomRepr::of_id(room.id, conn).unwrap();
+
+        match adjudicator {
+            Some(adjudicator) => {
+                let judge = repr
+                    .judges
+                    .iter()
+                    .find(|judge| judge.public_id == adjudicator)?
+                    .clone();
+                Some(render_admin_ballot_entry(
+                    &repr, &judge, None, false, user, conn,
+                ))
+            }
+            None => Some(page_of_body_and_flash_msg(
+                maud::html! {
+                    (page_title("Enter a ballot"))
+                    p { "Which adjudicator's ballot are you entering?" }
+                    ul {
+                        @for judge in &repr.judges {
+                            li {
+                                a href=(format!("/rooms/{}/ballots/enter?adjudicator={}", room.public_id, judge.public_id)) {
+                                    (repr.members[&judge.member_id].name)
+                                }
+                            }
+                        }
+                    }
+                },
+                None,
+                Some(user),
+            )),
+        }
+    })
+    .await
+}
+
+#[post(
+    "/rooms/<room_id>/ballots/enter?<adjudicator>&<pin>",
+    data = "<ballot>"
+)]
+/// Records a ballot entered by an administrator on behalf of an adjudicator.
+/// This applies the same checks as [`crate::spar_generation::ballots::do_submit_ballot`].
+///
+/// If `pin` is set, then the new ballot is also pinned as the result of the
+/// room (overriding the ballots submitted by the other adjudicators).
+pub async fn do_admin_enter_ballot(
+    room_id: &str,
+    adjudicator: &str,
+    pin: Option<bool>,
+    user: User,
+    db: DbConn,
+    ballot: Form<BpBallotForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let room_id = room_id.to_string();
+    let adjudicator = adjudicator.to_string();
+    let pin = pin.unwrap_or(false);
+    tx(span, db, move |conn| {
+        let (room, _) = match load_room_for_admin(&room_id, &user, conn)? {
+            Ok(t) => t,
+            Err(page) => return Some(Err(page)),
+        };
+
+        let repr = SparRoomRepr::of_id(room.id, conn).unwrap();
+        let judge = repr
+            .judges
+            .iter()
+            .find(|judge| judge.public_id == adjudicator)?
+            .clone();
+
+        if let Some(error) = check_ballot(&repr, &ballot, conn).unwrap() {
+            return Some(Err(render_admin_ballot_entry(
+                &repr,
+                &judge,
+                Some(error),
+                false,
+                user,
+                conn,
+            )));
+        }
+
+        if let Some(rules) = score_rules_of_room(&repr, conn).unwrap() {
+            if let Some(msg) = score_rules_message(&ballot, &rules) {
+                if rules.is_strict() || !ballot.force {
+                    return Some(Err(render_admin_ballot_entry(
+                        &repr,
+                        &judge,
+                        Some(&msg),
+                        !rules.is_strict(),
+                        user,
+                        conn,
+                    )));
+                }
+            }
+        }
+
+        let ballot_id = insert_ballot(
+            &repr,
+            judge.id,
+            &ballot,
+            SOURCE_ADMIN,
+            Some(user.id),
+            conn,
+        )
+        .unwrap();
+
+        if pin {
+            diesel::update(
+                adjudicator_ballots::table
+                    .filter(adjudicator_ballots::room_id.eq(room.id)),
+            )
+            .set(adjudicator_ballots::is_pinned.eq(false))
+            .execute(conn)
+            .unwrap();
+            diesel::update(
+                adjudicator_ballots::table
+                    .filter(adjudicator_ballots::id.eq(ballot_id)),
+            )
+            .set(adjudicator_ballots::is_pinned.eq(true))
+            .execute(conn)
+            .unwrap();
+        }
+
+        Some(Ok(Flash::success(
+            Redirect::to(format!("/rooms/{}/ballots", room.public_id)),
+            "The ballot has been entered.",
+        )))
+    })
+    .await
+}
@@ -125,6 +125,15 @@ fn render_search_form(
     page_of_body(markup, user)
 }
 
+fn inactive_member_msg(member: &SparSeriesMember) -> String {
+    format!(
+        "Error: {} is no longer an active member of this spar series, so \
+         cannot sign up for spars. Please ask the spar administrator if you \
+         would like to rejoin.",
+        member.name
+    )
+}
+
 #[derive(FromForm, Debug)]
 pub struct SearchForm {
     query: String,
@@ -172,7 +181,8 @@ pub async fn do_spar_signup_search(
                 r#"SELECT ssm.*
                  FROM spar_series_members_fts fts
                  INNER JOIN spar_series_members ssm ON ssm.id = fts.rowid
-                 WHERE ssm.spar_series_id = ? AND fts.name MATCH (?)||'*'
+                 WHERE ssm.spar_series_id = ? AND ssm.is_active
+                   AND fts.name MATCH (?)||'*'
                  ORDER BY rank"#;
 
             let matches = diesel::sql_query(raw_query)
@@ -257,6 +267,13 @@ pub async fn register_for_spar_page(
                 _ => return Ok(None),
             };
 
+            if !member.is_active {
+                return Ok(Some(error_403(
+                    Some(inactive_member_msg(&member)),
+                    user,
+                )));
+            }
+
             if spar.release_draw || !spar.is_open {
                 tracing::trace!(
                     "Branch not taken (release_draw={}, is_open={})",
@@ -287,6 +304,7 @@ pub async fn register_for_spar_page(
                         spar_series_members::spar_series_id
                             .eq(spar.spar_series_id),
                     )
+                    .filter(spar_series_members::is_active.eq(true))
                     .load::<SparSeriesMember>(conn)
                     .unwrap();
 
@@ -428,6 +446,10 @@ pub async fn do_register_for_spar(
                     }
                 };
 
+            if !member.is_active {
+                return Ok(error_403(Some(inactive_member_msg(&member)), user));
+            }
+
             let speaking_partner_id = if let Some(partner) =
                 form.speaking_partner
             {
@@ -451,6 +473,7 @@ pub async fn do_register_for_spar(
                         spar_series_members::spar_series_id
                             .eq(spar.spar_series_id),
                     )
+                    .filter(spar_series_members::is_active.eq(true))
                     .select(spar_series_members::id)
                     .first::<i64>(conn)
                     .optional()
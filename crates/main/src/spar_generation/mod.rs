@@ -2,5 +2,7 @@ pub mod allocation_problem;
 pub mod ballots;
 #[cfg(test)]
 pub mod basic_test_sequence;
+pub mod exports;
+pub mod feedback;
 pub mod individual_spars;
 pub mod spar_series;
@@ -5,9 +5,9 @@ use db::{
     group::Group,
     schema::{
         groups, spar_series, spar_series_join_requests, spar_series_members,
-        spars,
+        spars, users,
     },
-    spar::{Spar, SparSeries, SparSeriesMember},
+    spar::{Spar, SparSeries, SparSeriesJoinRequest, SparSeriesMember},
     user::User,
     DbConn,
 };
@@ -19,17 +19,19 @@ use maud::{html, Markup};
 use rocket::{
     form::Form,
     http::Status,
-    response::{status::Unauthorized, Redirect},
+    request::FlashMessage,
+    response::{status::Unauthorized, Flash, Redirect},
 };
 use serde::{Deserialize, Serialize};
 use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
-    html::{error_403, page_of_body, page_title},
+    html::{error_403, page_of_body, page_of_body_and_flash_msg, page_title},
     model::sync::id::gen_uuid,
     permissions::{has_permission, Permission},
     request_ids::TracingSpan,
+    spar_generation::exports::render_export_links,
     util::is_valid_email,
 };
 
@@ -74,9 +76,18 @@ pub async fn internal_page(
                         p { (description) }
                     }
                     a href=(format!("/spar_series/{}/add_member", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Add member" }
+                    a href=(format!("/spar_series/{}/import_members", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Import members" }
                     a href=(format!("/spar_series/{}/members", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Member overview" }
                     a href=(format!("/spar_series/{}/join_requests", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Manage join requests" }
+                    a href=(format!("/spar_series/{}/identities", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Link members to previous series" }
+                    a href=(format!("/spar_series/{}/score_rules", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Score rules" }
+                    a href=(format!("/spar_series/{}/ballot_settings", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Ballot settings" }
+                    a href=(format!("/spar_series/{}/adjudicator_feedback", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Adjudicator feedback" }
                     a href=(format!("/spar_series/{}/makesess", spar_series.public_id)) type="button" class="btn btn-primary m-1" { "Create new session" }
+                    details class="mt-2" {
+                        summary { "Export data" }
+                        (render_export_links(&format!("/spar_series/{}", spar_series.public_id)))
+                    }
                     table class="table" {
                         thead {
                             tr {
@@ -679,7 +690,7 @@ pub async fn join_requests_page(
             let join_requests = spar_series_join_requests::table
                 .filter(spar_series_join_requests::spar_series_id.eq(series.id))
                 .order_by(spar_series_join_requests::created_at.asc())
-                .load::<SparSeriesMember>(conn)
+                .load::<SparSeriesJoinRequest>(conn)
                 .unwrap();
 
             let table = if !join_requests.is_empty() {
@@ -776,7 +787,7 @@ pub async fn approve_join_request(
                     spar_series_join_requests::public_id
                         .eq(&form.id.to_string()),
                 )
-                .first::<SparSeriesMember>(conn)
+                .first::<SparSeriesJoinRequest>(conn)
                 .optional()
                 .unwrap()
             {
@@ -826,13 +837,34 @@ pub async fn approve_join_request(
     .await
 }
 
-#[get("/spar_series/<spar_series_id>/members")]
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Which members to show on the member overview page.
+pub enum MemberStatusFilter {
+    #[default]
+    Active,
+    Inactive,
+    All,
+}
+
+impl MemberStatusFilter {
+    fn includes(&self, member: &SparSeriesMember) -> bool {
+        match self {
+            MemberStatusFilter::Active => member.is_active,
+            MemberStatusFilter::Inactive => !member.is_active,
+            MemberStatusFilter::All => true,
+        }
+    }
+}
+
+#[get("/spar_series/<spar_series_id>/members?<status>")]
 pub async fn member_overview_page(
     db: DbConn,
     spar_series_id: &str,
+    status: Option<MemberStatusFilter>,
     user: User,
     span: TracingSpan,
 ) -> Option<Markup> {
+    let status = status.unwrap_or_default();
     let span1 = span.0.clone();
     let spar_series_id = spar_series_id.to_string();
     db.run(move |conn| {
@@ -863,11 +895,44 @@ pub async fn member_overview_page(
                 user.id
             );
 
-            let members = spar_series_members::table
+            let all_members = spar_series_members::table
                 .filter(spar_series_members::spar_series_id.eq(series.id))
                 .order_by(spar_series_members::name.asc())
                 .load::<SparSeriesMember>(conn)
                 .unwrap();
+            let inactive_count =
+                all_members.iter().filter(|member| !member.is_active).count();
+            let active_count = all_members.len() - inactive_count;
+            let members = all_members
+                .into_iter()
+                .filter(|member| status.includes(member))
+                .collect::<Vec<_>>();
+
+            let filters = [
+                (
+                    MemberStatusFilter::Active,
+                    "active",
+                    format!("Active ({active_count})"),
+                ),
+                (
+                    MemberStatusFilter::Inactive,
+                    "inactive",
+                    format!("Inactive ({inactive_count})"),
+                ),
+                (MemberStatusFilter::All, "all", "All".to_string()),
+            ];
+            let filter_nav = html! {
+                ul class="nav nav-pills my-2" {
+                    @for (filter, param, label) in &filters {
+                        li class="nav-item" {
+                            a class=(if *filter == status { "nav-link active" } else { "nav-link" })
+                                href=(format!("/spar_series/{spar_series_id}/members?status={param}")) {
+                                (label)
+                            }
+                        }
+                    }
+                }
+            };
 
             let table = if !members.is_empty() {
                 html! {
@@ -877,6 +942,7 @@ pub async fn member_overview_page(
                                 th scope="col" { "Name" }
                                 th scope="col" { "Email" }
                                 th scope="col" { "Join Date" }
+                                th scope="col" { "Status" }
                                 th scope="col" { "Edit" }
                             }
                         }
@@ -891,6 +957,13 @@ pub async fn member_overview_page(
                                     }
                                 }
                                     td { (member.created_at.format("%Y-%m-%d %H:%M:%S")) }
+                                    td {
+                                        @if member.is_active {
+                                            span class="badge text-bg-success" { "Active" }
+                                        } @else {
+                                            span class="badge text-bg-secondary" { "Inactive" }
+                                        }
+                                    }
                                     td {
                                         a href=(format!("/spar_series/{spar_series_id}/members/{}", member.public_id)) {
                                             "View member"
@@ -903,13 +976,18 @@ pub async fn member_overview_page(
                 }
             } else {
                 html! {
-                    p { "There are currently no members in this spar series." }
+                    @if status == MemberStatusFilter::All {
+                        p { "There are currently no members in this spar series." }
+                    } @else {
+                        p { "There are no members with this status." }
+                    }
                 }
             };
 
             let markup = html! {
                 h1 { "Members" }
                 a href=(format!("/spar_series/{}/add_member", series.public_id)) type="button" class="btn btn-primary m-1" { "Add member" }
+                (filter_nav)
                 (table)
             };
 
@@ -927,10 +1005,12 @@ pub async fn spar_series_member_overview(
     spar_member_id: &str,
     db: DbConn,
     user: User,
+    msg: Option<FlashMessage<'_>>,
     span: TracingSpan,
 ) -> Option<Markup> {
     let spar_series_id = spar_series_id.to_string();
     let spar_member_id = spar_member_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
 
     let span1 = span.0.clone();
 
@@ -969,6 +1049,15 @@ pub async fn spar_series_member_overview(
                 None => return Ok(None),
             };
 
+            let linked_account = match member.user_id {
+                Some(user_id) => Some(
+                    users::table
+                        .filter(users::id.eq(user_id))
+                        .first::<User>(conn)?,
+                ),
+                None => None,
+            };
+
             let markup = html! {
                 (page_title(format!("Record for {}", member.name)))
                     div class="card" style="width: 50%;" {
@@ -978,14 +1067,44 @@ pub async fn spar_series_member_overview(
                             p class="card-text" {
                                 "Member since: " (member.created_at.format("%Y-%m-%d %H:%M:%S"))
                             }
+                            p class="card-text" {
+                                @if member.is_active {
+                                    "Status: active"
+                                } @else {
+                                    "Status: inactive (cannot sign up for spars)"
+                                }
+                            }
+                            p class="card-text" {
+                                @if let Some(account) = &linked_account {
+                                    "Linked to the account " (account.email)
+                                } @else {
+                                    "Not linked to an account"
+                                }
+                            }
                             a href=(format!("/spar_series/{}/members/{}/set_email", spar_series_id, member.public_id)) class="btn btn-sm btn-outline-primary mt-2" {
                                 "Edit Email"
                             }
+                            a href=(format!("/spar_series/{}/members/{}/merge", spar_series_id, member.public_id)) class="btn btn-sm btn-outline-danger mt-2 ms-1" {
+                                "Merge with another member"
+                            }
+                            form method="post" action=(format!("/spar_series/{}/members/{}/set_active", spar_series_id, member.public_id)) class="d-inline" {
+                                @if member.is_active {
+                                    input type="hidden" name="is_active" value="false" {}
+                                    button type="submit" class="btn btn-sm btn-outline-secondary mt-2 ms-1" {
+                                        "Mark as inactive"
+                                    }
+                                } @else {
+                                    input type="hidden" name="is_active" value="true" {}
+                                    button type="submit" class="btn btn-sm btn-outline-success mt-2 ms-1" {
+                                        "Reactivate"
+                                    }
+                                }
+                            }
                         }
                     }
             };
 
-            Ok(Some(page_of_body(markup, Some(user))))
+            Ok(Some(page_of_body_and_flash_msg(markup, msg, Some(user))))
         })
         .unwrap()
     })
@@ -1135,7 +1254,12 @@ pub async fn set_member_email(
                 spar_series_members::table
                     .filter(spar_series_members::id.eq(member.id)),
             )
-            .set(spar_series_members::email.eq(&form.email))
+            .set((
+                spar_series_members::email.eq(&form.email),
+                // the account was linked on the basis of the old email
+                // address, so the link no longer applies
+                spar_series_members::user_id.eq(None::<i64>),
+            ))
             .execute(conn)?;
 
             Ok(Some(Ok(Redirect::to(format!(
@@ -1148,3 +1272,93 @@ pub async fn set_member_email(
     .instrument(span.0)
     .await
 }
+
+#[derive(FromForm, Serialize)]
+pub struct SetMemberActiveForm {
+    pub is_active: bool,
+}
+
+#[post(
+    "/spar_series/<spar_series_id>/members/<spar_member_id>/set_active",
+    data = "<form>"
+)]
+/// Marks a member as inactive (or reactivates them). Inactive members are
+/// hidden from the signup search and cannot sign up for spars, but everything
+/// they have previously done is kept.
+pub async fn set_member_active(
+    spar_series_id: &str,
+    spar_member_id: &str,
+    db: DbConn,
+    user: User,
+    form: Form<SetMemberActiveForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let spar_member_id = spar_member_id.to_string();
+
+    let span1 = span.0.clone();
+
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()
+                .unwrap()
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(Err(error_403(
+                    Some("Error: you are not authorized to modify this group!"),
+                    Some(user),
+                ))));
+            };
+
+            let member = match spar_series_members::table
+                .filter(spar_series_members::public_id.eq(&spar_member_id))
+                .filter(spar_series_members::spar_series_id.eq(series.id))
+                .first::<SparSeriesMember>(conn)
+                .optional()
+                .unwrap()
+            {
+                Some(m) => m,
+                None => return Ok(None),
+            };
+
+            diesel::update(
+                spar_series_members::table
+                    .filter(spar_series_members::id.eq(member.id)),
+            )
+            .set(spar_series_members::is_active.eq(form.is_active))
+            .execute(conn)?;
+
+            let msg = if form.is_active {
+                format!("{} has been reactivated.", member.name)
+            } else {
+                format!(
+                    "{} has been marked as inactive, and can no longer sign \
+                     up for spars.",
+                    member.name
+                )
+            };
+
+            Ok(Some(Ok(Flash::success(
+                Redirect::to(format!(
+                    "/spar_series/{}/members/{}",
+                    spar_series_id, spar_member_id
+                )),
+                msg,
+            ))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
@@ -0,0 +1,181 @@
+//! Settings which control how ballots are collected for the spars in a series
+//! (see [`db::ballot::BallotSettings`]).
+
+use db::{
+    ballot::BallotSettings,
+    schema::{spar_series, spar_series_ballot_settings},
+    spar::SparSeries,
+    user::User,
+    DbConn,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+use tracing::Instrument;
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+};
+
+#[get("/spar_series/<spar_series_id>/ballot_settings")]
+/// Shows (and allows administrators to change) the ballot settings of a spar
+/// series.
+pub async fn ballot_settings_page(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_series_id = spar_series_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(error_403(
+                    Some("Error: you are not authorized to view this group!"),
+                    Some(user),
+                )));
+            };
+
+            let settings = BallotSettings::of_series(series.id, conn)?;
+            let reminder_after_hours =
+                settings.as_ref().and_then(|s| s.reminder_after_hours);
+            let auto_complete =
+                settings.as_ref().map(|s| s.auto_complete).unwrap_or(false);
+
+            let markup = html! {
+                (page_title(format!("Ballot settings for {}", series.title)))
+                form method="post" {
+                    div class="mb-3" {
+                        label for="reminder_after_hours" class="form-label" {
+                            "Remind adjudicators who have not submitted their
+                             ballot this many hours after the spar starts"
+                        }
+                        input type="number" min="1" max="168" name="reminder_after_hours" id="reminder_after_hours" class="form-control" value=[reminder_after_hours] {}
+                        div class="form-text" {
+                            "Leave this blank to not send reminders
+                             automatically (you can still send them from the
+                             page of each spar)."
+                        }
+                    }
+                    div class="mb-3 form-check" {
+                        input type="checkbox" class="form-check-input" name="auto_complete" id="auto_complete" value="true" checked[auto_complete] {}
+                        label class="form-check-label" for="auto_complete" {
+                            "Automatically mark spars as complete once every
+                             room has a ballot (administrators are emailed
+                             when this happens)"
+                        }
+                    }
+                    button type="submit" class="btn btn-primary" { "Save" }
+                }
+            };
+
+            Ok(Some(page_of_body_and_flash_msg(markup, msg, Some(user))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct BallotSettingsForm {
+    pub reminder_after_hours: Option<i64>,
+    pub auto_complete: bool,
+}
+
+#[post("/spar_series/<spar_series_id>/ballot_settings", data = "<form>")]
+pub async fn do_set_ballot_settings(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    form: Form<BallotSettingsForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(Err(error_403(
+                    Some("Error: you are not authorized to modify this group!"),
+                    Some(user),
+                ))));
+            };
+
+            let redirect_to = Redirect::to(format!(
+                "/spar_series/{spar_series_id}/ballot_settings"
+            ));
+
+            if form
+                .reminder_after_hours
+                .map(|hours| !(1..=24 * 7).contains(&hours))
+                .unwrap_or(false)
+            {
+                return Ok(Some(Ok(Flash::error(
+                    redirect_to,
+                    "Error: reminders can be sent between 1 hour and 1 week \
+                     after the start of the spar.",
+                ))));
+            }
+
+            diesel::delete(spar_series_ballot_settings::table.filter(
+                spar_series_ballot_settings::spar_series_id.eq(series.id),
+            ))
+            .execute(conn)?;
+
+            insert_into(spar_series_ballot_settings::table)
+                .values((
+                    spar_series_ballot_settings::spar_series_id.eq(series.id),
+                    spar_series_ballot_settings::reminder_after_hours
+                        .eq(form.reminder_after_hours),
+                    spar_series_ballot_settings::auto_complete
+                        .eq(form.auto_complete),
+                ))
+                .execute(conn)?;
+
+            Ok(Some(Ok(Flash::success(
+                redirect_to,
+                "Saved the ballot settings.",
+            ))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
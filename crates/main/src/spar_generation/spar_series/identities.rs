@@ -0,0 +1,410 @@
+//! Linking members of different spar series (run by the same group) to a
+//! single group-level identity.
+//!
+//! Each new term groups tend to create a new spar series, which means that
+//! everyone is added as a new member. Linking these members allows us to
+//! carry their ratings over from one series to the next (see
+//! [`crate::spar_generation::allocation_problem::ratings`]). We suggest links
+//! between members with the same email address, but an administrator must
+//! always confirm them.
+
+use std::collections::HashMap;
+
+use db::{
+    schema::{group_member_identities, spar_series, spar_series_members},
+    spar::{SparSeries, SparSeriesMember},
+    user::User,
+    DbConn,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+use tracing::Instrument;
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    model::sync::id::gen_uuid,
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+};
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_ascii_lowercase()
+}
+
+#[get("/spar_series/<spar_series_id>/identities")]
+/// Shows which members of this spar series have been linked to members of
+/// previous spar series, and suggests new links (using email addresses).
+pub async fn member_identities_page(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_series_id = spar_series_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(error_403(
+                    Some("Error: you are not authorized to view this group!"),
+                    Some(user),
+                )));
+            };
+
+            let members = spar_series_members::table
+                .filter(spar_series_members::spar_series_id.eq(series.id))
+                .order_by(spar_series_members::name.asc())
+                .load::<SparSeriesMember>(conn)?;
+
+            // members of all the other spar series run by this group
+            let others = spar_series_members::table
+                .inner_join(spar_series::table)
+                .filter(spar_series::group_id.eq(series.group_id))
+                .filter(spar_series::id.ne(series.id))
+                .order_by(spar_series::created_at.desc())
+                .load::<(SparSeriesMember, SparSeries)>(conn)?;
+
+            let mut others_by_email: HashMap<
+                String,
+                Vec<&(SparSeriesMember, SparSeries)>,
+            > = HashMap::new();
+            for other in &others {
+                others_by_email
+                    .entry(normalize_email(&other.0.email))
+                    .or_default()
+                    .push(other);
+            }
+
+            let markup = html! {
+                (page_title(format!("Linked members for {}", series.title)))
+                p {
+                    "Members who are linked to a member of a previous spar
+                     series start from the rating they had at the end of that
+                     series (rather than from scratch). We suggest members who
+                     share an email address, but you need to confirm each
+                     link."
+                }
+                table class="table" {
+                    thead {
+                        tr {
+                            th scope="col" { "Name" }
+                            th scope="col" { "Email" }
+                            th scope="col" { "Linked to" }
+                            th scope="col" { "Suggested links" }
+                        }
+                    }
+                    tbody {
+                        @for member in &members {
+                            @let linked = others
+                                .iter()
+                                .filter(|(other, _)| {
+                                    member.identity_id.is_some()
+                                        && other.identity_id == member.identity_id
+                                })
+                                .collect::<Vec<_>>();
+                            @let suggested = others_by_email
+                                .get(&normalize_email(&member.email))
+                                .map(|candidates| {
+                                    candidates
+                                        .iter()
+                                        .filter(|(other, _)| {
+                                            member.identity_id.is_none()
+                                                || other.identity_id != member.identity_id
+                                        })
+                                        .collect::<Vec<_>>()
+                                })
+                                .unwrap_or_default();
+                            tr {
+                                td { (member.name) }
+                                td { (member.email) }
+                                td {
+                                    @for (other, other_series) in &linked {
+                                        p {
+                                            (other.name) " (" (other_series.title) ")"
+                                        }
+                                    }
+                                    @if member.identity_id.is_some() {
+                                        form method="post" action=(format!("/spar_series/{}/identities/unlink", series.public_id)) {
+                                            input type="hidden" name="member" value=(member.public_id);
+                                            button type="submit" class="btn btn-sm btn-outline-danger" { "Unlink" }
+                                        }
+                                    }
+                                }
+                                td {
+                                    @for (other, other_series) in &suggested {
+                                        form method="post" action=(format!("/spar_series/{}/identities/link", series.public_id)) {
+                                            input type="hidden" name="member" value=(member.public_id);
+                                            input type="hidden" name="other_member" value=(other.public_id);
+                                            span class="me-2" { (other.name) " (" (other_series.title) ")" }
+                                            button type="submit" class="btn btn-sm btn-outline-primary" { "Link" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            };
+
+            Ok(Some(page_of_body_and_flash_msg(markup, msg, Some(user))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct LinkMembersForm {
+    /// The public id of a member of the current spar series.
+    pub member: String,
+    /// The public id of a member of another spar series run by the same group.
+    pub other_member: String,
+}
+
+#[post("/spar_series/<spar_series_id>/identities/link", data = "<form>")]
+/// Records that a member of this spar series is the same person as a member of
+/// another spar series run by the same group.
+pub async fn do_link_members(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    form: Form<LinkMembersForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(Err(error_403(
+                    Some("Error: you are not authorized to modify this group!"),
+                    Some(user),
+                ))));
+            };
+
+            let member = match spar_series_members::table
+                .filter(spar_series_members::public_id.eq(&form.member))
+                .filter(spar_series_members::spar_series_id.eq(series.id))
+                .first::<SparSeriesMember>(conn)
+                .optional()?
+            {
+                Some(m) => m,
+                None => return Ok(None),
+            };
+
+            let other = match spar_series_members::table
+                .inner_join(spar_series::table)
+                .filter(spar_series_members::public_id.eq(&form.other_member))
+                .filter(spar_series::group_id.eq(series.group_id))
+                .filter(spar_series::id.ne(series.id))
+                .select(spar_series_members::all_columns)
+                .first::<SparSeriesMember>(conn)
+                .optional()?
+            {
+                Some(m) => m,
+                None => return Ok(None),
+            };
+
+            let redirect_to = Redirect::to(format!(
+                "/spar_series/{spar_series_id}/identities"
+            ));
+
+            let (identity_id, to_update) =
+                match (member.identity_id, other.identity_id) {
+                    (Some(a), Some(b)) if a == b => {
+                        return Ok(Some(Ok(Flash::success(
+                            redirect_to,
+                            "Those members are already linked.",
+                        ))));
+                    }
+                    (Some(_), Some(_)) => {
+                        return Ok(Some(Ok(Flash::error(
+                            redirect_to,
+                            "Error: both of those members are already linked \
+                             to different people. Please unlink one of them \
+                             first.",
+                        ))));
+                    }
+                    (Some(identity_id), None) => (identity_id, other.id),
+                    (None, Some(identity_id)) => (identity_id, member.id),
+                    (None, None) => {
+                        let public_id = gen_uuid().to_string();
+                        insert_into(group_member_identities::table)
+                            .values((
+                                group_member_identities::public_id
+                                    .eq(&public_id),
+                                group_member_identities::group_id
+                                    .eq(series.group_id),
+                                group_member_identities::created_at
+                                    .eq(diesel::dsl::now),
+                            ))
+                            .execute(conn)?;
+                        let identity_id = group_member_identities::table
+                            .filter(
+                                group_member_identities::public_id
+                                    .eq(&public_id),
+                            )
+                            .select(group_member_identities::id)
+                            .first::<i64>(conn)?;
+
+                        diesel::update(
+                            spar_series_members::table
+                                .filter(spar_series_members::id.eq(other.id)),
+                        )
+                        .set(
+                            spar_series_members::identity_id
+                                .eq(Some(identity_id)),
+                        )
+                        .execute(conn)?;
+
+                        (identity_id, member.id)
+                    }
+                };
+
+            // a person can only be a member of each spar series once
+            let to_update_series = spar_series_members::table
+                .filter(spar_series_members::id.eq(to_update))
+                .select(spar_series_members::spar_series_id)
+                .first::<i64>(conn)?;
+            let already_linked_in_series =
+                diesel::dsl::select(diesel::dsl::exists(
+                    spar_series_members::table
+                        .filter(
+                            spar_series_members::spar_series_id
+                                .eq(to_update_series),
+                        )
+                        .filter(
+                            spar_series_members::identity_id
+                                .eq(Some(identity_id)),
+                        ),
+                ))
+                .get_result::<bool>(conn)?;
+            if already_linked_in_series {
+                return Ok(Some(Ok(Flash::error(
+                    redirect_to,
+                    "Error: another member of that spar series has already \
+                     been linked to this person.",
+                ))));
+            }
+
+            diesel::update(
+                spar_series_members::table
+                    .filter(spar_series_members::id.eq(to_update)),
+            )
+            .set(spar_series_members::identity_id.eq(Some(identity_id)))
+            .execute(conn)?;
+
+            Ok(Some(Ok(Flash::success(
+                redirect_to,
+                format!("Linked {} to {}.", member.name, other.name),
+            ))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct UnlinkMemberForm {
+    pub member: String,
+}
+
+#[post("/spar_series/<spar_series_id>/identities/unlink", data = "<form>")]
+/// Removes a member of this spar series from the identity they were linked
+/// to (this does not affect other members linked to the same identity).
+pub async fn do_unlink_member(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    form: Form<UnlinkMemberForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(Err(error_403(
+                    Some("Error: you are not authorized to modify this group!"),
+                    Some(user),
+                ))));
+            };
+
+            let member = match spar_series_members::table
+                .filter(spar_series_members::public_id.eq(&form.member))
+                .filter(spar_series_members::spar_series_id.eq(series.id))
+                .first::<SparSeriesMember>(conn)
+                .optional()?
+            {
+                Some(m) => m,
+                None => return Ok(None),
+            };
+
+            diesel::update(
+                spar_series_members::table
+                    .filter(spar_series_members::id.eq(member.id)),
+            )
+            .set(spar_series_members::identity_id.eq(None::<i64>))
+            .execute(conn)?;
+
+            Ok(Some(Ok(Flash::success(
+                Redirect::to(format!(
+                    "/spar_series/{spar_series_id}/identities"
+                )),
+                format!("Unlinked {}.", member.name),
+            ))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
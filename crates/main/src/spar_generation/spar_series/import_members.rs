@@ -0,0 +1,553 @@
+//! Adds many members to a spar series at once, from a CSV file (e.g. one
+//! exported from a sign-up form at the start of term).
+//!
+//! The file is first shown to the administrator as a preview (listing which
+//! rows will be added, and why any others will be skipped), and the members
+//! are only added once the administrator confirms the import.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use db::{
+    schema::{spar_series, spar_series_members},
+    spar::{SparSeries, SparSeriesMember},
+    user::User,
+    DbConn,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    model::sync::id::gen_uuid,
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    util::tx,
+};
+
+/// Splits CSV text (as described in RFC 4180) into records. Both `\n` and
+/// `\r\n` are accepted as line endings, and blank lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.trim().is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                } else {
+                    record.clear();
+                }
+            }
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("a quoted field is missing its closing quote".to_string());
+    }
+    record.push(field);
+    if record.iter().any(|field| !field.trim().is_empty()) {
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A name and email address read from the CSV file.
+struct CsvMember {
+    /// The number of the record in the file (starting from one, and not
+    /// counting the header).
+    row: usize,
+    name: String,
+    email: String,
+}
+
+/// Reads the members from the CSV file. If the first record contains
+/// `name` and `email` headers then these are used to find the columns,
+/// otherwise the first column is taken to be the name and the second to be
+/// the email address.
+fn members_of_csv(text: &str) -> Result<Vec<CsvMember>, String> {
+    let mut records = parse_csv(text)?;
+    if records.is_empty() {
+        return Err("the file is empty".to_string());
+    }
+
+    let header = records[0]
+        .iter()
+        .map(|field| field.trim().to_lowercase())
+        .collect::<Vec<_>>();
+    let (name_col, email_col) = match (
+        header.iter().position(|h| h == "name"),
+        header.iter().position(|h| h == "email"),
+    ) {
+        (Some(name), Some(email)) => {
+            records.remove(0);
+            (name, email)
+        }
+        _ => (0, 1),
+    };
+
+    Ok(records
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let field =
+                |col: usize| record.get(col).map(|f| f.trim().to_string());
+            CsvMember {
+                row: i + 1,
+                name: field(name_col).unwrap_or_default(),
+                email: field(email_col).unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RowStatus {
+    /// The member will be added to the series.
+    New,
+    MissingName,
+    InvalidEmail,
+    /// A member of the series already has this name or email address.
+    AlreadyMember(String),
+    /// An earlier row of the file has the same name or email address.
+    DuplicateOf(usize),
+}
+
+impl RowStatus {
+    fn describe(&self) -> String {
+        match self {
+            RowStatus::New => "Will be added".to_string(),
+            RowStatus::MissingName => "Skipped: no name".to_string(),
+            RowStatus::InvalidEmail => {
+                "Skipped: invalid email address".to_string()
+            }
+            RowStatus::AlreadyMember(name) => {
+                format!("Skipped: already a member (as {name})")
+            }
+            RowStatus::DuplicateOf(row) => {
+                format!("Skipped: same person as row {row}")
+            }
+        }
+    }
+}
+
+/// Decides which of the rows should be added to the series. Names and email
+/// addresses are compared case-insensitively (both against the existing
+/// members, and against earlier rows of the file).
+fn check_members(
+    rows: &[CsvMember],
+    existing: &[SparSeriesMember],
+) -> Vec<RowStatus> {
+    let mut existing_names = HashMap::new();
+    let mut existing_emails = HashMap::new();
+    for member in existing {
+        existing_names.insert(member.name.to_lowercase(), member.name.clone());
+        existing_emails
+            .insert(member.email.to_lowercase(), member.name.clone());
+    }
+
+    let mut seen_names = HashMap::new();
+    let mut seen_emails = HashMap::new();
+    rows.iter()
+        .map(|row| {
+            if row.name.is_empty() {
+                return RowStatus::MissingName;
+            }
+            if !User::validate_email(&row.email) {
+                return RowStatus::InvalidEmail;
+            }
+
+            let name = row.name.to_lowercase();
+            let email = row.email.to_lowercase();
+            if let Some(member) = existing_emails
+                .get(&email)
+                .or_else(|| existing_names.get(&name))
+            {
+                return RowStatus::AlreadyMember(member.clone());
+            }
+            if let Some(prev) =
+                seen_emails.get(&email).or_else(|| seen_names.get(&name))
+            {
+                return RowStatus::DuplicateOf(*prev);
+            }
+
+            seen_names.insert(name, row.row);
+            seen_emails.insert(email, row.row);
+            RowStatus::New
+        })
+        .collect()
+}
+
+fn render_upload_form(spar_series_id: &str) -> Markup {
+    html! {
+        form method="post" action=(format!("/spar_series/{spar_series_id}/import_members/preview")) enctype="multipart/form-data" {
+            div class="mb-3" {
+                label for="file" class="form-label" { "CSV file" }
+                input type="file" name="file" id="file" accept=".csv,text/csv" class="form-control" {}
+            }
+            div class="mb-3" {
+                label for="text" class="form-label" { "Or paste the contents of the file" }
+                textarea name="text" id="text" rows="8" class="form-control font-monospace" placeholder="name,email" {}
+            }
+            button type="submit" class="btn btn-primary" { "Preview" }
+        }
+    }
+}
+
+#[get("/spar_series/<spar_series_id>/import_members")]
+pub async fn import_members_page(
+    spar_series_id: &str,
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_series_id = spar_series_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let series = spar_series::table
+            .filter(spar_series::public_id.eq(&spar_series_id))
+            .first::<SparSeries>(conn)
+            .optional()
+            .unwrap()?;
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+            conn,
+        ) {
+            return Some(error_403(
+                Some("Error: you are not authorized to modify this group!"),
+                Some(user),
+            ));
+        }
+
+        let markup = html! {
+            (page_title("Import members"))
+            p {
+                a href=(format!("/spar_series/{}", series.public_id)) { "Back to " (series.title) }
+                " | "
+                a href=(format!("/spar_series/{}/members", series.public_id)) { "Member overview" }
+            }
+            p {
+                "Upload a CSV file with the name and email address of each
+                 person to add. If the first row contains the headers "
+                code { "name" } " and " code { "email" } " then these columns
+                 are used (and any other columns are ignored), otherwise the
+                 name should be in the first column and the email address in
+                 the second."
+            }
+            p class="text-muted" {
+                "You will be shown a preview of the changes before any members
+                 are added."
+            }
+            (render_upload_form(&series.public_id))
+        };
+        Some(page_of_body_and_flash_msg(markup, msg, Some(user)))
+    })
+    .await
+}
+
+#[derive(FromForm)]
+pub struct ImportMembersForm {
+    /// The contents of the uploaded file.
+    pub file: Option<String>,
+    /// Text pasted into the form (only used if no file was uploaded).
+    pub text: Option<String>,
+}
+
+#[post(
+    "/spar_series/<spar_series_id>/import_members/preview",
+    data = "<form>"
+)]
+/// Shows what will happen when the CSV file is imported.
+pub async fn preview_import_members(
+    spar_series_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<ImportMembersForm>,
+    span: TracingSpan,
+) -> Option<Result<Markup, Flash<Redirect>>> {
+    let spar_series_id = spar_series_id.to_string();
+    let form = form.into_inner();
+    let csv = form
+        .file
+        .filter(|file| !file.trim().is_empty())
+        .or(form.text)
+        .unwrap_or_default();
+    tx(span, db, move |conn| {
+        let series = spar_series::table
+            .filter(spar_series::public_id.eq(&spar_series_id))
+            .first::<SparSeries>(conn)
+            .optional()
+            .unwrap()?;
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+            conn,
+        ) {
+            return Some(Ok(error_403(
+                Some("Error: you are not authorized to modify this group!"),
+                Some(user),
+            )));
+        }
+
+        let rows = match members_of_csv(&csv) {
+            Ok(rows) => rows,
+            Err(e) => {
+                return Some(Err(Flash::error(
+                    Redirect::to(format!(
+                        "/spar_series/{spar_series_id}/import_members"
+                    )),
+                    format!("Error: could not read the file ({e})."),
+                )))
+            }
+        };
+        let existing = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(series.id))
+            .load::<SparSeriesMember>(conn)
+            .unwrap();
+        let statuses = check_members(&rows, &existing);
+        let to_add = statuses
+            .iter()
+            .filter(|status| **status == RowStatus::New)
+            .count();
+
+        let markup = html! {
+            (page_title("Import members"))
+            p {
+                a href=(format!("/spar_series/{}/import_members", series.public_id)) { "Upload a different file" }
+            }
+            @if to_add == 0 {
+                div class="alert alert-warning" {
+                    "None of the rows in this file can be added."
+                }
+            } @else {
+                div class="alert alert-info" {
+                    (to_add) " of " (rows.len()) " rows will be added to "
+                    (series.title) "."
+                }
+                form method="post" action=(format!("/spar_series/{}/import_members", series.public_id)) {
+                    input type="hidden" name="csv" value=(csv) {}
+                    button type="submit" class="btn btn-primary mb-3" {
+                        "Add " (to_add) " members"
+                    }
+                }
+            }
+            table class="table table-sm" {
+                thead {
+                    tr {
+                        th { "Row" }
+                        th { "Name" }
+                        th { "Email" }
+                        th { "Status" }
+                    }
+                }
+                tbody {
+                    @for (row, status) in rows.iter().zip(&statuses) {
+                        tr class=(if *status == RowStatus::New { "table-success" } else { "table-warning" }) {
+                            td { (row.row) }
+                            td { (row.name) }
+                            td { (row.email) }
+                            td { (status.describe()) }
+                        }
+                    }
+                }
+            }
+        };
+        Some(Ok(page_of_body_and_flash_msg(markup, None, Some(user))))
+    })
+    .await
+}
+
+#[derive(FromForm)]
+pub struct ConfirmImportMembersForm {
+    pub csv: String,
+}
+
+#[post("/spar_series/<spar_series_id>/import_members", data = "<form>")]
+/// Adds the members in the CSV file (which was previously shown to the
+/// administrator using [`preview_import_members`]) to the series.
+///
+/// The rows are checked again (in case members have been added since the
+/// preview was shown), and all the members are added in a single
+/// transaction. The full text index used to search for members (see
+/// [`crate::spar_generation::individual_spars::signup_routes`]) is updated
+/// by the triggers on `spar_series_members`.
+pub async fn do_import_members(
+    spar_series_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<ConfirmImportMembersForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    tx(span, db, move |conn| {
+        let series = spar_series::table
+            .filter(spar_series::public_id.eq(&spar_series_id))
+            .first::<SparSeries>(conn)
+            .optional()
+            .unwrap()?;
+        if !has_permission(
+            Some(&user),
+            &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+            conn,
+        ) {
+            return Some(Err(error_403(
+                Some("Error: you are not authorized to modify this group!"),
+                Some(user),
+            )));
+        }
+
+        let redirect_to = Redirect::to(format!(
+            "/spar_series/{spar_series_id}/import_members"
+        ));
+
+        let rows = match members_of_csv(&form.csv) {
+            Ok(rows) => rows,
+            Err(e) => {
+                return Some(Ok(Flash::error(
+                    redirect_to,
+                    format!("Error: could not read the file ({e})."),
+                )))
+            }
+        };
+        let existing = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(series.id))
+            .load::<SparSeriesMember>(conn)
+            .unwrap();
+        let statuses = check_members(&rows, &existing);
+
+        let now = Utc::now().naive_utc();
+        let mut added = 0;
+        for (row, status) in rows.iter().zip(&statuses) {
+            if *status != RowStatus::New {
+                continue;
+            }
+            insert_into(spar_series_members::table)
+                .values((
+                    spar_series_members::public_id.eq(gen_uuid().to_string()),
+                    spar_series_members::name.eq(&row.name),
+                    spar_series_members::email.eq(&row.email),
+                    spar_series_members::spar_series_id.eq(series.id),
+                    spar_series_members::created_at.eq(now),
+                ))
+                .execute(conn)
+                .unwrap();
+            added += 1;
+        }
+
+        let skipped = rows.len() - added;
+        Some(Ok(Flash::success(
+            redirect_to,
+            format!(
+                "Added {added} members to the series ({skipped} rows were \
+                 skipped)."
+            ),
+        )))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test_import_members {
+    use chrono::NaiveDateTime;
+    use db::spar::SparSeriesMember;
+
+    use super::{check_members, members_of_csv, parse_csv, RowStatus};
+
+    #[test]
+    fn test_parse_quoted_fields() {
+        let records =
+            parse_csv("name,email\r\n\"Smith, \"\"Jo\"\"\",jo@example.com\n\n")
+                .unwrap();
+        assert_eq!(
+            records,
+            vec![
+                vec!["name".to_string(), "email".to_string()],
+                vec!["Smith, \"Jo\"".to_string(), "jo@example.com".to_string()],
+            ]
+        );
+        assert!(parse_csv("\"unterminated,field").is_err());
+    }
+
+    #[test]
+    fn test_header_columns() {
+        let members = members_of_csv(
+            "Email,Year,Name\nalex@example.com,1,Alex\nsam@example.com,2,Sam",
+        )
+        .unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[1].row, 2);
+        assert_eq!(members[1].name, "Sam");
+        assert_eq!(members[1].email, "sam@example.com");
+
+        // without a header, the first two columns are used
+        let members = members_of_csv("Alex,alex@example.com").unwrap();
+        assert_eq!(members[0].name, "Alex");
+        assert_eq!(members[0].email, "alex@example.com");
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let existing = vec![SparSeriesMember {
+            id: 1,
+            public_id: "m1".to_string(),
+            name: "Alex".to_string(),
+            email: "alex@example.com".to_string(),
+            spar_series_id: 1,
+            created_at: NaiveDateTime::default(),
+            identity_id: None,
+            user_id: None,
+            is_active: true,
+        }];
+        let rows = members_of_csv(
+            "name,email
+             Alex B,ALEX@example.com
+             Sam,sam@example.com
+             sam,sam2@example.com
+             Jo,not-an-email
+             ,nobody@example.com",
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_members(&rows, &existing),
+            vec![
+                RowStatus::AlreadyMember("Alex".to_string()),
+                RowStatus::New,
+                RowStatus::DuplicateOf(2),
+                RowStatus::InvalidEmail,
+                RowStatus::MissingName,
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Allows administrators to merge two members of a spar series who are
+//! really the same person (see [`db::merge_members`]).
+
+use db::{
+    merge_members::{merge_members, preview_merge, MergePreview},
+    schema::{spar_series, spar_series_members},
+    spar::{SparSeries, SparSeriesMember},
+    user::User,
+    DbConn, DbWrapper,
+};
+use diesel::prelude::*;
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+    resources::GroupRef,
+    util::tx,
+};
+
+/// Loads the series and the member, checking that the user is allowed to
+/// administer the series.
+fn series_and_member(
+    spar_series_id: &str,
+    member_id: &str,
+    user: &User,
+    conn: &mut DbWrapper,
+) -> Option<Result<(SparSeries, SparSeriesMember), Markup>> {
+    let series = spar_series::table
+        .filter(spar_series::public_id.eq(spar_series_id))
+        .first::<SparSeries>(conn)
+        .optional()
+        .unwrap()?;
+    if !has_permission(
+        Some(user),
+        &Permission::ModifyResourceInGroup(GroupRef(series.group_id)),
+        conn,
+    ) {
+        return Some(Err(error_403(
+            Some("Error: you are not authorized to modify this group!"),
+            Some(user.clone()),
+        )));
+    }
+
+    let member = spar_series_members::table
+        .filter(spar_series_members::public_id.eq(member_id))
+        .filter(spar_series_members::spar_series_id.eq(series.id))
+        .first::<SparSeriesMember>(conn)
+        .optional()
+        .unwrap()?;
+    Some(Ok((series, member)))
+}
+
+fn render_preview(preview: &MergePreview) -> Markup {
+    let rows = [
+        ("Signups moved", preview.signups),
+        ("Signups combined", preview.combined_signups),
+        ("Partner preferences", preview.partner_preferences),
+        ("Places in the draw as a speaker", preview.speakers),
+        ("Places in the draw as a judge", preview.adjudicators),
+        ("Speaker scores", preview.ballot_entries),
+        ("Ballot links", preview.ballot_links),
+        ("Draft draws", preview.draft_draws),
+    ];
+    html! {
+        table class="table table-sm w-auto" {
+            tbody {
+                @for (label, count) in rows {
+                    tr {
+                        td { (label) }
+                        td { (count) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[get("/spar_series/<spar_series_id>/members/<member_id>/merge?<into>")]
+/// Allows the administrator to pick which member to merge this member into,
+/// and then shows what will be changed by the merge.
+pub async fn merge_member_page(
+    spar_series_id: &str,
+    member_id: &str,
+    into: Option<&str>,
+    user: User,
+    db: DbConn,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_series_id = spar_series_id.to_string();
+    let member_id = member_id.to_string();
+    let into = into.map(|into| into.to_string());
+    let msg = msg.map(|msg| msg.message().to_string());
+    tx(span, db, move |conn| {
+        let (series, member) =
+            match series_and_member(&spar_series_id, &member_id, &user, conn)? {
+                Ok(t) => t,
+                Err(page) => return Some(page),
+            };
+
+        let others = spar_series_members::table
+            .filter(spar_series_members::spar_series_id.eq(series.id))
+            .filter(spar_series_members::id.ne(member.id))
+            .order_by(spar_series_members::name.asc())
+            .load::<SparSeriesMember>(conn)
+            .unwrap();
+        let into = into.and_then(|into| {
+            others.iter().find(|other| other.public_id == into).cloned()
+        });
+        let preview = into
+            .as_ref()
+            .map(|into| preview_merge(&member, into, conn).unwrap());
+
+        let markup = html! {
+            (page_title(format!("Merge {}", member.name)))
+            p {
+                a href=(format!("/spar_series/{}/members/{}", series.public_id, member.public_id)) { "Back to " (member.name) }
+            }
+            p {
+                "If " (member.name) " (" (member.email) ") was added to the
+                 series more than once, you can merge this record into the
+                 other one. Everything recorded for " (member.name) " will
+                 be moved to the other record, and this record will be
+                 deleted."
+            }
+            form method="get" class="row g-2 align-items-center mb-3" {
+                div class="col-auto" {
+                    select name="into" class="form-select" required {
+                        option value="" { "Merge into..." }
+                        @for other in &others {
+                            option value=(other.public_id) selected[into.as_ref().map(|into| into.id) == Some(other.id)] {
+                                (other.name) " (" (other.email) ")"
+                            }
+                        }
+                    }
+                }
+                div class="col-auto" {
+                    button type="submit" class="btn btn-outline-primary" { "Preview" }
+                }
+            }
+            @if let (Some(into), Some(preview)) = (&into, &preview) {
+                h3 { "Merging into " (into.name) }
+                (render_preview(preview))
+                @if preview.can_merge() {
+                    form method="post" action=(format!("/spar_series/{}/members/{}/merge", series.public_id, member.public_id)) {
+                        input type="hidden" name="into" value=(into.public_id) {}
+                        button type="submit" class="btn btn-danger" {
+                            "Merge " (member.name) " into " (into.name)
+                        }
+                    }
+                    p class="text-muted mt-2" { "This cannot be undone." }
+                } @else {
+                    div class="alert alert-danger" {
+                        "These members cannot be merged, as both of them are
+                         in the draw for the spars on: "
+                        (preview.conflicts.iter().map(|spar| spar.start_time.format("%Y-%m-%d %H:%M").to_string()).collect::<Vec<_>>().join(", "))
+                        ". Remove one of them from these draws first."
+                    }
+                }
+            }
+        };
+        Some(page_of_body_and_flash_msg(markup, msg, Some(user)))
+    })
+    .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct MergeMembersForm {
+    /// The public id of the member to keep.
+    pub into: String,
+}
+
+#[post(
+    "/spar_series/<spar_series_id>/members/<member_id>/merge",
+    data = "<form>"
+)]
+pub async fn do_merge_member(
+    spar_series_id: &str,
+    member_id: &str,
+    user: User,
+    db: DbConn,
+    form: Form<MergeMembersForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let member_id = member_id.to_string();
+    tx(span, db, move |conn| {
+        let (series, member) = match series_and_member(
+            &spar_series_id,
+            &member_id,
+            &user,
+            conn,
+        )? {
+            Ok(t) => t,
+            Err(page) => return Some(Err(page)),
+        };
+
+        let into = spar_series_members::table
+            .filter(spar_series_members::public_id.eq(&form.into))
+            .filter(spar_series_members::spar_series_id.eq(series.id))
+            .filter(spar_series_members::id.ne(member.id))
+            .first::<SparSeriesMember>(conn)
+            .optional()
+            .unwrap()?;
+
+        Some(Ok(match merge_members(&member, &into, conn).unwrap() {
+            Ok(_) => Flash::success(
+                Redirect::to(format!(
+                    "/spar_series/{}/members/{}",
+                    series.public_id, into.public_id
+                )),
+                format!("Merged {} into {}.", member.name, into.name),
+            ),
+            Err(_) => Flash::error(
+                Redirect::to(format!(
+                    "/spar_series/{}/members/{}/merge?into={}",
+                    series.public_id, member.public_id, into.public_id
+                )),
+                "Error: these members cannot be merged (both of them are in \
+                 the draw for the same spar).",
+            ),
+        }))
+    })
+    .await
+}
@@ -1 +1,6 @@
 pub mod admin_routes;
+pub mod ballot_settings;
+pub mod identities;
+pub mod import_members;
+pub mod merge_members;
+pub mod score_rules;
@@ -0,0 +1,215 @@
+//! Rules which the speaker scores on ballots submitted for spars in a series
+//! must follow (see [`db::ballot::ScoreRules`]).
+
+use db::{
+    ballot::{Score, ScoreRules, ENFORCE_ERROR, ENFORCE_WARN},
+    schema::{spar_series, spar_series_score_rules},
+    spar::SparSeries,
+    user::User,
+    DbConn,
+};
+use diesel::{dsl::insert_into, prelude::*};
+use maud::{html, Markup};
+use rocket::{
+    form::Form,
+    request::FlashMessage,
+    response::{Flash, Redirect},
+};
+use serde::Serialize;
+use tracing::Instrument;
+
+use crate::{
+    html::{error_403, page_of_body_and_flash_msg, page_title},
+    permissions::{has_permission, Permission},
+    request_ids::TracingSpan,
+};
+
+#[get("/spar_series/<spar_series_id>/score_rules")]
+/// Shows (and allows administrators to change) the score rules of a spar
+/// series.
+pub async fn score_rules_page(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    msg: Option<FlashMessage<'_>>,
+    span: TracingSpan,
+) -> Option<Markup> {
+    let spar_series_id = spar_series_id.to_string();
+    let msg = msg.map(|msg| msg.message().to_string());
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(error_403(
+                    Some("Error: you are not authorized to view this group!"),
+                    Some(user),
+                )));
+            };
+
+            let rules = ScoreRules::of_series(series.id, conn)?;
+            let min_score = rules.as_ref().and_then(|r| r.min_score);
+            let max_score = rules.as_ref().and_then(|r| r.max_score);
+            let step = rules.as_ref().and_then(|r| r.step);
+            let strict = rules.as_ref().map(|r| r.is_strict()).unwrap_or(true);
+
+            let markup = html! {
+                (page_title(format!("Score rules for {}", series.title)))
+                p {
+                    "Ballots with speaker scores outside of this range (or which
+                     are not a multiple of the step) can either be rejected, or
+                     adjudicators can be asked to confirm that their scores are
+                     correct. Leave a field blank to not check it. Half points
+                     can be used (e.g. a step of 0.5)."
+                }
+                form method="post" {
+                    div class="mb-3" {
+                        label for="min_score" class="form-label" { "Minimum score" }
+                        input type="number" step="0.5" name="min_score" id="min_score" class="form-control" value=[min_score] {}
+                    }
+                    div class="mb-3" {
+                        label for="max_score" class="form-label" { "Maximum score" }
+                        input type="number" step="0.5" name="max_score" id="max_score" class="form-control" value=[max_score] {}
+                    }
+                    div class="mb-3" {
+                        label for="step" class="form-label" { "Step" }
+                        input type="number" min="0.5" step="0.5" name="step" id="step" class="form-control" value=[step] {}
+                    }
+                    div class="mb-3" {
+                        label for="enforcement" class="form-label" { "When a ballot breaks these rules" }
+                        select name="enforcement" id="enforcement" class="form-select" {
+                            option value=(ENFORCE_ERROR) selected[strict] { "Reject the ballot" }
+                            option value=(ENFORCE_WARN) selected[!strict] { "Ask the adjudicator to confirm their scores" }
+                        }
+                    }
+                    button type="submit" class="btn btn-primary" { "Save" }
+                }
+            };
+
+            Ok(Some(page_of_body_and_flash_msg(markup, msg, Some(user))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
+
+#[derive(FromForm, Serialize)]
+pub struct ScoreRulesForm {
+    pub min_score: Option<Score>,
+    pub max_score: Option<Score>,
+    pub step: Option<Score>,
+    pub enforcement: String,
+}
+
+#[post("/spar_series/<spar_series_id>/score_rules", data = "<form>")]
+pub async fn do_set_score_rules(
+    spar_series_id: &str,
+    db: DbConn,
+    user: User,
+    form: Form<ScoreRulesForm>,
+    span: TracingSpan,
+) -> Option<Result<Flash<Redirect>, Markup>> {
+    let spar_series_id = spar_series_id.to_string();
+    let span1 = span.0.clone();
+    db.run(move |conn| {
+        let _guard = span1.enter();
+        conn.transaction(|conn| -> Result<_, diesel::result::Error> {
+            let series = match spar_series::table
+                .filter(spar_series::public_id.eq(&spar_series_id))
+                .first::<SparSeries>(conn)
+                .optional()?
+            {
+                Some(s) => s,
+                None => return Ok(None),
+            };
+
+            let required_permission = Permission::ModifyResourceInGroup(
+                crate::resources::GroupRef(series.group_id),
+            );
+            if !has_permission(Some(&user), &required_permission, conn) {
+                return Ok(Some(Err(error_403(
+                    Some("Error: you are not authorized to modify this group!"),
+                    Some(user),
+                ))));
+            };
+
+            let redirect_to = Redirect::to(format!(
+                "/spar_series/{spar_series_id}/score_rules"
+            ));
+
+            if form.enforcement != ENFORCE_ERROR
+                && form.enforcement != ENFORCE_WARN
+            {
+                return Ok(Some(Ok(Flash::error(
+                    redirect_to,
+                    "Error: invalid enforcement option.",
+                ))));
+            }
+
+            if let (Some(min), Some(max)) = (form.min_score, form.max_score) {
+                if min > max {
+                    return Ok(Some(Ok(Flash::error(
+                        redirect_to,
+                        "Error: the minimum score must not be greater than \
+                         the maximum score.",
+                    ))));
+                }
+            }
+
+            if form
+                .step
+                .map(|step| step < Score::of_half_points(1))
+                .unwrap_or(false)
+            {
+                return Ok(Some(Ok(Flash::error(
+                    redirect_to,
+                    "Error: the step must be at least half a point.",
+                ))));
+            }
+
+            diesel::delete(
+                spar_series_score_rules::table.filter(
+                    spar_series_score_rules::spar_series_id.eq(series.id),
+                ),
+            )
+            .execute(conn)?;
+
+            if form.min_score.is_some()
+                || form.max_score.is_some()
+                || form.step.is_some()
+            {
+                insert_into(spar_series_score_rules::table)
+                    .values((
+                        spar_series_score_rules::spar_series_id.eq(series.id),
+                        spar_series_score_rules::min_score.eq(form.min_score),
+                        spar_series_score_rules::max_score.eq(form.max_score),
+                        spar_series_score_rules::step.eq(form.step),
+                        spar_series_score_rules::enforcement
+                            .eq(&form.enforcement),
+                    ))
+                    .execute(conn)?;
+            }
+
+            Ok(Some(Ok(Flash::success(
+                redirect_to,
+                "Saved the score rules.",
+            ))))
+        })
+        .unwrap()
+    })
+    .instrument(span.0)
+    .await
+}
@@ -24,6 +24,25 @@ use crate::typst_as_library::TypstWrapperWorld;
 
 const DEFAULT_TEMPLATE: &'static str = include_str!("break_slides.typst");
 
+/// Compiles a Typst document to a PDF. `files` are made available to the
+/// document (e.g. JSON files containing the data to be rendered, which the
+/// document can load using `json("data.json")`).
+pub fn compile_pdf(
+    template: String,
+    files: Vec<(String, String)>,
+) -> Result<Vec<u8>, String> {
+    let mut world = TypstWrapperWorld::new(template);
+    for (name, contents) in files {
+        world = world.add_file(&name, contents);
+    }
+
+    let document = typst::compile(&world)
+        .output
+        .map_err(|e| format!("{:?}", e))?;
+    typst_pdf::pdf(&document, &PdfOptions::default())
+        .map_err(|e| format!("{:?}", e))
+}
+
 #[get("/break-slides")]
 pub async fn break_slides_page() -> Markup {
     ui::page_of_body(make_form(None, None), None)
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+alter table spar_series_members
+drop column identity_id;
+
+drop table group_member_identities;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- A person who is (or has been) a member of several spar series run by the
+-- same group. This allows us to carry ratings over from one spar series to
+-- the next.
+create table if not exists group_member_identities (
+    id integer primary key not null,
+    public_id text not null unique,
+    group_id integer not null references groups (id),
+    created_at timestamp not null default current_timestamp
+);
+
+alter table spar_series_members
+add column identity_id integer references group_member_identities (id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+alter table adjudicator_ballots drop column is_pinned;
+alter table adjudicator_ballots drop column submitted_by;
+alter table adjudicator_ballots drop column source;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- Ballots are never modified once they have been submitted (resubmitting a
+-- ballot creates a new row), so these columns record where each version of a
+-- ballot came from.
+--
+-- source is one of "link" (submitted by the adjudicator using their private
+-- URL) or "admin" (entered by an administrator)
+alter table adjudicator_ballots add column source text not null default 'link';
+-- the user who submitted the ballot (null if it was submitted through a link)
+alter table adjudicator_ballots add column submitted_by integer references users (id);
+-- administrators may pin a ballot, in which case it is always used as the
+-- result of the room
+alter table adjudicator_ballots add column is_pinned boolean not null default false;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop table if exists spar_series_score_rules;
+update adjudicator_ballot_entries set speak = speak / 2;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- speaker scores may include half points, so they are now stored as a whole
+-- number of half points (e.g. 75.5 is stored as 151)
+update adjudicator_ballot_entries set speak = speak * 2;
+
+-- the scores in these rules are also stored as a whole number of half points
+create table if not exists spar_series_score_rules (
+    id integer primary key not null,
+    spar_series_id integer not null unique references spar_series (id),
+    min_score integer,
+    max_score integer,
+    step integer,
+    -- either 'error' (violations are rejected) or 'warn' (the adjudicator is
+    -- asked to confirm that the scores are correct)
+    enforcement text not null default 'error'
+);
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+-- Links sent to each speaker once a spar has been completed, which they can
+-- use to give feedback on the adjudicators in their room.
+create table if not exists speaker_feedback_links (
+    id integer primary key not null,
+    public_id text not null unique,
+    link text not null unique,
+    speaker_id integer not null references spar_speakers (id) on delete cascade,
+    created_at timestamp not null default current_timestamp,
+    expires_at timestamp not null
+);
+
+-- Feedback given by a speaker on one of the adjudicators in their room. This
+-- is only visible to administrators of the spar series.
+create table if not exists adjudicator_feedback (
+    id integer primary key not null,
+    public_id text not null unique,
+    adjudicator_id integer not null references spar_adjudicators (id) on delete cascade,
+    speaker_id integer not null references spar_speakers (id) on delete cascade,
+    -- an (optional) score out of ten
+    score integer,
+    comments text,
+    created_at timestamp not null default current_timestamp,
+    unique (adjudicator_id, speaker_id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop table if exists adjudicator_ballot_team_comments;
+alter table adjudicator_ballot_entries drop column comments;
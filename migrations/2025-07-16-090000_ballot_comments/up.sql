@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- written feedback from the adjudicator to each speaker
+alter table adjudicator_ballot_entries add column comments text;
+
+-- written feedback from the adjudicator to each team
+create table if not exists adjudicator_ballot_team_comments (
+    id integer primary key not null,
+    public_id text not null unique,
+    ballot_id integer not null references adjudicator_ballots (id) on delete cascade,
+    team_id integer not null references spar_teams (id) on delete cascade,
+    comments text not null,
+    unique (ballot_id, team_id)
+);
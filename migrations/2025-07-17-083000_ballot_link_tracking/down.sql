@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table spar_adjudicator_ballot_links drop column opened_at;
+alter table spar_adjudicator_ballot_links drop column sent_at;
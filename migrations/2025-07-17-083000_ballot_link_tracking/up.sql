@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- when the ballot link was last emailed to the adjudicator (null if it has
+-- never been sent)
+alter table spar_adjudicator_ballot_links add column sent_at timestamp;
+-- when the adjudicator first opened the ballot link (null if they have not
+-- opened it yet)
+alter table spar_adjudicator_ballot_links add column opened_at timestamp;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- the user account belonging to this member (if any), which allows them to
+-- submit ballots without using the emailed link
+alter table spar_series_members add column user_id integer references users (id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop table if exists spar_series_ballot_settings;
+alter table spar_adjudicator_ballot_links drop column reminded_at;
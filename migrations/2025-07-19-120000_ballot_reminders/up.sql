@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+-- when the adjudicator was last reminded to submit their ballot (null if they
+-- have not been reminded)
+alter table spar_adjudicator_ballot_links add column reminded_at timestamp;
+
+create table if not exists spar_series_ballot_settings (
+    id integer primary key not null,
+    spar_series_id integer not null unique references spar_series (id),
+    -- if set, adjudicators who have not submitted their ballot this many hours
+    -- after the start of a spar are automatically reminded to do so
+    reminder_after_hours integer
+);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table spar_series_ballot_settings drop column auto_complete;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- whether spars are automatically marked as complete once every room has a
+-- ballot
+alter table spar_series_ballot_settings add column auto_complete boolean not null default false;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- a record of each time a completed spar was reopened (e.g. so that a ballot
+-- could be corrected)
+create table if not exists spar_reopenings (
+    id integer primary key not null,
+    public_id text not null unique,
+    spar_id integer not null references spars (id),
+    reopened_by integer not null references users (id),
+    reason text not null,
+    created_at timestamp not null default current_timestamp
+);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- previous versions of each draft draw (`draft_draws.data` always contains the
+-- data of the version given by `draft_draws.version`)
+create table if not exists draft_draw_versions (
+    id integer primary key not null,
+    public_id text not null unique,
+    draft_draw_id integer not null references draft_draws (id),
+    version integer not null,
+    data text not null,
+    -- the edit command(s) which produced this version (null for the version
+    -- produced by the draw generator)
+    command text,
+    created_by integer references users (id),
+    created_at timestamp not null default current_timestamp,
+    unique (draft_draw_id, version)
+);
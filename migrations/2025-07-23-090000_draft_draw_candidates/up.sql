@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- when a draw is generated, several candidate drafts are produced (using
+-- different weights for the solver), so that the administrator can pick the
+-- best one
+create table if not exists draft_draw_candidates (
+    id integer primary key not null,
+    draft_draw_id integer not null unique references draft_draws (id),
+    -- shared by all the candidates produced by the same request to generate a
+    -- draw
+    generation_id text not null,
+    preset text not null,
+    -- the metrics are null until the draw has been generated
+    max_team_gap double,
+    mean_team_gap double,
+    partner_preferences_honoured integer,
+    partner_preferences_requested integer
+);
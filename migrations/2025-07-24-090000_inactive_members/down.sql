@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table spar_series_members drop column is_active;
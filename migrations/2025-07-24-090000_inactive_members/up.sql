@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- members who have left can be marked as inactive, which hides them when
+-- people sign up for spars (but keeps their signups, draws and ballots)
+alter table spar_series_members add column is_active boolean not null default true;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table spar_adjudicator_ballot_links drop column auto_reminded_at;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- when the adjudicator was automatically reminded to submit their ballot (null
+-- if they have not been). This is tracked separately from `reminded_at` so
+-- that being reminded by an administrator does not stop the automatic
+-- reminder from being sent.
+alter table spar_adjudicator_ballot_links add column auto_reminded_at timestamp;